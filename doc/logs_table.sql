@@ -16,3 +16,95 @@ ENGINE = MergeTree
 PARTITION BY (toStartOfHour(`timestamp`), `machine_id`, `boot_id`)
 ORDER BY (`timestamp`)
 ;
+
+-- Optional ingest metadata columns, written when `ingest_metadata.enabled` is set
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `ingested_at` DateTime64(6) CODEC(DoubleDelta, ZSTD),
+    ADD COLUMN IF NOT EXISTS `ingest_host` LowCardinality(String),
+    ADD COLUMN IF NOT EXISTS `pipeline_version` LowCardinality(String)
+;
+
+-- Alternative `record` layouts, selected with `clickhouse.record_storage`:
+--
+-- "nested":
+--    `record` Nested(`key` LowCardinality(String), `value` String)
+--
+-- "arrays":
+--    `record_keys` Array(LowCardinality(String)),
+--    `record_values` Array(String)
+--
+-- Tables in this default layout can be copied into a table with another layout
+-- or with optional columns by `journalsqld migrate SOURCE`, writing to the
+-- configured `clickhouse.table`
+
+-- Optional Kubernetes columns, written when `kubernetes.enabled` is set
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `k8s_pod_uid` Nullable(String),
+    ADD COLUMN IF NOT EXISTS `k8s_namespace` LowCardinality(Nullable(String)),
+    ADD COLUMN IF NOT EXISTS `k8s_container` LowCardinality(Nullable(String))
+;
+
+-- Optional repeat count, written when `repeat_compression.enabled` is set
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `repeat_count` UInt32 DEFAULT 1
+;
+
+-- Optional exact timestamps, written when `timestamp_columns.enabled` is set.
+-- `timezone` only when `timestamp_columns.timezone_field` is set as well
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `timestamp_us` Int64 CODEC(DoubleDelta, ZSTD),
+    ADD COLUMN IF NOT EXISTS `source_timestamp_us` Nullable(Int64),
+    ADD COLUMN IF NOT EXISTS `timezone` LowCardinality(Nullable(String))
+;
+
+-- Optional identity of the authenticated forwarder, written when
+-- `authentication.identities` are configured. NULL for unauthenticated input.
+-- Rows whose `hostname` doesn't belong to their identity point to spoofing
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `source_identity` LowCardinality(Nullable(String))
+;
+
+-- Optional labels of the input rows were received on, written when any input
+-- has `labels` configured. Unlike journal fields, senders can't forge them
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `labels` Map(LowCardinality(String), LowCardinality(String))
+;
+
+-- Optional journald namespace, written when `journal_upload.namespace` is set.
+-- NULL for entries of the default namespace
+ALTER TABLE logs2
+    ADD COLUMN IF NOT EXISTS `namespace` LowCardinality(Nullable(String))
+;
+
+-- Optional cursor index, written when `cursor_index.enabled` is set. Rows are
+-- only added for the first entry seen per machine and hour, so an hour can have
+-- more than one row after restarts; take the earliest:
+--
+--    SELECT argMin(`cursor`, `timestamp`), min(`timestamp`)
+--    FROM logs2_cursor_index WHERE `machine_id` = ? AND `hour` = ?
+CREATE TABLE IF NOT EXISTS logs2_cursor_index (
+    `machine_id` LowCardinality(String),
+    `hour` DateTime,
+    `timestamp` DateTime64(6),
+    `cursor` String
+)
+ENGINE = MergeTree
+ORDER BY (`machine_id`, `hour`)
+;
+
+-- Entries and malformed input journalsqld dropped, with [dead_letter] output =
+-- "clickhouse". `entry` holds the dropped entry as a JSON object, `offset`,
+-- `discarded_bytes` and `excerpt` describe skipped malformed input:
+CREATE TABLE IF NOT EXISTS logs2_dead_letter (
+    `time` DateTime64(6),
+    `stage` LowCardinality(String),
+    `reason` LowCardinality(String),
+    `message` String,
+    `entry` Nullable(String),
+    `offset` Nullable(UInt64),
+    `discarded_bytes` Nullable(UInt64),
+    `excerpt` Nullable(String)
+)
+ENGINE = MergeTree
+ORDER BY (`time`)
+;
@@ -30,15 +30,44 @@
         };
 
         rust = pkgs.rust-bin.fromRustupToolchainFile ./rust-toolchain.toml;
+        rustPlatform = pkgs.makeRustPlatform {
+          cargo = rust;
+          rustc = rust;
+        };
 
         mkShell = pkgs.callPackage clean-devshell.lib.mkDevShell { };
       in
       rec {
+        packages.journalsqld = rustPlatform.buildRustPackage {
+          pname = "journalsqld";
+          version = "0.0.0";
+          src = self;
+
+          cargoLock = {
+            lockFile = ./Cargo.lock;
+            allowBuiltinFetchGit = true;
+          };
+
+          cargoBuildFlags = [ "--package" "journalsqld" ];
+          cargoTestFlags = [ "--workspace" ];
+
+          meta.mainProgram = "journalsqld";
+        };
+
+        packages.default = packages.journalsqld;
+
         devShell = mkShell {
           packages = [
             rust
             pkgs.cargo-flamegraph
           ];
         };
-      });
+      }) // {
+      nixosModules.journalsqld = { lib, pkgs, ... }: {
+        imports = [ ./nix/module.nix ];
+        services.journalsqld.package = lib.mkDefault self.packages.${pkgs.system}.journalsqld;
+      };
+
+      nixosModules.default = self.nixosModules.journalsqld;
+    };
 }
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+use tokio::sync::oneshot;
+
+/// Marks barrier entries, which are never inserted
+const BARRIER_FIELD: &str = "__JOURNALSQLD_BARRIER";
+
+/// Tells producers when the entries they queued are in ClickHouse. A producer
+/// queues a barrier after its entries, the consumer notes barriers as it takes
+/// them off the queue and releases them once every row written before them is
+/// inserted. As the queue is in order, so is everything the producer queued
+/// before its barrier.
+#[derive(Default)]
+pub struct CommitAcks {
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<u64, oneshot::Sender<()>>>,
+    /// Barriers the consumer passed, waiting for a commit
+    reached: Mutex<Vec<u64>>,
+}
+
+impl CommitAcks {
+    /// Entry to queue after those to acknowledge, and a receiver resolving once
+    /// they are inserted. It fails if the consumer stops first.
+    pub fn barrier(&self) -> (JournalEntry, oneshot::Receiver<()>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.waiting
+            .lock()
+            .expect("commit acks lock poisoned")
+            .insert(id, sender);
+
+        let mut entry = JournalEntry::default();
+        entry.put(BARRIER_FIELD, JournalFieldValue::UTF8(id.to_string()));
+        (entry, receiver)
+    }
+
+    /// Notes `entry` if it is a barrier, returns whether it was one. Barriers
+    /// of an earlier run, replayed from the spool, are skipped as well.
+    pub fn reach(&self, entry: &JournalEntry) -> bool {
+        let Some(id) = entry.get(BARRIER_FIELD) else {
+            return false;
+        };
+
+        if let Ok(id) = String::from(id).parse() {
+            self.reached
+                .lock()
+                .expect("commit acks lock poisoned")
+                .push(id);
+        }
+        true
+    }
+
+    /// Releases the barriers reached so far, to be called once all rows written
+    /// so far are inserted
+    pub fn committed(&self) {
+        let reached = std::mem::take(&mut *self.reached.lock().expect("commit acks lock poisoned"));
+        if reached.is_empty() {
+            return;
+        }
+
+        let mut waiting = self.waiting.lock().expect("commit acks lock poisoned");
+        for id in reached {
+            if let Some(sender) = waiting.remove(&id) {
+                // The producer may have given up waiting
+                let _ = sender.send(());
+            }
+        }
+    }
+
+    /// Fails the barriers still waiting, as the consumer stopped
+    pub fn abandon(&self) {
+        self.reached
+            .lock()
+            .expect("commit acks lock poisoned")
+            .clear();
+        self.waiting
+            .lock()
+            .expect("commit acks lock poisoned")
+            .clear();
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicQosOptions};
+use lapin::types::FieldTable;
+use lapin::{Channel, Connection, ConnectionProperties, Consumer};
+use log::{debug, warn};
+use systemd_journal_parser::JournalEntry;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+
+use crate::config::{AmqpConfig, MessageFormat, ParserConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::journal::{prepare_entry, EntryOrigin};
+use crate::message::parse_message;
+use crate::watchdog::{Stage, Watchdog};
+
+/// Delivery whose entries were queued, awaiting their commit to ClickHouse
+struct PendingDelivery {
+    /// Of the last entry in the message, if any
+    cursor: Option<String>,
+    delivery_tag: u64,
+}
+
+/// Consumer of an AMQP queue. Deliveries are only acknowledged through
+/// [`Self::commit_through`], so the broker redelivers messages whose entries
+/// didn't make it to ClickHouse once the connection is gone.
+pub struct AmqpInput {
+    /// Keeps the connection open
+    _connection: Connection,
+    channel: Channel,
+    consumer: Mutex<Option<Consumer>>,
+    format: MessageFormat,
+    pending: Mutex<VecDeque<PendingDelivery>>,
+    /// Labels of the input
+    origin: EntryOrigin,
+}
+
+impl AmqpInput {
+    pub async fn connect(config: &AmqpConfig) -> Result<Self, lapin::Error> {
+        let connection = Connection::connect(&config.uri, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel
+            .basic_qos(config.prefetch, BasicQosOptions::default())
+            .await?;
+        let consumer = channel
+            .basic_consume(
+                &config.queue,
+                &config.consumer_tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(Self {
+            _connection: connection,
+            channel,
+            consumer: Mutex::new(Some(consumer)),
+            format: config.format,
+            pending: Mutex::new(VecDeque::new()),
+            origin: EntryOrigin::default().with_labels(&config.labels),
+        })
+    }
+
+    /// Reads messages and queues their entries until the connection or the
+    /// consumer goes away. Malformed messages are skipped after queueing the
+    /// entries before the error, and acknowledged along with the next ones,
+    /// as they would otherwise be delivered again forever.
+    pub async fn read_messages(
+        &self,
+        config: ParserConfig,
+        sender: mpsc::Sender<JournalEntry>,
+        watchdog: Arc<Watchdog>,
+        dead_letters: Arc<DeadLetterQueue>,
+    ) -> Result<(), lapin::Error> {
+        let Some(mut consumer) = self.consumer.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        loop {
+            let delivery = tokio::select! {
+                delivery = consumer.next() => match delivery {
+                    Some(delivery) => delivery?,
+                    None => return Ok(()),
+                },
+                _ = sender.closed() => return Ok(()),
+            };
+
+            let entries =
+                parse_message(&delivery.data, self.format, &config, &dead_letters, "AMQP");
+
+            let mut cursor = None;
+            for mut entry in entries {
+                prepare_entry(&mut entry, &config, &self.origin);
+                cursor = entry.get("__CURSOR").map(String::from);
+
+                watchdog.busy(Stage::Producer);
+                if let Err(err) = sender.send(entry).await {
+                    debug!("producer channel closed: {:?}", err);
+                    return Ok(());
+                }
+                watchdog.idle(Stage::Producer);
+                watchdog.produced();
+            }
+
+            self.pending.lock().unwrap().push_back(PendingDelivery {
+                cursor,
+                delivery_tag: delivery.delivery_tag,
+            });
+        }
+    }
+
+    /// Acknowledges the deliveries up to the one whose last entry has `cursor`,
+    /// after it was committed to ClickHouse
+    pub fn commit_through(&self, cursor: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(position) = pending
+            .iter()
+            .rposition(|delivery| delivery.cursor.as_deref() == Some(cursor))
+        else {
+            return;
+        };
+
+        // Delivery tags grow on a channel, so acknowledging the last one with
+        // `multiple` covers those before it
+        let delivery_tag = pending[position].delivery_tag;
+        pending.drain(..=position);
+        drop(pending);
+
+        let channel = self.channel.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = channel
+                .basic_ack(delivery_tag, BasicAckOptions { multiple: true })
+                .await
+            {
+                warn!("failed to acknowledge AMQP deliveries: {}", err);
+            }
+        });
+    }
+}
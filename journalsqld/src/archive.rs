@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use hyper::body::Bytes;
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use log::{error, info};
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path;
+use object_store::ObjectStore;
+use time::OffsetDateTime;
+
+use crate::config::ArchiveConfig;
+use crate::inserter::InsertError;
+use crate::parquet_file::ParquetFile;
+use crate::row::LogRecordRow;
+use crate::Error;
+
+const HOUR: i64 = 3600;
+
+/// Wait after a failed upload before trying again
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Archives rows as Parquet objects in S3 compatible storage, one per machine
+/// and hour of the entry timestamps. Objects are uploaded once the hour they
+/// were started in is over, entries arriving late for an earlier hour end up
+/// in another object for it. Rows count as committed once all objects they
+/// were finished with are uploaded.
+pub struct S3Archive {
+    store: AmazonS3,
+    path: String,
+    row_group_entries: usize,
+    /// Objects being filled, by machine ID and start of the hour
+    objects: BTreeMap<(String, i64), ParquetFile>,
+    /// Hour the objects being filled were started in
+    started: i64,
+    /// Encoded objects waiting for their upload, by key
+    finished: Vec<(String, Bytes, u64)>,
+    retry_at: Option<Instant>,
+    /// Of the last row written
+    last_cursor: Option<String>,
+    /// Of the last row in the finished objects
+    finished_cursor: Option<String>,
+    committed_cursor: Option<String>,
+}
+
+impl S3Archive {
+    pub fn new(config: &ArchiveConfig) -> Result<Self, Error> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&config.bucket)
+            .with_region(&config.region);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(access_key_id) = &config.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+
+        Ok(Self {
+            store: builder.build()?,
+            path: config.path.clone(),
+            row_group_entries: config.row_group_entries,
+            objects: BTreeMap::new(),
+            started: current_hour(),
+            finished: Vec::new(),
+            retry_at: None,
+            last_cursor: None,
+            finished_cursor: None,
+            committed_cursor: None,
+        })
+    }
+
+    /// Encodes the objects being filled for their upload
+    fn finish_objects(&mut self) -> Result<(), InsertError> {
+        let id = OffsetDateTime::now_utc().unix_timestamp();
+        for ((machine_id, hour), object) in std::mem::take(&mut self.objects) {
+            let entries = object.entries();
+            let data = object
+                .finish()
+                .map_err(|err| InsertError::ArchiveError(err.into()))?;
+            let key = object_key(&self.path, &machine_id, hour, id);
+            self.finished.push((key, Bytes::from(data), entries));
+        }
+        self.started = current_hour();
+        if self.last_cursor.is_some() {
+            self.finished_cursor = self.last_cursor.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Uploads the finished objects, those failing stay queued for a retry
+    async fn upload(&mut self) -> Result<Quantities, InsertError> {
+        let mut archived = Quantities::default();
+        while let Some((key, data, entries)) = self.finished.first() {
+            if let Err(err) = self
+                .store
+                .put(&Path::from(key.as_str()), data.clone())
+                .await
+            {
+                self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                return Err(InsertError::ArchiveError(err.into()));
+            }
+            info!("archived {} entries to {}", entries, key);
+
+            archived += Quantities {
+                entries: *entries,
+                transactions: 1,
+            };
+            self.finished.remove(0);
+        }
+        self.retry_at = None;
+        if self.finished_cursor.is_some() {
+            self.committed_cursor = self.finished_cursor.take();
+        }
+
+        Ok(archived)
+    }
+}
+
+impl Sink<LogRecordRow> for S3Archive {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        for row in rows {
+            self.last_cursor = Some(row.cursor.clone());
+            let hour = row.timestamp.unix_timestamp().div_euclid(HOUR) * HOUR;
+            let row_group_entries = self.row_group_entries;
+            let object = self
+                .objects
+                .entry((row.machine_id.clone(), hour))
+                .or_insert_with(|| ParquetFile::new(Vec::new(), row_group_entries));
+            if let Err(err) = object.write(row) {
+                error!("failed to encode archived row: {}", err);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if current_hour() != self.started {
+                self.finish_objects()?;
+            }
+            if self.finished.is_empty() || self.retry_at.map_or(false, |at| at > Instant::now()) {
+                return Ok(Quantities::default());
+            }
+
+            Ok(self.upload().await?)
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            self.finish_objects()?;
+            Ok(self.upload().await?)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.finished.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+/// Fills in the placeholders of the path template: `{year}`, `{month}`,
+/// `{day}`, `{hour}`, `{date}` for `{year}-{month}-{day}`, `{machine_id}` and
+/// `{id}`, the time the object was finished in seconds since the epoch
+fn object_key(template: &str, machine_id: &str, hour: i64, id: i64) -> String {
+    let hour = OffsetDateTime::from_unix_timestamp(hour).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let (year, month, day) = (
+        format!("{:04}", hour.year()),
+        format!("{:02}", u8::from(hour.month())),
+        format!("{:02}", hour.day()),
+    );
+
+    template
+        .replace("{date}", &format!("{}-{}-{}", year, month, day))
+        .replace("{year}", &year)
+        .replace("{month}", &month)
+        .replace("{day}", &day)
+        .replace("{hour}", &format!("{:02}", hour.hour()))
+        .replace("{machine_id}", machine_id)
+        .replace("{id}", &id.to_string())
+}
+
+fn current_hour() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp().div_euclid(HOUR) * HOUR
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::io;
+
+use crate::config::AuthenticationConfig;
+use crate::tls::{certificate_names, matches_pattern};
+
+struct Identity {
+    name: String,
+    tokens: Vec<String>,
+    client_names: Vec<String>,
+}
+
+/// Forwarders known by a bearer token or the names in their client
+/// certificate, so the source of entries doesn't rest on what they claim in
+/// `_HOSTNAME`
+pub struct Identities {
+    identities: Vec<Identity>,
+    required: bool,
+}
+
+impl Identities {
+    /// Reads the token files of the configured identities
+    pub fn load(config: &AuthenticationConfig) -> io::Result<Self> {
+        let mut identities = Vec::with_capacity(config.identities.len());
+        for identity in &config.identities {
+            let mut tokens: Vec<String> = identity.token.iter().cloned().collect();
+            if let Some(path) = &identity.token_file {
+                let token = fs::read_to_string(path).map_err(|err| {
+                    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+                })?;
+                tokens.push(token.trim().to_string());
+            }
+
+            identities.push(Identity {
+                name: identity.name.clone(),
+                tokens,
+                client_names: identity.client_names.clone(),
+            });
+        }
+
+        Ok(Self {
+            identities,
+            required: config.required,
+        })
+    }
+
+    /// Whether connections and uploads without a known identity are refused
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// Identity whose token is `token`
+    pub fn by_token(&self, token: &str) -> Option<&str> {
+        self.identities
+            .iter()
+            .find(|identity| {
+                identity
+                    .tokens
+                    .iter()
+                    .any(|known| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            })
+            .map(|identity| identity.name.as_str())
+    }
+
+    /// Identity matching a name of the verified client certificate, the first
+    /// one in `certificates`
+    pub fn by_certificate(&self, certificates: Option<&[rustls::Certificate]>) -> Option<&str> {
+        let names = certificate_names(certificates?.first()?)?;
+        self.identities
+            .iter()
+            .find(|identity| {
+                identity
+                    .client_names
+                    .iter()
+                    .any(|pattern| names.iter().any(|name| matches_pattern(pattern, name)))
+            })
+            .map(|identity| identity.name.as_str())
+    }
+}
+
+/// Compares without returning early, so response times don't reveal how much
+/// of a token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
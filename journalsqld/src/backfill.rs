@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use time::OffsetDateTime;
+use tokio::io::AsyncRead;
+use tokio::sync::Semaphore;
+
+use crate::client::Client;
+use crate::config::{Config, ParserConfig};
+use crate::decompress::decompressing;
+use crate::import::{self, Progress};
+use crate::inserter::Inserter;
+use crate::sampling::Sampler;
+use crate::schema::Schema;
+use crate::transform::Transform;
+use crate::Error;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// File to backfill from
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Source {
+    /// Binary journal file, as journald writes them
+    Journal(PathBuf),
+    /// Export format dump, optionally gzip or zstd compressed
+    Export(PathBuf),
+}
+
+impl Source {
+    fn path(&self) -> &Path {
+        match self {
+            Source::Journal(path) | Source::Export(path) => path,
+        }
+    }
+}
+
+/// Imports the archived journal files and export dumps in `paths`, searching
+/// directories recursively, once and without following them. Like `import`,
+/// files are imported in order per machine and machines in parallel, but with
+/// the larger batches of `[backfill]`. Progress is reported with an estimate
+/// of the remaining time, based on the size of the files imported so far.
+pub async fn run(config: &Config, client: Client, paths: Vec<PathBuf>) -> Result<(), Error> {
+    if paths.is_empty() {
+        return Err("usage: journalsqld backfill DIRECTORY|FILE...".into());
+    }
+
+    let started = Instant::now();
+    let mut sources = Vec::new();
+    for path in paths {
+        find_sources(&path, &mut sources).await?;
+    }
+    if sources.is_empty() {
+        return Err("found no journal files or export dumps to backfill".into());
+    }
+
+    let progress = Arc::new(Progress::default());
+    let bytes_done = Arc::new(AtomicU64::new(0));
+    let total_files = sources.len();
+    let mut total_bytes = 0;
+
+    let mut machines: BTreeMap<String, Vec<(OffsetDateTime, u64, Source)>> = BTreeMap::new();
+    for source in sources {
+        let size = tokio::fs::metadata(source.path()).await?.len();
+        match probe(&source, config).await {
+            Ok((machine_id, first_timestamp)) => {
+                total_bytes += size;
+                machines
+                    .entry(machine_id)
+                    .or_default()
+                    .push((first_timestamp, size, source));
+            }
+            Err(err) => {
+                error!("failed to probe {}: {}", source.path().display(), err);
+                progress.files_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    info!(
+        "backfilling {} files of {} machines, {} bytes",
+        total_files,
+        machines.len(),
+        total_bytes
+    );
+
+    let workers = match config.backfill.workers {
+        0 => num_cpus::get(),
+        workers => workers,
+    };
+    let permits = Arc::new(Semaphore::new(workers));
+    let mut tasks = Vec::with_capacity(machines.len());
+    for (machine_id, mut files) in machines {
+        files.sort();
+
+        let mut inserter = Inserter::new(
+            client.clone(),
+            &config.clickhouse.table,
+            Schema::new(config),
+        )
+        .with_format(config.clickhouse.format)
+        .with_max_entries(config.backfill.max_entries);
+        let parser_config = config.parser.clone();
+        let bytes_rendering = config.bytes_rendering.clone();
+        let kubernetes_enabled = config.kubernetes.enabled;
+        let sampler = Sampler::new(config.sampling.percent);
+        let transform = Transform::new(&config.transform);
+        let (permits, progress, bytes_done) =
+            (permits.clone(), progress.clone(), bytes_done.clone());
+
+        tasks.push(tokio::task::spawn(async move {
+            let _permit = permits.acquire().await.expect("semaphore closed");
+            for (_, size, source) in files {
+                let path = source.path().to_path_buf();
+                let result = match open(source, &parser_config).await {
+                    Ok(reader) => {
+                        import::import_reader(
+                            reader,
+                            &path.display().to_string(),
+                            &parser_config,
+                            &bytes_rendering,
+                            kubernetes_enabled,
+                            &sampler,
+                            &transform,
+                            &mut inserter,
+                            &progress,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match result {
+                    Ok(()) => progress.files_done.fetch_add(1, Ordering::Relaxed),
+                    Err(err) => {
+                        error!(
+                            "failed to backfill {} ({}): {}",
+                            path.display(),
+                            machine_id,
+                            err
+                        );
+                        progress.files_failed.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+                bytes_done.fetch_add(size, Ordering::Relaxed);
+            }
+        }));
+    }
+
+    let reporter = {
+        let (progress, bytes_done) = (progress.clone(), bytes_done.clone());
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let done = bytes_done.load(Ordering::Relaxed);
+                let eta = remaining(started.elapsed(), done, total_bytes)
+                    .map_or_else(|| "unknown".to_string(), |eta| format!("{:.0?}", eta));
+                info!(
+                    "backfill progress: files={}/{} failed={} entries={} errors={} bytes={}/{} eta={}",
+                    progress.files_done.load(Ordering::Relaxed),
+                    total_files,
+                    progress.files_failed.load(Ordering::Relaxed),
+                    progress.entries.load(Ordering::Relaxed),
+                    progress.errors.load(Ordering::Relaxed),
+                    done,
+                    total_bytes,
+                    eta
+                );
+            }
+        })
+    };
+
+    for task in tasks {
+        task.await?;
+    }
+    reporter.abort();
+
+    let failed = progress.files_failed.load(Ordering::Relaxed);
+    println!("files:    {} ({} failed)", total_files, failed);
+    println!("bytes:    {}", total_bytes);
+    println!("entries:  {}", progress.entries.load(Ordering::Relaxed));
+    println!("errors:   {}", progress.errors.load(Ordering::Relaxed));
+    println!("duration: {:.1?}", started.elapsed());
+
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to backfill", failed, total_files).into());
+    }
+
+    Ok(())
+}
+
+/// Adds the journal files and export dumps at `path` to `sources`, searching
+/// directories recursively. Files journald set aside as corrupted, ending in
+/// `.journal~`, are skipped.
+async fn find_sources(path: &Path, sources: &mut Vec<Source>) -> Result<(), Error> {
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if !tokio::fs::metadata(&path).await?.is_dir() {
+            sources.push(classify(path));
+            continue;
+        }
+
+        let mut directory = tokio::fs::read_dir(&path).await?;
+        while let Some(dir_entry) = directory.next_entry().await? {
+            let path = dir_entry.path();
+            let name = dir_entry.file_name();
+            let name = name.to_string_lossy();
+            if dir_entry.file_type().await?.is_dir() {
+                pending.push(path);
+            } else if name.ends_with(".journal") || name.contains(".export") {
+                sources.push(classify(path));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn classify(path: PathBuf) -> Source {
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "journal")
+    {
+        Source::Journal(path)
+    } else {
+        Source::Export(path)
+    }
+}
+
+/// Machine ID and timestamp of the first entry of a source
+async fn probe(source: &Source, config: &Config) -> Result<(String, OffsetDateTime), Error> {
+    match source {
+        Source::Export(path) => import::probe(path, &config.parser).await,
+        #[cfg(feature = "journal-directory")]
+        Source::Journal(path) => {
+            let header = systemd_journal_parser::JournalFile::open(path)?
+                .header()
+                .clone();
+            let machine_id = header
+                .machine_id
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let timestamp = OffsetDateTime::from_unix_timestamp_nanos(
+                header.head_entry_realtime as i128 * 1000,
+            )?;
+
+            Ok((machine_id, timestamp))
+        }
+        #[cfg(not(feature = "journal-directory"))]
+        Source::Journal(_) => {
+            Err("reading .journal files requires the journal-directory feature".into())
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "journal-directory"), allow(unused_variables))]
+async fn open(
+    source: Source,
+    config: &ParserConfig,
+) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+    match source {
+        Source::Export(path) => Ok(decompressing(tokio::fs::File::open(path).await?).await?),
+        #[cfg(feature = "journal-directory")]
+        Source::Journal(path) => Ok(Box::new(crate::journal_directory::export_file(
+            path,
+            config.options(),
+        )?)),
+        #[cfg(not(feature = "journal-directory"))]
+        Source::Journal(_) => {
+            Err("reading .journal files requires the journal-directory feature".into())
+        }
+    }
+}
+
+/// Estimate of the time left, assuming the rest is imported at the rate so far
+fn remaining(elapsed: Duration, done: u64, total: u64) -> Option<Duration> {
+    if done == 0 {
+        return None;
+    }
+
+    Some(elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64))
+}
@@ -0,0 +1,303 @@
+use std::hash::Hasher;
+use std::io::Write;
+use std::time::Duration;
+
+use fnv::FnvHasher;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_ENCODING;
+use hyper::{Body, Method, Request};
+use hyper_rustls::HttpsConnector;
+use log::warn;
+use url::Url;
+
+use crate::config::{Compression, Profile};
+use crate::proxy::{Proxy, ProxyConnector};
+
+// ClickHouse closes idle keep-alive connections after a few seconds by default
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ClickHouse Cloud services idle down and may take a minute to wake up
+const CLOUD_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+const CLOUD_MAX_ATTEMPTS: u32 = 5;
+const CLOUD_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+const CLOUD_DEFAULT_PORT: u16 = 8443;
+const DEFAULT_PORT: u16 = 8123;
+
+type HttpClient = hyper::Client<HttpsConnector<ProxyConnector>>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("HTTP error")]
+    HttpError(hyper::Error),
+
+    #[error("Request error")]
+    RequestError(hyper::http::Error),
+
+    #[error("I/O error")]
+    IOError(std::io::Error),
+
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+
+    #[error("ClickHouse responded with {status}: {message}")]
+    ServerError { status: u16, message: String },
+}
+
+impl ClientError {
+    /// Whether the request may succeed if retried, e.g. while a service is starting up
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::HttpError(err) => err.is_connect() || err.is_incomplete_message(),
+            Self::Timeout(_) => true,
+            Self::ServerError { status, .. } => matches!(status, 502 | 503 | 504),
+            Self::RequestError(_) | Self::IOError(_) => false,
+        }
+    }
+
+    /// ClickHouse exception code of a server error, from the `Code: 27.` its
+    /// error messages start with
+    pub fn exception_code(&self) -> Option<u32> {
+        let Self::ServerError { message, .. } = self else {
+            return None;
+        };
+        let code = message.strip_prefix("Code: ")?;
+        let length = code
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(code.len());
+
+        code[..length].parse().ok()
+    }
+}
+
+/// Minimal ClickHouse HTTP interface client
+#[derive(Clone)]
+pub struct Client {
+    http: HttpClient,
+    tls: Option<rustls::ClientConfig>,
+    proxy: Option<Proxy>,
+    url: Url,
+    database: String,
+    user: Option<String>,
+    password: Option<String>,
+    compression: Compression,
+    options: Vec<(String, String)>,
+    timeout: Duration,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    /// Whether inserts carry a token derived from their data, so the server
+    /// drops a retried insert it already applied
+    deduplicate: bool,
+}
+
+impl Client {
+    pub fn from_uri(uri: &str) -> Result<Self, url::ParseError> {
+        let mut url: Url = uri.parse()?;
+
+        let user = Some(url.username())
+            .filter(|user| !user.is_empty())
+            .map(String::from);
+        let password = url.password().map(String::from);
+        let database = url
+            .path()
+            .strip_prefix('/')
+            .filter(|path| !path.is_empty())
+            .unwrap_or("default")
+            .to_string();
+
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+        url.set_path("/");
+        url.set_query(None);
+
+        Ok(Self {
+            http: http_client(None, None),
+            tls: None,
+            proxy: None,
+            url,
+            database,
+            user,
+            password,
+            compression: Compression::None,
+            options: Vec::new(),
+            timeout: REQUEST_TIMEOUT,
+            max_attempts: 1,
+            retry_backoff: Duration::ZERO,
+            deduplicate: false,
+        })
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Uses `config` for HTTPS connections instead of the system trust store
+    pub fn with_tls(mut self, config: rustls::ClientConfig) -> Self {
+        self.tls = Some(config);
+        self.http = http_client(self.tls.as_ref(), self.proxy.as_ref());
+        self
+    }
+
+    /// Connects through `proxy`, unless the host is on its no-proxy list
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self.http = http_client(self.tls.as_ref(), self.proxy.as_ref());
+        self
+    }
+
+    /// Adds a ClickHouse setting sent along with every query
+    pub fn with_option(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        match profile {
+            Profile::Default => self,
+            Profile::ClickhouseCloud => {
+                // Cloud only accepts TLS connections, by default on port 8443, so
+                // the plain HTTP port is replaced as well
+                if self.url.scheme() != "https" {
+                    let port = match self.url.port() {
+                        None | Some(DEFAULT_PORT) => CLOUD_DEFAULT_PORT,
+                        Some(port) => port,
+                    };
+                    let _ = self.url.set_scheme("https");
+                    let _ = self.url.set_port(Some(port));
+                }
+
+                // A request that timed out or lost its connection may have been
+                // applied, so retried inserts are deduplicated by their token
+                self.timeout = CLOUD_REQUEST_TIMEOUT;
+                self.max_attempts = CLOUD_MAX_ATTEMPTS;
+                self.retry_backoff = CLOUD_RETRY_BACKOFF;
+                self.deduplicate = true;
+
+                // Buffer inserts server side instead of creating a part per batch, and
+                // only acknowledge once data has been flushed.
+                self.with_option("async_insert", "1")
+                    .with_option("async_insert_deduplicate", "1")
+                    .with_option("wait_for_async_insert", "1")
+                    .with_option("wait_end_of_query", "1")
+            }
+        }
+    }
+
+    /// Executes `query`, sending `data` as the request body
+    pub async fn execute(&self, query: &str, data: Vec<u8>) -> Result<Bytes, ClientError> {
+        let mut url = self.url.clone();
+        {
+            let mut pairs = url.query_pairs_mut();
+            pairs.append_pair("database", &self.database);
+            pairs.append_pair("query", query);
+            for (name, value) in self.options.iter() {
+                pairs.append_pair(name, value);
+            }
+            if self.deduplicate && !data.is_empty() {
+                pairs.append_pair(
+                    "insert_deduplication_token",
+                    &deduplication_token(query, &data),
+                );
+            }
+        }
+
+        let data = match self.compression {
+            Compression::None => Bytes::from(data),
+            Compression::Lz4 => Bytes::from(lz4(&data).map_err(ClientError::IOError)?),
+            Compression::Gzip => Bytes::from(gzip(&data).map_err(ClientError::IOError)?),
+        };
+
+        let mut attempt = 1;
+        loop {
+            match self.send(&url, data.clone()).await {
+                Err(err) if err.is_transient() && attempt < self.max_attempts => {
+                    warn!(
+                        "ClickHouse request failed, retrying (attempt {}/{}): {}",
+                        attempt, self.max_attempts, err
+                    );
+                    tokio::time::sleep(self.retry_backoff * attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send(&self, url: &Url, data: Bytes) -> Result<Bytes, ClientError> {
+        let mut builder = Request::builder().method(Method::POST).uri(url.as_str());
+        if let Some(user) = &self.user {
+            builder = builder.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            builder = builder.header("X-ClickHouse-Key", password);
+        }
+        match self.compression {
+            Compression::None => {}
+            Compression::Lz4 => builder = builder.header(CONTENT_ENCODING, "lz4"),
+            Compression::Gzip => builder = builder.header(CONTENT_ENCODING, "gzip"),
+        }
+
+        let request = builder
+            .body(Body::from(data))
+            .map_err(ClientError::RequestError)?;
+        let response = tokio::time::timeout(self.timeout, self.http.request(request))
+            .await
+            .map_err(|_| ClientError::Timeout(self.timeout))?
+            .map_err(ClientError::HttpError)?;
+
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(ClientError::HttpError)?;
+
+        if !status.is_success() {
+            return Err(ClientError::ServerError {
+                status: status.as_u16(),
+                message: String::from_utf8_lossy(&body).trim().to_string(),
+            });
+        }
+
+        Ok(body)
+    }
+}
+
+/// HTTP client trusting the system roots unless `tls` is given
+fn http_client(tls: Option<&rustls::ClientConfig>, proxy: Option<&Proxy>) -> HttpClient {
+    let builder = match tls {
+        Some(config) => hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(config.clone()),
+        None => hyper_rustls::HttpsConnectorBuilder::new().with_native_roots(),
+    };
+    // SNI is sent based on the request host
+    let connector = builder
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(ProxyConnector::new(proxy.cloned()));
+
+    hyper::Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .build(connector)
+}
+
+/// Same for every attempt of a request
+fn deduplication_token(query: &str, data: &[u8]) -> String {
+    let mut hasher = FnvHasher::default();
+    hasher.write(query.as_bytes());
+    hasher.write(data);
+    format!("{:016x}-{}", hasher.finish(), data.len())
+}
+
+fn lz4(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::with_capacity(data.len() / 2));
+    encoder.write_all(data)?;
+    encoder.finish().map_err(std::io::Error::from)
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = flate2::write::GzEncoder::new(
+        Vec::with_capacity(data.len() / 4),
+        flate2::Compression::fast(),
+    );
+    encoder.write_all(data)?;
+    encoder.finish()
+}
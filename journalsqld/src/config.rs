@@ -0,0 +1,1548 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use journalsql_pipeline::{QueueFull, SinkQueueConfig};
+use serde::Deserialize;
+use systemd_journal_parser::{BytesRendering, ParseOptions, ParserLimits, Utf8Mode};
+
+use crate::matcher::Matcher;
+
+pub const CONFIG_PATH_ENV: &str = "JOURNALSQLD_CONFIG";
+pub const CLICKHOUSE_URI_ENV: &str = "CLICKHOUSE_URI";
+
+/// Static labels of an input, e.g. `site = "eu-1"`, written to the `labels`
+/// column of its rows
+pub type Labels = BTreeMap<String, String>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub clickhouse: ClickhouseConfig,
+    pub ingest_metadata: IngestMetadataConfig,
+    pub kubernetes: KubernetesConfig,
+    pub parser: ParserConfig,
+    pub bytes_rendering: BytesRenderingConfig,
+    pub transform: TransformConfig,
+    pub journal_upload: JournalUploadConfig,
+    pub http: HttpConfig,
+    pub input: InputConfig,
+    pub remote: RemoteConfig,
+    pub syslog: SyslogConfig,
+    pub gelf: GelfConfig,
+    pub fluent: FluentConfig,
+    pub grpc: GrpcConfig,
+    pub docker: DockerConfig,
+    pub pod_logs: PodLogsConfig,
+    pub kafka: KafkaConfig,
+    pub amqp: AmqpConfig,
+    pub socket_activation: SocketActivationConfig,
+    pub input_tls: InputTlsConfig,
+    pub authentication: AuthenticationConfig,
+    pub watchdog: WatchdogConfig,
+    pub dead_letter: DeadLetterConfig,
+    pub cursor_index: CursorIndexConfig,
+    pub source_cursors: SourceCursorsConfig,
+    pub sampling: SamplingConfig,
+    pub repeat_compression: RepeatCompressionConfig,
+    pub timestamp_columns: TimestampColumnsConfig,
+    pub slo: SloConfig,
+    pub spool: SpoolConfig,
+    pub backfill: BackfillConfig,
+    pub otlp: OtlpConfig,
+    pub archive: ArchiveConfig,
+    pub file_output: FileOutputConfig,
+    pub relay: RelayConfig,
+    pub syslog_output: SyslogOutputConfig,
+    pub sqlite: SqliteConfig,
+    pub duckdb: DuckdbConfig,
+    /// Sinks of the entries matching a route, the first matching route
+    /// applies and entries matching none go to every sink
+    pub routes: Vec<RouteConfig>,
+    pub proxy: ProxyConfig,
+}
+
+/// Sends the entries matching `matcher` only to `sinks`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(rename = "match")]
+    pub matcher: Matcher,
+    /// Names of the sinks: clickhouse, file, relay, syslog, otlp, archive,
+    /// sqlite or duckdb
+    pub sinks: Vec<String>,
+    /// Table the matching entries are inserted into when sent to ClickHouse,
+    /// taking precedence over tenant and machine tables
+    pub table: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickhouseConfig {
+    /// Whether entries are inserted, as opposed to only sent to other sinks
+    pub enabled: bool,
+    /// Overridden by the `CLICKHOUSE_URI` environment variable when set
+    pub uri: Option<String>,
+    pub profile: Profile,
+    pub table: String,
+    pub format: InsertFormat,
+    pub record_storage: RecordStorage,
+    pub compression: Compression,
+    pub max_entries: u64,
+    /// Maximum time in seconds between inserts
+    pub period: u64,
+    /// Per-machine overrides, the first matching entry applies
+    pub machines: Vec<MachineConfig>,
+    /// Overrides the `proxy` section for ClickHouse connections
+    pub proxy: Option<ProxyConfig>,
+    pub queue: SinkQueueConfig,
+}
+
+impl ClickhouseConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+}
+
+impl Default for ClickhouseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            uri: None,
+            profile: Profile::default(),
+            table: String::from("logs2"),
+            format: InsertFormat::default(),
+            record_storage: RecordStorage::default(),
+            compression: Compression::default(),
+            max_entries: 100_000,
+            period: 5,
+            machines: Vec::new(),
+            proxy: None,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Inserter settings for machines whose ID matches a pattern. Unset options are
+/// inherited from the `clickhouse` section.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    /// Glob pattern, `*` matches any sequence of characters and `?` a single one
+    pub machine_id: String,
+    pub table: Option<String>,
+    pub format: Option<InsertFormat>,
+    pub max_entries: Option<u64>,
+    /// Maximum time in seconds between inserts
+    pub period: Option<u64>,
+    /// Inserters with a higher priority insert first, e.g. so that quiet
+    /// appliances aren't held up behind noisy build machines. 0 when unset, like
+    /// the default inserter.
+    pub priority: Option<i32>,
+}
+
+/// Connection profile, adjusting client settings for a particular kind of deployment
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    #[default]
+    Default,
+    ClickhouseCloud,
+}
+
+/// Wire format used for inserts. Native is not supported, as it is columnar.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum InsertFormat {
+    #[default]
+    RowBinary,
+    #[serde(rename = "JSONEachRow")]
+    JsonEachRow,
+    /// RowBinary, falling back to JSONEachRow if the server can't parse it
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+/// Table representation of the journal fields not stored in dedicated columns
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStorage {
+    /// `record Map(String, String)`
+    #[default]
+    Map,
+    /// `record Nested(key String, value String)`
+    Nested,
+    /// `record_keys Array(String)` and `record_values Array(String)`
+    Arrays,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    /// LZ4 frames, cheapest to compress
+    #[default]
+    Lz4,
+    Gzip,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestMetadataConfig {
+    /// Adds `ingested_at`, `ingest_host` and `pipeline_version` columns to inserts
+    pub enabled: bool,
+    /// Defaults to the system hostname
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KubernetesConfig {
+    /// Adds `k8s_pod_uid`, `k8s_namespace` and `k8s_container` columns to inserts,
+    /// derived from container runtime fields or those of the pod log input when
+    /// present
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParserConfig {
+    pub utf8: Utf8Mode,
+    pub key_validation: KeyValidation,
+    pub limits: ParserLimits,
+    /// Skip to the next entry boundary on malformed input instead of aborting
+    pub recover: bool,
+    /// Truncate values over `limits.max_field_size`, marking the entry with
+    /// `_TRUNCATED=1`, instead of failing
+    pub truncate_oversized: bool,
+    /// Insert the last entry of input which ends without its terminating blank
+    /// line instead of dropping it
+    pub flush_at_eof: bool,
+}
+
+impl ParserConfig {
+    pub fn options(&self) -> ParseOptions {
+        ParseOptions {
+            utf8: self.utf8,
+            validate_keys: self.key_validation == KeyValidation::Reject,
+            limits: self.limits,
+            truncate_oversized: self.truncate_oversized,
+            flush_at_eof: self.flush_at_eof,
+        }
+    }
+}
+
+/// Rendering of binary field values in the record column
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BytesRenderingConfig {
+    pub default: BytesRendering,
+    /// Overrides by field key
+    pub fields: HashMap<String, BytesRendering>,
+}
+
+impl BytesRenderingConfig {
+    pub fn for_field(&self, key: &str) -> BytesRendering {
+        self.fields.get(key).copied().unwrap_or(self.default)
+    }
+}
+
+/// Outbound proxy for network sinks
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// `http://`, `socks5://` or `socks5h://` URL, optionally with credentials
+    pub url: Option<String>,
+    /// Hosts connected to directly, matching subdomains too; `*` matches all
+    pub no_proxy: Vec<String>,
+}
+
+/// Rewrites of field values applied before rows are created
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformConfig {
+    /// Remove ANSI escape sequences, e.g. terminal colors, from values
+    pub strip_ansi_escapes: bool,
+    /// Transcode a MESSAGE which isn't valid UTF-8 from its detected charset
+    pub detect_charset: bool,
+    /// Add the parameters of RFC 5424 structured data as separate fields
+    pub expand_structured_data: bool,
+}
+
+/// Checking of field keys against journald naming rules
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyValidation {
+    #[default]
+    Off,
+    /// Count and log entries with invalid keys, but keep them
+    Flag,
+    /// Fail parsing on invalid keys
+    Reject,
+}
+
+/// Compression of the streams sent to the input listeners, told apart by the
+/// magic bytes they start with
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputCompression {
+    /// gzip, zstd or uncompressed
+    #[default]
+    Auto,
+    /// Only zstd frames, other connections are closed
+    Zstd,
+    /// Read as is
+    None,
+}
+
+/// How entries are read from the local journal
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalSource {
+    /// `journalctl --follow --output=export` child process
+    #[default]
+    Journalctl,
+    /// libsystemd `sd_journal` API, needs the `sd-journal` feature
+    SdJournal,
+    /// `.journal` files in `directory`, watched with inotify, needs the
+    /// `journal-directory` feature
+    Directory,
+}
+
+/// systemd-journal-upload compatibility: reads the URL and certificates from its
+/// configuration, reads the journal after its saved cursor and keeps its state file
+/// updated with committed cursors.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalUploadConfig {
+    pub enabled: bool,
+    pub config: PathBuf,
+    pub state_file: PathBuf,
+    pub source: JournalSource,
+    /// Journal files read by the `directory` source, the machine's directory
+    /// in `/var/log/journal` when unset
+    pub directory: Option<PathBuf>,
+    /// journald namespace to read instead of the default one, `*` for all of
+    /// them. Entries are tagged with theirs in the `namespace` column.
+    pub namespace: Option<String>,
+}
+
+impl JournalUploadConfig {
+    pub fn directory(&self) -> std::io::Result<PathBuf> {
+        if let Some(directory) = &self.directory {
+            return Ok(directory.clone());
+        }
+
+        let machine_id = std::fs::read_to_string("/etc/machine-id")?;
+        let name = match self.namespace.as_deref() {
+            None => machine_id.trim().to_string(),
+            Some("*") => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the directory source reads a single namespace",
+                ))
+            }
+            Some(namespace) => format!("{}.{}", machine_id.trim(), namespace),
+        };
+        Ok(Path::new("/var/log/journal").join(name))
+    }
+}
+
+impl Default for JournalUploadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            config: PathBuf::from("/etc/systemd/journal-upload.conf"),
+            state_file: PathBuf::from("/var/lib/systemd/journal-upload/state"),
+            source: JournalSource::default(),
+            directory: None,
+            namespace: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Addresses to serve `/healthz` and `/metrics` on, a single address or a
+    /// list. Disabled when empty, unless systemd passes sockets for it.
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+}
+
+/// Address accepting connections in the export format, `tcp://host:port` or
+/// just the socket address, or `unix:/path` for a Unix stream socket
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            // unix:///run/journalsqld.sock and unix:/run/journalsqld.sock alike
+            let path = path.strip_prefix("//").unwrap_or(path);
+            if !path.starts_with('/') {
+                return Err(format!("Unix socket path in {:?} must be absolute", s));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        let addr = s.strip_prefix("tcp://").unwrap_or(s);
+        addr.parse().map(Self::Tcp).map_err(|_| {
+            format!(
+                "invalid listen address {:?}, expected tcp://ADDRESS:PORT or unix:/PATH",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Listeners for forwarders sending the export format and files to read, each
+/// connection or file read as an independent stream instead of stdin
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    /// Extended by `--listen` arguments
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<ListenAddress>,
+    pub socket: SocketConfig,
+    /// Permissions of Unix sockets, e.g. `0o660`, the umask applies when unset
+    pub unix_mode: Option<u32>,
+    /// Compression accepted on connections
+    pub compression: InputCompression,
+    pub files: Vec<FileInputConfig>,
+    /// Applies to each connection, file and stdin on its own
+    pub rate_limit: RateLimitConfig,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+/// Rate an input is read at, pausing it while over the limit. 0 is unlimited.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub entries_per_second: u64,
+    pub bytes_per_second: u64,
+    /// Seconds worth of the rates that may be read at once after being idle
+    pub burst: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            entries_per_second: 0,
+            bytes_per_second: 0,
+            burst: 1.0,
+        }
+    }
+}
+
+/// File or named pipe in the export format, read concurrently with the others
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileInputConfig {
+    pub path: PathBuf,
+    /// Stored in `_JOURNALSQLD_SOURCE` of each entry, the path when unset
+    pub label: Option<String>,
+    /// Keeps reading as the file grows, or as writers reopen the pipe,
+    /// instead of stopping at the end
+    #[serde(default)]
+    pub follow: bool,
+}
+
+impl FileInputConfig {
+    pub fn label(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+/// Payload encoding of Kafka and AMQP messages
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageFormat {
+    /// One or more entries in the export format
+    #[default]
+    Export,
+    /// One `journalctl --output=json` entry per line
+    Json,
+}
+
+/// Consumes entries from Kafka topics instead of reading stdin, needs the
+/// `kafka` feature. Offsets are committed once the entries of a message are
+/// committed to ClickHouse.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub enabled: bool,
+    pub brokers: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub topics: Vec<String>,
+    pub group_id: String,
+    pub format: MessageFormat,
+    /// Further librdkafka consumer properties, e.g. `security.protocol`
+    pub properties: HashMap<String, String>,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            brokers: String::from("localhost:9092"),
+            topics: vec![],
+            group_id: String::from("journalsqld"),
+            format: MessageFormat::default(),
+            properties: HashMap::new(),
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// Consumes entries from an AMQP queue, e.g. of RabbitMQ, instead of reading
+/// stdin, needs the `amqp` feature. Messages are acknowledged once their
+/// entries are committed to ClickHouse.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmqpConfig {
+    pub enabled: bool,
+    /// `amqp://` or `amqps://` URI of the broker, with credentials and vhost
+    pub uri: String,
+    pub queue: String,
+    pub consumer_tag: String,
+    /// Maximum of unacknowledged messages delivered at a time
+    pub prefetch: u16,
+    pub format: MessageFormat,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl Default for AmqpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            uri: String::from("amqp://localhost:5672/%2f"),
+            queue: String::from("journal"),
+            consumer_tag: String::from("journalsqld"),
+            prefetch: 10_000,
+            format: MessageFormat::default(),
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// systemd-journal-remote compatible endpoint, accepting `POST /upload` from
+/// systemd-journal-upload. Uses the `input_tls` settings for HTTPS.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Addresses to accept uploads on, a single address or a list. Disabled
+    /// when empty, unless systemd passes sockets for it.
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+    /// Applies to each connection on its own
+    pub rate_limit: RateLimitConfig,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+/// Syslog receiver for RFC 5424 and RFC 3164 messages over UDP and TCP,
+/// translated into journald-style entries
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogConfig {
+    /// Addresses to receive datagrams on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_udp: Vec<SocketAddr>,
+    /// Addresses to accept connections on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_tcp: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+    /// Longer datagrams are truncated, TCP connections sending longer messages
+    /// are closed
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl SyslogConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.listen_udp.is_empty() || !self.listen_tcp.is_empty()
+    }
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            listen_udp: vec![],
+            listen_tcp: vec![],
+            socket: SocketConfig::default(),
+            max_message_size: 64 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// GELF receiver, e.g. for Docker's `gelf` logging driver, over UDP (chunked
+/// and compressed) and TCP
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GelfConfig {
+    /// Addresses to receive datagrams on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_udp: Vec<SocketAddr>,
+    /// Addresses to accept connections on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_tcp: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+    /// Larger messages, after reassembly and decompression, are dropped, TCP
+    /// connections sending them are closed
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl GelfConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.listen_udp.is_empty() || !self.listen_tcp.is_empty()
+    }
+}
+
+impl Default for GelfConfig {
+    fn default() -> Self {
+        Self {
+            listen_udp: vec![],
+            listen_tcp: vec![],
+            socket: SocketConfig::default(),
+            max_message_size: 1024 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// Fluentd and Fluent Bit `forward` protocol receiver
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FluentConfig {
+    /// Addresses to accept connections on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+    /// Connections sending larger messages, after decompression, are closed
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl Default for FluentConfig {
+    fn default() -> Self {
+        Self {
+            listen: vec![],
+            socket: SocketConfig::default(),
+            max_message_size: 16 * 1024 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// gRPC ingestion service, see `proto/ingest.proto`
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// Addresses to accept connections on, a single address or a list
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<SocketAddr>,
+    pub socket: SocketConfig,
+    /// Batches of a stream read ahead of their acknowledgement
+    pub max_in_flight: usize,
+    /// Larger batches are refused
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            listen: vec![],
+            socket: SocketConfig::default(),
+            max_in_flight: 16,
+            max_message_size: 16 * 1024 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// Tails the `json-file` logs of Docker containers, for hosts not using its
+/// `journald` logging driver
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    pub enabled: bool,
+    /// Directory with a subdirectory per container
+    pub containers_path: PathBuf,
+    /// Seconds between looking for new containers
+    pub scan_interval: u64,
+    /// Reads the logs present at startup from their beginning instead of
+    /// only what is appended to them
+    pub from_beginning: bool,
+    /// Longer lines are skipped, longer messages Docker split are cut off
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl DockerConfig {
+    pub fn scan_interval(&self) -> Duration {
+        Duration::from_secs(self.scan_interval.max(1))
+    }
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            containers_path: PathBuf::from("/var/lib/docker/containers"),
+            scan_interval: 5,
+            from_beginning: false,
+            max_message_size: 1024 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// Tails the container logs the kubelet writes in the CRI format, e.g. from a
+/// DaemonSet with the host's `/var/log/pods` mounted
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PodLogsConfig {
+    pub enabled: bool,
+    /// Directory with a subdirectory per pod
+    pub path: PathBuf,
+    /// Seconds between looking for new pods and containers
+    pub scan_interval: u64,
+    /// Reads the logs present at startup from their beginning instead of
+    /// only what is appended to them
+    pub from_beginning: bool,
+    /// Longer lines are skipped, longer partial messages are cut off
+    pub max_message_size: usize,
+    /// Attached to every entry of the input, see `Labels`
+    pub labels: Labels,
+}
+
+impl PodLogsConfig {
+    pub fn scan_interval(&self) -> Duration {
+        Duration::from_secs(self.scan_interval.max(1))
+    }
+}
+
+impl Default for PodLogsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/var/log/pods"),
+            scan_interval: 5,
+            from_beginning: false,
+            max_message_size: 1024 * 1024,
+            labels: Labels::new(),
+        }
+    }
+}
+
+/// Sockets passed by systemd (`LISTEN_FDS`) are matched to listeners by the
+/// name set with `FileDescriptorName=`
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketActivationConfig {
+    /// Name of sockets serving `/healthz` and `/metrics`
+    pub http_name: String,
+    /// Name of TCP or Unix sockets accepting connections in the export
+    /// format, read instead of stdin
+    pub input_name: String,
+    /// Name of sockets accepting uploads from systemd-journal-upload
+    pub remote_name: String,
+    /// Stdin is a single connection accepted by systemd (`Accept=yes`) or
+    /// inetd. The HTTP listen addresses and the spool are skipped, as several
+    /// instances may run at once.
+    pub inetd: bool,
+}
+
+impl Default for SocketActivationConfig {
+    fn default() -> Self {
+        Self {
+            http_name: String::from("http"),
+            input_name: String::from("export"),
+            remote_name: String::from("journal-remote"),
+            inetd: false,
+        }
+    }
+}
+
+/// TLS on connections accepted on input and remote sockets. The server name a client
+/// presents (SNI) selects its tenant, clients without a known one are refused.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputTlsConfig {
+    pub enabled: bool,
+    pub tenants: Vec<TenantConfig>,
+    /// Requires clients to present a certificate issued by one of these CAs
+    pub client_ca_file: Option<PathBuf>,
+    /// Patterns, with `*` matching any characters, of which one has to match
+    /// the common name or a DNS or URI subject alternative name of the client
+    /// certificate. Any certificate `client_ca_file` verifies is accepted when
+    /// empty.
+    pub allowed_clients: Vec<String>,
+}
+
+/// Forwarders identified by a bearer token or their client certificate. The
+/// identity of each entry's sender is written to the `source_identity` column.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthenticationConfig {
+    /// Refuses uploads and TCP connections without a known identity
+    pub required: bool,
+    pub identities: Vec<IdentityConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub name: String,
+    /// Bearer token of uploads (`Authorization: Bearer TOKEN`)
+    pub token: Option<String>,
+    /// File holding a bearer token, keeping it out of the configuration
+    pub token_file: Option<PathBuf>,
+    /// Patterns matching the names of client certificates, like
+    /// `input_tls.allowed_clients`
+    #[serde(default)]
+    pub client_names: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub server_name: String,
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    /// Inserter settings for the tenant's entries, taking precedence over
+    /// `clickhouse.machines`. Unset options are inherited from the `clickhouse`
+    /// section, the tenant's entries are inserted with the others when all are.
+    pub table: Option<String>,
+    pub format: Option<InsertFormat>,
+    pub max_entries: Option<u64>,
+    /// Maximum time in seconds between inserts
+    pub period: Option<u64>,
+    /// Like `clickhouse.machines.priority`
+    pub priority: Option<i32>,
+}
+
+impl TenantConfig {
+    /// Whether the tenant has inserter settings of its own
+    pub fn has_inserter(&self) -> bool {
+        self.table.is_some()
+            || self.format.is_some()
+            || self.max_entries.is_some()
+            || self.period.is_some()
+            || self.priority.is_some()
+    }
+}
+
+/// Options of listening sockets
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// Whether IPv6 wildcard addresses accept IPv4 connections as well
+    pub dual_stack: bool,
+    /// Sets `SO_REUSEPORT` so several processes can share an address
+    pub reuse_port: bool,
+    /// Idle seconds before TCP keepalive probes are sent, disabled when unset
+    pub keepalive: Option<u64>,
+    pub backlog: i32,
+}
+
+impl SocketConfig {
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive.map(Duration::from_secs)
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            dual_stack: true,
+            reuse_port: false,
+            keepalive: Some(60),
+            backlog: 1024,
+        }
+    }
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// Seconds a stage may have pending work without making progress
+    pub stall_timeout: u64,
+}
+
+impl WatchdogConfig {
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout)
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stall_timeout: 300,
+        }
+    }
+}
+
+/// Sparse (machine_id, hour) to first cursor and timestamp table, see `logs_table.sql`
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CursorIndexConfig {
+    pub enabled: bool,
+    pub table: String,
+}
+
+impl Default for CursorIndexConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table: String::from("logs2_cursor_index"),
+        }
+    }
+}
+
+/// Last committed cursor per (machine_id, boot_id), kept in `path` across
+/// restarts
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceCursorsConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for SourceCursorsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/var/lib/journalsqld/source-cursors.json"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingConfig {
+    /// Share of entries to keep, selected by a hash of their cursor
+    pub percent: f64,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self { percent: 100.0 }
+    }
+}
+
+/// Collapses runs of entries with the same unit and `MESSAGE` into one row with
+/// a `repeat_count` column
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepeatCompressionConfig {
+    pub enabled: bool,
+    /// Seconds a run may span, measured from its first entry
+    pub window: u64,
+}
+
+impl RepeatCompressionConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+}
+
+impl Default for RepeatCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: 10,
+        }
+    }
+}
+
+/// Exact epoch columns next to the DateTime64 `timestamp`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimestampColumnsConfig {
+    /// Adds `timestamp_us` and `source_timestamp_us`, the raw microsecond
+    /// values of `__REALTIME_TIMESTAMP` and `_SOURCE_REALTIME_TIMESTAMP`
+    pub enabled: bool,
+    /// Field holding the host's timezone, written to a `timezone` column
+    pub timezone_field: Option<String>,
+}
+
+/// End-to-end delivery latency objective, e.g. 99% of entries inserted within 30s
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SloConfig {
+    pub enabled: bool,
+    /// Seconds from the journal timestamp of an entry to its insert
+    pub latency_target: u64,
+    /// Percentage of entries which have to meet the latency target
+    pub objective: f64,
+    /// Sliding windows in seconds to export burn rates for
+    pub burn_rate_windows: Vec<u64>,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latency_target: 30,
+            objective: 99.0,
+            burn_rate_windows: vec![300, 3600, 21600],
+        }
+    }
+}
+
+/// Where dead letters are written
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadLetterOutput {
+    /// JSON lines appended to `path`
+    #[default]
+    File,
+    /// Rows inserted into `table`, through the ClickHouse connection settings
+    Clickhouse,
+}
+
+/// Dropped entries and skipped malformed input are appended to `path` as JSON
+/// lines, or inserted into `table`, with the reason and pipeline stage
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadLetterConfig {
+    pub enabled: bool,
+    pub output: DeadLetterOutput,
+    pub path: PathBuf,
+    /// ClickHouse table of the `clickhouse` output
+    pub table: String,
+    /// Summary of the drops written at shutdown when any occurred, regardless
+    /// of `enabled`
+    pub report_path: PathBuf,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output: DeadLetterOutput::default(),
+            path: PathBuf::from("/var/lib/journalsqld/dead-letter.jsonl"),
+            table: String::from("logs2_dead_letter"),
+            report_path: PathBuf::from("/var/lib/journalsqld/drop-report.json"),
+        }
+    }
+}
+
+/// Entries read but not yet consumed at shutdown are written to compressed
+/// segments in `path` and replayed before new input on the next start
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpoolConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// zstd compression level of segments
+    pub compression_level: i32,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/var/lib/journalsqld/spool"),
+            compression_level: 3,
+        }
+    }
+}
+
+/// Batching of `journalsqld backfill`, which favours throughput over latency
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackfillConfig {
+    /// Entries per insert
+    pub max_entries: u64,
+    /// Machines imported concurrently, 0 for the number of CPUs
+    pub workers: usize,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1_000_000,
+            workers: 0,
+        }
+    }
+}
+
+/// Exports entries as OpenTelemetry log records over OTLP/gRPC, needs the
+/// `otlp` feature
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    pub enabled: bool,
+    /// Collector URL, `https://` for TLS with the system trust store
+    pub endpoint: String,
+    /// Sent as gRPC metadata with every export, e.g. for authentication
+    pub headers: HashMap<String, String>,
+    /// Records per export
+    pub max_entries: u64,
+    /// Maximum time in seconds between exports
+    pub period: u64,
+    /// Timeout of an export in seconds
+    pub timeout: u64,
+    pub queue: SinkQueueConfig,
+}
+
+impl OtlpConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: String::from("http://localhost:4317"),
+            headers: HashMap::new(),
+            max_entries: 10_000,
+            period: 5,
+            timeout: 30,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Archives entries as hourly Parquet objects in S3 compatible storage, in
+/// addition to the ClickHouse inserts. Needs the `archive` feature.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    pub bucket: String,
+    pub region: String,
+    /// Of S3 compatible storage, e.g. MinIO, instead of AWS
+    pub endpoint: Option<String>,
+    /// Taken from the `AWS_*` environment variables when unset
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Key of the objects, see `archive::object_key` for the placeholders
+    pub path: String,
+    pub row_group_entries: usize,
+    /// Not checkpointed and dropping rows when full by default, as objects
+    /// are only uploaded hourly
+    pub queue: SinkQueueConfig,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bucket: String::new(),
+            region: String::from("us-east-1"),
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+            path: String::from("journal/{date}/{machine_id}/{hour}-{id}.parquet"),
+            row_group_entries: 100_000,
+            queue: SinkQueueConfig {
+                when_full: QueueFull::Drop,
+                checkpoint: false,
+                ..SinkQueueConfig::default()
+            },
+        }
+    }
+}
+
+/// Writes entries to a local SQLite database, for single-node deployments
+/// without ClickHouse. Needs the `sqlite` feature.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteConfig {
+    pub enabled: bool,
+    /// Created if missing
+    pub path: PathBuf,
+    /// Whether MESSAGE is indexed for full text search
+    pub full_text: bool,
+    /// Entries per transaction
+    pub max_entries: u64,
+    /// Maximum time in seconds between transactions
+    pub period: u64,
+    pub queue: SinkQueueConfig,
+}
+
+impl SqliteConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/var/lib/journalsqld/journal.sqlite"),
+            full_text: false,
+            max_entries: 10_000,
+            period: 1,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Appends entries to a local DuckDB database for analysis. Needs the `duckdb`
+/// feature.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuckdbConfig {
+    pub enabled: bool,
+    /// Created if missing
+    pub path: PathBuf,
+    /// Entries per append
+    pub max_entries: u64,
+    /// Maximum time in seconds between appends
+    pub period: u64,
+    pub queue: SinkQueueConfig,
+}
+
+impl DuckdbConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+}
+
+impl Default for DuckdbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/var/lib/journalsqld/journal.duckdb"),
+            max_entries: 100_000,
+            period: 5,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Encoding of output files
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    /// One JSON object per line, as in `JSONEachRow` inserts
+    #[default]
+    Jsonl,
+    /// Needs the `parquet` feature
+    Parquet,
+}
+
+/// Compression of JSON lines output files, Parquet pages are always zstd
+/// compressed
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
+/// Writes entries to rotating local files
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileOutputConfig {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    pub format: FileFormat,
+    pub compression: FileCompression,
+    pub compression_level: i32,
+    /// Size in bytes a file is rotated at
+    pub max_size: u64,
+    /// Age in seconds a file is rotated at
+    pub max_age: u64,
+    pub row_group_entries: usize,
+    pub queue: SinkQueueConfig,
+}
+
+impl FileOutputConfig {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age)
+    }
+}
+
+impl Default for FileOutputConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: PathBuf::from("/var/lib/journalsqld/output"),
+            format: FileFormat::default(),
+            compression: FileCompression::default(),
+            compression_level: 3,
+            max_size: 256 * 1024 * 1024,
+            max_age: 3600,
+            row_group_entries: 100_000,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Forwards entries in the export format to the input of an upstream
+/// journalsqld
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    pub enabled: bool,
+    /// `HOST:PORT` of the upstream's input listener
+    pub address: String,
+    pub tls: bool,
+    /// Server name presented to the upstream, selecting its tenant, the host
+    /// of `address` when unset
+    pub server_name: Option<String>,
+    /// CA bundle the upstream's certificate is verified against, the system
+    /// trust store when unset
+    pub ca_file: Option<PathBuf>,
+    /// Client certificate and key, for upstreams requiring mutual TLS
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub compression: FileCompression,
+    pub compression_level: i32,
+    /// Entries per batch
+    pub max_entries: u64,
+    /// Maximum time in seconds between batches
+    pub period: u64,
+    /// Timeout of a send in seconds, including connecting
+    pub timeout: u64,
+    pub queue: SinkQueueConfig,
+}
+
+impl RelayConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::from("localhost:19532"),
+            tls: false,
+            server_name: None,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            compression: FileCompression::default(),
+            compression_level: 3,
+            max_entries: 10_000,
+            period: 1,
+            timeout: 30,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+/// Transport of forwarded syslog messages
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    /// A datagram per message (RFC 5426)
+    Udp,
+    /// Octet counted messages on a connection (RFC 6587)
+    #[default]
+    Tcp,
+    /// Octet counted messages on a TLS connection (RFC 5425)
+    Tls,
+}
+
+/// Forwards entries as RFC 5424 syslog messages
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogOutputConfig {
+    pub enabled: bool,
+    /// `HOST:PORT` of the receiver
+    pub address: String,
+    pub protocol: SyslogProtocol,
+    /// Server name verified with `tls`, the host of `address` when unset
+    pub server_name: Option<String>,
+    /// CA bundle the receiver's certificate is verified against, the system
+    /// trust store when unset
+    pub ca_file: Option<PathBuf>,
+    /// Client certificate and key, for receivers requiring mutual TLS
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    /// Only matching entries are forwarded, all of them when unset
+    pub filter: Option<Matcher>,
+    /// Facility of entries without `SYSLOG_FACILITY`
+    pub facility: u8,
+    /// Longer messages are truncated
+    pub max_message_size: usize,
+    /// Messages per batch
+    pub max_entries: u64,
+    /// Maximum time in seconds between batches
+    pub period: u64,
+    /// Timeout of a send in seconds, including connecting
+    pub timeout: u64,
+    pub queue: SinkQueueConfig,
+}
+
+impl SyslogOutputConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl Default for SyslogOutputConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::from("localhost:514"),
+            protocol: SyslogProtocol::default(),
+            server_name: None,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            filter: None,
+            facility: 1,
+            max_message_size: 8192,
+            max_entries: 1000,
+            period: 1,
+            timeout: 30,
+            queue: SinkQueueConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("I/O error")]
+    IOError(std::io::Error),
+
+    #[error("Parse error: {0}")]
+    ParseError(toml::de::Error),
+}
+
+impl Config {
+    /// Loads configuration from the file pointed to by `JOURNALSQLD_CONFIG`, if any,
+    /// and applies environment overrides.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match std::env::var_os(CONFIG_PATH_ENV) {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if let Ok(uri) = std::env::var(CLICKHOUSE_URI_ENV) {
+            config.clickhouse.uri = Some(uri);
+        }
+
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(ConfigError::IOError)?;
+        toml::from_str(&contents).map_err(ConfigError::ParseError)
+    }
+
+    /// Whether any input has labels, which adds the `labels` column
+    pub fn has_labels(&self) -> bool {
+        [
+            &self.input.labels,
+            &self.remote.labels,
+            &self.syslog.labels,
+            &self.gelf.labels,
+            &self.fluent.labels,
+            &self.grpc.labels,
+            &self.docker.labels,
+            &self.pod_logs.labels,
+            &self.kafka.labels,
+            &self.amqp.labels,
+        ]
+        .iter()
+        .any(|labels| !labels.is_empty())
+    }
+
+    /// Drops the configured inputs other than stdin, so a single file can be
+    /// read in their place. The journal upload URL still applies, its cursor
+    /// isn't read or saved.
+    pub fn clear_inputs(&mut self) {
+        self.input.listen.clear();
+        self.input.files.clear();
+        self.remote.listen.clear();
+        self.syslog.listen_udp.clear();
+        self.syslog.listen_tcp.clear();
+        self.gelf.listen_udp.clear();
+        self.gelf.listen_tcp.clear();
+        self.fluent.listen.clear();
+        self.grpc.listen.clear();
+        self.docker.enabled = false;
+        self.pod_logs.enabled = false;
+        self.kafka.enabled = false;
+        self.amqp.enabled = false;
+    }
+}
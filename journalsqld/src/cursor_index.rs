@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+use crate::client::{Client, ClientError};
+use crate::row::LogRecordRow;
+
+/// Hours older than this are forgotten, a late entry for them is indexed again
+const RETENTION: Duration = Duration::hours(48);
+
+/// Maintains a sparse table mapping (machine_id, hour) to the earliest cursor and
+/// timestamp seen in it, letting cursor-based lookups be turned into timestamp
+/// ranges. Rows are only written when an hour is first seen or an earlier entry
+/// shows up, readers take the minimum per key.
+pub struct CursorIndex {
+    client: Client,
+    table: String,
+    // (machine_id, unix hour) to earliest timestamp written
+    earliest: HashMap<(String, i64), OffsetDateTime>,
+}
+
+impl CursorIndex {
+    pub fn new(client: Client, table: &str) -> Self {
+        Self {
+            client: client.with_option("date_time_input_format", "best_effort"),
+            table: table.to_string(),
+            earliest: HashMap::new(),
+        }
+    }
+
+    /// Writes index rows for inserted rows which are the earliest of their hour
+    pub async fn record(&mut self, rows: &[LogRecordRow]) -> Result<(), ClientError> {
+        let mut updates: HashMap<(String, i64), &LogRecordRow> = HashMap::new();
+        for row in rows.iter() {
+            let key = (
+                row.machine_id.clone(),
+                row.timestamp.unix_timestamp() / 3600,
+            );
+            if matches!(self.earliest.get(&key), Some(earliest) if *earliest <= row.timestamp) {
+                continue;
+            }
+
+            let update = updates.entry(key).or_insert(row);
+            if row.timestamp < update.timestamp {
+                *update = row;
+            }
+        }
+
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        for ((_, hour), row) in updates.iter() {
+            let hour = OffsetDateTime::from_unix_timestamp(hour * 3600).unwrap_or(row.timestamp);
+            let record = serde_json::json!({
+                "machine_id": row.machine_id,
+                "hour": hour.format(&Rfc3339).unwrap_or_default(),
+                "timestamp": row.timestamp.format(&Rfc3339).unwrap_or_default(),
+                "cursor": row.cursor,
+            });
+            data.extend_from_slice(record.to_string().as_bytes());
+            data.push(b'\n');
+        }
+
+        let query = format!(
+            "INSERT INTO {}(machine_id, hour, timestamp, cursor) FORMAT JSONEachRow",
+            self.table
+        );
+        self.client.execute(&query, data).await?;
+
+        for (key, row) in updates {
+            self.earliest.insert(key, row.timestamp);
+        }
+
+        let cutoff = (OffsetDateTime::now_utc() - RETENTION).unix_timestamp() / 3600;
+        self.earliest.retain(|(_, hour), _| *hour >= cutoff);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{error, warn};
+use serde::{Serialize, Serializer};
+use systemd_journal_parser::{FieldErrorKind, JournalEntry, ParseErrorInfo};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::client::Client;
+use crate::config::{DeadLetterConfig, DeadLetterOutput};
+use crate::metrics;
+use crate::watchdog::Stage;
+
+/// Dead letters waiting to be inserted into the table, further ones are only
+/// counted while it's full
+const TABLE_QUEUE_ENTRIES: usize = 10_000;
+/// Time dead letters are gathered for before they are inserted
+const TABLE_INSERT_INTERVAL: Duration = Duration::from_secs(1);
+const TABLE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+/// Time the remaining dead letters are given to be inserted at shutdown
+const TABLE_CLOSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Machine-readable reason for dropping input
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// Entry lacks a field required for a row
+    MissingField,
+    /// `__REALTIME_TIMESTAMP` is not a valid timestamp
+    InvalidTimestamp,
+    /// Field or entry exceeded the parser limits
+    Oversized,
+    /// Input skipped while recovering from a parse error
+    MalformedInput,
+}
+
+impl DropReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MissingField => "missing_field",
+            Self::InvalidTimestamp => "invalid_timestamp",
+            Self::Oversized => "oversized",
+            Self::MalformedInput => "malformed_input",
+        }
+    }
+
+    pub fn from_parse_error(kind: FieldErrorKind) -> Self {
+        match kind {
+            FieldErrorKind::FieldTooLarge
+            | FieldErrorKind::TooManyFields
+            | FieldErrorKind::EntryTooLarge => Self::Oversized,
+            _ => Self::MalformedInput,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    time: String,
+    stage: String,
+    reason: DropReason,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<&'a JournalEntry>,
+    /// Stream offset of malformed input
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    discarded_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excerpt: Option<String>,
+}
+
+/// Summary of the input dropped since startup, for accounting of lost data
+/// after an incident. Maps are ordered, so the same drops always produce the
+/// same report.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DropReport {
+    /// Dropped entries and skipped chunks of malformed input
+    pub total: u64,
+    pub discarded_bytes: u64,
+    pub by_reason: BTreeMap<&'static str, u64>,
+    pub by_stage: BTreeMap<String, u64>,
+    /// `_HOSTNAME` of dropped entries, "unknown" for malformed input
+    pub by_hostname: BTreeMap<String, u64>,
+    #[serde(serialize_with = "serialize_time")]
+    pub first_drop: Option<OffsetDateTime>,
+    #[serde(serialize_with = "serialize_time")]
+    pub last_drop: Option<OffsetDateTime>,
+    /// Range of `__REALTIME_TIMESTAMP` of the dropped entries
+    #[serde(serialize_with = "serialize_time")]
+    pub oldest_entry: Option<OffsetDateTime>,
+    #[serde(serialize_with = "serialize_time")]
+    pub newest_entry: Option<OffsetDateTime>,
+}
+
+impl DropReport {
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    fn add(&mut self, record: &DeadLetter) {
+        let now = OffsetDateTime::now_utc();
+
+        self.total += 1;
+        self.discarded_bytes += record.discarded_bytes.unwrap_or(0);
+        *self.by_reason.entry(record.reason.as_str()).or_default() += 1;
+        *self.by_stage.entry(record.stage.clone()).or_default() += 1;
+        self.first_drop.get_or_insert(now);
+        self.last_drop = Some(now);
+
+        let hostname = record
+            .entry
+            .and_then(|entry| entry.get("_HOSTNAME"))
+            .map(String::from)
+            .unwrap_or_else(|| String::from("unknown"));
+        *self.by_hostname.entry(hostname).or_default() += 1;
+
+        if let Some(Ok(timestamp)) = record.entry.and_then(|entry| entry.realtime_timestamp()) {
+            self.oldest_entry = Some(self.oldest_entry.map_or(timestamp, |t| t.min(timestamp)));
+            self.newest_entry = Some(self.newest_entry.map_or(timestamp, |t| t.max(timestamp)));
+        }
+    }
+}
+
+fn serialize_time<S: Serializer>(
+    time: &Option<OffsetDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    time.and_then(|time| time.format(&Rfc3339).ok())
+        .serialize(serializer)
+}
+
+impl DeadLetter<'_> {
+    /// Row of the dead-letter table, with the entry serialized as a string
+    fn table_row(&self) -> serde_json::Value {
+        serde_json::json!({
+            "time": self.time,
+            "stage": self.stage,
+            "reason": self.reason,
+            "message": self.message,
+            "entry": self.entry.and_then(|entry| serde_json::to_string(entry).ok()),
+            "offset": self.offset,
+            "discarded_bytes": self.discarded_bytes,
+            "excerpt": self.excerpt,
+        })
+    }
+}
+
+/// Queue of the task inserting dead letters into a ClickHouse table
+struct Table {
+    /// `None` once closed
+    sender: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Counts dropped input by stage and reason and, when enabled, writes a JSON
+/// record describing it to the dead-letter file or table
+pub struct DeadLetterQueue {
+    file: Option<Mutex<BufWriter<File>>>,
+    table: Option<Table>,
+    report: Mutex<DropReport>,
+}
+
+impl DeadLetterQueue {
+    /// Opens the dead-letter file when enabled, the table is added by
+    /// `with_table`
+    pub fn open(config: &DeadLetterConfig) -> io::Result<Self> {
+        let file = if config.enabled && config.output == DeadLetterOutput::File {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.path)?;
+            Some(Mutex::new(BufWriter::new(file)))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            file,
+            table: None,
+            report: Mutex::default(),
+        })
+    }
+
+    /// Inserts the dead letters into `table`, starting the task inserting them
+    pub fn with_table(mut self, client: Client, table: &str) -> Self {
+        let (sender, receiver) = mpsc::channel(TABLE_QUEUE_ENTRIES);
+        let task = tokio::task::spawn(insert_rows(
+            client.with_option("date_time_input_format", "best_effort"),
+            table.to_string(),
+            receiver,
+        ));
+
+        self.table = Some(Table {
+            sender: Mutex::new(Some(sender)),
+            task: Mutex::new(Some(task)),
+        });
+        self
+    }
+
+    /// Inserts the dead letters still queued for the table, giving up after
+    /// `TABLE_CLOSE_TIMEOUT`
+    pub async fn close(&self) {
+        let Some(table) = &self.table else {
+            return;
+        };
+
+        table
+            .sender
+            .lock()
+            .expect("dead-letter table lock poisoned")
+            .take();
+        let task = table
+            .task
+            .lock()
+            .expect("dead-letter table lock poisoned")
+            .take();
+        if let Some(task) = task {
+            if tokio::time::timeout(TABLE_CLOSE_TIMEOUT, task)
+                .await
+                .is_err()
+            {
+                error!("gave up inserting the remaining dead letters at shutdown");
+            }
+        }
+    }
+
+    /// Drops so far
+    pub fn report(&self) -> DropReport {
+        self.report
+            .lock()
+            .expect("drop report lock poisoned")
+            .clone()
+    }
+
+    pub fn drop_entry(
+        &self,
+        stage: Stage,
+        reason: DropReason,
+        message: &str,
+        entry: &JournalEntry,
+    ) {
+        self.write(DeadLetter {
+            time: now(),
+            stage: stage.to_string(),
+            reason,
+            message: message.to_string(),
+            entry: Some(entry),
+            offset: None,
+            discarded_bytes: None,
+            excerpt: None,
+        });
+    }
+
+    pub fn drop_input(&self, stage: Stage, info: &ParseErrorInfo, discarded_bytes: u64) {
+        self.write(DeadLetter {
+            time: now(),
+            stage: stage.to_string(),
+            reason: DropReason::from_parse_error(info.kind),
+            message: info.to_string(),
+            entry: None,
+            offset: Some(info.offset),
+            discarded_bytes: Some(discarded_bytes),
+            excerpt: Some(info.hexdump()),
+        });
+    }
+
+    fn write(&self, record: DeadLetter) {
+        metrics::inc_dead_letters(&record.stage, record.reason.as_str()).unwrap();
+        self.report
+            .lock()
+            .expect("drop report lock poisoned")
+            .add(&record);
+
+        if let Some(table) = &self.table {
+            let mut line = record.table_row().to_string().into_bytes();
+            line.push(b'\n');
+            let sender = table
+                .sender
+                .lock()
+                .expect("dead-letter table lock poisoned");
+            if let Some(Err(err)) = sender.as_ref().map(|sender| sender.try_send(line)) {
+                warn!("failed to queue dead-letter record: {}", err);
+            }
+        }
+
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let mut file = file.lock().expect("dead-letter file lock poisoned");
+        let result = serde_json::to_writer(&mut *file, &record)
+            .map_err(io::Error::from)
+            .and_then(|_| file.write_all(b"\n"))
+            .and_then(|_| file.flush());
+
+        if let Err(err) = result {
+            warn!("failed to write dead-letter record: {}", err);
+        }
+    }
+}
+
+/// Inserts the dead letters from `receiver` into `table`, gathering them for
+/// `TABLE_INSERT_INTERVAL` first. Failed inserts are retried while further
+/// dead letters wait in the queue.
+async fn insert_rows(client: Client, table: String, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let query = format!("INSERT INTO {} FORMAT JSONEachRow", table);
+
+    while let Some(mut data) = receiver.recv().await {
+        tokio::time::sleep(TABLE_INSERT_INTERVAL).await;
+        while let Ok(line) = receiver.try_recv() {
+            data.extend_from_slice(&line);
+        }
+
+        while let Err(err) = client.execute(&query, data.clone()).await {
+            warn!(
+                "failed to insert dead letters into {}, retrying in {:?}: {}",
+                table, TABLE_RETRY_INTERVAL, err
+            );
+            tokio::time::sleep(TABLE_RETRY_INTERVAL).await;
+        }
+    }
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
@@ -0,0 +1,61 @@
+use std::io;
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::config::InputCompression;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wraps `reader` in a decoder if its data starts with a gzip or zstd header,
+/// so compressed export dumps can be read directly. Concatenated compressed
+/// streams are decoded as one.
+pub async fn decompressing<R>(reader: R) -> io::Result<Box<dyn AsyncRead + Send + Unpin>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let mut reader = BufReader::new(reader);
+    let head = reader.fill_buf().await?;
+
+    if head.starts_with(GZIP_MAGIC) {
+        let mut decoder = GzipDecoder::new(reader);
+        decoder.multiple_members(true);
+        Ok(Box::new(decoder))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        let mut decoder = ZstdDecoder::new(reader);
+        decoder.multiple_members(true);
+        Ok(Box::new(decoder))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Like `decompressing`, restricted to what `compression` accepts. Streams
+/// not starting with a zstd frame fail when only zstd is accepted.
+pub async fn decompressing_as<R>(
+    reader: R,
+    compression: InputCompression,
+) -> io::Result<Box<dyn AsyncRead + Send + Unpin>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    match compression {
+        InputCompression::Auto => decompressing(reader).await,
+        InputCompression::None => Ok(Box::new(reader)),
+        InputCompression::Zstd => {
+            let mut reader = BufReader::new(reader);
+            let head = reader.fill_buf().await?;
+            if !head.is_empty() && !head.starts_with(ZSTD_MAGIC) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream is not zstd compressed",
+                ));
+            }
+
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Ok(Box::new(decoder))
+        }
+    }
+}
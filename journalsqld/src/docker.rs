@@ -0,0 +1,161 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::Deserialize;
+use systemd_journal_parser::JournalEntry;
+
+use crate::config::DockerConfig;
+use crate::metrics;
+use crate::network::NetworkEntries;
+use crate::tail::{container_entry, host_fields, Tailers};
+
+/// Line of a `json-file` log
+#[derive(Deserialize)]
+struct LogLine {
+    log: String,
+    stream: String,
+    time: String,
+}
+
+/// Parts of a container's `config.v2.json`
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct ContainerConfig {
+    name: String,
+    config: ContainerImage,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+struct ContainerImage {
+    image: String,
+}
+
+/// Tails the `<id>-json.log` files of the containers in `containers_path`
+/// until the consumer goes away, picking up containers as they are created.
+/// Files present at startup are read from their end unless `from_beginning` is
+/// set, later ones from the start. Rotated files are reopened, tailing stops
+/// once a file is removed along with its container.
+pub async fn tail_containers(config: DockerConfig, entries: NetworkEntries) -> io::Result<()> {
+    let host = host_fields()?;
+    let mut tailers = Tailers::default();
+    let mut scan = tokio::time::interval(config.scan_interval());
+    let mut first_scan = true;
+    info!(
+        "tailing Docker container logs in {}",
+        config.containers_path.display()
+    );
+
+    loop {
+        tokio::select! {
+            _ = scan.tick() => {},
+            _ = entries.closed() => return Ok(()),
+        }
+
+        tailers.retain_running();
+        for (id, path) in log_files(&config.containers_path).await? {
+            if tailers.contains(&path) {
+                continue;
+            }
+
+            let mut fields = host.clone();
+            fields.extend(container_fields(&config.containers_path, &id).await);
+            let parse = parser(path.clone(), fields, config.max_message_size);
+            tailers.spawn(
+                path,
+                first_scan && !config.from_beginning,
+                config.max_message_size,
+                "docker",
+                entries.clone(),
+                parse,
+            );
+        }
+        first_scan = false;
+    }
+}
+
+/// IDs and log files of the containers
+async fn log_files(containers_path: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut directory = tokio::fs::read_dir(containers_path).await?;
+    while let Some(container) = directory.next_entry().await? {
+        let Some(id) = container.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        let path = container.path().join(format!("{}-json.log", id));
+        if tokio::fs::metadata(&path).await.is_ok() {
+            files.push((id, path));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Fields of a container, named like Docker's `journald` logging driver does
+/// with its default tag
+async fn container_fields(containers_path: &Path, id: &str) -> Vec<(&'static str, String)> {
+    let path = containers_path.join(id).join("config.v2.json");
+    let config = match tokio::fs::read(&path).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!("malformed {}: {}", path.display(), err);
+            ContainerConfig::default()
+        }),
+        Err(err) => {
+            warn!("failed to read {}: {}", path.display(), err);
+            ContainerConfig::default()
+        }
+    };
+
+    let short_id: String = id.chars().take(12).collect();
+    let mut fields = vec![
+        ("CONTAINER_ID", short_id.clone()),
+        ("CONTAINER_ID_FULL", id.to_string()),
+        ("CONTAINER_TAG", short_id.clone()),
+        ("SYSLOG_IDENTIFIER", short_id),
+    ];
+    let name = config.name.trim_start_matches('/');
+    if !name.is_empty() {
+        fields.push(("CONTAINER_NAME", name.to_string()));
+    }
+    if !config.config.image.is_empty() {
+        fields.push(("IMAGE_NAME", config.config.image));
+    }
+
+    fields
+}
+
+/// Translates the lines of a log into entries, joining the parts Docker split
+/// longer lines into. Malformed lines are skipped.
+fn parser(
+    path: PathBuf,
+    fields: Vec<(&'static str, String)>,
+    max_message_size: usize,
+) -> impl FnMut(&[u8]) -> Option<JournalEntry> + Send + 'static {
+    let mut message = String::new();
+    move |line| {
+        let line: LogLine = match serde_json::from_slice(line) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("malformed line in {}, skipping it: {}", path.display(), err);
+                metrics::inc_malformed_input_discarded(line.len() as u64);
+                return None;
+            }
+        };
+
+        // Parts of 16 KiB, all but the last lack the newline
+        message.push_str(&line.log);
+        if !message.ends_with('\n') && message.len() < max_message_size {
+            return None;
+        }
+
+        let entry = container_entry(
+            &fields,
+            &line.stream,
+            &line.time,
+            message.trim_end_matches('\n'),
+        );
+        message.clear();
+        Some(entry)
+    }
+}
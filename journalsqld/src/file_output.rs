@@ -0,0 +1,251 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use log::info;
+use time::OffsetDateTime;
+
+use crate::config::{FileCompression, FileFormat, FileOutputConfig};
+use crate::inserter::InsertError;
+#[cfg(feature = "parquet")]
+use crate::parquet_file::ParquetFile;
+use crate::row::LogRecordRow;
+use crate::schema::Schema;
+
+/// Suffix of the file being written, dropped once it is rotated
+const PART_SUFFIX: &str = ".part";
+
+enum FileWriter {
+    Json(BufWriter<File>),
+    JsonZstd(zstd::Encoder<'static, BufWriter<File>>),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetFile<BufWriter<File>>),
+}
+
+struct OpenFile {
+    /// Final path, without `PART_SUFFIX`
+    path: PathBuf,
+    writer: FileWriter,
+    opened: Instant,
+    /// Of the last row written to the file
+    cursor: Option<String>,
+}
+
+/// Writes rows to rotating local files, as JSON lines in the `JSONEachRow`
+/// shape of the inserts or as Parquet. Files are rotated by size and age, the
+/// file being written ends in `.part`. Rows of JSON lines are checkpointed once
+/// written, those of Parquet files once the file is rotated.
+pub struct FileOutput {
+    directory: PathBuf,
+    format: FileFormat,
+    compression: FileCompression,
+    compression_level: i32,
+    max_size: u64,
+    max_age: Duration,
+    #[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+    row_group_entries: usize,
+    schema: Schema,
+    rows: Vec<LogRecordRow>,
+    current: Option<OpenFile>,
+    committed_cursor: Option<String>,
+    /// Files opened by this run, tells apart files opened in the same second
+    sequence: u64,
+}
+
+impl FileOutput {
+    pub fn new(config: &FileOutputConfig, schema: Schema) -> io::Result<Self> {
+        #[cfg(not(feature = "parquet"))]
+        if config.format == FileFormat::Parquet {
+            return Err(parquet_unsupported());
+        }
+        std::fs::create_dir_all(&config.directory)?;
+
+        Ok(Self {
+            directory: config.directory.clone(),
+            format: config.format,
+            compression: config.compression,
+            compression_level: config.compression_level,
+            max_size: config.max_size,
+            max_age: config.max_age(),
+            row_group_entries: config.row_group_entries,
+            schema,
+            rows: Vec::new(),
+            current: None,
+            committed_cursor: None,
+            sequence: 0,
+        })
+    }
+
+    /// Writes the buffered rows and rotates the file when due
+    fn write_rows(&mut self) -> io::Result<Quantities> {
+        let mut written = Quantities::default();
+        if !self.rows.is_empty() {
+            let rows = std::mem::take(&mut self.rows);
+            written.entries = rows.len() as u64;
+            written.transactions = 1;
+
+            let file = match self.current.take() {
+                Some(file) => file,
+                None => self.open()?,
+            };
+            let file = self.current.insert(file);
+            file.cursor = rows.last().map(|row| row.cursor.clone());
+            match &mut file.writer {
+                FileWriter::Json(writer) => write_json(&self.schema, writer, &rows)?,
+                FileWriter::JsonZstd(writer) => write_json(&self.schema, writer, &rows)?,
+                #[cfg(feature = "parquet")]
+                FileWriter::Parquet(writer) => {
+                    for row in rows {
+                        writer.write(row).map_err(parquet_error)?;
+                    }
+                }
+            }
+
+            // Parquet files can only be read once their footer is written
+            match &mut file.writer {
+                FileWriter::Json(writer) => writer.flush()?,
+                FileWriter::JsonZstd(writer) => writer.flush()?,
+                #[cfg(feature = "parquet")]
+                FileWriter::Parquet(_) => {}
+            }
+            if self.format != FileFormat::Parquet {
+                self.committed_cursor = file.cursor.clone();
+            }
+        }
+
+        let Some(file) = &self.current else {
+            return Ok(written);
+        };
+        let size = std::fs::metadata(part_path(&file.path))?.len();
+        if size >= self.max_size || file.opened.elapsed() >= self.max_age {
+            self.rotate()?;
+        }
+
+        Ok(written)
+    }
+
+    fn open(&mut self) -> io::Result<OpenFile> {
+        let now = OffsetDateTime::now_utc();
+        let extension = match (self.format, self.compression) {
+            (FileFormat::Jsonl, FileCompression::None) => "jsonl",
+            (FileFormat::Jsonl, FileCompression::Zstd) => "jsonl.zst",
+            (FileFormat::Parquet, _) => "parquet",
+        };
+        let path = self.directory.join(format!(
+            "journal-{:04}{:02}{:02}T{:02}{:02}{:02}Z-{}.{}",
+            now.year(),
+            u8::from(now.month()),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            self.sequence,
+            extension
+        ));
+        self.sequence += 1;
+
+        let output = BufWriter::new(File::create(part_path(&path))?);
+        let writer = match (self.format, self.compression) {
+            (FileFormat::Jsonl, FileCompression::None) => FileWriter::Json(output),
+            (FileFormat::Jsonl, FileCompression::Zstd) => {
+                FileWriter::JsonZstd(zstd::Encoder::new(output, self.compression_level)?)
+            }
+            #[cfg(feature = "parquet")]
+            (FileFormat::Parquet, _) => {
+                FileWriter::Parquet(ParquetFile::new(output, self.row_group_entries))
+            }
+            #[cfg(not(feature = "parquet"))]
+            (FileFormat::Parquet, _) => return Err(parquet_unsupported()),
+        };
+
+        Ok(OpenFile {
+            path,
+            writer,
+            opened: Instant::now(),
+            cursor: None,
+        })
+    }
+
+    /// Completes the current file and drops its `.part` suffix
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(file) = self.current.take() else {
+            return Ok(());
+        };
+
+        let part_path = part_path(&file.path);
+        let mut output = match file.writer {
+            FileWriter::Json(writer) => writer,
+            FileWriter::JsonZstd(writer) => writer.finish()?,
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => writer.finish().map_err(parquet_error)?,
+        };
+        output.flush()?;
+        output.get_ref().sync_all()?;
+        std::fs::rename(&part_path, &file.path)?;
+        info!("rotated output file {}", file.path.display());
+
+        if file.cursor.is_some() {
+            self.committed_cursor = file.cursor;
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink<LogRecordRow> for FileOutput {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        self.rows.extend(rows);
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move { Ok(self.write_rows().map_err(InsertError::FileError)?) })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let written = self.write_rows().map_err(InsertError::FileError)?;
+            self.rotate().map_err(InsertError::FileError)?;
+
+            Ok(written)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+fn write_json(schema: &Schema, writer: &mut impl Write, rows: &[LogRecordRow]) -> io::Result<()> {
+    let mut data = Vec::new();
+    for row in rows {
+        schema.write_json_each_row(&mut data, row)?;
+    }
+
+    writer.write_all(&data)
+}
+
+/// `path` with `PART_SUFFIX`
+fn part_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(PART_SUFFIX);
+    path.into()
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Parquet files require the parquet feature",
+    )
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_error(err: parquet::errors::ParquetError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
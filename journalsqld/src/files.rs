@@ -0,0 +1,132 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use log::{error, info};
+use systemd_journal_parser::{JournalEntry, JournalReadError};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::time::Sleep;
+
+use crate::config::{FileInputConfig, ParserConfig, RateLimitConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::decompress::decompressing;
+use crate::journal::{read_journal_entries, EntryOrigin};
+use crate::rate_limit::RateLimiter;
+use crate::watchdog::Watchdog;
+
+/// How often a followed file is checked for more data once at its end
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads the configured files and named pipes concurrently, optionally
+/// compressed, tagging entries with the label of their input and the labels
+/// of `origin`, each paused while over `rate_limit`. Returns once all of them
+/// ended, which followed inputs only do when the consumer goes away.
+#[allow(clippy::too_many_arguments)]
+pub async fn read_files(
+    inputs: Vec<FileInputConfig>,
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    origin: EntryOrigin,
+    rate_limit: RateLimitConfig,
+) {
+    let mut readers = Vec::new();
+    for input in inputs {
+        let (config, sender) = (config.clone(), sender.clone());
+        let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+        let origin = origin.clone();
+        let limiter = RateLimiter::new(&rate_limit, &input.path.display().to_string());
+
+        readers.push(tokio::task::spawn(async move {
+            let label = input.label();
+            let result = async {
+                // Opening a named pipe waits for a writer
+                let file = File::open(&input.path)
+                    .await
+                    .map_err(JournalReadError::IOError)?;
+                info!("reading {} as {:?}", input.path.display(), label);
+
+                let file: Box<dyn AsyncRead + Send + Unpin> = if input.follow {
+                    Box::new(Follow::new(file))
+                } else {
+                    Box::new(file)
+                };
+                let file = decompressing(file)
+                    .await
+                    .map_err(JournalReadError::IOError)?;
+                let origin = EntryOrigin {
+                    source: Some(label.clone()),
+                    ..origin
+                };
+                read_journal_entries(
+                    file,
+                    config,
+                    sender,
+                    watchdog,
+                    dead_letters,
+                    origin,
+                    limiter,
+                )
+                .await
+            };
+
+            match result.await {
+                Ok(()) => info!("finished reading {}", input.path.display()),
+                Err(err) => error!(
+                    "failed to read entries from {}: {}",
+                    input.path.display(),
+                    err
+                ),
+            }
+        }));
+    }
+
+    for reader in readers {
+        if let Err(err) = reader.await {
+            error!("file input task failed: {}", err);
+        }
+    }
+}
+
+/// Keeps reading past the end of a growing file, or of a named pipe without
+/// writers until another one opens it. Truncation and rotation aren't noticed.
+struct Follow<R> {
+    inner: R,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> Follow<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, delay: None }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Follow<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+            if buf.filled().len() > filled || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // At the end for now, check again later
+            self.delay = Some(Box::pin(tokio::time::sleep(FOLLOW_INTERVAL)));
+        }
+    }
+}
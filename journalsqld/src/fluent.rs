@@ -0,0 +1,370 @@
+use std::io::{self, Read};
+use std::net::SocketAddr;
+
+use flate2::read::MultiGzDecoder;
+use log::{debug, info, warn};
+use rmpv::Value;
+use systemd_journal_parser::{intern, JournalEntry, JournalFieldValue, ParseOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::listener::accept_failed;
+use crate::metrics;
+use crate::network::NetworkEntries;
+
+/// `EventTime` extension type, seconds and nanoseconds as big endian `u32`s
+const EVENT_TIME_TYPE: i8 = 0;
+
+/// Fluent forward protocol message which can't be translated
+#[derive(Debug, thiserror::Error)]
+pub enum FluentError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("MessagePack error: {0}")]
+    Decode(#[from] rmpv::decode::Error),
+
+    #[error("malformed message: {0}")]
+    Malformed(&'static str),
+
+    #[error("unsupported compression {0:?}")]
+    Compression(String),
+
+    #[error("message exceeds the size limit")]
+    TooLarge,
+
+    #[error("record has too many fields")]
+    TooManyFields,
+}
+
+/// Accepts connections of Fluentd and Fluent Bit `forward` outputs until the
+/// consumer goes away. The Message, Forward, PackedForward and gzip
+/// CompressedPackedForward modes are understood, and chunks are acknowledged
+/// once their entries are queued when the sender asks for it. Connections
+/// sending malformed or larger messages than `max_message_size` are closed.
+pub async fn accept_connections(
+    listeners: Vec<std::net::TcpListener>,
+    max_message_size: usize,
+    entries: NetworkEntries,
+) -> io::Result<()> {
+    let mut accept_loops = Vec::new();
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let entries = entries.clone();
+
+        accept_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(addr, err).await;
+                            continue;
+                        }
+                    },
+                    _ = entries.closed() => return Ok::<_, io::Error>(()),
+                };
+                info!("accepted Fluent forward connection from {}", peer);
+
+                let entries = entries.clone();
+                tokio::task::spawn(async move {
+                    match receive(stream, peer, max_message_size, &entries).await {
+                        Ok(()) => debug!("Fluent forward connection from {} closed", peer),
+                        Err(err) => {
+                            warn!("Fluent forward connection from {} failed: {}", peer, err)
+                        }
+                    }
+                });
+            }
+        }));
+    }
+
+    for accept_loop in accept_loops {
+        accept_loop.await??;
+    }
+
+    Ok(())
+}
+
+/// Reads messages until the end of the connection or the consumer going away
+async fn receive(
+    stream: TcpStream,
+    peer: SocketAddr,
+    max_message_size: usize,
+    entries: &NetworkEntries,
+) -> Result<(), FluentError> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let options = entries.config().options();
+
+    loop {
+        // Messages are MessagePack arrays sent back to back
+        let mut consumed = 0;
+        loop {
+            let mut rest = &buffer[consumed..];
+            let message = match rmpv::decode::read_value(&mut rest) {
+                Ok(message) => message,
+                Err(err) if is_incomplete(&err) => break,
+                Err(err) => return Err(err.into()),
+            };
+            consumed = buffer.len() - rest.len();
+
+            let (events, ack) = translate(message, max_message_size, &options)?;
+            for entry in events {
+                if !entries.submit(entry, "fluent", peer.ip()).await {
+                    return Ok(());
+                }
+            }
+            if let Some(ack) = ack {
+                let mut response = Vec::new();
+                let response_value = Value::Map(vec![(Value::from("ack"), ack)]);
+                rmpv::encode::write_value(&mut response, &response_value)
+                    .expect("failed to encode acknowledgement");
+                writer.write_all(&response).await?;
+            }
+        }
+        buffer.drain(..consumed);
+        if buffer.len() > max_message_size {
+            return Err(FluentError::TooLarge);
+        }
+
+        let read = tokio::select! {
+            read = reader.read(&mut chunk) => read?,
+            _ = entries.closed() => return Ok(()),
+        };
+        if read == 0 {
+            if !buffer.is_empty() {
+                metrics::inc_malformed_input_discarded(buffer.len() as u64);
+                return Err(FluentError::Malformed("connection closed within a message"));
+            }
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Whether decoding failed only because the rest of the value is yet to arrive
+fn is_incomplete(err: &rmpv::decode::Error) -> bool {
+    match err {
+        rmpv::decode::Error::InvalidMarkerRead(err) | rmpv::decode::Error::InvalidDataRead(err) => {
+            err.kind() == io::ErrorKind::UnexpectedEof
+        }
+        _ => false,
+    }
+}
+
+/// Entries of a message and the chunk ID to acknowledge, if requested
+fn translate(
+    message: Value,
+    max_message_size: usize,
+    options: &ParseOptions,
+) -> Result<(Vec<JournalEntry>, Option<Value>), FluentError> {
+    let Value::Array(mut parts) = message else {
+        return Err(FluentError::Malformed("message is not an array"));
+    };
+    if !(2..=4).contains(&parts.len()) {
+        return Err(FluentError::Malformed("unexpected number of elements"));
+    }
+    let tag = parts[0]
+        .as_str()
+        .ok_or(FluentError::Malformed("tag is not a string"))?
+        .to_string();
+
+    let (events, option) = match std::mem::replace(&mut parts[1], Value::Nil) {
+        // Forward: [tag, [[time, record], ...], option]
+        Value::Array(events) => (events, parts.get(2)),
+        // PackedForward: [tag, events as one MessagePack stream, option]
+        Value::Binary(packed) => (
+            unpack(&packed, parts.get(2), max_message_size)?,
+            parts.get(2),
+        ),
+        Value::String(packed) => (
+            unpack(packed.as_bytes(), parts.get(2), max_message_size)?,
+            parts.get(2),
+        ),
+        // Message: [tag, time, record, option]
+        time => {
+            let record = parts
+                .get_mut(2)
+                .map(|record| std::mem::replace(record, Value::Nil))
+                .ok_or(FluentError::Malformed("message lacks a record"))?;
+            (vec![Value::Array(vec![time, record])], parts.get(3))
+        }
+    };
+
+    let ack = option
+        .and_then(|option| option_value(option, "chunk"))
+        .cloned();
+    let entries = events
+        .into_iter()
+        .filter_map(|event| match translate_event(event, &tag, options) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                warn!("malformed Fluent event, skipping it: {}", err);
+                metrics::inc_malformed_input_discarded(0);
+                None
+            }
+        })
+        .collect();
+
+    Ok((entries, ack))
+}
+
+fn option_value<'a>(option: &'a Value, key: &str) -> Option<&'a Value> {
+    option
+        .as_map()?
+        .iter()
+        .find(|(name, _)| name.as_str() == Some(key))
+        .map(|(_, value)| value)
+}
+
+/// Events of PackedForward, decompressed if the option says so
+fn unpack(
+    packed: &[u8],
+    option: Option<&Value>,
+    max_message_size: usize,
+) -> Result<Vec<Value>, FluentError> {
+    let compression = option.and_then(|option| option_value(option, "compressed"));
+    let mut unpacked = Vec::new();
+    let packed = match compression.map(Value::as_str) {
+        None | Some(Some("text")) => packed,
+        Some(Some("gzip")) => {
+            MultiGzDecoder::new(packed)
+                .take(max_message_size as u64 + 1)
+                .read_to_end(&mut unpacked)?;
+            if unpacked.len() > max_message_size {
+                return Err(FluentError::TooLarge);
+            }
+            &unpacked[..]
+        }
+        Some(compression) => {
+            return Err(FluentError::Compression(
+                compression.unwrap_or_default().to_string(),
+            ))
+        }
+    };
+
+    let mut events = Vec::new();
+    let mut rest = packed;
+    while !rest.is_empty() {
+        events.push(rmpv::decode::read_value(&mut rest)?);
+    }
+
+    Ok(events)
+}
+
+/// Translates a `[time, record]` event into an entry:
+///
+/// - the tag into `FLUENT_TAG`
+/// - the time, seconds or an `EventTime`, into `_SOURCE_REALTIME_TIMESTAMP`
+/// - `message`, `log` or `msg` into `MESSAGE` when the record has none
+/// - `host` or `hostname` into `_HOSTNAME` when the record has none
+/// - other keys as they are if journald allows them, like the fields of
+///   Fluent Bit's `systemd` input, otherwise upper cased with characters
+///   journald doesn't allow replaced by `_`
+///
+/// Nested values are stored as JSON, `nil` values are skipped.
+fn translate_event(
+    event: Value,
+    tag: &str,
+    options: &ParseOptions,
+) -> Result<JournalEntry, FluentError> {
+    let Value::Array(event) = event else {
+        return Err(FluentError::Malformed("event is not an array"));
+    };
+    let [time, Value::Map(record)] = <[Value; 2]>::try_from(event)
+        .map_err(|_| FluentError::Malformed("event is not [time, record]"))?
+    else {
+        return Err(FluentError::Malformed("record is not a map"));
+    };
+    if record.len() > options.limits.max_fields_per_entry {
+        return Err(FluentError::TooManyFields);
+    }
+
+    let mut entry = JournalEntry::default();
+    entry.put("FLUENT_TAG", text(tag.to_string()));
+    if let Some(micros) = event_time(&time) {
+        entry.put("_SOURCE_REALTIME_TIMESTAMP", text(micros.to_string()));
+    }
+
+    let mut aliases = Vec::new();
+    for (key, value) in record {
+        let Some(key) = key.as_str().map(String::from) else {
+            continue;
+        };
+        let value = match value {
+            Value::Nil => continue,
+            Value::String(value) => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            Value::Binary(value) => String::from_utf8_lossy(&value).into_owned(),
+            value => value.to_string(),
+        };
+        if value.len() as u64 > options.limits.max_field_size {
+            return Err(FluentError::TooLarge);
+        }
+
+        match key.as_str() {
+            "message" | "log" | "msg" => aliases.push(("MESSAGE", value.clone())),
+            "host" | "hostname" => aliases.push(("_HOSTNAME", value.clone())),
+            _ => {}
+        }
+        entry.put(intern(field_name(&key)), text(value));
+    }
+    for (name, value) in aliases {
+        if entry.get(name).is_none() {
+            entry.put(name, text(value));
+        }
+    }
+
+    if entry.len() > options.limits.max_fields_per_entry {
+        return Err(FluentError::TooManyFields);
+    }
+
+    Ok(entry)
+}
+
+/// Microseconds since the epoch
+fn event_time(time: &Value) -> Option<u64> {
+    match time {
+        Value::Ext(EVENT_TIME_TYPE, data) if data.len() == 8 => {
+            let seconds = u32::from_be_bytes(data[..4].try_into().ok()?);
+            let nanos = u32::from_be_bytes(data[4..].try_into().ok()?);
+            Some(u64::from(seconds) * 1_000_000 + u64::from(nanos) / 1000)
+        }
+        Value::F64(seconds) if *seconds >= 0.0 => Some((seconds * 1_000_000.0).round() as u64),
+        time => time.as_u64().map(|seconds| seconds * 1_000_000),
+    }
+}
+
+fn text(value: String) -> JournalFieldValue {
+    JournalFieldValue::UTF8(value)
+}
+
+/// `name` if journald allows it as a key, otherwise upper cased with other
+/// characters replaced, prefixed if it would start with a digit or underscore
+fn field_name(name: &str) -> String {
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| matches!(c, 'A'..='Z' | '0'..='9' | '_'));
+    if valid {
+        return name.to_string();
+    }
+
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+
+    match name.chars().next() {
+        Some('0'..='9') | Some('_') | None => format!("FLUENT{}", name),
+        _ => name,
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use log::{debug, info, warn};
+use serde_json::{Map, Value};
+use systemd_journal_parser::{intern, JournalEntry, JournalFieldValue, ParseOptions};
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::listener::accept_failed;
+use crate::metrics;
+use crate::network::{read_delimited, NetworkEntries};
+
+/// Starts a chunk of a message split over several datagrams
+const CHUNK_MAGIC: &[u8] = &[0x1e, 0x0f];
+/// Magic, message ID, sequence number and count
+const CHUNK_HEADER_SIZE: usize = 12;
+/// Messages with more chunks are dropped, as the specification requires
+const MAX_CHUNKS: u8 = 128;
+/// Incomplete messages are dropped after this long
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZLIB_MAGIC: u8 = 0x78;
+
+/// GELF (Graylog Extended Log Format) message which can't be translated
+#[derive(Debug, thiserror::Error)]
+pub enum GelfError {
+    #[error("decompression failed: {0}")]
+    Decompression(io::Error),
+
+    #[error("JSON error: {0}")]
+    Json(serde_json::Error),
+
+    #[error("message is not a JSON object")]
+    NotAnObject,
+
+    #[error("message lacks \"short_message\"")]
+    MissingMessage,
+
+    #[error("message exceeds the size limit")]
+    TooLarge,
+
+    #[error("message has too many fields")]
+    TooManyFields,
+}
+
+/// Receives GELF messages as UDP datagrams, optionally chunked and gzip or
+/// zlib compressed, and on TCP connections delimited by null bytes, until the
+/// consumer goes away. This is what Docker's `gelf` logging driver sends.
+/// Decompressed messages larger than `max_message_size` are dropped, TCP
+/// connections sending larger messages are closed.
+pub async fn accept_messages(
+    udp_sockets: Vec<std::net::UdpSocket>,
+    listeners: Vec<std::net::TcpListener>,
+    max_message_size: usize,
+    entries: NetworkEntries,
+) -> io::Result<()> {
+    let mut receive_loops = Vec::new();
+    for socket in udp_sockets {
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        let entries = entries.clone();
+
+        receive_loops.push(tokio::task::spawn(async move {
+            let mut buffer = vec![0; 64 * 1024];
+            let mut chunks = Chunks::default();
+            loop {
+                let (length, peer) = tokio::select! {
+                    received = socket.recv_from(&mut buffer) => received?,
+                    _ = entries.closed() => return Ok::<_, io::Error>(()),
+                };
+                let Some(message) = chunks.push(&buffer[..length], peer, max_message_size) else {
+                    continue;
+                };
+                let Some(entry) = parse(&message, &entries, peer, max_message_size) else {
+                    continue;
+                };
+                if !entries.submit(entry, "gelf", peer.ip()).await {
+                    return Ok(());
+                }
+            }
+        }));
+    }
+
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let entries = entries.clone();
+
+        receive_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(addr, err).await;
+                            continue;
+                        }
+                    },
+                    _ = entries.closed() => return Ok::<_, io::Error>(()),
+                };
+                info!("accepted GELF connection from {}", peer);
+
+                let entries = entries.clone();
+                tokio::task::spawn(async move {
+                    let mut reader = BufReader::new(stream);
+                    let mut frame = Vec::new();
+                    loop {
+                        frame.clear();
+                        match read_delimited(&mut reader, &mut frame, b'\0', max_message_size).await
+                        {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                debug!("GELF connection from {} closed", peer);
+                                return;
+                            }
+                            Err(err) => {
+                                warn!("GELF connection from {} failed: {}", peer, err);
+                                return;
+                            }
+                        }
+
+                        if frame.last() == Some(&b'\0') {
+                            frame.pop();
+                        }
+                        if frame.is_empty() {
+                            continue;
+                        }
+                        let Some(entry) = parse(&frame, &entries, peer, max_message_size) else {
+                            continue;
+                        };
+                        if !entries.submit(entry, "gelf", peer.ip()).await {
+                            return;
+                        }
+                    }
+                });
+            }
+        }));
+    }
+
+    for receive_loop in receive_loops {
+        receive_loop.await??;
+    }
+
+    Ok(())
+}
+
+/// Malformed messages are skipped
+fn parse(
+    message: &[u8],
+    entries: &NetworkEntries,
+    peer: SocketAddr,
+    max_message_size: usize,
+) -> Option<JournalEntry> {
+    let result = decompress(message, max_message_size)
+        .and_then(|message| parse_gelf_message(&message, &entries.config().options()));
+    match result {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            warn!("malformed GELF message from {}, skipping it: {}", peer, err);
+            metrics::inc_malformed_input_discarded(message.len() as u64);
+            None
+        }
+    }
+}
+
+fn decompress(message: &[u8], max_size: usize) -> Result<Vec<u8>, GelfError> {
+    let mut decoder: Box<dyn Read + '_> = if message.starts_with(GZIP_MAGIC) {
+        Box::new(GzDecoder::new(message))
+    } else if message.first() == Some(&ZLIB_MAGIC) {
+        Box::new(ZlibDecoder::new(message))
+    } else {
+        return Ok(message.to_vec());
+    };
+
+    let mut decompressed = Vec::new();
+    decoder
+        .by_ref()
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(GelfError::Decompression)?;
+    if decompressed.len() > max_size {
+        return Err(GelfError::TooLarge);
+    }
+
+    Ok(decompressed)
+}
+
+/// Translates a GELF message into an entry:
+///
+/// - `short_message` into `MESSAGE`, `full_message` into `FULL_MESSAGE`
+/// - `host` into `_HOSTNAME`
+/// - `level`, a syslog severity, into `PRIORITY`
+/// - `timestamp`, seconds since the epoch, into `_SOURCE_REALTIME_TIMESTAMP`
+/// - the fields Docker adds into those of its `journald` logging driver:
+///   `CONTAINER_ID`, `CONTAINER_ID_FULL`, `CONTAINER_NAME`, `CONTAINER_TAG`,
+///   `SYSLOG_IDENTIFIER` and `IMAGE_NAME`
+/// - other additional fields (`_name`) into upper case fields without the
+///   underscore, with characters journald doesn't allow replaced by `_`
+///
+/// Numbers and booleans are stored as text, `null` values are skipped.
+pub fn parse_gelf_message(
+    message: &[u8],
+    options: &ParseOptions,
+) -> Result<JournalEntry, GelfError> {
+    if message.len() > options.limits.max_entry_size {
+        return Err(GelfError::TooLarge);
+    }
+
+    let fields: Map<String, Value> = match serde_json::from_slice(message) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(_) => return Err(GelfError::NotAnObject),
+        Err(err) => return Err(GelfError::Json(err)),
+    };
+    if fields.len() > options.limits.max_fields_per_entry {
+        return Err(GelfError::TooManyFields);
+    }
+    if !fields.contains_key("short_message") {
+        return Err(GelfError::MissingMessage);
+    }
+
+    let mut entry = JournalEntry::default();
+    for (key, value) in fields {
+        let value = match value {
+            Value::Null => continue,
+            Value::String(value) => value,
+            Value::Number(number) if key == "timestamp" => match number.as_f64() {
+                Some(seconds) if seconds >= 0.0 => {
+                    let micros = (seconds * 1_000_000.0).round() as u64;
+                    entry.put("_SOURCE_REALTIME_TIMESTAMP", text(micros.to_string()));
+                    continue;
+                }
+                _ => continue,
+            },
+            value => value.to_string(),
+        };
+        if value.len() as u64 > options.limits.max_field_size {
+            return Err(GelfError::TooLarge);
+        }
+
+        let name = match key.as_str() {
+            "version" => continue,
+            "short_message" => "MESSAGE",
+            "full_message" => "FULL_MESSAGE",
+            "host" => "_HOSTNAME",
+            "level" => "PRIORITY",
+            "_container_id" => {
+                let short: String = value.chars().take(12).collect();
+                entry.put("CONTAINER_ID", text(short));
+                "CONTAINER_ID_FULL"
+            }
+            "_container_name" => "CONTAINER_NAME",
+            "_image_name" => "IMAGE_NAME",
+            "_tag" => {
+                entry.put("SYSLOG_IDENTIFIER", text(value.clone()));
+                "CONTAINER_TAG"
+            }
+            _ => {
+                let name = match key.strip_prefix('_') {
+                    Some(name) if !name.is_empty() => field_name(name),
+                    // Not part of GELF, kept under a name journald allows
+                    _ => format!("GELF_{}", field_name(&key)),
+                };
+                entry.put(intern(name), text(value));
+                continue;
+            }
+        };
+        entry.put(name, text(value));
+    }
+
+    if entry.len() > options.limits.max_fields_per_entry {
+        return Err(GelfError::TooManyFields);
+    }
+
+    Ok(entry)
+}
+
+fn text(value: String) -> JournalFieldValue {
+    JournalFieldValue::UTF8(value)
+}
+
+/// Upper case name with characters journald doesn't allow in keys replaced,
+/// prefixed if it would start with a digit
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+
+    match name.chars().next() {
+        Some('0'..='9') | Some('_') => format!("GELF{}", name),
+        _ => name,
+    }
+}
+
+/// Chunks of a message split over several datagrams
+struct PartialMessage {
+    peer: SocketAddr,
+    chunks: Vec<Option<Vec<u8>>>,
+    size: usize,
+    first_seen: Instant,
+}
+
+/// Reassembles chunked messages of a socket
+#[derive(Default)]
+struct Chunks {
+    messages: HashMap<[u8; 8], PartialMessage>,
+}
+
+impl Chunks {
+    /// Returns the message once all of its chunks arrived, or right away if the
+    /// datagram isn't chunked
+    fn push(&mut self, datagram: &[u8], peer: SocketAddr, max_size: usize) -> Option<Vec<u8>> {
+        if !datagram.starts_with(CHUNK_MAGIC) {
+            return Some(datagram.to_vec());
+        }
+        if datagram.len() < CHUNK_HEADER_SIZE {
+            warn!("truncated GELF chunk from {}, skipping it", peer);
+            return None;
+        }
+
+        let now = Instant::now();
+        self.messages.retain(|_, message| {
+            let expired = now.duration_since(message.first_seen) > CHUNK_TIMEOUT;
+            if expired {
+                debug!("incomplete GELF message from {} expired", message.peer);
+                metrics::inc_malformed_input_discarded(message.size as u64);
+            }
+            !expired
+        });
+
+        let id: [u8; 8] = datagram[2..10].try_into().unwrap();
+        let (sequence, count) = (datagram[10], datagram[11]);
+        if count == 0 || count > MAX_CHUNKS || sequence >= count {
+            warn!("invalid GELF chunk from {}, skipping it", peer);
+            return None;
+        }
+
+        let message = self.messages.entry(id).or_insert_with(|| PartialMessage {
+            peer,
+            chunks: vec![None; usize::from(count)],
+            size: 0,
+            first_seen: now,
+        });
+        if message.peer != peer || message.chunks.len() != usize::from(count) {
+            warn!("conflicting GELF chunk from {}, skipping it", peer);
+            return None;
+        }
+
+        let chunk = &datagram[CHUNK_HEADER_SIZE..];
+        let slot = &mut message.chunks[usize::from(sequence)];
+        if slot.is_none() {
+            message.size += chunk.len();
+            *slot = Some(chunk.to_vec());
+        }
+        if message.size > max_size {
+            warn!(
+                "chunked GELF message from {} is too large, dropping it",
+                peer
+            );
+            metrics::inc_malformed_input_discarded(message.size as u64);
+            self.messages.remove(&id);
+            return None;
+        }
+        if message.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let message = self.messages.remove(&id)?;
+        Some(message.chunks.into_iter().flatten().flatten().collect())
+    }
+}
@@ -0,0 +1,374 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use log::{debug, info, warn};
+use systemd_journal_parser::{
+    intern, is_valid_field_key, JournalEntry, JournalFieldValue, ParseOptions,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::acks::CommitAcks;
+use crate::auth::Identities;
+use crate::config::{KeyValidation, ParserConfig};
+use crate::journal::{prepare_entry, EntryOrigin};
+use crate::metrics;
+use crate::watchdog::{Stage, Watchdog};
+
+mod proto {
+    tonic::include_proto!("journalsqld.ingest.v1");
+}
+
+use proto::ingest_server::{Ingest, IngestServer};
+use proto::{field, Batch, BatchAck};
+
+/// Acknowledgement of a queued batch, to send once its entries are inserted
+type PendingAck = Result<(BatchAck, oneshot::Receiver<()>), Status>;
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// Accepted connection, with TLS or without, and the origin of its entries
+struct Connection {
+    stream: Box<dyn Io>,
+    origin: EntryOrigin,
+}
+
+impl Connected for Connection {
+    type ConnectInfo = EntryOrigin;
+
+    fn connect_info(&self) -> EntryOrigin {
+        self.origin.clone()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone)]
+struct IngestService {
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    acks: Arc<CommitAcks>,
+    identities: Arc<Identities>,
+    max_in_flight: usize,
+    /// Labels of the input
+    labels: Option<String>,
+}
+
+impl IngestService {
+    /// Tenant and identity of the client certificate, or the identity of the
+    /// bearer token if there is one. Unknown tokens are refused, as are
+    /// requests without an identity when one is required.
+    fn origin<T>(&self, request: &Request<T>) -> Result<EntryOrigin, Status> {
+        let mut origin = request
+            .extensions()
+            .get::<EntryOrigin>()
+            .cloned()
+            .unwrap_or_default();
+        origin.labels = self.labels.clone();
+
+        if let Some(authorization) = request.metadata().get("authorization") {
+            let identity = authorization
+                .to_str()
+                .ok()
+                .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                .and_then(|token| self.identities.by_token(token.trim()))
+                .ok_or_else(|| Status::unauthenticated("unknown token"))?;
+            origin.identity = Some(identity.to_string());
+        }
+        if origin.identity.is_none() && self.identities.is_required() {
+            return Err(Status::unauthenticated("authentication required"));
+        }
+
+        Ok(origin)
+    }
+
+    /// Queues the entries of `batch` followed by a barrier, which is released
+    /// once they are inserted
+    async fn queue(&self, batch: Batch, origin: &EntryOrigin) -> PendingAck {
+        let options = self.config.options();
+        let mut ack = BatchAck {
+            sequence: batch.sequence,
+            accepted: 0,
+            rejected: 0,
+        };
+
+        self.watchdog.busy(Stage::Producer);
+        for entry in batch.entries {
+            let Some(mut entry) = translate(entry, &options, self.config.key_validation) else {
+                metrics::inc_malformed_input_discarded(0);
+                ack.rejected += 1;
+                continue;
+            };
+            prepare_entry(&mut entry, &self.config, origin);
+            self.send(entry).await?;
+            self.watchdog.produced();
+            ack.accepted += 1;
+        }
+        let (barrier, inserted) = self.acks.barrier();
+        self.send(barrier).await?;
+        self.watchdog.idle(Stage::Producer);
+
+        Ok((ack, inserted))
+    }
+
+    async fn send(&self, entry: JournalEntry) -> Result<(), Status> {
+        self.sender.send(entry).await.map_err(|err| {
+            debug!("producer channel closed: {:?}", err);
+            Status::unavailable("shutting down")
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Ingest for IngestService {
+    async fn push(&self, request: Request<Batch>) -> Result<Response<BatchAck>, Status> {
+        let origin = self.origin(&request)?;
+        let (ack, inserted) = self.queue(request.into_inner(), &origin).await?;
+        inserted
+            .await
+            .map_err(|_| Status::unavailable("shut down before the batch was inserted"))?;
+
+        Ok(Response::new(ack))
+    }
+
+    type ForwardStream = ReceiverStream<Result<BatchAck, Status>>;
+
+    async fn forward(
+        &self,
+        request: Request<Streaming<Batch>>,
+    ) -> Result<Response<Self::ForwardStream>, Status> {
+        let origin = self.origin(&request)?;
+        let mut batches = request.into_inner();
+        let (pending_sender, mut pending) = mpsc::channel::<PendingAck>(self.max_in_flight);
+        let (ack_sender, acks) = mpsc::channel(self.max_in_flight);
+
+        // Stops reading once `max_in_flight` batches wait for their insert
+        let service = self.clone();
+        tokio::task::spawn(async move {
+            loop {
+                let batch = match batches.message().await {
+                    Ok(Some(batch)) => batch,
+                    Ok(None) => return,
+                    Err(status) => {
+                        debug!("gRPC stream failed: {}", status);
+                        return;
+                    }
+                };
+                let queued = service.queue(batch, &origin).await;
+                let failed = queued.is_err();
+                if pending_sender.send(queued).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        tokio::task::spawn(async move {
+            while let Some(queued) = pending.recv().await {
+                let response = match queued {
+                    Ok((ack, inserted)) => match inserted.await {
+                        Ok(()) => Ok(ack),
+                        Err(_) => Err(Status::unavailable(
+                            "shut down before the batch was inserted",
+                        )),
+                    },
+                    Err(status) => Err(status),
+                };
+                let failed = response.is_err();
+                if ack_sender.send(response).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(acks)))
+    }
+}
+
+/// Entry of the message, `None` if it exceeds the parser limits, has a field
+/// without a value or, when keys are validated strictly, an invalid key
+fn translate(
+    message: proto::Entry,
+    options: &ParseOptions,
+    key_validation: KeyValidation,
+) -> Option<JournalEntry> {
+    if message.fields.len() > options.limits.max_fields_per_entry {
+        return None;
+    }
+
+    let mut entry = JournalEntry::default();
+    let mut size = 0;
+    for field in message.fields {
+        if key_validation == KeyValidation::Reject && !is_valid_field_key(&field.name) {
+            return None;
+        }
+        let value = match field.value? {
+            field::Value::Text(text) => JournalFieldValue::UTF8(text),
+            field::Value::Binary(binary) => JournalFieldValue::Bytes(binary.into()),
+        };
+        if value.len() as u64 > options.limits.max_field_size {
+            return None;
+        }
+        size += field.name.len() + value.len();
+        if size > options.limits.max_entry_size {
+            return None;
+        }
+        entry.put(intern(field.name), value);
+    }
+
+    Some(entry)
+}
+
+/// Serves the `Ingest` service of `proto/ingest.proto` until the consumer
+/// goes away. Batches are acknowledged once their entries are inserted into
+/// ClickHouse, not just queued. With TLS, entries are tagged with the tenant
+/// selected by the server name. Entries are tagged with the identity of the
+/// bearer token or, failing that, of the client certificate. Batches larger
+/// than `max_message_size` are refused. `origin` holds the labels of the input.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    listeners: Vec<std::net::TcpListener>,
+    tls: Option<TlsAcceptor>,
+    identities: Arc<Identities>,
+    max_in_flight: usize,
+    max_message_size: usize,
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    acks: Arc<CommitAcks>,
+    origin: EntryOrigin,
+) -> Result<(), tonic::transport::Error> {
+    let (connections, incoming) = mpsc::channel::<io::Result<Connection>>(64);
+    for listener in listeners {
+        let listener = listener
+            .set_nonblocking(true)
+            .and_then(|()| TcpListener::from_std(listener));
+        let listener = match listener {
+            Ok(listener) => listener,
+            Err(err) => {
+                let _ = connections.send(Err(err)).await;
+                continue;
+            }
+        };
+        let (tls, connections) = (tls.clone(), connections.clone());
+        let identities = identities.clone();
+
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            let _ = connections.send(Err(err)).await;
+                            return;
+                        }
+                    },
+                    _ = connections.closed() => return,
+                };
+                info!("accepted gRPC connection from {}", peer);
+
+                let (tls, connections) = (tls.clone(), connections.clone());
+                let identities = identities.clone();
+                tokio::task::spawn(async move {
+                    let connection = match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => {
+                                let (_, connection) = stream.get_ref();
+                                let origin = EntryOrigin {
+                                    tenant: connection.server_name().map(String::from),
+                                    identity: identities
+                                        .by_certificate(connection.peer_certificates())
+                                        .map(String::from),
+                                    ..EntryOrigin::default()
+                                };
+                                debug!(
+                                    "connection from {} is for tenant {:?} as {:?}",
+                                    peer, origin.tenant, origin.identity
+                                );
+                                Connection {
+                                    stream: Box::new(stream),
+                                    origin,
+                                }
+                            }
+                            Err(err) => {
+                                warn!("TLS handshake with {} failed: {}", peer, err);
+                                return;
+                            }
+                        },
+                        None => Connection {
+                            stream: Box::new(stream),
+                            origin: EntryOrigin::default(),
+                        },
+                    };
+                    let _ = connections.send(Ok(connection)).await;
+                });
+            }
+        });
+    }
+    drop(connections);
+
+    let closed = sender.clone();
+    let service = IngestService {
+        config,
+        sender,
+        watchdog,
+        acks,
+        identities,
+        max_in_flight: max_in_flight.max(1),
+        labels: origin.labels,
+    };
+    Server::builder()
+        .add_service(IngestServer::new(service).max_decoding_message_size(max_message_size))
+        .serve_with_incoming_shutdown(ReceiverStream::new(incoming), async move {
+            closed.closed().await
+        })
+        .await
+}
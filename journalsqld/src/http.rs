@@ -0,0 +1,99 @@
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+
+use crate::dead_letter::{DeadLetterQueue, DropReport};
+use crate::metrics::{self, StageStats};
+use crate::source_cursors::SourceCursors;
+use crate::watchdog::Watchdog;
+
+#[derive(Serialize)]
+struct Stats {
+    stages: Vec<StageStats>,
+    drops: DropReport,
+}
+
+/// Serves `/healthz`, reflecting the watchdog state, `/metrics` in the
+/// Prometheus text format, `/stats`, a JSON breakdown of the time spent per
+/// pipeline stage and of the input dropped so far, and `/cursors`, the last
+/// inserted cursor per source when tracked
+pub async fn serve(
+    listener: TcpListener,
+    keepalive: Option<Duration>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    source_cursors: Option<Arc<SourceCursors>>,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let watchdog = watchdog.clone();
+        let dead_letters = dead_letters.clone();
+        let source_cursors = source_cursors.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request, &watchdog, &dead_letters, source_cursors.as_deref());
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    Server::from_tcp(listener)?
+        .tcp_keepalive(keepalive)
+        .serve(make_service)
+        .await
+}
+
+fn handle(
+    request: Request<Body>,
+    watchdog: &Watchdog,
+    dead_letters: &DeadLetterQueue,
+    source_cursors: Option<&SourceCursors>,
+) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") if watchdog.is_healthy() => respond(StatusCode::OK, "ok\n"),
+        (&Method::GET, "/healthz") => respond(StatusCode::SERVICE_UNAVAILABLE, "stalled\n"),
+        (&Method::GET, "/metrics") => {
+            let metrics = prometheus::gather();
+            match prometheus::TextEncoder::new().encode_to_string(&metrics) {
+                Ok(encoded) => respond(StatusCode::OK, encoded),
+                Err(err) => respond(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            }
+        }
+        (&Method::GET, "/stats") => {
+            let stats = Stats {
+                stages: metrics::stage_stats(),
+                drops: dead_letters.report(),
+            };
+            match serde_json::to_string_pretty(&stats) {
+                Ok(encoded) => respond(StatusCode::OK, encoded),
+                Err(err) => respond(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            }
+        }
+        (&Method::GET, "/cursors") => {
+            let Some(source_cursors) = source_cursors else {
+                return respond(StatusCode::NOT_FOUND, "source cursors are not tracked\n");
+            };
+            // ?machine_id=<id> narrows the list down to the boots of one machine
+            let machine_id = request.uri().query().and_then(|query| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("machine_id="))
+            });
+            match serde_json::to_string_pretty(&source_cursors.list(machine_id)) {
+                Ok(encoded) => respond(StatusCode::OK, encoded),
+                Err(err) => respond(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            }
+        }
+        _ => respond(StatusCode::NOT_FOUND, "not found\n"),
+    }
+}
+
+fn respond<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
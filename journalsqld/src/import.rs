@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use systemd_journal_parser::{EntryReader, JournalReadError};
+use time::OffsetDateTime;
+use tokio::io::AsyncRead;
+
+use crate::client::Client;
+use crate::config::{BytesRenderingConfig, Config, ParserConfig};
+use crate::decompress::decompressing;
+use crate::inserter::Inserter;
+use crate::kubernetes::KubernetesInfo;
+use crate::row::LogRecordRow;
+use crate::sampling::Sampler;
+use crate::schema::Schema;
+use crate::transform::Transform;
+use crate::Error;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Progress {
+    pub files_done: AtomicU64,
+    pub files_failed: AtomicU64,
+    pub entries: AtomicU64,
+    pub errors: AtomicU64,
+}
+
+/// Imports journal export files, optionally gzip or zstd compressed. Files are
+/// probed for the machine they belong to and imported by one worker per machine,
+/// in order of their first entry, so rows of a machine are inserted in the order
+/// they were logged while different machines are imported in parallel.
+pub async fn run(config: &Config, client: Client, paths: Vec<PathBuf>) -> Result<(), Error> {
+    if paths.is_empty() {
+        return Err("usage: journalsqld import FILE...".into());
+    }
+
+    let started = Instant::now();
+    let progress = Arc::new(Progress::default());
+    let total_files = paths.len();
+
+    let mut machines: BTreeMap<String, Vec<(OffsetDateTime, PathBuf)>> = BTreeMap::new();
+    for path in paths {
+        match probe(&path, &config.parser).await {
+            Ok((machine_id, first_timestamp)) => machines
+                .entry(machine_id)
+                .or_default()
+                .push((first_timestamp, path)),
+            Err(err) => {
+                error!("failed to probe {}: {}", path.display(), err);
+                progress.files_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    let mut workers = Vec::with_capacity(machines.len());
+    for (machine_id, mut files) in machines {
+        files.sort();
+
+        let inserter = new_inserter(config, client.clone());
+        let parser_config = config.parser.clone();
+        let bytes_rendering = config.bytes_rendering.clone();
+        let kubernetes_enabled = config.kubernetes.enabled;
+        let sampler = Sampler::new(config.sampling.percent);
+        let transform = Transform::new(&config.transform);
+        let progress = progress.clone();
+
+        workers.push(tokio::task::spawn(async move {
+            let mut inserter = inserter;
+            for (_, path) in files {
+                let result = import_file(
+                    &path,
+                    &parser_config,
+                    &bytes_rendering,
+                    kubernetes_enabled,
+                    &sampler,
+                    &transform,
+                    &mut inserter,
+                    &progress,
+                )
+                .await;
+
+                match result {
+                    Ok(()) => progress.files_done.fetch_add(1, Ordering::Relaxed),
+                    Err(err) => {
+                        error!(
+                            "failed to import {} ({}): {}",
+                            path.display(),
+                            machine_id,
+                            err
+                        );
+                        progress.files_failed.fetch_add(1, Ordering::Relaxed)
+                    }
+                };
+            }
+        }));
+    }
+
+    let reporter = {
+        let progress = progress.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                info!(
+                    "import progress: files={}/{} failed={} entries={} errors={}",
+                    progress.files_done.load(Ordering::Relaxed),
+                    total_files,
+                    progress.files_failed.load(Ordering::Relaxed),
+                    progress.entries.load(Ordering::Relaxed),
+                    progress.errors.load(Ordering::Relaxed)
+                );
+            }
+        })
+    };
+
+    for worker in workers {
+        worker.await?;
+    }
+    reporter.abort();
+
+    let failed = progress.files_failed.load(Ordering::Relaxed);
+    println!("files:    {} ({} failed)", total_files, failed);
+    println!("entries:  {}", progress.entries.load(Ordering::Relaxed));
+    println!("errors:   {}", progress.errors.load(Ordering::Relaxed));
+    println!("duration: {:.1?}", started.elapsed());
+
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to import", failed, total_files).into());
+    }
+
+    Ok(())
+}
+
+/// Imports export format data from `reader`, named `name` in messages. Returns
+/// the number of imported entries.
+pub async fn import_stream<R: AsyncRead + Unpin>(
+    config: &Config,
+    client: Client,
+    name: &str,
+    reader: R,
+) -> Result<u64, Error> {
+    let mut inserter = new_inserter(config, client);
+    let progress = Progress::default();
+
+    import_reader(
+        reader,
+        name,
+        &config.parser,
+        &config.bytes_rendering,
+        config.kubernetes.enabled,
+        &Sampler::new(config.sampling.percent),
+        &Transform::new(&config.transform),
+        &mut inserter,
+        &progress,
+    )
+    .await?;
+
+    Ok(progress.entries.load(Ordering::Relaxed))
+}
+
+fn new_inserter(config: &Config, client: Client) -> Inserter {
+    Inserter::new(client, &config.clickhouse.table, Schema::new(config))
+        .with_format(config.clickhouse.format)
+        .with_max_entries(config.clickhouse.max_entries)
+}
+
+/// Machine ID and timestamp of the first entry of a file
+pub async fn probe(path: &Path, config: &ParserConfig) -> Result<(String, OffsetDateTime), Error> {
+    let file = decompressing(tokio::fs::File::open(path).await?).await?;
+    let mut reader = EntryReader::new(file).with_options(config.options());
+
+    let mut entry = reader
+        .next_entry()
+        .await?
+        .ok_or("file contains no entries")?;
+    let machine_id = entry
+        .take_machine_id()
+        .ok_or("first entry has no _MACHINE_ID")?;
+    let timestamp = entry
+        .take_realtime_timestamp()
+        .ok_or("first entry has no __REALTIME_TIMESTAMP")??;
+
+    Ok((machine_id, timestamp))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn import_file(
+    path: &Path,
+    config: &ParserConfig,
+    bytes_rendering: &BytesRenderingConfig,
+    kubernetes_enabled: bool,
+    sampler: &Sampler,
+    transform: &Transform,
+    inserter: &mut Inserter,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let file = decompressing(tokio::fs::File::open(path).await?).await?;
+
+    import_reader(
+        file,
+        &path.display().to_string(),
+        config,
+        bytes_rendering,
+        kubernetes_enabled,
+        sampler,
+        transform,
+        inserter,
+        progress,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn import_reader<R: AsyncRead + Unpin>(
+    reader: R,
+    name: &str,
+    config: &ParserConfig,
+    bytes_rendering: &BytesRenderingConfig,
+    kubernetes_enabled: bool,
+    sampler: &Sampler,
+    transform: &Transform,
+    inserter: &mut Inserter,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let mut reader = EntryReader::new(reader).with_options(config.options());
+
+    loop {
+        let mut entry = match reader.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(JournalReadError::ParseError(info)) if config.recover => {
+                warn!(
+                    "{}: malformed input, skipping to next entry: {}",
+                    name, info
+                );
+                progress.errors.fetch_add(1, Ordering::Relaxed);
+                reader.resync().await?;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if !sampler.keep(&entry) {
+            continue;
+        }
+
+        transform.apply(&mut entry);
+
+        let kubernetes = if kubernetes_enabled {
+            KubernetesInfo::from_entry(&entry)
+        } else {
+            KubernetesInfo::default()
+        };
+
+        let mut row = match LogRecordRow::from_entry(entry, bytes_rendering) {
+            Ok(row) => row,
+            Err(err) => {
+                warn!("{}: failed to produce row: {}", name, err);
+                progress.errors.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+        row.kubernetes = kubernetes;
+
+        inserter.write(row);
+        progress.entries.fetch_add(1, Ordering::Relaxed);
+        inserter.commit().await?;
+    }
+
+    inserter.end().await?;
+
+    Ok(())
+}
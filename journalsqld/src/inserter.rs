@@ -0,0 +1,343 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::Quantities;
+use log::warn;
+
+use crate::client::{Client, ClientError};
+use crate::config::InsertFormat;
+use crate::cursor_index::CursorIndex;
+use crate::metrics::{self, PipelineStage};
+use crate::row::LogRecordRow;
+use crate::schema::Schema;
+use crate::slo::SloTracker;
+use crate::source_cursors::SourceCursors;
+
+/// ClickHouse exception codes for insert data it couldn't read, on which
+/// `InsertFormat::Auto` falls back to JSONEachRow
+const FORMAT_ERROR_CODES: [u32; 13] = [
+    6,   // CANNOT_PARSE_TEXT
+    25,  // CANNOT_PARSE_ESCAPE_SEQUENCE
+    26,  // CANNOT_PARSE_QUOTED_STRING
+    27,  // CANNOT_PARSE_INPUT_ASSERTION_FAILED
+    32,  // ATTEMPT_TO_READ_AFTER_EOF
+    33,  // CANNOT_READ_ALL_DATA
+    38,  // CANNOT_PARSE_DATE
+    41,  // CANNOT_PARSE_DATETIME
+    72,  // CANNOT_PARSE_NUMBER
+    73,  // UNKNOWN_FORMAT
+    117, // INCORRECT_DATA
+    131, // TOO_LARGE_STRING_SIZE
+    376, // CANNOT_PARSE_UUID
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum InsertError {
+    #[error("Client error")]
+    ClientError(ClientError),
+
+    #[error("Encode error")]
+    EncodeError(serde_json::Error),
+
+    #[error("File output error: {0}")]
+    FileError(std::io::Error),
+
+    #[error("Relay error: {0}")]
+    RelayError(std::io::Error),
+
+    #[error("Syslog error: {0}")]
+    SyslogError(std::io::Error),
+
+    #[cfg(feature = "otlp")]
+    #[error("Export error")]
+    ExportError(tonic::Status),
+
+    #[cfg(feature = "archive")]
+    #[error("Archive error: {0}")]
+    ArchiveError(crate::Error),
+}
+
+/// Buffers rows and inserts them in batches, bounded by entry count and time
+pub struct Inserter {
+    client: Client,
+    schema: Schema,
+    table: String,
+    format: InsertFormat,
+    rows: Vec<LogRecordRow>,
+    max_entries: u64,
+    period: Option<Duration>,
+    /// Inserters of a router with a higher priority insert first
+    priority: i32,
+    last_insert: Instant,
+    committed_cursor: Option<String>,
+    cursor_index: Option<CursorIndex>,
+    slo: Option<Arc<SloTracker>>,
+    source_cursors: Option<Arc<SourceCursors>>,
+}
+
+impl Inserter {
+    pub fn new(client: Client, table: &str, schema: Schema) -> Self {
+        Self {
+            client,
+            schema,
+            table: table.to_string(),
+            format: InsertFormat::default(),
+            rows: Vec::new(),
+            max_entries: u64::MAX,
+            period: None,
+            priority: 0,
+            last_insert: Instant::now(),
+            committed_cursor: None,
+            cursor_index: None,
+            slo: None,
+            source_cursors: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: InsertFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: u64) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_period(mut self, period: Option<Duration>) -> Self {
+        self.period = period;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_cursor_index(mut self, cursor_index: CursorIndex) -> Self {
+        self.cursor_index = Some(cursor_index);
+        self
+    }
+
+    pub fn with_slo(mut self, slo: Arc<SloTracker>) -> Self {
+        self.slo = Some(slo);
+        self
+    }
+
+    pub fn with_source_cursors(mut self, source_cursors: Arc<SourceCursors>) -> Self {
+        self.source_cursors = Some(source_cursors);
+        self
+    }
+
+    /// Summary of the table and batching settings, as lines
+    pub fn describe(&self) -> Vec<String> {
+        let mut batching = format!("{:?}, max {} entries", self.format, self.max_entries);
+        if let Some(period) = self.period {
+            batching += &format!(", every {:?}", period);
+        }
+
+        let mut lines = vec![format!("ClickHouse table {}", self.table), batching];
+        if self.priority != 0 {
+            lines.push(format!("priority {}", self.priority));
+        }
+        if self.cursor_index.is_some() {
+            lines.push(String::from("+ cursor index"));
+        }
+        if self.slo.is_some() {
+            lines.push(String::from("+ SLO tracking"));
+        }
+        if self.source_cursors.is_some() {
+            lines.push(String::from("+ source cursors"));
+        }
+
+        lines
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Cursor of the most recent successfully inserted row
+    pub fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+
+    /// Whether there are no buffered rows waiting to be inserted
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn write(&mut self, row: LogRecordRow) {
+        self.rows.push(row);
+    }
+
+    /// Inserts buffered rows if either the entry or time limit has been reached
+    pub async fn commit(&mut self) -> Result<Quantities, InsertError> {
+        let period_elapsed = self
+            .period
+            .map(|period| self.last_insert.elapsed() >= period)
+            .unwrap_or(false);
+
+        if self.rows.len() as u64 >= self.max_entries || period_elapsed {
+            self.insert().await
+        } else {
+            Ok(Quantities::default())
+        }
+    }
+
+    /// Inserts all remaining buffered rows
+    pub async fn end(&mut self) -> Result<Quantities, InsertError> {
+        self.insert().await
+    }
+
+    /// Rows stay buffered until the insert succeeds, so a cancelled insert is
+    /// retried by the next commit
+    async fn insert(&mut self) -> Result<Quantities, InsertError> {
+        self.last_insert = Instant::now();
+        if self.rows.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let started = Instant::now();
+        match self.format {
+            InsertFormat::RowBinary => self.insert_row_binary().await?,
+            InsertFormat::JsonEachRow => self.insert_json_each_row().await?,
+            InsertFormat::Auto => match self.insert_row_binary().await {
+                Err(InsertError::ClientError(err)) if is_format_error(&err) => {
+                    warn!(
+                        "RowBinary insert was rejected, falling back to JSONEachRow: {}",
+                        err
+                    );
+                    self.format = InsertFormat::JsonEachRow;
+                    self.insert_json_each_row().await?
+                }
+                result => result?,
+            },
+        }
+
+        metrics::observe_stage_duration(PipelineStage::Insert, started.elapsed());
+
+        self.committed_cursor = self.rows.last().map(|row| row.cursor.clone());
+        let rows = std::mem::take(&mut self.rows);
+
+        if let Some(slo) = &self.slo {
+            slo.record(&rows);
+        }
+        if let Some(source_cursors) = &self.source_cursors {
+            source_cursors.record(&rows);
+        }
+
+        // Rows are in the table already, a failed index update must not retry them
+        if let Some(cursor_index) = &mut self.cursor_index {
+            if let Err(err) = cursor_index.record(&rows).await {
+                warn!("failed to update cursor index: {}", err);
+            }
+        }
+
+        let entries = rows.len() as u64;
+
+        Ok(Quantities {
+            entries,
+            transactions: 1,
+        })
+    }
+
+    async fn insert_row_binary(&self) -> Result<(), InsertError> {
+        let query = format!(
+            "INSERT INTO {}({}) FORMAT RowBinary",
+            self.table,
+            self.schema.column_list()
+        );
+
+        let mut data = Vec::new();
+        for row in self.rows.iter() {
+            self.schema.write_row_binary(&mut data, row);
+        }
+
+        self.client
+            .execute(&query, data)
+            .await
+            .map_err(InsertError::ClientError)?;
+
+        Ok(())
+    }
+
+    async fn insert_json_each_row(&self) -> Result<(), InsertError> {
+        let query = format!(
+            "INSERT INTO {}({}) FORMAT JSONEachRow",
+            self.table,
+            self.schema.column_list()
+        );
+
+        let mut data = Vec::new();
+        for row in self.rows.iter() {
+            self.schema
+                .write_json_each_row(&mut data, row)
+                .map_err(InsertError::EncodeError)?;
+        }
+
+        self.client
+            .clone()
+            .with_option("date_time_input_format", "best_effort")
+            .execute(&query, data)
+            .await
+            .map_err(InsertError::ClientError)?;
+
+        Ok(())
+    }
+}
+
+/// Whether ClickHouse rejected an insert because it couldn't read the data in
+/// its format, rather than e.g. for a missing table or permissions
+fn is_format_error(err: &ClientError) -> bool {
+    err.exception_code()
+        .map_or(false, |code| FORMAT_ERROR_CODES.contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_error(status: u16, message: &str) -> ClientError {
+        ClientError::ServerError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn falls_back_on_format_errors_only() {
+        let parse_error = server_error(
+            500,
+            "Code: 27. DB::ParsingException: Cannot parse input: expected '\\t' before: ... \
+             (CANNOT_PARSE_INPUT_ASSERTION_FAILED) (version 23.3.1.2823 (official build))",
+        );
+        assert_eq!(parse_error.exception_code(), Some(27));
+        assert!(is_format_error(&parse_error));
+        assert!(is_format_error(&server_error(
+            500,
+            "Code: 33. DB::Exception: Cannot read all data. (CANNOT_READ_ALL_DATA)"
+        )));
+        assert!(is_format_error(&server_error(
+            400,
+            "Code: 73. DB::Exception: Unknown format RowBinary. (UNKNOWN_FORMAT)"
+        )));
+
+        for err in [
+            server_error(
+                404,
+                "Code: 60. DB::Exception: Table default.logs doesn't exist. (UNKNOWN_TABLE)",
+            ),
+            server_error(
+                403,
+                "Code: 497. DB::Exception: default: Not enough privileges. (ACCESS_DENIED)",
+            ),
+            server_error(503, "Service Unavailable"),
+            server_error(500, "Code: none"),
+            server_error(500, ""),
+            ClientError::Timeout(Duration::from_secs(1)),
+        ] {
+            assert!(!is_format_error(&err), "{}", err);
+        }
+    }
+}
@@ -1,154 +1,334 @@
-use std::io::Read;
-use std::{collections::HashMap, num::ParseIntError};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
 
-use log::{debug, trace};
-use serde::ser::SerializeMap;
-use serde::Serialize;
-use systemd_journal_parser::{parse_journal_field, JournalFieldValue};
+use log::{debug, error, info, trace, warn};
+use systemd_journal_parser::{
+    is_valid_field_key, EntryReader, JournalEntry, JournalFieldValue, JournalReadError,
+    TRUNCATED_FIELD,
+};
+use time::OffsetDateTime;
+use tokio::io::AsyncRead;
+use tokio::net::{TcpListener, UnixListener};
 use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
 
-use crate::metrics;
-use crate::util::measure;
+use crate::auth::Identities;
+use crate::config::{InputCompression, KeyValidation, Labels, ParserConfig, RateLimitConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::decompress::decompressing_as;
+use crate::listener::accept_failed;
+use crate::metrics::{self, PipelineStage};
+use crate::rate_limit::RateLimiter;
+use crate::row::{IDENTITY_FIELD, LABELS_FIELD, RECEIVED_AT_FIELD, SOURCE_FIELD, TENANT_FIELD};
+use crate::watchdog::{Stage, Watchdog};
 
-#[derive(Debug)]
-pub struct JournalEntry {
-    pub(super) fields: HashMap<String, JournalFieldValue, fnv::FnvBuildHasher>,
+/// Where entries came from, recorded in fields only the receiving side may
+/// assign
+#[derive(Clone, Debug, Default)]
+pub struct EntryOrigin {
+    /// Selected by the TLS server name
+    pub tenant: Option<String>,
+    /// Label of a configured file input
+    pub source: Option<String>,
+    /// Authenticated forwarder, see `Identities`
+    pub identity: Option<String>,
+    /// Labels of the input, encoded once rather than per entry
+    pub labels: Option<String>,
 }
 
-impl Serialize for JournalEntry {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
-        for (key, value) in self.fields.iter() {
-            map.serialize_key(key)?;
-            map.serialize_value(value)?;
-        }
-
-        map.end()
+impl EntryOrigin {
+    pub fn with_labels(mut self, labels: &Labels) -> Self {
+        self.labels = (!labels.is_empty())
+            .then(|| serde_json::to_string(labels).expect("failed to encode labels"));
+        self
     }
 }
 
-impl JournalEntry {
-    pub fn put(&mut self, key: String, value: JournalFieldValue) -> bool {
-        self.fields.insert(key, value).is_some()
-    }
-
-    pub fn take_transport(&mut self) -> Option<String> {
-        self.fields.remove("_TRANSPORT").map(|field| field.into())
-    }
+/// Reads entries in the export format from `reader` and queues them, pausing
+/// while over `rate_limit`
+#[allow(clippy::too_many_arguments)]
+pub async fn read_journal_entries<R: AsyncRead + Unpin>(
+    reader: R,
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    origin: EntryOrigin,
+    rate_limit: Option<RateLimiter>,
+) -> Result<(), JournalReadError> {
+    let mut reader = EntryReader::new(reader).with_options(config.options());
 
-    pub fn take_hostname(&mut self) -> Option<String> {
-        self.fields.remove("_HOSTNAME").map(|field| field.into())
-    }
+    loop {
+        let started = Instant::now();
+        let mut entry = match reader.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(JournalReadError::ParseError(info)) if config.recover => {
+                warn!("malformed input, skipping to next entry: {}", info);
+                let discarded = reader.resync().await?;
+                metrics::inc_malformed_input_discarded(discarded);
+                dead_letters.drop_input(Stage::Producer, &info, discarded);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
 
-    pub fn take_machine_id(&mut self) -> Option<String> {
-        self.fields.remove("_MACHINE_ID").map(|field| field.into())
-    }
+        let parse_time = reader.last_entry_parse_time();
+        metrics::observe_stage_duration(PipelineStage::Parse, parse_time);
+        metrics::observe_stage_duration(
+            PipelineStage::Read,
+            started.elapsed().saturating_sub(parse_time),
+        );
+        metrics::set_last_entry_parse_time(parse_time).unwrap();
+        prepare_entry(&mut entry, &config, &origin);
+        if let Some(rate_limit) = &rate_limit {
+            rate_limit.acquire(entry.approx_size_bytes()).await;
+        }
 
-    pub fn take_boot_id(&mut self) -> Option<String> {
-        self.fields.remove("_BOOT_ID").map(|field| field.into())
+        watchdog.busy(Stage::Producer);
+        if let Err(err) = sender.send(entry).await {
+            debug!("producer channel closed: {:?}", err);
+            break;
+        }
+        watchdog.idle(Stage::Producer);
+        watchdog.produced();
     }
 
-    fn parse_realtime_timerstamp(
-        entry: &JournalFieldValue,
-    ) -> Result<time::OffsetDateTime, ParseIntError> {
-        let micros = String::from(entry).parse::<i128>()?;
+    Ok(())
+}
 
-        Ok(time::OffsetDateTime::from_unix_timestamp_nanos(micros * 1000).unwrap())
-    }
+/// Records metrics of a parsed entry, checks its keys and sets the fields of
+/// its origin and time of receipt, replacing any the sender set
+pub fn prepare_entry(entry: &mut JournalEntry, config: &ParserConfig, origin: &EntryOrigin) {
+    let received_at = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1000;
 
-    pub fn take_realtime_timestamp(
-        &mut self,
-    ) -> Option<Result<time::OffsetDateTime, ParseIntError>> {
-        self.fields
-            .remove("__REALTIME_TIMESTAMP")
-            .map(|v| Self::parse_realtime_timerstamp(&v))
+    metrics::observe_entry_size(entry.approx_size_bytes(), entry.field_count());
+    if entry.get(TRUNCATED_FIELD).is_some() {
+        metrics::inc_entries_truncated();
     }
+    trace!("processed={:?}", entry);
 
-    pub fn take_cursor(&mut self) -> Option<String> {
-        self.fields.remove("__CURSOR").map(|field| field.into())
+    if config.key_validation == KeyValidation::Flag {
+        flag_invalid_keys(entry);
     }
-}
 
-impl Default for JournalEntry {
-    fn default() -> Self {
-        Self {
-            fields: HashMap::with_capacity_and_hasher(16, fnv::FnvBuildHasher::default()),
+    let fields = [
+        (TENANT_FIELD, &origin.tenant),
+        (SOURCE_FIELD, &origin.source),
+        (IDENTITY_FIELD, &origin.identity),
+        (LABELS_FIELD, &origin.labels),
+    ];
+    for (key, value) in fields {
+        entry.remove(key);
+        if let Some(value) = value {
+            entry.put(key, JournalFieldValue::UTF8(value.clone()));
         }
     }
+    entry.remove(RECEIVED_AT_FIELD);
+    entry.put(
+        RECEIVED_AT_FIELD,
+        JournalFieldValue::UTF8(received_at.to_string()),
+    );
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum JournalReadError {
-    #[error("I/O error")]
-    IOError(std::io::Error),
-
-    #[error("Parse error")]
-    ParseError(nom::error::ErrorKind, Vec<u8>),
-}
-
-pub async fn read_journal_entries(
-    mut reader: Box<impl std::io::Read + Send>,
+/// Accepts connections on listening sockets and reads each as a stream in the
+/// export format, optionally compressed, until the consumer goes away. With
+/// TLS, entries of TCP connections are tagged with the tenant selected by the
+/// server name and the identity of the client certificate. TCP connections
+/// without an identity are refused when `identities` requires one. `origin`
+/// holds the labels of the input, `rate_limit` applies to each connection.
+/// Connections may send a compressed stream as `compression` allows, e.g. zstd
+/// frames to save bandwidth over WANs.
+#[allow(clippy::too_many_arguments)]
+pub async fn accept_journal_entries(
+    listeners: Vec<std::net::TcpListener>,
+    unix_listeners: Vec<std::os::unix::net::UnixListener>,
+    tls: Option<TlsAcceptor>,
+    identities: Arc<Identities>,
+    config: ParserConfig,
     sender: mpsc::Sender<JournalEntry>,
-) -> Result<(), JournalReadError> {
-    let mut current_entry = JournalEntry::default();
-    let mut input = Vec::with_capacity(8192);
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    origin: EntryOrigin,
+    rate_limit: RateLimitConfig,
+    compression: InputCompression,
+) -> std::io::Result<()> {
+    let mut accept_loops = Vec::new();
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let (tls, config, sender) = (tls.clone(), config.clone(), sender.clone());
+        let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+        let (identities, origin) = (identities.clone(), origin.clone());
+        let rate_limit = rate_limit.clone();
 
-    const READ_STEP: usize = 1;
-
-    loop {
-        let (elapsed, parse_result) = measure(|| parse_journal_field(&input));
-        metrics::set_last_entry_parse_time(elapsed).unwrap();
+        accept_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(addr, err).await;
+                            continue;
+                        }
+                    },
+                    _ = sender.closed() => return Ok::<_, std::io::Error>(()),
+                };
+                info!("accepted connection from {}", peer);
 
-        let to_read = match parse_result {
-            Ok((remaining, parsed)) => {
-                trace!("processed={:?}", parsed);
+                let (tls, identities) = (tls.clone(), identities.clone());
+                let (config, sender) = (config.clone(), sender.clone());
+                let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+                let mut origin = origin.clone();
+                let limiter = RateLimiter::new(&rate_limit, &format!("connection from {}", peer));
+                tokio::task::spawn(async move {
+                    let stream: Box<dyn AsyncRead + Send + Unpin> = match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => {
+                                let connection = stream.get_ref().1;
+                                origin.tenant = connection.server_name().map(String::from);
+                                origin.identity = identities
+                                    .by_certificate(connection.peer_certificates())
+                                    .map(String::from);
+                                debug!(
+                                    "connection from {} is for tenant {:?} as {:?}",
+                                    peer, origin.tenant, origin.identity
+                                );
+                                Box::new(stream)
+                            }
+                            Err(err) => {
+                                warn!("TLS handshake with {} failed: {}", peer, err);
+                                return;
+                            }
+                        },
+                        None => Box::new(stream),
+                    };
+                    if origin.identity.is_none() && identities.is_required() {
+                        warn!("refusing unauthenticated connection from {}", peer);
+                        return;
+                    }
 
-                let remaining = Vec::from(remaining);
-                input.truncate(remaining.len());
-                input.extend(&remaining);
+                    let peer = peer.to_string();
+                    read_connection(
+                        stream,
+                        &peer,
+                        config,
+                        sender,
+                        watchdog,
+                        dead_letters,
+                        origin,
+                        limiter,
+                        compression,
+                    )
+                    .await;
+                });
+            }
+        }));
+    }
 
-                current_entry.put(parsed.key, parsed.value);
+    for listener in unix_listeners {
+        listener.set_nonblocking(true)?;
+        let listener = UnixListener::from_std(listener)?;
+        let (config, sender) = (config.clone(), sender.clone());
+        let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+        let (origin, rate_limit) = (origin.clone(), rate_limit.clone());
 
-                READ_STEP
-            }
-            Err(nom::Err::Incomplete(nom::Needed::Unknown)) => READ_STEP,
-            Err(nom::Err::Incomplete(nom::Needed::Size(sz))) => sz.get(),
-            Err(nom::Err::Error(e)) => {
-                if e.code == nom::error::ErrorKind::Eof {
-                    // If we've hit an eof and have only newline in the buffer, then it's end of the journal entry
-                    if input.len() == 1 && input[0] == b'\n' {
-                        if let Err(err) = sender.send(current_entry).await {
-                            debug!("producer channel closed: {:?}", err);
-                            break;
+        accept_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            let path = addr.as_pathname().unwrap_or_else(|| Path::new("unnamed"));
+            loop {
+                let (stream, _) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(path.display(), err).await;
+                            continue;
                         }
+                    },
+                    _ = sender.closed() => return Ok::<_, std::io::Error>(()),
+                };
+                // Local peers are unnamed, the credentials say more
+                let peer = match stream.peer_cred() {
+                    Ok(cred) => format!(
+                        "{} (pid {:?}, uid {})",
+                        path.display(),
+                        cred.pid(),
+                        cred.uid()
+                    ),
+                    Err(_) => path.display().to_string(),
+                };
+                info!("accepted connection on {}", peer);
 
-                        current_entry = JournalEntry::default();
-                        input.truncate(0);
-                    }
-
-                    READ_STEP
-                } else {
-                    return Err(JournalReadError::ParseError(e.code, e.input.to_owned()));
-                }
+                let (config, sender) = (config.clone(), sender.clone());
+                let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+                let origin = origin.clone();
+                let limiter = RateLimiter::new(&rate_limit, &format!("connection on {}", peer));
+                tokio::task::spawn(async move {
+                    read_connection(
+                        stream,
+                        &peer,
+                        config,
+                        sender,
+                        watchdog,
+                        dead_letters,
+                        origin,
+                        limiter,
+                        compression,
+                    )
+                    .await;
+                });
             }
-            Err(nom::Err::Failure(e)) => {
-                return Err(JournalReadError::ParseError(e.code, e.input.to_owned()));
-            }
-        };
+        }));
+    }
 
-        reader
-            .as_mut()
-            .take(to_read as u64)
-            .read_to_end(&mut input)
-            .map_err(JournalReadError::IOError)?;
+    for accept_loop in accept_loops {
+        accept_loop.await??;
+    }
 
-        if input.is_empty() {
-            break;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn read_connection<R: AsyncRead + Send + Unpin + 'static>(
+    stream: R,
+    peer: &str,
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    origin: EntryOrigin,
+    rate_limit: Option<RateLimiter>,
+    compression: InputCompression,
+) {
+    let result = match decompressing_as(stream, compression).await {
+        Ok(stream) => {
+            read_journal_entries(
+                stream,
+                config,
+                sender,
+                watchdog,
+                dead_letters,
+                origin,
+                rate_limit,
+            )
+            .await
         }
+        Err(err) => Err(JournalReadError::IOError(err)),
+    };
+
+    match result {
+        Ok(()) => debug!("connection from {} closed", peer),
+        Err(err) => error!("failed to read entries from {}: {}", peer, err),
     }
+}
 
-    Ok(())
+fn flag_invalid_keys(entry: &JournalEntry) {
+    for (key, _) in entry.iter() {
+        if !is_valid_field_key(key) {
+            warn!("entry contains invalid field key {:?}", key);
+            metrics::inc_invalid_field_keys();
+        }
+    }
 }
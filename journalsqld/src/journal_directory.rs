@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use inotify::{Inotify, WatchMask};
+use log::{debug, error, warn};
+use systemd_journal_parser::{write_journal_entry, Cursor, JournalFile, ParseOptions};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::runtime::Handle;
+
+/// Export format buffered between the reader thread and the parser
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// `state` of a journal file journald is still appending to
+const STATE_ONLINE: u8 = 1;
+
+/// Follows the `.journal` files in a journal directory as journald appends to,
+/// rotates and archives them, resuming after `cursor` if given. Entries are
+/// re-encoded in the export format, so they go through the same parsing as the
+/// `journalctl` pipe. Reading stops when the returned stream is dropped.
+pub fn spawn(
+    directory: PathBuf,
+    after_cursor: Option<&str>,
+    options: ParseOptions,
+) -> Result<DuplexStream, io::Error> {
+    let after = after_cursor
+        .map(str::parse::<Cursor>)
+        .transpose()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    // Any change in the directory triggers a rescan, so the events themselves
+    // don't matter
+    let mut inotify = Inotify::init()?;
+    inotify.watches().add(
+        &directory,
+        WatchMask::CREATE | WatchMask::MODIFY | WatchMask::MOVED_TO,
+    )?;
+
+    let (reader, mut writer) = tokio::io::duplex(PIPE_CAPACITY);
+    let handle = Handle::current();
+    let mut follower = Follower {
+        directory,
+        options,
+        after,
+        consumed: HashMap::new(),
+        buffer: Vec::new(),
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let mut events = [0; 4096];
+        loop {
+            match follower.read_new(&handle, &mut writer) {
+                Ok(true) => {}
+                Ok(false) => {
+                    debug!("Journal stream closed, stopping directory reader");
+                    return;
+                }
+                Err(err) => {
+                    error!("Reading the journal directory failed: {}", err);
+                    return;
+                }
+            }
+
+            if let Err(err) = inotify.read_events_blocking(&mut events) {
+                error!("Watching the journal directory failed: {}", err);
+                return;
+            }
+        }
+    });
+
+    Ok(reader)
+}
+
+/// Reads the entries of a single, archived journal file once, re-encoded in
+/// the export format like `spawn` does. Unreadable entries are skipped.
+pub fn export_file(path: PathBuf, options: ParseOptions) -> Result<DuplexStream, io::Error> {
+    let mut file = JournalFile::open(&path)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        .with_options(options);
+
+    let (reader, mut writer) = tokio::io::duplex(PIPE_CAPACITY);
+    let handle = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut buffer = Vec::new();
+        for entry in file.entries() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("skipping entry in {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+
+            buffer.clear();
+            if let Err(err) = write_journal_entry(&mut buffer, &entry) {
+                error!("Encoding an entry of {} failed: {}", path.display(), err);
+                return;
+            }
+            if handle.block_on(writer.write_all(&buffer)).is_err() {
+                debug!("Journal stream closed, stopping file reader");
+                return;
+            }
+        }
+    });
+
+    Ok(reader)
+}
+
+struct Follower {
+    directory: PathBuf,
+    options: ParseOptions,
+    /// Cursor to resume after, only applied to the entries present on startup
+    after: Option<Cursor>,
+    /// Entries read so far per file ID, which stays the same when journald
+    /// renames a file on archiving it
+    consumed: HashMap<[u8; 16], u64>,
+    buffer: Vec<u8>,
+}
+
+impl Follower {
+    /// Writes the entries appended since the last call, returns whether the
+    /// stream is still open
+    fn read_new(&mut self, handle: &Handle, writer: &mut DuplexStream) -> io::Result<bool> {
+        let mut files = Vec::new();
+        for dir_entry in fs::read_dir(&self.directory)? {
+            let path = dir_entry?.path();
+            // Corrupted files journald set aside end in `.journal~`
+            if path.extension() != Some(OsStr::new("journal")) {
+                continue;
+            }
+
+            match JournalFile::open(&path) {
+                Ok(file) => files.push((path, file.with_options(self.options.clone()))),
+                Err(err) => warn!("skipping journal file {}: {}", path.display(), err),
+            }
+        }
+
+        // Roughly in the order entries were written: archived files first
+        files.sort_by_key(|(_, file)| file.header().head_entry_realtime);
+
+        for (path, mut file) in files {
+            let header = file.header().clone();
+            let consumed = self.consumed.entry(header.file_id).or_insert(0);
+
+            for entry in file.entries_from(*consumed) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    // Possibly still being written, retried on the next change
+                    Err(err) if header.state == STATE_ONLINE => {
+                        debug!(
+                            "stopping at unreadable entry in {}: {}",
+                            path.display(),
+                            err
+                        );
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("skipping entry in {}: {}", path.display(), err);
+                        *consumed += 1;
+                        continue;
+                    }
+                };
+                *consumed += 1;
+
+                let already_read = match (entry.cursor(), &self.after) {
+                    (Some(Ok(cursor)), Some(after)) => is_before(&cursor, after),
+                    _ => false,
+                };
+                if already_read {
+                    continue;
+                }
+
+                self.buffer.clear();
+                write_journal_entry(&mut self.buffer, &entry)?;
+                if handle.block_on(writer.write_all(&self.buffer)).is_err() {
+                    return Ok(false);
+                }
+            }
+        }
+
+        self.after = None;
+        Ok(true)
+    }
+}
+
+/// Whether an entry was already read before `after` was saved. Sequence
+/// numbers are compared within the same sequence, timestamps otherwise.
+fn is_before(cursor: &Cursor, after: &Cursor) -> bool {
+    if cursor.seqnum_id == after.seqnum_id {
+        cursor.seqnum <= after.seqnum
+    } else {
+        cursor.realtime <= after.realtime
+    }
+}
@@ -0,0 +1,128 @@
+//! Compatibility with systemd-journal-upload configuration and state files, so
+//! journalsqld can replace it on hosts already set up for it.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::tls::{self, TlsError};
+
+const STATE_HEADER: &str = "# This is private data. Do not parse.\n";
+const CURSOR_KEY: &str = "LAST_CURSOR=";
+
+/// `[Upload]` section of journal-upload.conf
+#[derive(Debug, Default)]
+pub struct UploadConfig {
+    pub url: Option<String>,
+    pub server_key_file: Option<PathBuf>,
+    pub server_certificate_file: Option<PathBuf>,
+    pub trusted_certificate_file: Option<PathBuf>,
+}
+
+impl UploadConfig {
+    /// Loads `path` followed by drop-ins from `<path>.d/*.conf`, later files
+    /// overriding earlier ones.
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let mut config = Self::default();
+        config.apply_file(path)?;
+
+        let mut drop_in_dir = path.as_os_str().to_owned();
+        drop_in_dir.push(".d");
+
+        let mut drop_ins: Vec<PathBuf> = match std::fs::read_dir(&drop_in_dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().map(|ext| ext == "conf").unwrap_or(false))
+                .collect(),
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        drop_ins.sort();
+
+        for drop_in in drop_ins.iter() {
+            config.apply_file(drop_in)?;
+        }
+
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let mut in_upload_section = false;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                in_upload_section = line == "[Upload]";
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if !in_upload_section {
+                continue;
+            }
+
+            let value = value.trim();
+            let value = Some(value).filter(|value| !value.is_empty());
+            match key.trim() {
+                "URL" => self.url = value.map(String::from),
+                "ServerKeyFile" => self.server_key_file = value.map(PathBuf::from),
+                "ServerCertificateFile" => self.server_certificate_file = value.map(PathBuf::from),
+                "TrustedCertificateFile" => {
+                    self.trusted_certificate_file = value.map(PathBuf::from)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// TLS client configuration from the configured certificates, if any are set
+    pub fn tls_config(&self) -> Result<Option<rustls::ClientConfig>, TlsError> {
+        let client_auth = match (&self.server_certificate_file, &self.server_key_file) {
+            (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+            _ => None,
+        };
+
+        if client_auth.is_none() && self.trusted_certificate_file.is_none() {
+            return Ok(None);
+        }
+
+        tls::client_config(self.trusted_certificate_file.as_deref(), client_auth).map(Some)
+    }
+}
+
+/// Reads the last uploaded cursor from a journal-upload state file
+pub fn read_state(path: &Path) -> Result<Option<String>, std::io::Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    Ok(contents
+        .lines()
+        .find_map(|line| line.strip_prefix(CURSOR_KEY))
+        .map(String::from))
+}
+
+/// Atomically replaces the journal-upload state file with `cursor`
+pub fn write_state(path: &Path, cursor: &str) -> Result<(), std::io::Error> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    std::fs::write(
+        &temporary,
+        format!("{}{}{}\n", STATE_HEADER, CURSOR_KEY, cursor),
+    )?;
+    std::fs::rename(&temporary, path)
+}
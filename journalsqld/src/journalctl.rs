@@ -0,0 +1,56 @@
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{Child, ChildStdout, Command};
+
+/// Export format output of a `journalctl` child process, which is killed when
+/// this is dropped
+pub struct JournalctlReader {
+    _child: Child,
+    stdout: ChildStdout,
+}
+
+impl AsyncRead for JournalctlReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+/// Spawns `journalctl --follow --output=export`, resuming after `cursor` if
+/// given, reading `namespace` instead of the default one if given
+pub fn spawn(
+    after_cursor: Option<&str>,
+    namespace: Option<&str>,
+) -> Result<JournalctlReader, std::io::Error> {
+    let mut command = Command::new("journalctl");
+    command
+        .arg("--follow")
+        .arg("--output=export")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+
+    if let Some(cursor) = after_cursor {
+        command.arg(format!("--after-cursor={}", cursor));
+    }
+    if let Some(namespace) = namespace {
+        command.arg(format!("--namespace={}", namespace));
+    }
+
+    let mut child = command.spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .expect("journalctl stdout should be piped");
+
+    Ok(JournalctlReader {
+        _child: child,
+        stdout,
+    })
+}
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use systemd_journal_parser::JournalEntry;
+use tokio::sync::mpsc;
+
+use crate::config::{KafkaConfig, MessageFormat, ParserConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::journal::{prepare_entry, EntryOrigin};
+use crate::message::parse_message;
+use crate::watchdog::{Stage, Watchdog};
+
+/// Message whose entries were queued, awaiting their commit to ClickHouse
+struct PendingMessage {
+    /// Of the last entry in the message, if any
+    cursor: Option<String>,
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+/// Consumer group member reading entries from Kafka topics. Offsets are only
+/// committed through [`Self::commit_through`], so messages whose entries didn't
+/// make it to ClickHouse are consumed again after a restart.
+pub struct KafkaInput {
+    consumer: StreamConsumer,
+    format: MessageFormat,
+    pending: Mutex<VecDeque<PendingMessage>>,
+    /// Labels of the input
+    origin: EntryOrigin,
+}
+
+impl KafkaInput {
+    pub fn connect(config: &KafkaConfig) -> Result<Self, KafkaError> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group_id)
+            .set("enable.auto.commit", "false");
+        for (key, value) in &config.properties {
+            client_config.set(key, value);
+        }
+
+        let consumer: StreamConsumer = client_config.create()?;
+        let topics: Vec<&str> = config.topics.iter().map(String::as_str).collect();
+        consumer.subscribe(&topics)?;
+
+        Ok(Self {
+            consumer,
+            format: config.format,
+            pending: Mutex::new(VecDeque::new()),
+            origin: EntryOrigin::default().with_labels(&config.labels),
+        })
+    }
+
+    /// Reads messages and queues their entries until the consumer goes away.
+    /// Malformed messages are skipped after queueing the entries before the
+    /// error, as they would otherwise be consumed again forever.
+    pub async fn read_messages(
+        &self,
+        config: ParserConfig,
+        sender: mpsc::Sender<JournalEntry>,
+        watchdog: Arc<Watchdog>,
+        dead_letters: Arc<DeadLetterQueue>,
+    ) -> Result<(), KafkaError> {
+        loop {
+            let message = tokio::select! {
+                message = self.consumer.recv() => message?,
+                _ = sender.closed() => return Ok(()),
+            };
+
+            let entries = match message.payload() {
+                Some(payload) => {
+                    parse_message(payload, self.format, &config, &dead_letters, "Kafka")
+                }
+                None => vec![],
+            };
+
+            let mut cursor = None;
+            for mut entry in entries {
+                prepare_entry(&mut entry, &config, &self.origin);
+                cursor = entry.get("__CURSOR").map(String::from);
+
+                watchdog.busy(Stage::Producer);
+                if let Err(err) = sender.send(entry).await {
+                    debug!("producer channel closed: {:?}", err);
+                    return Ok(());
+                }
+                watchdog.idle(Stage::Producer);
+                watchdog.produced();
+            }
+
+            self.pending.lock().unwrap().push_back(PendingMessage {
+                cursor,
+                topic: message.topic().to_string(),
+                partition: message.partition(),
+                offset: message.offset(),
+            });
+        }
+    }
+
+    /// Commits the offsets of the messages up to the one whose last entry has
+    /// `cursor`, after it was committed to ClickHouse
+    pub fn commit_through(&self, cursor: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(position) = pending
+            .iter()
+            .rposition(|message| message.cursor.as_deref() == Some(cursor))
+        else {
+            return;
+        };
+
+        // Offsets only grow within a partition, so the last message wins
+        let mut offsets = HashMap::new();
+        for message in pending.drain(..=position) {
+            offsets.insert((message.topic, message.partition), message.offset);
+        }
+        drop(pending);
+
+        let mut list = TopicPartitionList::new();
+        for ((topic, partition), offset) in offsets {
+            // The committed offset is the next message to consume
+            if let Err(err) =
+                list.add_partition_offset(&topic, partition, Offset::Offset(offset + 1))
+            {
+                warn!(
+                    "failed to commit offset of {}/{}: {}",
+                    topic, partition, err
+                );
+            }
+        }
+        if let Err(err) = self.consumer.commit(&list, CommitMode::Async) {
+            warn!("failed to commit Kafka offsets: {}", err);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+
+/// Kubernetes pod metadata derived from container runtime journal fields
+#[derive(Debug, Default, Clone)]
+pub struct KubernetesInfo {
+    pub pod_uid: Option<String>,
+    pub namespace: Option<String>,
+    pub container: Option<String>,
+}
+
+impl KubernetesInfo {
+    pub fn from_entry(entry: &JournalEntry) -> Self {
+        // Set by the pod log input
+        if let Some(pod_uid) = entry.get("KUBERNETES_POD_UID").and_then(as_str) {
+            return Self {
+                pod_uid: Some(pod_uid.to_string()),
+                namespace: entry
+                    .get("KUBERNETES_NAMESPACE")
+                    .and_then(as_str)
+                    .map(String::from),
+                container: entry
+                    .get("KUBERNETES_CONTAINER_NAME")
+                    .and_then(as_str)
+                    .map(String::from),
+            };
+        }
+
+        let mut info = Self::default();
+
+        // Docker journald logging driver names containers managed by the kubelet
+        // as k8s_<container>_<pod>_<namespace>_<pod uid>_<attempt>
+        if let Some(name) = entry.get("CONTAINER_NAME").and_then(as_str) {
+            let parts: Vec<&str> = name.split('_').collect();
+            if let ["k8s", container, _pod, namespace, pod_uid, _attempt] = parts[..] {
+                info.container = Some(container.to_string());
+                info.namespace = Some(namespace.to_string());
+                info.pod_uid = Some(pod_uid.to_string());
+            }
+        }
+
+        if info.pod_uid.is_none() {
+            info.pod_uid = entry
+                .get("_SYSTEMD_CGROUP")
+                .and_then(as_str)
+                .and_then(pod_uid_from_cgroup);
+        }
+
+        info
+    }
+}
+
+fn as_str(value: &JournalFieldValue) -> Option<&str> {
+    match value {
+        JournalFieldValue::UTF8(value) => Some(value),
+        JournalFieldValue::Bytes(_) => None,
+    }
+}
+
+/// Extracts the pod UID from kubepods cgroup paths, as laid out by both the systemd
+/// (`kubepods-burstable-pod<uid>.slice`, dashes escaped as underscores) and the
+/// cgroupfs (`kubepods/burstable/pod<uid>`) cgroup drivers.
+fn pod_uid_from_cgroup(cgroup: &str) -> Option<String> {
+    if !cgroup.contains("kubepods") {
+        return None;
+    }
+
+    cgroup.split('/').find_map(|segment| {
+        let segment = segment.strip_suffix(".slice").unwrap_or(segment);
+        let uid = segment
+            .rsplit_once("-pod")
+            .map(|(_, uid)| uid)
+            .or_else(|| segment.strip_prefix("pod"))?;
+
+        if uid.is_empty() {
+            None
+        } else {
+            Some(uid.replace('_', "-"))
+        }
+    })
+}
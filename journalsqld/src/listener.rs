@@ -0,0 +1,179 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::time::Duration;
+
+use log::warn;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::SocketConfig;
+
+/// First file descriptor passed by systemd
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Pause before accepting again after `accept()` failed, so running out of
+/// file descriptors doesn't turn into a busy loop
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Logs a failed `accept()` and waits before the next attempt. The error only
+/// concerns the connection being accepted, e.g. one reset while queued, or is
+/// temporary like `EMFILE`, so the listener is kept.
+pub async fn accept_failed(listener: impl fmt::Display, err: io::Error) {
+    warn!("accepting a connection on {} failed: {}", listener, err);
+    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+}
+
+/// Binds a listening TCP socket. IPv6 wildcard addresses accept IPv4
+/// connections too unless dual stack is disabled.
+pub fn bind(addr: SocketAddr, config: &SocketConfig) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(!config.dual_stack)?;
+    }
+    socket.set_reuse_address(true)?;
+    if config.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(config.backlog)?;
+
+    Ok(socket.into())
+}
+
+/// Binds a listening Unix stream socket. A socket file left behind by a
+/// previous run is replaced, any other file at `path` is an error.
+pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
+/// Listening socket passed by systemd
+enum PassedSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// Sockets passed through systemd socket activation (`LISTEN_FDS`), with the
+/// names given by `FileDescriptorName=` in the socket unit
+pub struct ActivatedSockets {
+    sockets: Vec<(Option<String>, PassedSocket)>,
+}
+
+impl ActivatedSockets {
+    /// Takes the sockets passed to this process and clears the environment
+    /// variables, so child processes don't pick them up
+    pub fn from_env() -> Self {
+        let pid = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok());
+        let count = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<RawFd>().ok());
+        let names = env::var("LISTEN_FDNAMES").ok();
+
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(var);
+        }
+
+        let count = match (pid, count) {
+            (Some(pid), Some(count)) if pid == std::process::id() => count,
+            _ => return Self { sockets: vec![] },
+        };
+        let mut names = names.as_deref().map(|names| names.split(':'));
+
+        let sockets = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                let name = names.as_mut().and_then(Iterator::next).map(String::from);
+                // SAFETY: systemd hands these descriptors over to this process
+                // and nothing else in it uses them
+                let socket = unsafe { Socket::from_raw_fd(fd) };
+                // Keep them from leaking into journalctl
+                if let Err(err) = socket.set_cloexec(true) {
+                    warn!("failed to set FD_CLOEXEC on passed socket {}: {}", fd, err);
+                }
+                let socket = match socket.local_addr().map(|addr| addr.domain()) {
+                    Ok(Domain::UNIX) => PassedSocket::Unix(socket.into()),
+                    _ => PassedSocket::Tcp(socket.into()),
+                };
+                (name, socket)
+            })
+            .collect();
+
+        Self { sockets }
+    }
+
+    /// Removes and returns the TCP sockets named `name`. When systemd passed
+    /// no names at all, every socket matches.
+    pub fn take(&mut self, name: &str) -> Vec<TcpListener> {
+        self.take_with(name, |socket| match socket {
+            PassedSocket::Tcp(listener) => Ok(listener),
+            socket => Err(socket),
+        })
+    }
+
+    /// Removes and returns the Unix sockets named `name`, like [`Self::take`]
+    pub fn take_unix(&mut self, name: &str) -> Vec<UnixListener> {
+        self.take_with(name, |socket| match socket {
+            PassedSocket::Unix(listener) => Ok(listener),
+            socket => Err(socket),
+        })
+    }
+
+    fn take_with<T>(
+        &mut self,
+        name: &str,
+        convert: impl Fn(PassedSocket) -> Result<T, PassedSocket>,
+    ) -> Vec<T> {
+        let mut taken = Vec::new();
+        let mut rest = Vec::new();
+        for (socket_name, socket) in self.sockets.drain(..) {
+            let matches = socket_name
+                .as_deref()
+                .map_or(true, |socket_name| socket_name == name);
+            if !matches {
+                rest.push((socket_name, socket));
+                continue;
+            }
+
+            match convert(socket) {
+                Ok(listener) => taken.push(listener),
+                Err(socket) => rest.push((socket_name, socket)),
+            }
+        }
+        self.sockets = rest;
+
+        taken
+    }
+
+    /// Names of the sockets nothing took
+    pub fn remaining(&self) -> impl Iterator<Item = &str> {
+        self.sockets
+            .iter()
+            .map(|(name, _)| name.as_deref().unwrap_or("unknown"))
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use systemd_journal_parser::JournalEntry;
+
+use crate::row::LogRecordRow;
+
+/// Writes rows to a local database sink of the pipeline crate, such as
+/// `SqliteSink` or `DuckdbSink`, as the entries they were made from. Batched by
+/// entry count and time like `Inserter`, as those sinks write everything
+/// buffered on each flush.
+pub struct LocalDatabase<S> {
+    sink: S,
+    /// Written since the last successful flush
+    entries: u64,
+    max_entries: u64,
+    period: Duration,
+    last_flush: Instant,
+}
+
+impl<S: Sink<JournalEntry>> LocalDatabase<S> {
+    pub fn new(sink: S, max_entries: u64, period: Duration) -> Self {
+        Self {
+            sink,
+            entries: 0,
+            max_entries,
+            period,
+            last_flush: Instant::now(),
+        }
+    }
+}
+
+impl<S: Sink<JournalEntry>> Sink<LogRecordRow> for LocalDatabase<S> {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        self.entries += rows.len() as u64;
+        self.sink
+            .write_batch(rows.into_iter().map(LogRecordRow::into_entry).collect());
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if self.entries < self.max_entries && self.last_flush.elapsed() < self.period {
+                return Ok(Quantities::default());
+            }
+
+            self.last_flush = Instant::now();
+            let res = self.sink.flush().await?;
+            self.entries = 0;
+
+            Ok(res)
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        self.sink.shutdown()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.sink.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.sink.committed_cursor()
+    }
+}
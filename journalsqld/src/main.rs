@@ -1,24 +1,133 @@
-use std::os::fd::{AsFd, AsRawFd, FromRawFd};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use clickhouse::inserter::Inserter;
-use log::{debug, error, info, trace};
-use row::LogRecordRow;
+#[cfg(feature = "duckdb")]
+use journalsql_pipeline::DuckdbSink;
+#[cfg(feature = "sqlite")]
+use journalsql_pipeline::SqliteSink;
+use journalsql_pipeline::{Fanout, Quantities, Sink};
+use log::{debug, error, info, trace, warn};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
 };
-use time::OffsetDateTime;
+use systemd_journal_parser::JournalEntry;
+use tokio::io::AsyncRead;
 use tokio::sync::{broadcast, mpsc};
-use url::Url;
+use tokio_rustls::TlsAcceptor;
 
+#[cfg(feature = "grpc")]
+mod acks;
+#[cfg(feature = "amqp")]
+mod amqp;
+#[cfg(feature = "archive")]
+mod archive;
+mod auth;
+mod backfill;
+mod client;
+mod config;
+mod cursor_index;
+mod dead_letter;
+mod decompress;
+mod docker;
+mod file_output;
+mod files;
+mod fluent;
+mod gelf;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod http;
+mod import;
+mod inserter;
 mod journal;
+#[cfg(feature = "journal-directory")]
+mod journal_directory;
+mod journal_upload;
+mod journalctl;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod kubernetes;
+mod listener;
+#[cfg(any(feature = "sqlite", feature = "duckdb"))]
+mod local_db;
+mod matcher;
+#[cfg(any(feature = "kafka", feature = "amqp"))]
+mod message;
 mod metrics;
+mod migrate;
+mod network;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "parquet")]
+mod parquet_file;
+mod pods;
+mod proxy;
+mod rate_limit;
+mod relay;
+mod remote;
+mod repeat;
+mod replay;
+mod router;
 mod row;
-mod util;
+mod sampling;
+mod schema;
+#[cfg(feature = "sd-journal")]
+mod sd_journal;
+mod slo;
+mod source_cursors;
+mod spool;
+mod spool_cli;
+mod syslog;
+mod syslog_output;
+mod tail;
+mod tls;
+mod topology;
+mod transform;
+mod watchdog;
 
-use crate::journal::{read_journal_entries, JournalEntry};
+#[cfg(feature = "grpc")]
+use crate::acks::CommitAcks;
+#[cfg(feature = "amqp")]
+use crate::amqp::AmqpInput;
+#[cfg(feature = "archive")]
+use crate::archive::S3Archive;
+use crate::auth::Identities;
+use crate::client::Client;
+use crate::config::{Config, DeadLetterOutput, JournalSource, ListenAddress};
+use crate::cursor_index::CursorIndex;
+use crate::dead_letter::{DeadLetterQueue, DropReport};
+use crate::decompress::decompressing;
+use crate::file_output::FileOutput;
+use crate::inserter::Inserter;
+use crate::journal::{accept_journal_entries, read_journal_entries, EntryOrigin};
+use crate::journal_upload::UploadConfig;
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaInput;
+use crate::kubernetes::KubernetesInfo;
+use crate::listener::ActivatedSockets;
+#[cfg(any(feature = "sqlite", feature = "duckdb"))]
+use crate::local_db::LocalDatabase;
+use crate::metrics::PipelineStage;
+use crate::network::NetworkEntries;
+#[cfg(feature = "otlp")]
+use crate::otlp::OtlpExporter;
+use crate::proxy::Proxy;
+use crate::rate_limit::RateLimiter;
+use crate::relay::Relay;
+use crate::repeat::RepeatCompressor;
+use crate::replay::Replay;
+use crate::router::InserterRouter;
+use crate::row::LogRecordRow;
+use crate::sampling::Sampler;
+use crate::schema::Schema;
+use crate::slo::SloTracker;
+use crate::source_cursors::SourceCursors;
+use crate::spool::Spool;
+use crate::syslog_output::SyslogForwarder;
+use crate::transform::Transform;
+use crate::watchdog::{Stage, Watchdog};
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -46,67 +155,685 @@ fn sigint_notifier() -> Result<broadcast::Receiver<()>, Error> {
     Ok(receiver)
 }
 
-fn create_client(uri: &str) -> Result<clickhouse::Client, url::ParseError> {
-    let mut uri: Url = uri.parse()?;
-    let mut client = clickhouse::Client::default().with_compression(clickhouse::Compression::Lz4);
+/// Where the cursor of the last committed entry is recorded
+struct Checkpoints {
+    /// systemd-journal-upload state file
+    state_file: Option<PathBuf>,
+    /// Per (machine_id, boot_id), see `SourceCursors`
+    source_cursors: Option<Arc<SourceCursors>>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<Arc<KafkaInput>>,
+    #[cfg(feature = "amqp")]
+    amqp: Option<Arc<AmqpInput>>,
+}
 
-    if uri.username() != "" {
-        client = client.with_user(uri.username());
+fn save_cursor(checkpoints: &Checkpoints, sink: &dyn Sink<LogRecordRow>) {
+    // Recorded per inserter, so these don't wait for the others to commit
+    if let Some(source_cursors) = &checkpoints.source_cursors {
+        if let Err(err) = source_cursors.save() {
+            warn!("failed to save source cursors: {}", err);
+        }
     }
 
-    if let Some(password) = uri.password() {
-        client = client.with_password(password);
+    let Some(cursor) = sink.committed_cursor() else {
+        return;
+    };
+
+    if let Some(state_file) = &checkpoints.state_file {
+        if let Err(err) = journal_upload::write_state(state_file, cursor) {
+            warn!(
+                "failed to write state file {}: {}",
+                state_file.display(),
+                err
+            );
+        }
     }
 
-    client = client.with_database(
-        uri.path()
-            .strip_prefix('/')
-            .filter(|path| !path.is_empty())
-            .unwrap_or("default"),
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = &checkpoints.kafka {
+        kafka.commit_through(cursor);
+    }
+
+    #[cfg(feature = "amqp")]
+    if let Some(amqp) = &checkpoints.amqp {
+        amqp.commit_through(cursor);
+    }
+}
+
+/// Flushes due rows, abandoning the flush when the watchdog asks the consumer to
+/// restart. Abandoned rows stay buffered and are retried by the next flush.
+async fn commit(
+    sink: &mut dyn Sink<LogRecordRow>,
+    watchdog: &Watchdog,
+) -> Result<Quantities, Error> {
+    let res = tokio::select! {
+        res = sink.flush() => res?,
+        _ = watchdog.restart_requested() => {
+            warn!("insert abandoned by watchdog, retrying on next commit");
+            Quantities::default()
+        },
+    };
+
+    if sink.is_empty() {
+        watchdog.idle(Stage::Consumer);
+    }
+
+    Ok(res)
+}
+
+fn clickhouse_client(
+    config: &Config,
+    upload_config: Option<&UploadConfig>,
+) -> Result<Client, Error> {
+    let clickhouse_uri = config
+        .clickhouse
+        .uri
+        .as_deref()
+        .or_else(|| upload_config.and_then(|upload| upload.url.as_deref()))
+        .ok_or("ClickHouse URI is not configured, set CLICKHOUSE_URI")?;
+    let proxy = Proxy::from_config(config.clickhouse.proxy.as_ref().unwrap_or(&config.proxy))?;
+    let mut db = Client::from_uri(clickhouse_uri)?
+        .with_compression(config.clickhouse.compression)
+        .with_profile(config.clickhouse.profile)
+        .with_proxy(proxy);
+
+    if let Some(upload_config) = upload_config {
+        if let Some(tls_config) = upload_config.tls_config()? {
+            db = db.with_tls(tls_config);
+        }
+    }
+
+    Ok(db)
+}
+
+/// Inserters of the default table and of the route, tenant and per-machine
+/// tables
+fn inserter_router(
+    config: &Config,
+    db: &Client,
+    slo: Option<&Arc<SloTracker>>,
+    source_cursors: Option<&Arc<SourceCursors>>,
+) -> InserterRouter {
+    let new_inserter = |table: &str| {
+        let mut inserter = Inserter::new(db.clone(), table, Schema::new(config));
+        if config.cursor_index.enabled {
+            inserter = inserter
+                .with_cursor_index(CursorIndex::new(db.clone(), &config.cursor_index.table));
+        }
+        if let Some(slo) = slo {
+            inserter = inserter.with_slo(slo.clone());
+        }
+        if let Some(source_cursors) = source_cursors {
+            inserter = inserter.with_source_cursors(source_cursors.clone());
+        }
+
+        inserter
+    };
+
+    let mut logs_inserter = InserterRouter::new(
+        new_inserter(&config.clickhouse.table)
+            .with_format(config.clickhouse.format)
+            .with_max_entries(config.clickhouse.max_entries)
+            .with_period(Some(config.clickhouse.period())),
     );
 
-    let _ = uri.set_username("");
-    let _ = uri.set_password(None);
-    uri.set_path("/");
+    for route in config.routes.iter() {
+        if let Some(table) = &route.table {
+            let inserter = new_inserter(table)
+                .with_format(config.clickhouse.format)
+                .with_max_entries(config.clickhouse.max_entries)
+                .with_period(Some(config.clickhouse.period()));
+
+            logs_inserter = logs_inserter.with_matcher_route(route.matcher.clone(), inserter);
+        }
+    }
+
+    if config.input_tls.enabled {
+        for tenant in config.input_tls.tenants.iter() {
+            if !tenant.has_inserter() {
+                continue;
+            }
+
+            let table = tenant.table.as_ref().unwrap_or(&config.clickhouse.table);
+            let period = tenant.period.unwrap_or(config.clickhouse.period);
+            let inserter = new_inserter(table)
+                .with_format(tenant.format.unwrap_or(config.clickhouse.format))
+                .with_max_entries(tenant.max_entries.unwrap_or(config.clickhouse.max_entries))
+                .with_period(Some(Duration::from_secs(period)))
+                .with_priority(tenant.priority.unwrap_or_default());
+
+            logs_inserter = logs_inserter.with_tenant_route(&tenant.server_name, inserter);
+        }
+    }
+
+    for machine in config.clickhouse.machines.iter() {
+        let table = machine.table.as_ref().unwrap_or(&config.clickhouse.table);
+        let period = machine.period.unwrap_or(config.clickhouse.period);
+        let inserter = new_inserter(table)
+            .with_format(machine.format.unwrap_or(config.clickhouse.format))
+            .with_max_entries(machine.max_entries.unwrap_or(config.clickhouse.max_entries))
+            .with_period(Some(Duration::from_secs(period)))
+            .with_priority(machine.priority.unwrap_or_default());
+
+        logs_inserter = logs_inserter.with_route(&machine.machine_id, inserter);
+    }
+
+    logs_inserter
+}
+
+/// Logs and persists the drops of this run, if there were any
+fn write_drop_report(report: &DropReport, path: &Path) {
+    if report.is_empty() {
+        return;
+    }
+
+    let encoded = match serde_json::to_string_pretty(report) {
+        Ok(encoded) => encoded,
+        Err(err) => {
+            error!("failed to encode drop report: {}", err);
+            return;
+        }
+    };
+    warn!("input was dropped during this run: {}", encoded);
 
-    client = client.with_url(uri.to_string());
-    Ok(client)
+    if let Err(err) = std::fs::write(path, encoded + "\n") {
+        error!("failed to write drop report to {:?}: {}", path, err);
+    }
 }
 
 async fn entrypoint() -> Result<(), Error> {
-    let clickhouse_uri = std::env::var("CLICKHOUSE_URI").expect("CLICKHOUSE_URI envvar is not set");
-    let db = create_client(&clickhouse_uri)?;
+    let mut config = Config::load()?;
+    let mut args = std::env::args_os().skip(1).peekable();
+    while args.peek().map_or(false, |arg| arg == "--listen") {
+        args.next();
+        let addr = args
+            .next()
+            .and_then(|addr| addr.into_string().ok())
+            .ok_or("--listen requires an address, e.g. tcp://0.0.0.0:19532")?;
+        config.input.listen.push(addr.parse()?);
+    }
+
+    let upload_config = if config.journal_upload.enabled {
+        Some(UploadConfig::load(&config.journal_upload.config)?)
+    } else {
+        None
+    };
+    let client = || clickhouse_client(&config, upload_config.as_ref());
+
+    let mut ingest = None;
+    let mut replay: Option<Replay> = None;
+    match args.next() {
+        // Runs the streaming pipeline on a file instead of stdin
+        Some(command) if command == "ingest" => {
+            let (Some(path), None) = (args.next(), args.next()) else {
+                return Err("usage: journalsqld ingest FILE, - for stdin".into());
+            };
+            ingest = Some(PathBuf::from(path));
+        }
+        // Like ingest, paced by the timestamps of the captured entries
+        Some(command) if command == "replay" => {
+            let (path, options) = replay::parse_args(args)?;
+            ingest = Some(path);
+            replay = Some(options);
+        }
+        Some(command) => {
+            return match command.to_str() {
+                Some("backfill") => {
+                    backfill::run(&config, client()?, args.map(PathBuf::from).collect()).await
+                }
+                Some("import") => {
+                    import::run(&config, client()?, args.map(PathBuf::from).collect()).await
+                }
+                Some("migrate") => migrate::run(&config, args.collect(), client).await,
+                Some("spool") => spool_cli::run(&config, args.collect(), client).await,
+                Some("topology") => topology::run(&config, args.collect(), client),
+                _ => Err(format!(
+                    "unknown command {:?}, expected \"backfill\", \"import\", \"ingest\", \"migrate\", \"replay\", \"spool\" or \"topology\"",
+                    command
+                )
+                .into()),
+            };
+        }
+        None => {}
+    }
+
+    if ingest.is_some() {
+        config.clear_inputs();
+    }
+    let socket_activation = &config.socket_activation;
+    let mut activated_sockets = ActivatedSockets::from_env();
+    let mut input_listeners = activated_sockets.take(&socket_activation.input_name);
+    let mut unix_listeners = activated_sockets.take_unix(&socket_activation.input_name);
+    let mut remote_listeners = activated_sockets.take(&socket_activation.remote_name);
+    let mut http_listeners = activated_sockets.take(&socket_activation.http_name);
+    for name in activated_sockets.remaining() {
+        warn!("ignoring passed socket {:?}", name);
+    }
+    if socket_activation.inetd {
+        let stdin = std::io::stdin();
+        match socket2::SockRef::from(&stdin).peer_addr() {
+            Ok(peer) => info!("serving connection from {:?}", peer.as_socket()),
+            Err(err) => warn!("stdin is not a connected socket: {}", err),
+        }
+    }
+
+    let input_tls = config
+        .input_tls
+        .enabled
+        .then(|| tls::server_config(&config.input_tls))
+        .transpose()?
+        .map(|tls_config| TlsAcceptor::from(Arc::new(tls_config)));
+    let identities =
+        Arc::new(Identities::load(&config.authentication).context("failed to load identities")?);
+
+    // Concurrent instances would compete for the addresses
+    if !socket_activation.inetd {
+        for addr in &config.input.listen {
+            match addr {
+                ListenAddress::Tcp(tcp_addr) => input_listeners.push(
+                    listener::bind(*tcp_addr, &config.input.socket)
+                        .with_context(|| format!("failed to listen on {}", addr))?,
+                ),
+                ListenAddress::Unix(path) => unix_listeners.push(
+                    listener::bind_unix(path, config.input.unix_mode)
+                        .with_context(|| format!("failed to listen on {}", addr))?,
+                ),
+            }
+        }
+        for &addr in &config.remote.listen {
+            let listener = listener::bind(addr, &config.remote.socket)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            remote_listeners.push(listener);
+        }
+    }
+    let mut syslog_sockets = Vec::new();
+    let mut syslog_listeners = Vec::new();
+    if !socket_activation.inetd {
+        for &addr in &config.syslog.listen_udp {
+            let socket = std::net::UdpSocket::bind(addr)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            syslog_sockets.push(socket);
+        }
+        for &addr in &config.syslog.listen_tcp {
+            let listener = listener::bind(addr, &config.syslog.socket)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            syslog_listeners.push(listener);
+        }
+    }
+    let mut gelf_sockets = Vec::new();
+    let mut gelf_listeners = Vec::new();
+    if !socket_activation.inetd {
+        for &addr in &config.gelf.listen_udp {
+            let socket = std::net::UdpSocket::bind(addr)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            gelf_sockets.push(socket);
+        }
+        for &addr in &config.gelf.listen_tcp {
+            let listener = listener::bind(addr, &config.gelf.socket)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            gelf_listeners.push(listener);
+        }
+    }
+    let mut fluent_listeners = Vec::new();
+    if !socket_activation.inetd {
+        for &addr in &config.fluent.listen {
+            let listener = listener::bind(addr, &config.fluent.socket)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            fluent_listeners.push(listener);
+        }
+    }
+    #[cfg(not(feature = "grpc"))]
+    if !config.grpc.listen.is_empty() {
+        return Err("grpc.listen requires the grpc feature".into());
+    }
+    #[cfg(feature = "grpc")]
+    let mut grpc_listeners = Vec::new();
+    #[cfg(feature = "grpc")]
+    if !socket_activation.inetd {
+        for &addr in &config.grpc.listen {
+            let listener = listener::bind(addr, &config.grpc.socket)
+                .with_context(|| format!("failed to listen on {}", addr))?;
+            grpc_listeners.push(listener);
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    let kafka_input = config
+        .kafka
+        .enabled
+        .then(|| KafkaInput::connect(&config.kafka))
+        .transpose()?
+        .map(Arc::new);
+    #[cfg(not(feature = "kafka"))]
+    if config.kafka.enabled {
+        return Err("kafka.enabled requires the kafka feature".into());
+    }
+
+    #[cfg(feature = "amqp")]
+    let amqp_input = if config.amqp.enabled {
+        let amqp_input = AmqpInput::connect(&config.amqp)
+            .await
+            .with_context(|| format!("failed to consume from AMQP queue {}", config.amqp.queue))?;
+        Some(Arc::new(amqp_input))
+    } else {
+        None
+    };
+    #[cfg(not(feature = "amqp"))]
+    if config.amqp.enabled {
+        return Err("amqp.enabled requires the amqp feature".into());
+    }
 
-    let mut logs_inserter: Inserter<LogRecordRow> = db
-        .inserter("logs2")?
-        .with_max_entries(100_000)
-        .with_period(Some(Duration::from_secs(5)));
+    let state_file = upload_config
+        .as_ref()
+        .filter(|_| ingest.is_none())
+        .map(|_| config.journal_upload.state_file.clone());
+    let input: Option<Box<dyn AsyncRead + Send + Unpin>> = match (&ingest, &state_file) {
+        (Some(path), _) if path == Path::new("-") => Some(Box::new(tokio::io::stdin())),
+        (Some(path), _) => {
+            Some(Box::new(tokio::fs::File::open(path).await.with_context(
+                || format!("failed to open {}", path.display()),
+            )?))
+        }
+        (None, Some(state_file)) => {
+            let cursor = journal_upload::read_state(state_file)?;
+            let reader: Box<dyn AsyncRead + Send + Unpin> = match config.journal_upload.source {
+                JournalSource::Journalctl => Box::new(journalctl::spawn(
+                    cursor.as_deref(),
+                    config.journal_upload.namespace.as_deref(),
+                )?),
+                #[cfg(feature = "sd-journal")]
+                JournalSource::SdJournal => Box::new(
+                    sd_journal::spawn(cursor, config.journal_upload.namespace.clone()).await?,
+                ),
+                #[cfg(not(feature = "sd-journal"))]
+                JournalSource::SdJournal => {
+                    return Err(
+                        "journal_upload.source = \"sd-journal\" requires the sd-journal feature"
+                            .into(),
+                    )
+                }
+                #[cfg(feature = "journal-directory")]
+                JournalSource::Directory => Box::new(journal_directory::spawn(
+                    config.journal_upload.directory()?,
+                    cursor.as_deref(),
+                    config.parser.options(),
+                )?),
+                #[cfg(not(feature = "journal-directory"))]
+                JournalSource::Directory => return Err(
+                    "journal_upload.source = \"directory\" requires the journal-directory feature"
+                        .into(),
+                ),
+            };
+            Some(reader)
+        }
+        (None, None)
+            if !input_listeners.is_empty()
+                || !unix_listeners.is_empty()
+                || !remote_listeners.is_empty()
+                || !config.input.files.is_empty()
+                || config.syslog.is_enabled()
+                || config.gelf.is_enabled()
+                || !config.fluent.listen.is_empty()
+                || !config.grpc.listen.is_empty()
+                || config.docker.enabled
+                || config.pod_logs.enabled
+                || config.kafka.enabled
+                || config.amqp.enabled =>
+        {
+            None
+        }
+        (None, None) => Some(Box::new(tokio::io::stdin())),
+    };
+
+    let slo = config
+        .slo
+        .enabled
+        .then(|| Arc::new(SloTracker::new(&config.slo)));
+    let source_cursors = config
+        .source_cursors
+        .enabled
+        .then(|| SourceCursors::load(&config.source_cursors))
+        .transpose()
+        .with_context(|| {
+            format!(
+                "failed to load source cursors from {}",
+                config.source_cursors.path.display()
+            )
+        })?
+        .map(Arc::new);
+    let watchdog = Arc::new(Watchdog::new(config.watchdog.stall_timeout()));
+    if config.watchdog.enabled {
+        let watchdog = watchdog.clone();
+        tokio::task::spawn(async move { watchdog.run().await });
+    }
+
+    let mut sinks: Fanout<LogRecordRow> = Fanout::new()
+        .with_restart_signal(watchdog.restart_signal())
+        .with_dropped_callback(metrics::inc_sink_entries_dropped);
+    if config.clickhouse.enabled {
+        let inserter = inserter_router(
+            &config,
+            &clickhouse_client(&config, upload_config.as_ref())?,
+            slo.as_ref(),
+            source_cursors.as_ref(),
+        );
+        sinks = sinks.with_sink("clickhouse", Box::new(inserter), &config.clickhouse.queue);
+    }
+    if config.file_output.enabled {
+        let file_output =
+            FileOutput::new(&config.file_output, Schema::new(&config)).with_context(|| {
+                format!(
+                    "failed to write output files to {}",
+                    config.file_output.directory.display()
+                )
+            })?;
+        sinks = sinks.with_sink("file", Box::new(file_output), &config.file_output.queue);
+    }
+    if config.relay.enabled {
+        let relay = Relay::new(&config.relay)?;
+        sinks = sinks.with_sink("relay", Box::new(relay), &config.relay.queue);
+    }
+    if config.syslog_output.enabled {
+        let forwarder = SyslogForwarder::new(&config.syslog_output)?;
+        sinks = sinks.with_sink("syslog", Box::new(forwarder), &config.syslog_output.queue);
+    }
+    #[cfg(feature = "otlp")]
+    if config.otlp.enabled {
+        let exporter = OtlpExporter::new(&config.otlp)?;
+        sinks = sinks.with_sink("otlp", Box::new(exporter), &config.otlp.queue);
+    }
+    #[cfg(not(feature = "otlp"))]
+    if config.otlp.enabled {
+        return Err("otlp.enabled requires the otlp feature".into());
+    }
+    #[cfg(feature = "archive")]
+    if config.archive.enabled {
+        let archive = S3Archive::new(&config.archive)?;
+        sinks = sinks.with_sink("archive", Box::new(archive), &config.archive.queue);
+    }
+    #[cfg(not(feature = "archive"))]
+    if config.archive.enabled {
+        return Err("archive.enabled requires the archive feature".into());
+    }
+    #[cfg(feature = "sqlite")]
+    if config.sqlite.enabled {
+        let database = SqliteSink::open(&config.sqlite.path, config.sqlite.full_text)
+            .with_context(|| format!("failed to open {}", config.sqlite.path.display()))?;
+        let database =
+            LocalDatabase::new(database, config.sqlite.max_entries, config.sqlite.period());
+        sinks = sinks.with_sink("sqlite", Box::new(database), &config.sqlite.queue);
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if config.sqlite.enabled {
+        return Err("sqlite.enabled requires the sqlite feature".into());
+    }
+    #[cfg(feature = "duckdb")]
+    if config.duckdb.enabled {
+        let database = DuckdbSink::open(&config.duckdb.path)
+            .with_context(|| format!("failed to open {}", config.duckdb.path.display()))?;
+        let database =
+            LocalDatabase::new(database, config.duckdb.max_entries, config.duckdb.period());
+        sinks = sinks.with_sink("duckdb", Box::new(database), &config.duckdb.queue);
+    }
+    #[cfg(not(feature = "duckdb"))]
+    if config.duckdb.enabled {
+        return Err("duckdb.enabled requires the duckdb feature".into());
+    }
+    if !sinks.has_sinks() {
+        return Err("no sink is enabled".into());
+    }
+    for route in config.routes.iter() {
+        if let Some(name) = route.sinks.iter().find(|name| !sinks.has_sink(name)) {
+            return Err(format!(
+                "route {:?} sends to sink {}, which isn't enabled",
+                route.matcher.to_string(),
+                name
+            )
+            .into());
+        }
+        let matcher = route.matcher.clone();
+        sinks = sinks.with_route(move |row| matcher.matches(row), &route.sinks);
+    }
+    let mut sink: Box<dyn Sink<LogRecordRow>> = Box::new(sinks);
+
+    // Concurrent instances would compete for the addresses
+    let listen: &[_] = if socket_activation.inetd {
+        &[]
+    } else {
+        &config.http.listen
+    };
+    for &addr in listen {
+        let listener = listener::bind(addr, &config.http.socket)
+            .with_context(|| format!("failed to listen on {}", addr))?;
+        http_listeners.push(listener);
+    }
+
+    let mut dead_letters = DeadLetterQueue::open(&config.dead_letter)?;
+    if config.dead_letter.enabled && config.dead_letter.output == DeadLetterOutput::Clickhouse {
+        dead_letters = dead_letters.with_table(
+            clickhouse_client(&config, upload_config.as_ref())?,
+            &config.dead_letter.table,
+        );
+    }
+    let dead_letters = Arc::new(dead_letters);
+    for listener in http_listeners {
+        let watchdog = watchdog.clone();
+        let dead_letters = dead_letters.clone();
+        let source_cursors = source_cursors.clone();
+        let keepalive = config.http.socket.keepalive();
+        let addr = listener.local_addr()?;
+        tokio::task::spawn(async move {
+            let served = http::serve(listener, keepalive, watchdog, dead_letters, source_cursors);
+            if let Err(err) = served.await {
+                error!("HTTP server on {} failed: {}", addr, err);
+            }
+        });
+    }
+
+    // Concurrent instances would corrupt the manifest
+    let spool = (config.spool.enabled && !socket_activation.inetd)
+        .then(|| Spool::open(&config.spool))
+        .transpose()?
+        .map(|spool| Arc::new(Mutex::new(spool)));
 
     let mut sigint_ch = sigint_notifier()?;
-    let machines = 1;
+    let machines = 1 + config.clickhouse.machines.len();
     let (entry_sender, entry_receiver) =
         mpsc::channel::<JournalEntry>(4 * num_cpus::get() * machines);
 
+    let kubernetes_enabled = config.kubernetes.enabled;
+    let bytes_rendering = config.bytes_rendering.clone();
+    let transform = Transform::new(&config.transform);
+    let sampler = Sampler::new(config.sampling.percent);
+    let mut repeats = RepeatCompressor::new(&config.repeat_compression);
+    let consumer_watchdog = watchdog.clone();
+    let consumer_dead_letters = dead_letters.clone();
+    let report_dead_letters = dead_letters.clone();
+    let consumer_spool = spool.clone();
+    #[cfg(feature = "grpc")]
+    let commit_acks = Arc::new(CommitAcks::default());
+    #[cfg(feature = "grpc")]
+    let consumer_acks = commit_acks.clone();
+    let checkpoints = Checkpoints {
+        state_file,
+        source_cursors,
+        #[cfg(feature = "kafka")]
+        kafka: kafka_input.clone(),
+        #[cfg(feature = "amqp")]
+        amqp: amqp_input.clone(),
+    };
     let consumer_fut = async move {
+        let watchdog = consumer_watchdog;
+        let dead_letters = consumer_dead_letters;
         let mut receiver = entry_receiver;
+        // Flushes rows once their period elapsed, even if no more entries arrive
+        let mut commit_interval = tokio::time::interval(Duration::from_secs(1));
 
-        'the_loop: loop {
+        let result = 'the_loop: loop {
             tokio::select! {
                 _ = sigint_ch.recv() => {
-                    break 'the_loop;
+                    break 'the_loop Ok(());
+                },
+
+                _ = commit_interval.tick() => {
+                    if let Some(row) = repeats.take_expired() {
+                        sink.write_batch(vec![row]);
+                    }
+                    let res = match commit(sink.as_mut(), &watchdog).await {
+                        Ok(res) => res,
+                        Err(err) => break 'the_loop Err(err),
+                    };
+                    if res.entries > 0 {
+                        save_cursor(&checkpoints, sink.as_ref());
+                        info!("inserted={} txns={}", res.entries, res.transactions);
+                    }
+                    #[cfg(feature = "grpc")]
+                    if sink.is_empty() && repeats.is_empty() {
+                        consumer_acks.committed();
+                    }
                 },
 
                 entry = receiver.recv() => {
-                    let entry = match entry {
+                    let mut entry = match entry {
                         Some(entry) => entry,
                         None => {
                             trace!("we done");
-                            break;
+                            break Ok(());
                         },
                     };
+                    #[cfg(feature = "grpc")]
+                    if consumer_acks.reach(&entry) {
+                        if sink.is_empty() && repeats.is_empty() {
+                            consumer_acks.committed();
+                        }
+                        continue;
+                    }
+                    watchdog.consumed();
+
+                    if !sampler.keep(&entry) {
+                        metrics::inc_entries_sampled_out();
+                        continue;
+                    }
+
+                    if let Err(err) = LogRecordRow::validate(&entry) {
+                        error!("failed to produce row: {}", err);
+                        metrics::inc_log_entries_unprocessed("unknown").unwrap();
+                        dead_letters.drop_entry(Stage::Consumer, err.drop_reason(), &err.to_string(), &entry);
+                        continue;
+                    }
+
+                    let started = Instant::now();
+                    transform.apply(&mut entry);
+                    metrics::observe_stage_duration(PipelineStage::Transform, started.elapsed());
+
+                    let started = Instant::now();
+                    let kubernetes = if kubernetes_enabled {
+                        KubernetesInfo::from_entry(&entry)
+                    } else {
+                        KubernetesInfo::default()
+                    };
 
-                    let current_timestamp = OffsetDateTime::now_utc();
-                    let row = match LogRecordRow::try_from(entry) {
+                    let mut row = match LogRecordRow::from_entry(entry, &bytes_rendering) {
                         Ok(row) => row,
                         Err(err) => {
                             error!("failed to produce row: {}", err);
@@ -114,42 +841,285 @@ async fn entrypoint() -> Result<(), Error> {
                             continue;
                         }
                     };
+                    row.kubernetes = kubernetes;
+                    metrics::observe_stage_duration(PipelineStage::Convert, started.elapsed());
 
                     metrics::inc_log_entries_processed(&row.hostname).unwrap();
                     metrics::set_last_received_entry_timestamp(&row.hostname, &row.timestamp).unwrap();
-                    let ts_diff = current_timestamp - row.timestamp;
+                    let ts_diff = row.ingested_at - row.timestamp;
 
-                    // Insert
-                    logs_inserter.write(&row).await?;
-                    let res = logs_inserter.commit().await?;
+                    // Insert, unless the row is held back as a possible repeat
+                    let Some(row) = repeats.push(row) else {
+                        continue;
+                    };
+                    sink.write_batch(vec![row]);
+                    watchdog.busy(Stage::Consumer);
+                    let res = match commit(sink.as_mut(), &watchdog).await {
+                        Ok(res) => res,
+                        Err(err) => break 'the_loop Err(err),
+                    };
 
                     if res.entries > 0 {
+                        save_cursor(&checkpoints, sink.as_ref());
+
                         if ts_diff.is_positive() && ts_diff.whole_seconds() > 5 {
                             info!("inserted={} txns={} behind={}", res.entries, res.transactions, ts_diff);
                         } else {
                             info!("inserted={} txns={}", res.entries, res.transactions);
                         }
                     }
+                    #[cfg(feature = "grpc")]
+                    if sink.is_empty() && repeats.is_empty() {
+                        consumer_acks.committed();
+                    }
                 },
             }
+        };
+
+        // Entries read but not consumed yet would be lost otherwise
+        if let Some(spool) = &consumer_spool {
+            spool::spill(spool, &mut receiver);
         }
+        #[cfg(feature = "grpc")]
+        if result.is_err() {
+            consumer_acks.abandon();
+        }
+        result.map_err(|err| anyhow::anyhow!(err))?;
 
-        logs_inserter
-            .end()
-            .await
-            .context("failed to end logs inserter")
+        if let Some(row) = repeats.take() {
+            sink.write_batch(vec![row]);
+        }
+        let res = sink.shutdown().await;
+        #[cfg(feature = "grpc")]
+        {
+            if res.is_ok() {
+                consumer_acks.committed();
+            }
+            consumer_acks.abandon();
+        }
+        let res = res
+            .map_err(|err| anyhow::anyhow!(err))
+            .context("failed to shut down sink")?;
+        save_cursor(&checkpoints, sink.as_ref());
+
+        Ok(res)
     };
 
+    let parser_config = config.parser.clone();
+    let input_files = config.input.files.clone();
+    let syslog_max_message_size = config.syslog.max_message_size;
+    let gelf_max_message_size = config.gelf.max_message_size;
+    let fluent_max_message_size = config.fluent.max_message_size;
+    let docker_config = config.docker.clone();
+    let pod_logs_config = config.pod_logs.clone();
+    #[cfg(feature = "grpc")]
+    let (grpc_max_in_flight, grpc_max_message_size) =
+        (config.grpc.max_in_flight, config.grpc.max_message_size);
+    #[cfg(feature = "grpc")]
+    let grpc_origin = EntryOrigin::default().with_labels(&config.grpc.labels);
+    let input_origin = EntryOrigin::default().with_labels(&config.input.labels);
+    let input_compression = config.input.compression;
+    let (input_rate_limit, remote_rate_limit) = (
+        config.input.rate_limit.clone(),
+        config.remote.rate_limit.clone(),
+    );
+    let remote_origin = EntryOrigin::default().with_labels(&config.remote.labels);
+    let (syslog_labels, gelf_labels, fluent_labels) = (
+        config.syslog.labels.clone(),
+        config.gelf.labels.clone(),
+        config.fluent.labels.clone(),
+    );
     let producer_fut = async move {
-        let stdin = {
-            let stdin = std::io::stdin().lock();
-            let fd = stdin.as_fd();
-            unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) }
-        };
+        if let Some(spool) = &spool {
+            spool::replay(spool, &parser_config, &entry_sender)
+                .await
+                .map_err(|err| anyhow::anyhow!(err))
+                .context("failed to replay spool")?;
+        }
 
-        read_journal_entries(Box::new(stdin), entry_sender)
+        if !input_files.is_empty() {
+            tokio::task::spawn(files::read_files(
+                input_files,
+                parser_config.clone(),
+                entry_sender.clone(),
+                watchdog.clone(),
+                dead_letters.clone(),
+                input_origin.clone(),
+                input_rate_limit.clone(),
+            ));
+        }
+
+        #[cfg(feature = "kafka")]
+        if let Some(kafka_input) = kafka_input {
+            let (parser_config, sender) = (parser_config.clone(), entry_sender.clone());
+            let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+            tokio::task::spawn(async move {
+                if let Err(err) = kafka_input
+                    .read_messages(parser_config, sender, watchdog, dead_letters)
+                    .await
+                {
+                    error!("failed to consume from Kafka: {}", err);
+                }
+            });
+        }
+
+        #[cfg(feature = "amqp")]
+        if let Some(amqp_input) = amqp_input {
+            let (parser_config, sender) = (parser_config.clone(), entry_sender.clone());
+            let (watchdog, dead_letters) = (watchdog.clone(), dead_letters.clone());
+            tokio::task::spawn(async move {
+                if let Err(err) = amqp_input
+                    .read_messages(parser_config, sender, watchdog, dead_letters)
+                    .await
+                {
+                    error!("failed to consume from AMQP: {}", err);
+                }
+            });
+        }
+
+        #[cfg(feature = "grpc")]
+        if !grpc_listeners.is_empty() {
+            let served = grpc::serve(
+                grpc_listeners,
+                input_tls.clone(),
+                identities.clone(),
+                grpc_max_in_flight,
+                grpc_max_message_size,
+                parser_config.clone(),
+                entry_sender.clone(),
+                watchdog.clone(),
+                commit_acks,
+                grpc_origin,
+            );
+            tokio::task::spawn(async move {
+                if let Err(err) = served.await {
+                    error!("failed to serve gRPC: {}", err);
+                }
+            });
+        }
+
+        if !remote_listeners.is_empty() {
+            let uploads = remote::accept_uploads(
+                remote_listeners,
+                input_tls.clone(),
+                identities.clone(),
+                parser_config.clone(),
+                entry_sender.clone(),
+                watchdog.clone(),
+                dead_letters.clone(),
+                remote_origin,
+                remote_rate_limit,
+            );
+            tokio::task::spawn(async move {
+                if let Err(err) = uploads.await {
+                    error!("failed to accept uploads: {}", err);
+                }
+            });
+        }
+
+        let network_entries = NetworkEntries::new(
+            parser_config.clone(),
+            entry_sender.clone(),
+            watchdog.clone(),
+        );
+        if !syslog_sockets.is_empty() || !syslog_listeners.is_empty() {
+            let messages = syslog::accept_messages(
+                syslog_sockets,
+                syslog_listeners,
+                syslog_max_message_size,
+                network_entries.clone().with_labels(&syslog_labels),
+            );
+            tokio::task::spawn(async move {
+                if let Err(err) = messages.await {
+                    error!("failed to receive syslog messages: {}", err);
+                }
+            });
+        }
+        if !gelf_sockets.is_empty() || !gelf_listeners.is_empty() {
+            let messages = gelf::accept_messages(
+                gelf_sockets,
+                gelf_listeners,
+                gelf_max_message_size,
+                network_entries.clone().with_labels(&gelf_labels),
+            );
+            tokio::task::spawn(async move {
+                if let Err(err) = messages.await {
+                    error!("failed to receive GELF messages: {}", err);
+                }
+            });
+        }
+        if !fluent_listeners.is_empty() {
+            let connections = fluent::accept_connections(
+                fluent_listeners,
+                fluent_max_message_size,
+                network_entries.clone().with_labels(&fluent_labels),
+            );
+            tokio::task::spawn(async move {
+                if let Err(err) = connections.await {
+                    error!("failed to accept Fluent forward connections: {}", err);
+                }
+            });
+        }
+        if docker_config.enabled {
+            let entries = network_entries.clone().with_labels(&docker_config.labels);
+            let tailed = docker::tail_containers(docker_config, entries);
+            tokio::task::spawn(async move {
+                if let Err(err) = tailed.await {
+                    error!("failed to tail Docker container logs: {}", err);
+                }
+            });
+        }
+        if pod_logs_config.enabled {
+            let entries = network_entries.with_labels(&pod_logs_config.labels);
+            let tailed = pods::tail_pods(pod_logs_config, entries);
+            tokio::task::spawn(async move {
+                if let Err(err) = tailed.await {
+                    error!("failed to tail pod logs: {}", err);
+                }
+            });
+        }
+
+        match input {
+            Some(input) => {
+                // Waits for the first input, so it is done here rather than on startup
+                let input = decompressing(input).await.context("failed to read input")?;
+                let entry_sender = match replay {
+                    Some(replay) => {
+                        let (paced_sender, paced_receiver) =
+                            mpsc::channel(entry_sender.max_capacity());
+                        tokio::task::spawn(replay::pace(replay, paced_receiver, entry_sender));
+                        paced_sender
+                    }
+                    None => entry_sender,
+                };
+                read_journal_entries(
+                    input,
+                    parser_config,
+                    entry_sender,
+                    watchdog,
+                    dead_letters,
+                    input_origin,
+                    RateLimiter::new(&input_rate_limit, "input"),
+                )
+                .await
+                .context("failed to read entries")
+            }
+            None => accept_journal_entries(
+                input_listeners,
+                unix_listeners,
+                input_tls,
+                identities,
+                parser_config,
+                entry_sender,
+                watchdog,
+                dead_letters,
+                input_origin,
+                input_rate_limit,
+                input_compression,
+            )
             .await
-            .context("failed to read entries")
+            .context("failed to accept connections"),
+        }
     };
 
     let consumer = tokio::task::spawn(consumer_fut);
@@ -164,6 +1134,12 @@ async fn entrypoint() -> Result<(), Error> {
         debug!("producer err={:?}", err);
     }
 
+    report_dead_letters.close().await;
+    write_drop_report(
+        &report_dead_letters.report(),
+        &config.dead_letter.report_path,
+    );
+
     if log::log_enabled!(log::Level::Debug) {
         let metrics = prometheus::gather();
         let encoded = prometheus::TextEncoder::new().encode_to_string(&metrics)?;
@@ -0,0 +1,329 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::router::glob_match;
+use crate::row::LogRecordRow;
+
+/// Condition on the fields of a row, e.g.
+/// `_SYSTEMD_UNIT=sshd.service AND PRIORITY<=3`. Comparisons are joined by
+/// `AND` and `OR`, `AND` binding tighter:
+/// - `FIELD=PATTERN` and `FIELD!=PATTERN` match the value against a glob
+///   pattern, `*` matching any sequence of characters and `?` a single one
+/// - `FIELD<N`, `FIELD<=N`, `FIELD>N` and `FIELD>=N` compare it as a number
+///
+/// Values containing ` AND ` or ` OR `, or with surrounding whitespace, can be
+/// put in double quotes, in which a backslash escapes the next character, e.g.
+/// `MESSAGE="* AND *"`.
+///
+/// Only `!=` matches rows lacking the field or with a value that isn't a
+/// number where one is expected.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Matcher {
+    source: String,
+    /// Comparisons of which any group has to match all
+    any: Vec<Vec<Comparison>>,
+}
+
+#[derive(Clone, Debug)]
+struct Comparison {
+    field: String,
+    op: Op,
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Eq(String),
+    Ne(String),
+    Lt(f64),
+    Le(f64),
+    Gt(f64),
+    Ge(f64),
+}
+
+/// Operators, those starting with another one first
+const OPERATORS: [&str; 6] = ["!=", "<=", ">=", "=", "<", ">"];
+
+impl Matcher {
+    pub fn matches(&self, row: &LogRecordRow) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.matches(row)))
+    }
+}
+
+impl Comparison {
+    fn matches(&self, row: &LogRecordRow) -> bool {
+        let value = match self.field.as_str() {
+            "_MACHINE_ID" => Some(row.machine_id.as_str()),
+            "_BOOT_ID" => Some(row.boot_id.as_str()),
+            "_HOSTNAME" => Some(row.hostname.as_str()),
+            "_TRANSPORT" => Some(row.transport.as_str()),
+            "__CURSOR" => Some(row.cursor.as_str()),
+            field => row.field(field),
+        };
+        let number = || value.and_then(|value| value.trim().parse::<f64>().ok());
+
+        match &self.op {
+            Op::Eq(pattern) => value.map_or(false, |value| {
+                glob_match(pattern.as_bytes(), value.as_bytes())
+            }),
+            Op::Ne(pattern) => !value.map_or(false, |value| {
+                glob_match(pattern.as_bytes(), value.as_bytes())
+            }),
+            Op::Lt(limit) => number().map_or(false, |number| number < *limit),
+            Op::Le(limit) => number().map_or(false, |number| number <= *limit),
+            Op::Gt(limit) => number().map_or(false, |number| number > *limit),
+            Op::Ge(limit) => number().map_or(false, |number| number >= *limit),
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, operator) = s
+            .char_indices()
+            .find_map(|(index, _)| {
+                OPERATORS
+                    .iter()
+                    .find(|operator| s[index..].starts_with(*operator))
+                    .map(|operator| (index, *operator))
+            })
+            .ok_or_else(|| format!("{:?} lacks a comparison operator", s))?;
+
+        let field = s[..start].trim();
+        if field.is_empty() || field.contains(char::is_whitespace) {
+            return Err(format!("invalid field name in {:?}", s));
+        }
+        let value = unquote(s[start + operator.len()..].trim())
+            .ok_or_else(|| format!("invalid quoted value in {:?}", s))?;
+        let value = value.as_str();
+
+        let number = || {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("{:?} compares against {:?}, not a number", s, value))
+        };
+        let op = match operator {
+            "=" => Op::Eq(value.to_string()),
+            "!=" => Op::Ne(value.to_string()),
+            "<" => Op::Lt(number()?),
+            "<=" => Op::Le(number()?),
+            ">" => Op::Gt(number()?),
+            _ => Op::Ge(number()?),
+        };
+
+        Ok(Self {
+            field: field.to_string(),
+            op,
+        })
+    }
+}
+
+impl FromStr for Matcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let any = split_unquoted(s, " OR ")
+            .and_then(|any| {
+                any.into_iter()
+                    .map(|all| {
+                        split_unquoted(all, " AND ")?
+                            .into_iter()
+                            .map(str::parse)
+                            .collect::<Result<Vec<Comparison>, _>>()
+                    })
+                    .collect::<Result<_, _>>()
+            })
+            .map_err(|err| format!("invalid match {:?}: {}", s, err))?;
+
+        Ok(Self {
+            source: s.trim().to_string(),
+            any,
+        })
+    }
+}
+
+/// Splits `s` at each `separator` outside of double quotes
+fn split_unquoted<'a>(s: &'a str, separator: &str) -> Result<Vec<&'a str>, String> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, c) in s.char_indices() {
+        if index < start {
+            continue;
+        }
+
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if !quoted && s[index..].starts_with(separator) => {
+                parts.push(&s[start..index]);
+                start = index + separator.len();
+            }
+            _ => {}
+        }
+    }
+
+    if quoted {
+        return Err(format!("unterminated quote in {:?}", s));
+    }
+    parts.push(&s[start..]);
+
+    Ok(parts)
+}
+
+/// Value without its double quotes and escaping backslashes, `None` if the
+/// closing quote is missing or followed by more text
+fn unquote(value: &str) -> Option<String> {
+    let Some(quoted) = value.strip_prefix('"') else {
+        return Some(value.to_string());
+    };
+
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return chars.as_str().is_empty().then_some(unquoted),
+            '\\' => unquoted.push(chars.next()?),
+            c => unquoted.push(c),
+        }
+    }
+
+    None
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl TryFrom<String> for Matcher {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use systemd_journal_parser::intern;
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::kubernetes::KubernetesInfo;
+
+    fn row(fields: &[(&str, &str)]) -> LogRecordRow {
+        LogRecordRow {
+            machine_id: "machine".to_string(),
+            boot_id: "boot".to_string(),
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            hostname: "host".to_string(),
+            transport: "journal".to_string(),
+            cursor: "s=1".to_string(),
+            record: fields
+                .iter()
+                .map(|&(key, value)| (intern(key), value.to_string()))
+                .collect(),
+            ingested_at: OffsetDateTime::UNIX_EPOCH,
+            kubernetes: KubernetesInfo::default(),
+            tenant: None,
+            repeat_count: 1,
+        }
+    }
+
+    fn matches(matcher: &str, fields: &[(&str, &str)]) -> bool {
+        matcher.parse::<Matcher>().unwrap().matches(&row(fields))
+    }
+
+    #[test]
+    fn compares_fields() {
+        let fields = [("_SYSTEMD_UNIT", "sshd.service"), ("PRIORITY", "3")];
+
+        assert!(matches("_SYSTEMD_UNIT=sshd.service", &fields));
+        assert!(matches("_SYSTEMD_UNIT=ssh?.*", &fields));
+        assert!(!matches("_SYSTEMD_UNIT=cron.service", &fields));
+        assert!(matches("_SYSTEMD_UNIT!=cron.*", &fields));
+        assert!(matches("_HOSTNAME=host", &fields));
+        assert!(matches("__CURSOR=s=*", &fields));
+
+        assert!(matches("PRIORITY<=3", &fields));
+        assert!(matches("PRIORITY < 4", &fields));
+        assert!(!matches("PRIORITY>3", &fields));
+        assert!(matches("PRIORITY>=3", &fields));
+        assert!(!matches("PRIORITY<3", &fields));
+    }
+
+    #[test]
+    fn missing_fields_only_match_not_equal() {
+        assert!(!matches("MISSING=*", &[]));
+        assert!(matches("MISSING!=x", &[]));
+        assert!(!matches("MISSING<1", &[]));
+        assert!(!matches("PRIORITY<1", &[("PRIORITY", "low")]));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let matcher = "A=1 OR B=1 AND C=1";
+
+        assert!(matches(matcher, &[("A", "1")]));
+        assert!(matches(matcher, &[("B", "1"), ("C", "1")]));
+        assert!(!matches(matcher, &[("B", "1")]));
+        assert!(!matches(matcher, &[("C", "1")]));
+
+        let matcher = "A=1 AND B=1 OR C=1 AND D=1";
+        assert!(matches(matcher, &[("A", "1"), ("B", "1")]));
+        assert!(matches(matcher, &[("C", "1"), ("D", "1")]));
+        assert!(!matches(matcher, &[("A", "1"), ("D", "1")]));
+    }
+
+    #[test]
+    fn quoted_values_keep_keywords_and_spaces() {
+        let fields = [("MESSAGE", "retry OR fail AND exit"), ("A", "1")];
+
+        assert!(matches(r#"MESSAGE="retry OR fail AND exit""#, &fields));
+        assert!(matches(r#"MESSAGE="* AND *" AND A=1"#, &fields));
+        assert!(!matches(r#"MESSAGE="* AND *" AND A=2"#, &fields));
+        assert!(matches(r#"A=2 OR MESSAGE = "retry OR *""#, &fields));
+
+        assert!(matches(r#"MESSAGE=" padded ""#, &[("MESSAGE", " padded ")]));
+        assert!(!matches("MESSAGE= padded ", &[("MESSAGE", " padded ")]));
+        assert!(matches(
+            r#"MESSAGE="say \"hi\" C:\\""#,
+            &[("MESSAGE", r#"say "hi" C:\"#)]
+        ));
+        assert!(matches("MESSAGE=\"\"", &[("MESSAGE", "")]));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for matcher in [
+            "",
+            "MESSAGE",
+            "=value",
+            "TWO WORDS=value",
+            "PRIORITY<high",
+            "A=1 AND ",
+            "A=1 OR  OR B=1",
+            r#"MESSAGE="unterminated"#,
+            r#"MESSAGE="unterminated OR A=1"#,
+            r#"MESSAGE="quoted"trailing"#,
+            r#"MESSAGE="escaped quote\""#,
+        ] {
+            assert!(matcher.parse::<Matcher>().is_err(), "{:?}", matcher);
+        }
+    }
+
+    #[test]
+    fn displays_its_source() {
+        let matcher: Matcher = r#" A=1 OR MESSAGE="a b" "#.parse().unwrap();
+
+        assert_eq!(matcher.to_string(), r#"A=1 OR MESSAGE="a b""#);
+    }
+}
@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+
+use log::warn;
+use systemd_journal_parser::{parse_entries_with, parse_json_entry, JournalEntry};
+
+use crate::config::{MessageFormat, ParserConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::metrics;
+use crate::watchdog::Stage;
+
+/// Parses the entries of a broker message, `transport` names the broker in
+/// warnings. Entries before a malformed part are kept, the rest is discarded.
+pub fn parse_message(
+    payload: &[u8],
+    format: MessageFormat,
+    config: &ParserConfig,
+    dead_letters: &DeadLetterQueue,
+    transport: &str,
+) -> Vec<JournalEntry> {
+    let options = config.options();
+    match format {
+        MessageFormat::Export => {
+            // The blank line terminating the last entry is often left out
+            let payload = if payload.ends_with(b"\n\n") {
+                Cow::Borrowed(payload)
+            } else {
+                let mut terminated = payload.to_vec();
+                terminated.extend_from_slice(if payload.ends_with(b"\n") {
+                    b"\n"
+                } else {
+                    b"\n\n"
+                });
+                Cow::Owned(terminated)
+            };
+
+            let (entries, stats, rest) = parse_entries_with(&payload, &options);
+            if let Some(info) = stats.error {
+                warn!(
+                    "malformed {} message, skipping its rest: {}",
+                    transport, info
+                );
+                metrics::inc_malformed_input_discarded(rest.len() as u64);
+                dead_letters.drop_input(Stage::Producer, &info, rest.len() as u64);
+            }
+            entries
+        }
+        MessageFormat::Json => payload
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match parse_json_entry(line, &options) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!(
+                        "malformed entry in {} message, skipping it: {}",
+                        transport, err
+                    );
+                    metrics::inc_malformed_input_discarded(line.len() as u64);
+                    None
+                }
+            })
+            .collect(),
+    }
+}
@@ -3,11 +3,53 @@ use std::{num::TryFromIntError, time::Duration};
 
 use lazy_static::lazy_static;
 use prometheus::{
-    register_histogram, register_int_counter_vec, register_int_gauge_vec, Histogram, IntCounterVec,
-    IntGaugeVec,
+    exponential_buckets, register_gauge_vec, register_histogram, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge_vec, GaugeVec, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
 };
+use serde::Serialize;
 
 pub const LABEL_HOSTNAME: &str = "hostname";
+pub const LABEL_STAGE: &str = "stage";
+pub const LABEL_REASON: &str = "reason";
+pub const LABEL_RESULT: &str = "result";
+pub const LABEL_WINDOW: &str = "window";
+pub const LABEL_SINK: &str = "sink";
+
+/// Pipeline stages timed in `journal_stage_duration_seconds`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipelineStage {
+    /// Reading input, per entry, including waiting for it
+    Read,
+    /// Parsing fields, per entry
+    Parse,
+    /// Field value transforms, per entry
+    Transform,
+    /// Creating the row, per entry
+    Convert,
+    /// Encoding and inserting rows, per batch
+    Insert,
+}
+
+impl PipelineStage {
+    pub const ALL: [Self; 5] = [
+        Self::Read,
+        Self::Parse,
+        Self::Transform,
+        Self::Convert,
+        Self::Insert,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Parse => "parse",
+            Self::Transform => "transform",
+            Self::Convert => "convert",
+            Self::Insert => "insert",
+        }
+    }
+}
 
 lazy_static! {
     pub static ref LOG_ENTRIES_PROCESSED: IntCounterVec = register_int_counter_vec!(
@@ -28,6 +70,81 @@ lazy_static! {
         &[LABEL_HOSTNAME]
     )
     .unwrap();
+    pub static ref INVALID_FIELD_KEYS: IntCounter = register_int_counter!(
+        "journal_invalid_field_keys",
+        "Total number of field keys not following journald naming rules"
+    )
+    .unwrap();
+    pub static ref MALFORMED_INPUT_RECOVERIES: IntCounter = register_int_counter!(
+        "journal_malformed_input_recoveries",
+        "Total number of times parsing resumed after malformed input"
+    )
+    .unwrap();
+    pub static ref MALFORMED_INPUT_DISCARDED_BYTES: IntCounter = register_int_counter!(
+        "journal_malformed_input_discarded_bytes",
+        "Total number of bytes skipped while recovering from malformed input"
+    )
+    .unwrap();
+    pub static ref ENTRIES_SAMPLED_OUT: IntCounter = register_int_counter!(
+        "journal_entries_sampled_out",
+        "Total number of journal entries dropped by sampling"
+    )
+    .unwrap();
+    pub static ref ENTRIES_TRUNCATED: IntCounter = register_int_counter!(
+        "journal_entries_truncated",
+        "Total number of journal entries with values truncated to the field size limit"
+    )
+    .unwrap();
+    pub static ref INPUT_PAUSES: IntCounter = register_int_counter!(
+        "journal_input_pauses",
+        "Total number of times a connection or file was paused by its rate limit"
+    )
+    .unwrap();
+    pub static ref SINK_ENTRIES_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "journal_sink_entries_dropped",
+        "Total number of entries dropped for a sink as its queue was full",
+        &[LABEL_SINK]
+    )
+    .unwrap();
+    pub static ref DEAD_LETTERS: IntCounterVec = register_int_counter_vec!(
+        "journal_dead_letters",
+        "Total number of entries or input chunks dropped, by stage and reason",
+        &[LABEL_STAGE, LABEL_REASON]
+    )
+    .unwrap();
+    pub static ref SLO_ENTRIES: IntCounterVec = register_int_counter_vec!(
+        "journal_slo_entries",
+        "Total number of inserted entries, by whether they met the latency target",
+        &[LABEL_RESULT]
+    )
+    .unwrap();
+    pub static ref SLO_BURN_RATE: GaugeVec = register_gauge_vec!(
+        "journal_slo_burn_rate",
+        "Latency SLO error budget burn rate over a sliding window",
+        &[LABEL_WINDOW]
+    )
+    .unwrap();
+    pub static ref STAGE_DURATION: HistogramVec = register_histogram_vec!(
+        "journal_stage_duration_seconds",
+        "Time spent in a pipeline stage, per entry or per batch for inserts",
+        &[LABEL_STAGE],
+        // 1us to ~67s
+        exponential_buckets(1e-6, 4.0, 14).unwrap()
+    )
+    .unwrap();
+    pub static ref ENTRY_SIZE: Histogram = register_histogram!(
+        "journal_entry_size_bytes",
+        "Estimated in-memory size of parsed journal entries",
+        // 64B to 64MiB
+        exponential_buckets(64.0, 4.0, 11).unwrap()
+    )
+    .unwrap();
+    pub static ref ENTRY_FIELDS: Histogram = register_histogram!(
+        "journal_entry_fields",
+        "Number of distinct fields of parsed journal entries",
+        exponential_buckets(4.0, 2.0, 9).unwrap()
+    )
+    .unwrap();
     pub static ref LAST_ENTRY_PARSE_TIME: Histogram = register_histogram!(
         "journal_last_entry_parse_time",
         "Last journal entry parse time in microseconds"
@@ -49,6 +166,58 @@ pub fn inc_log_entries_unprocessed(hostname: &str) -> Result<(), prometheus::Err
     Ok(())
 }
 
+pub fn inc_dead_letters(stage: &str, reason: &str) -> Result<(), prometheus::Error> {
+    let metric = DEAD_LETTERS.get_metric_with_label_values(&[stage, reason])?;
+    metric.inc();
+
+    Ok(())
+}
+
+pub fn inc_entries_sampled_out() {
+    ENTRIES_SAMPLED_OUT.inc();
+}
+
+pub fn inc_entries_truncated() {
+    ENTRIES_TRUNCATED.inc();
+}
+
+pub fn inc_input_pauses() {
+    INPUT_PAUSES.inc();
+}
+
+pub fn inc_sink_entries_dropped(sink: &str, entries: u64) {
+    SINK_ENTRIES_DROPPED
+        .with_label_values(&[sink])
+        .inc_by(entries);
+}
+
+pub fn inc_slo_entries(within_target: u64, late: u64) -> Result<(), prometheus::Error> {
+    SLO_ENTRIES
+        .get_metric_with_label_values(&["within_target"])?
+        .inc_by(within_target);
+    SLO_ENTRIES
+        .get_metric_with_label_values(&["late"])?
+        .inc_by(late);
+
+    Ok(())
+}
+
+pub fn set_slo_burn_rate(window: &str, burn_rate: f64) -> Result<(), prometheus::Error> {
+    let metric = SLO_BURN_RATE.get_metric_with_label_values(&[window])?;
+    metric.set(burn_rate);
+
+    Ok(())
+}
+
+pub fn inc_invalid_field_keys() {
+    INVALID_FIELD_KEYS.inc();
+}
+
+pub fn inc_malformed_input_discarded(bytes: u64) {
+    MALFORMED_INPUT_RECOVERIES.inc();
+    MALFORMED_INPUT_DISCARDED_BYTES.inc_by(bytes);
+}
+
 pub fn set_last_received_entry_timestamp(
     hostname: &str,
     timestamp: &time::OffsetDateTime,
@@ -60,6 +229,60 @@ pub fn set_last_received_entry_timestamp(
     Ok(())
 }
 
+pub fn observe_stage_duration(stage: PipelineStage, duration: Duration) {
+    STAGE_DURATION
+        .with_label_values(&[stage.as_str()])
+        .observe(duration.as_secs_f64());
+}
+
+#[derive(Serialize)]
+pub struct StageStats {
+    stage: &'static str,
+    count: u64,
+    seconds: f64,
+    mean_seconds: f64,
+    /// Share of the time spent in all stages
+    share: f64,
+}
+
+/// Time spent per pipeline stage since startup, for `/stats`
+pub fn stage_stats() -> Vec<StageStats> {
+    let mut stats: Vec<_> = PipelineStage::ALL
+        .iter()
+        .map(|stage| {
+            let histogram = STAGE_DURATION.with_label_values(&[stage.as_str()]);
+            let count = histogram.get_sample_count();
+            let seconds = histogram.get_sample_sum();
+
+            StageStats {
+                stage: stage.as_str(),
+                count,
+                seconds,
+                mean_seconds: if count > 0 {
+                    seconds / count as f64
+                } else {
+                    0.0
+                },
+                share: 0.0,
+            }
+        })
+        .collect();
+
+    let total: f64 = stats.iter().map(|stage| stage.seconds).sum();
+    if total > 0.0 {
+        for stage in stats.iter_mut() {
+            stage.share = stage.seconds / total;
+        }
+    }
+
+    stats
+}
+
+pub fn observe_entry_size(bytes: usize, fields: usize) {
+    ENTRY_SIZE.observe(bytes as f64);
+    ENTRY_FIELDS.observe(fields as f64);
+}
+
 pub fn set_last_entry_parse_time(duration: Duration) -> Result<(), TryFromIntError> {
     let nanos = u32::try_from(duration.as_nanos())?;
     let micros = f64::from(nanos) / 1000.0;
@@ -0,0 +1,162 @@
+use std::ffi::OsString;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::client::Client;
+use crate::config::Config;
+use crate::schema::Schema;
+use crate::Error;
+
+const USAGE: &str = "usage: journalsqld migrate SOURCE [--batch-hours N] [--since UNIX_SECONDS]
+
+Copies the rows of SOURCE, a table in the legacy logs2 layout, into the
+configured table in the configured layout, in batches of N hours (default 24)
+of entry timestamps. Progress is printed after every batch; an interrupted
+migration continues from a batch start with --since.";
+
+const DEFAULT_BATCH_HOURS: u32 = 24;
+
+struct Options<'a> {
+    source: &'a str,
+    batch_hours: u32,
+    since: Option<i64>,
+}
+
+fn parse_args<'a>(args: &[&'a str]) -> Result<Options<'a>, Error> {
+    let (source, mut flags) = match args {
+        [source, flags @ ..] if !source.starts_with("--") => (*source, flags),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut options = Options {
+        source,
+        batch_hours: DEFAULT_BATCH_HOURS,
+        since: None,
+    };
+    while let [flag, value, rest @ ..] = flags {
+        match *flag {
+            "--batch-hours" => options.batch_hours = value.parse().map_err(|_| USAGE)?,
+            "--since" => options.since = Some(value.parse().map_err(|_| USAGE)?),
+            _ => return Err(USAGE.into()),
+        }
+        flags = rest;
+    }
+    if !flags.is_empty() || options.batch_hours == 0 {
+        return Err(USAGE.into());
+    }
+
+    Ok(options)
+}
+
+/// `journalsqld migrate`, reshapes data written by earlier versions into the
+/// table layout selected by the configuration with `INSERT INTO … SELECT`
+pub async fn run<F>(config: &Config, args: Vec<OsString>, client: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<Client, Error>,
+{
+    let args = args
+        .iter()
+        .map(|arg| arg.to_str().ok_or(USAGE))
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = parse_args(&args)?;
+
+    let target = &config.clickhouse.table;
+    if options.source == target.as_str() {
+        return Err(format!(
+            "source and target are both {:?}, set clickhouse.table to a new table",
+            target
+        )
+        .into());
+    }
+
+    let db = client()?;
+    let schema = Schema::new(config);
+    let since = options.since.unwrap_or(i64::MIN);
+
+    let range = query_values(
+        &db,
+        &format!(
+            "SELECT toInt64(toStartOfHour(min(`timestamp`))), toInt64(max(`timestamp`)), count() \
+             FROM {} WHERE toInt64(`timestamp`) >= {} FORMAT TabSeparated",
+            options.source, since
+        ),
+    )
+    .await?;
+    let [first, last, rows] = <[i64; 3]>::try_from(range).map_err(|_| "unexpected response")?;
+    if rows == 0 {
+        println!("{} has no rows to migrate", options.source);
+        return Ok(());
+    }
+
+    let step = i64::from(options.batch_hours) * 3600;
+    let batches = (last - first) / step + 1;
+    println!(
+        "migrating {} rows of {} into {} in {} batches of {} hours",
+        rows, options.source, target, batches, options.batch_hours
+    );
+
+    let insert = format!(
+        "INSERT INTO {}({}) SELECT {} FROM {}",
+        target,
+        schema.column_list(),
+        schema.legacy_select_list(),
+        options.source
+    );
+    for batch in 0..batches {
+        let start = first + batch * step;
+        let end = start + step;
+        let query = format!(
+            "{} WHERE `timestamp` >= toDateTime64({}, 6) AND `timestamp` < toDateTime64({}, 6)",
+            insert, start, end
+        );
+        db.execute(&query, Vec::new()).await?;
+
+        println!(
+            "[{}/{}] migrated {} .. {} (resume with --since {})",
+            batch + 1,
+            batches,
+            format_timestamp(start),
+            format_timestamp(end),
+            end
+        );
+    }
+
+    let migrated = query_values(
+        &db,
+        &format!(
+            "SELECT count() FROM {} WHERE toInt64(`timestamp`) >= {} FORMAT TabSeparated",
+            target,
+            since.max(first)
+        ),
+    )
+    .await?;
+    println!(
+        "done, {} now has {} rows from {} on, {} had {}",
+        target,
+        migrated.first().copied().unwrap_or(0),
+        format_timestamp(first),
+        options.source,
+        rows
+    );
+
+    Ok(())
+}
+
+/// Runs a query returning a single row of integers
+async fn query_values(db: &Client, query: &str) -> Result<Vec<i64>, Error> {
+    let response = db.execute(query, Vec::new()).await?;
+    let values = std::str::from_utf8(&response)?
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+
+    Ok(values)
+}
+
+fn format_timestamp(seconds: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(seconds)
+        .ok()
+        .and_then(|timestamp| timestamp.format(&Rfc3339).ok())
+        .unwrap_or_else(|| seconds.to_string())
+}
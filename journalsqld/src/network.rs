@@ -0,0 +1,168 @@
+use std::hash::Hasher;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fnv::FnvHasher;
+use log::debug;
+use systemd_journal_parser::{Cursor, JournalEntry, JournalFieldValue};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use tokio::sync::mpsc;
+
+use crate::config::{Labels, ParserConfig};
+use crate::journal::{prepare_entry, EntryOrigin};
+use crate::watchdog::{Stage, Watchdog};
+
+/// Queues entries of network protocols which carry no journal metadata, like
+/// syslog and GELF, filling in the fields the `logs` table requires from the
+/// sending host
+#[derive(Clone)]
+pub struct NetworkEntries {
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    /// Sequence number of the last entry, shared by all listeners
+    seqnum: Arc<AtomicU64>,
+    /// Labels of the input
+    origin: EntryOrigin,
+}
+
+impl NetworkEntries {
+    pub fn new(
+        config: ParserConfig,
+        sender: mpsc::Sender<JournalEntry>,
+        watchdog: Arc<Watchdog>,
+    ) -> Self {
+        Self {
+            config,
+            sender,
+            watchdog,
+            // Starting at the current time keeps sequence numbers growing
+            // across restarts
+            seqnum: Arc::new(AtomicU64::new(now())),
+            origin: EntryOrigin::default(),
+        }
+    }
+
+    /// Tags the entries with `labels`, sharing the sequence numbers
+    pub fn with_labels(mut self, labels: &Labels) -> Self {
+        self.origin = EntryOrigin::default().with_labels(labels);
+        self
+    }
+
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
+    /// Resolves once the consumer went away
+    pub async fn closed(&self) {
+        self.sender.closed().await
+    }
+
+    /// Queues `entry`, returns whether the consumer is still there.
+    ///
+    /// Like journald, `__REALTIME_TIMESTAMP` is the time of receipt. The
+    /// hostname of the entry, or the address of `peer` if it has none, becomes
+    /// `_HOSTNAME`, and a stable `_MACHINE_ID` is derived from it unless the
+    /// entry has one already, as do those of local inputs.
+    pub async fn submit(&self, mut entry: JournalEntry, transport: &str, peer: IpAddr) -> bool {
+        let realtime = now();
+        let hostname = match entry.get("_HOSTNAME") {
+            Some(hostname) => String::from(hostname),
+            None => peer.to_string(),
+        };
+        let machine_id = entry
+            .get("_MACHINE_ID")
+            .and_then(parse_id)
+            .unwrap_or_else(|| machine_id(&hostname));
+        let boot_id = entry.get("_BOOT_ID").and_then(parse_id).unwrap_or(0);
+        let cursor = Cursor {
+            seqnum_id: machine_id,
+            seqnum: self.seqnum.fetch_add(1, Ordering::Relaxed) + 1,
+            boot_id,
+            monotonic: 0,
+            realtime,
+            xor_hash: None,
+        };
+
+        let fields = [
+            ("_HOSTNAME", hostname),
+            ("_TRANSPORT", transport.to_string()),
+            ("_MACHINE_ID", format!("{:032x}", machine_id)),
+            ("_BOOT_ID", format!("{:032x}", boot_id)),
+            ("__REALTIME_TIMESTAMP", realtime.to_string()),
+            ("__CURSOR", cursor.to_string()),
+        ];
+        for (key, value) in fields {
+            entry.put(key, JournalFieldValue::UTF8(value));
+        }
+        prepare_entry(&mut entry, &self.config, &self.origin);
+
+        self.watchdog.busy(Stage::Producer);
+        if let Err(err) = self.sender.send(entry).await {
+            debug!("producer channel closed: {:?}", err);
+            return false;
+        }
+        self.watchdog.idle(Stage::Producer);
+        self.watchdog.produced();
+
+        true
+    }
+}
+
+/// Reads up to and including the next `delimiter` into `frame`, failing if
+/// that is longer than `max_size`. The last frame may lack the delimiter.
+/// Returns false at the end of the stream.
+pub async fn read_delimited<R>(
+    reader: &mut R,
+    frame: &mut Vec<u8>,
+    delimiter: u8,
+    max_size: usize,
+) -> io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let read = (&mut *reader)
+        .take(max_size as u64 + 1)
+        .read_until(delimiter, frame)
+        .await?;
+    if frame.len() > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+
+    Ok(read > 0)
+}
+
+/// Microseconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// 128-bit ID in hex, with or without dashes
+fn parse_id(id: &JournalFieldValue) -> Option<u128> {
+    let id = String::from(id).replace('-', "");
+    if id.len() != 32 {
+        return None;
+    }
+
+    u128::from_str_radix(&id, 16).ok()
+}
+
+/// Stable ID derived from the hostname, as these protocols don't carry machine
+/// IDs. FNV-1a with two offsets fills the 128 bits.
+fn machine_id(hostname: &str) -> u128 {
+    let mut high = FnvHasher::default();
+    high.write(hostname.as_bytes());
+    let mut low = FnvHasher::with_key(high.finish());
+    low.write(hostname.as_bytes());
+
+    u128::from(high.finish()) << 64 | u128::from(low.finish())
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::config::OtlpConfig;
+use crate::inserter::InsertError;
+use crate::metrics::{self, PipelineStage};
+use crate::row::LogRecordRow;
+use crate::Error;
+
+/// Fields turned into the body and severity instead of attributes
+const MAPPED_FIELDS: [&str; 2] = ["MESSAGE", "PRIORITY"];
+
+/// Buffers rows and exports them as OpenTelemetry log records in batches,
+/// bounded by entry count and time like `Inserter`
+pub struct OtlpExporter {
+    client: LogsServiceClient<Channel>,
+    headers: MetadataMap,
+    rows: Vec<LogRecordRow>,
+    max_entries: u64,
+    period: Duration,
+    last_export: Instant,
+    committed_cursor: Option<String>,
+}
+
+impl OtlpExporter {
+    /// Connects lazily, so an unreachable collector fails the first export
+    pub fn new(config: &OtlpConfig) -> Result<Self, Error> {
+        let mut endpoint = Endpoint::from_str(&config.endpoint)?.timeout(config.timeout());
+        if config.endpoint.starts_with("https://") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+        }
+
+        let mut headers = MetadataMap::new();
+        for (name, value) in &config.headers {
+            headers.insert(
+                MetadataKey::from_bytes(name.to_lowercase().as_bytes())?,
+                MetadataValue::try_from(value.as_str())?,
+            );
+        }
+
+        Ok(Self {
+            client: LogsServiceClient::new(endpoint.connect_lazy()),
+            headers,
+            rows: Vec::new(),
+            max_entries: config.max_entries,
+            period: config.period(),
+            last_export: Instant::now(),
+            committed_cursor: None,
+        })
+    }
+
+    /// Rows stay buffered until the export succeeds, so a cancelled export is
+    /// retried by the next flush
+    async fn export(&mut self) -> Result<Quantities, InsertError> {
+        self.last_export = Instant::now();
+        if self.rows.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let started = Instant::now();
+        let mut request = tonic::Request::new(export_request(&self.rows));
+        *request.metadata_mut() = self.headers.clone();
+        self.client
+            .export(request)
+            .await
+            .map_err(InsertError::ExportError)?;
+        metrics::observe_stage_duration(PipelineStage::Insert, started.elapsed());
+
+        self.committed_cursor = self.rows.last().map(|row| row.cursor.clone());
+        let rows = std::mem::take(&mut self.rows);
+
+        Ok(Quantities {
+            entries: rows.len() as u64,
+            transactions: 1,
+        })
+    }
+}
+
+impl Sink<LogRecordRow> for OtlpExporter {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        self.rows.extend(rows);
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if self.rows.len() as u64 >= self.max_entries
+                || self.last_export.elapsed() >= self.period
+            {
+                Ok(self.export().await?)
+            } else {
+                Ok(Quantities::default())
+            }
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move { Ok(self.export().await?) })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+/// Groups the records of `rows` under a resource per machine
+fn export_request(rows: &[LogRecordRow]) -> ExportLogsServiceRequest {
+    let mut machines: BTreeMap<(&str, &str), Vec<LogRecord>> = BTreeMap::new();
+    for row in rows {
+        machines
+            .entry((row.machine_id.as_str(), row.hostname.as_str()))
+            .or_default()
+            .push(log_record(row));
+    }
+
+    let resource_logs = machines
+        .into_iter()
+        .map(|((machine_id, hostname), log_records)| ResourceLogs {
+            resource: Some(Resource {
+                attributes: vec![
+                    string_attribute("host.name", hostname),
+                    string_attribute("host.id", machine_id),
+                ],
+                ..Default::default()
+            }),
+            scope_logs: vec![ScopeLogs {
+                scope: Some(InstrumentationScope {
+                    name: String::from("journalsqld"),
+                    version: String::from(env!("CARGO_PKG_VERSION")),
+                    ..Default::default()
+                }),
+                log_records,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .collect();
+
+    ExportLogsServiceRequest { resource_logs }
+}
+
+fn log_record(row: &LogRecordRow) -> LogRecord {
+    let (severity_number, severity_text) = row
+        .field("PRIORITY")
+        .and_then(|priority| priority.parse().ok())
+        .map_or((SeverityNumber::Unspecified, ""), severity);
+
+    let mut attributes = vec![
+        string_attribute("_TRANSPORT", &row.transport),
+        string_attribute("_BOOT_ID", &row.boot_id),
+    ];
+    attributes.extend(
+        row.record
+            .iter()
+            .filter(|(field, _)| !MAPPED_FIELDS.contains(&field.as_ref()))
+            .map(|(field, value)| string_attribute(field, value)),
+    );
+    if row.repeat_count > 1 {
+        attributes.push(KeyValue {
+            key: String::from("journalsqld.repeat_count"),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::IntValue(row.repeat_count.into())),
+            }),
+        });
+    }
+
+    LogRecord {
+        time_unix_nano: row.timestamp.unix_timestamp_nanos() as u64,
+        observed_time_unix_nano: row.ingested_at.unix_timestamp_nanos() as u64,
+        severity_number: severity_number as i32,
+        severity_text: severity_text.to_string(),
+        body: row.field("MESSAGE").map(|message| AnyValue {
+            value: Some(any_value::Value::StringValue(message.to_string())),
+        }),
+        attributes,
+        ..Default::default()
+    }
+}
+
+/// OpenTelemetry severity of a syslog priority, with its syslog name as text
+fn severity(priority: u8) -> (SeverityNumber, &'static str) {
+    match priority {
+        0 => (SeverityNumber::Fatal3, "emerg"),
+        1 => (SeverityNumber::Fatal2, "alert"),
+        2 => (SeverityNumber::Fatal, "crit"),
+        3 => (SeverityNumber::Error, "err"),
+        4 => (SeverityNumber::Warn, "warning"),
+        5 => (SeverityNumber::Info2, "notice"),
+        6 => (SeverityNumber::Info, "info"),
+        7 => (SeverityNumber::Debug, "debug"),
+        _ => (SeverityNumber::Unspecified, ""),
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::builder::{MapBuilder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMicrosecondArray, UInt32Array};
+use arrow_schema::ArrowError;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use time::OffsetDateTime;
+
+use crate::row::LogRecordRow;
+
+/// Parquet file written to `W`, in memory by default. Rows are encoded into
+/// zstd compressed row groups of `row_group_entries` as they come in, the file
+/// is only readable once finished. Columns follow the `logs` table, with
+/// `record` as a map.
+pub struct ParquetFile<W: Write + Send = Vec<u8>> {
+    /// Until the first row group, whose schema the writer is created with
+    output: Option<W>,
+    writer: Option<ArrowWriter<W>>,
+    /// Rows of the row group being filled
+    rows: Vec<LogRecordRow>,
+    row_group_entries: usize,
+    entries: u64,
+}
+
+impl<W: Write + Send> ParquetFile<W> {
+    pub fn new(output: W, row_group_entries: usize) -> Self {
+        Self {
+            output: Some(output),
+            writer: None,
+            rows: Vec::new(),
+            row_group_entries: row_group_entries.max(1),
+            entries: 0,
+        }
+    }
+
+    /// Rows written so far
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn write(&mut self, row: LogRecordRow) -> Result<(), ParquetError> {
+        self.rows.push(row);
+        self.entries += 1;
+        if self.rows.len() >= self.row_group_entries {
+            self.write_row_group()?;
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the remaining rows and writes the footer, returns the output
+    pub fn finish(mut self) -> Result<W, ParquetError> {
+        if !self.rows.is_empty() || self.writer.is_none() {
+            self.write_row_group()?;
+        }
+
+        self.writer
+            .take()
+            .expect("a row group was written")
+            .into_inner()
+    }
+
+    fn write_row_group(&mut self) -> Result<(), ParquetError> {
+        let batch = record_batch(&self.rows)?;
+        self.rows.clear();
+
+        let writer = match (self.writer.take(), self.output.take()) {
+            (Some(writer), _) => writer,
+            (None, Some(output)) => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .build();
+                ArrowWriter::try_new(output, batch.schema(), Some(properties))?
+            }
+            (None, None) => {
+                return Err(ParquetError::General(String::from(
+                    "creating the writer failed before",
+                )))
+            }
+        };
+        let writer = self.writer.insert(writer);
+        writer.write(&batch)?;
+        writer.flush()
+    }
+}
+
+fn record_batch(rows: &[LogRecordRow]) -> Result<RecordBatch, ArrowError> {
+    let mut record = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for row in rows {
+        for (field, value) in &row.record {
+            record.keys().append_value(field);
+            record.values().append_value(value);
+        }
+        record.append(true)?;
+    }
+
+    let strings = |column: fn(&LogRecordRow) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(rows.iter().map(column)))
+    };
+    let timestamps = |column: fn(&LogRecordRow) -> OffsetDateTime| -> ArrayRef {
+        Arc::new(
+            TimestampMicrosecondArray::from_iter_values(
+                rows.iter()
+                    .map(|row| (column(row).unix_timestamp_nanos() / 1000) as i64),
+            )
+            .with_timezone("UTC"),
+        )
+    };
+
+    // Nullable throughout, so every row group has the same schema
+    RecordBatch::try_from_iter_with_nullable([
+        ("timestamp", timestamps(|row| row.timestamp), true),
+        ("ingested_at", timestamps(|row| row.ingested_at), true),
+        ("machine_id", strings(|row| row.machine_id.as_str()), true),
+        ("boot_id", strings(|row| row.boot_id.as_str()), true),
+        ("hostname", strings(|row| row.hostname.as_str()), true),
+        ("transport", strings(|row| row.transport.as_str()), true),
+        ("cursor", strings(|row| row.cursor.as_str()), true),
+        (
+            "tenant",
+            Arc::new(
+                rows.iter()
+                    .map(|row| row.tenant.as_deref())
+                    .collect::<StringArray>(),
+            ) as ArrayRef,
+            true,
+        ),
+        (
+            "repeat_count",
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|row| row.repeat_count),
+            )) as ArrayRef,
+            true,
+        ),
+        ("record", Arc::new(record.finish()) as ArrayRef, true),
+    ])
+}
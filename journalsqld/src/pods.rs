@@ -0,0 +1,141 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use systemd_journal_parser::JournalEntry;
+
+use crate::config::PodLogsConfig;
+use crate::metrics;
+use crate::network::NetworkEntries;
+use crate::tail::{container_entry, host_fields, Tailers};
+
+/// Tails the container logs the kubelet keeps in `path`, laid out as
+/// `<namespace>_<pod>_<pod uid>/<container>/<restart count>.log`, until the
+/// consumer goes away. Files present at startup are read from their end unless
+/// `from_beginning` is set, those of later pods and restarts from the start.
+pub async fn tail_pods(config: PodLogsConfig, entries: NetworkEntries) -> io::Result<()> {
+    let host = host_fields()?;
+    let mut tailers = Tailers::default();
+    let mut scan = tokio::time::interval(config.scan_interval());
+    let mut first_scan = true;
+    info!("tailing pod logs in {}", config.path.display());
+
+    loop {
+        tokio::select! {
+            _ = scan.tick() => {},
+            _ = entries.closed() => return Ok(()),
+        }
+
+        tailers.retain_running();
+        for (fields, path) in log_files(&config.path).await? {
+            if tailers.contains(&path) {
+                continue;
+            }
+
+            let fields = [host.clone(), fields].concat();
+            let parse = parser(path.clone(), fields, config.max_message_size);
+            tailers.spawn(
+                path,
+                first_scan && !config.from_beginning,
+                config.max_message_size,
+                "kubernetes",
+                entries.clone(),
+                parse,
+            );
+        }
+        first_scan = false;
+    }
+}
+
+/// Log files of the containers with the fields of their pod and container
+async fn log_files(path: &Path) -> io::Result<Vec<(Vec<(&'static str, String)>, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut pods = tokio::fs::read_dir(path).await?;
+    while let Some(pod) = pods.next_entry().await? {
+        // Neither namespaces nor pod names may contain underscores
+        let name = pod.file_name();
+        let Some([namespace, pod_name, pod_uid]) = name
+            .to_str()
+            .and_then(|name| name.split('_').collect::<Vec<_>>().try_into().ok())
+        else {
+            continue;
+        };
+
+        let Ok(mut containers) = tokio::fs::read_dir(pod.path()).await else {
+            continue;
+        };
+        while let Some(container) = containers.next_entry().await? {
+            let Some(container_name) = container.file_name().to_str().map(String::from) else {
+                continue;
+            };
+            let Ok(mut logs) = tokio::fs::read_dir(container.path()).await else {
+                continue;
+            };
+            while let Some(log) = logs.next_entry().await? {
+                // Rotated logs have a timestamp appended, or are compressed
+                if log
+                    .path()
+                    .extension()
+                    .map_or(true, |extension| extension != "log")
+                {
+                    continue;
+                }
+
+                let fields = vec![
+                    ("KUBERNETES_NAMESPACE", namespace.to_string()),
+                    ("KUBERNETES_POD_NAME", pod_name.to_string()),
+                    ("KUBERNETES_POD_UID", pod_uid.to_string()),
+                    ("KUBERNETES_CONTAINER_NAME", container_name.clone()),
+                    ("SYSLOG_IDENTIFIER", container_name.clone()),
+                ];
+                files.push((fields, log.path()));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Translates lines of the CRI format, `<time> <stream> <tag> <message>`, into
+/// entries, joining partial lines (tag `P`) up to the next full one (`F`).
+/// Malformed lines are skipped.
+fn parser(
+    path: PathBuf,
+    fields: Vec<(&'static str, String)>,
+    max_message_size: usize,
+) -> impl FnMut(&[u8]) -> Option<JournalEntry> + Send + 'static {
+    let mut message = String::new();
+    move |line| {
+        let line = String::from_utf8_lossy(line);
+        let Some((time, stream, partial, text)) = parse_cri_line(&line) else {
+            warn!("malformed line in {}, skipping it", path.display());
+            metrics::inc_malformed_input_discarded(line.len() as u64);
+            return None;
+        };
+
+        message.push_str(text);
+        if partial && message.len() < max_message_size {
+            return None;
+        }
+
+        let entry = container_entry(&fields, stream, time, &message);
+        message.clear();
+        Some(entry)
+    }
+}
+
+/// Time, stream, whether the line is partial and the message. Tags after the
+/// first, separated by `:`, are reserved and ignored.
+fn parse_cri_line(line: &str) -> Option<(&str, &str, bool, &str)> {
+    let mut parts = line.splitn(4, ' ');
+    let time = parts.next()?;
+    let stream = parts.next()?;
+    let partial = match parts.next()?.split(':').next()? {
+        "P" => true,
+        "F" => false,
+        _ => return None,
+    };
+    let text = parts.next().unwrap_or_default();
+
+    Some((time, stream, partial, text))
+}
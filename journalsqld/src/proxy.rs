@@ -0,0 +1,314 @@
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use base64::engine::general_purpose::STANDARD as b64;
+use base64::Engine;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+use crate::config::ProxyConfig;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Upper bound of the CONNECT response head
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_USER_PASSWORD: u8 = 2;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("Invalid proxy URL: {0}")]
+    InvalidUrl(url::ParseError),
+
+    #[error("Unsupported proxy scheme {0:?}, expected http, socks5 or socks5h")]
+    UnsupportedScheme(String),
+
+    #[error("Proxy URL has no host")]
+    MissingHost,
+
+    #[error("Proxy handshake failed: {0}")]
+    Handshake(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scheme {
+    /// Tunnels through `CONNECT`
+    Http,
+    /// Target names are resolved locally
+    Socks5,
+    /// Target names are resolved by the proxy
+    Socks5h,
+}
+
+/// Outbound proxy connections are tunneled through, unless the target host is
+/// on the no-proxy list
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    scheme: Scheme,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    no_proxy: Vec<String>,
+}
+
+impl Proxy {
+    /// `None` when no proxy URL is configured
+    pub fn from_config(config: &ProxyConfig) -> Result<Option<Self>, ProxyError> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+        let url = Url::parse(url).map_err(ProxyError::InvalidUrl)?;
+
+        let (scheme, default_port) = match url.scheme() {
+            "http" => (Scheme::Http, 80),
+            "socks5" => (Scheme::Socks5, 1080),
+            "socks5h" => (Scheme::Socks5h, 1080),
+            other => return Err(ProxyError::UnsupportedScheme(other.to_string())),
+        };
+        let host = url.host_str().ok_or(ProxyError::MissingHost)?;
+        let credentials = Some(url.username())
+            .filter(|user| !user.is_empty())
+            .map(|user| (user.to_string(), url.password().unwrap_or("").to_string()));
+
+        Ok(Some(Self {
+            scheme,
+            // IPv6 hosts are bracketed
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port: url.port().unwrap_or(default_port),
+            credentials,
+            no_proxy: config
+                .no_proxy
+                .iter()
+                .map(|host| host.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+        }))
+    }
+
+    /// Whether `host` is connected to directly. Entries match the host itself
+    /// and its subdomains, `*` matches all hosts.
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+
+        self.no_proxy.iter().any(|entry| {
+            entry == "*"
+                || host == *entry
+                || host
+                    .strip_suffix(entry.as_str())
+                    .map_or(false, |prefix| prefix.ends_with('.'))
+        })
+    }
+
+    async fn connect(
+        &self,
+        http: &mut HttpConnector,
+        host: &str,
+        port: u16,
+    ) -> Result<TcpStream, BoxError> {
+        let proxy_uri: Uri =
+            format!("http://{}:{}", authority_host(&self.host), self.port).parse()?;
+        let mut stream = http.call(proxy_uri).await?;
+
+        match self.scheme {
+            Scheme::Http => self.http_connect(&mut stream, host, port).await?,
+            Scheme::Socks5 | Scheme::Socks5h => {
+                self.socks5_connect(&mut stream, host, port).await?
+            }
+        }
+
+        Ok(stream)
+    }
+
+    async fn http_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), BoxError> {
+        let authority = format!("{}:{}", authority_host(host), port);
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((user, password)) = &self.credentials {
+            let token = b64.encode(format!("{}:{}", user, password));
+            request += &format!("Proxy-Authorization: Basic {}\r\n", token);
+        }
+        request += "\r\n";
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read the response head byte by byte, the tunnel starts right after it
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_RESPONSE_HEAD {
+                return Err(ProxyError::Handshake("response head too large".into()).into());
+            }
+            head.push(stream.read_u8().await?);
+        }
+
+        let status_line = String::from_utf8_lossy(&head);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(ProxyError::Handshake(format!("CONNECT refused: {}", status_line)).into()),
+        }
+    }
+
+    async fn socks5_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), BoxError> {
+        let method = if self.credentials.is_some() {
+            SOCKS_USER_PASSWORD
+        } else {
+            SOCKS_NO_AUTH
+        };
+        stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [SOCKS_VERSION, method] {
+            return Err(ProxyError::Handshake("no acceptable SOCKS auth method".into()).into());
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            let mut auth = vec![1];
+            for value in [user, password] {
+                let len = u8::try_from(value.len())
+                    .map_err(|_| ProxyError::Handshake("SOCKS credentials too long".into()))?;
+                auth.push(len);
+                auth.extend_from_slice(value.as_bytes());
+            }
+            stream.write_all(&auth).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(ProxyError::Handshake("SOCKS authentication failed".into()).into());
+            }
+        }
+
+        let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+        let address = match host.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) if self.scheme == Scheme::Socks5 => {
+                let mut addresses = tokio::net::lookup_host((host, port)).await?;
+                let address = addresses
+                    .next()
+                    .ok_or_else(|| ProxyError::Handshake(format!("{} does not resolve", host)))?;
+                Some(address.ip())
+            }
+            Err(_) => None,
+        };
+        match address {
+            Some(IpAddr::V4(address)) => {
+                request.push(SOCKS_IPV4);
+                request.extend_from_slice(&address.octets());
+            }
+            Some(IpAddr::V6(address)) => {
+                request.push(SOCKS_IPV6);
+                request.extend_from_slice(&address.octets());
+            }
+            None => {
+                let len = u8::try_from(host.len())
+                    .map_err(|_| ProxyError::Handshake("host name too long".into()))?;
+                request.push(SOCKS_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(
+                ProxyError::Handshake(format!("SOCKS connect failed ({})", reply[1])).into(),
+            );
+        }
+
+        // Skip the bound address and port
+        let address_len = match reply[3] {
+            SOCKS_IPV4 => 4,
+            SOCKS_IPV6 => 16,
+            SOCKS_DOMAIN => usize::from(stream.read_u8().await?),
+            other => {
+                return Err(ProxyError::Handshake(format!("SOCKS address type {}", other)).into())
+            }
+        };
+        let mut bound = vec![0; address_len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(())
+    }
+}
+
+/// Connector making direct connections or, with a proxy, tunneled ones. TLS is
+/// layered on top by the HTTPS connector.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    http: HttpConnector,
+    proxy: Option<Proxy>,
+}
+
+impl ProxyConnector {
+    pub fn new(proxy: Option<Proxy>) -> Self {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+
+        Self { http, proxy }
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut http = self.http.clone();
+        let proxy = self.proxy.clone();
+
+        Box::pin(async move {
+            let host = uri.host().ok_or("URI has no host")?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+
+            match proxy.filter(|proxy| !proxy.bypasses(host)) {
+                Some(proxy) => {
+                    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                        Some("https") => 443,
+                        _ => 80,
+                    });
+                    proxy.connect(&mut http, host, port).await
+                }
+                None => Ok(http.call(uri).await?),
+            }
+        })
+    }
+}
+
+/// Host as used in an authority, IPv6 addresses in brackets
+fn authority_host(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::info;
+use tokio::time::Instant;
+
+use crate::config::RateLimitConfig;
+use crate::metrics;
+
+/// Token bucket refilled at `rate` per second, holding up to `capacity`
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, burst: f64) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+
+        let rate = rate as f64;
+        let capacity = rate * burst.max(0.0);
+        Some(Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Takes `amount` tokens, returns how long to wait until they were
+    /// refilled if there weren't enough. Taking more than the capacity is
+    /// allowed, it just takes longer to pay back.
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Limits the entries and bytes a single connection or file is read at. Once
+/// over its limit, the reader pauses instead of dropping entries, so the
+/// sender is slowed down by backpressure and can't starve the other inputs.
+/// Clones share the limit, e.g. across the requests of a connection.
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: Arc<str>,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    entries: Option<Bucket>,
+    bytes: Option<Bucket>,
+    paused: bool,
+}
+
+impl RateLimiter {
+    /// `None` when `config` sets no limit
+    pub fn new(config: &RateLimitConfig, name: &str) -> Option<Self> {
+        let entries = Bucket::new(config.entries_per_second, config.burst);
+        let bytes = Bucket::new(config.bytes_per_second, config.burst);
+        if entries.is_none() && bytes.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            name: Arc::from(name),
+            state: Arc::new(Mutex::new(State {
+                entries,
+                bytes,
+                paused: false,
+            })),
+        })
+    }
+
+    /// Accounts for an entry of `size` bytes, waiting while over the limit
+    pub async fn acquire(&self, size: usize) {
+        let wait = {
+            let mut state = self.state.lock().expect("rate limiter lock poisoned");
+            let now = Instant::now();
+            let mut wait = Duration::ZERO;
+            if let Some(entries) = &mut state.entries {
+                wait = wait.max(entries.take(1.0, now));
+            }
+            if let Some(bytes) = &mut state.bytes {
+                wait = wait.max(bytes.take(size as f64, now));
+            }
+
+            if wait.is_zero() && state.paused {
+                info!("resuming {}, back within its rate limit", self.name);
+                state.paused = false;
+            } else if !wait.is_zero() && !state.paused {
+                info!("pausing {}, over its rate limit", self.name);
+                metrics::inc_input_pauses();
+                state.paused = true;
+            }
+            wait
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
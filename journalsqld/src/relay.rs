@@ -0,0 +1,167 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use systemd_journal_parser::write_journal_entry;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{FileCompression, RelayConfig};
+use crate::inserter::InsertError;
+use crate::metrics::{self, PipelineStage};
+use crate::row::LogRecordRow;
+use crate::tls;
+use crate::Error;
+
+/// Forwards rows in the export format to the input of an upstream journalsqld,
+/// over TCP or TLS, batched by entry count and time like `Inserter`. Each batch
+/// is a zstd frame of its own when compressed, which the upstream reads as one
+/// stream. Rows count as sent once written to the connection; a batch whose
+/// send failed is sent again in full on a new connection, so the upstream may
+/// receive entries twice.
+pub struct Relay {
+    address: String,
+    tls: Option<(TlsConnector, rustls::ServerName)>,
+    compression_level: Option<i32>,
+    timeout: Duration,
+    /// `None` until connected, and again after a failed or cancelled send left
+    /// the stream in an unknown state
+    connection: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    /// Rows not sent yet, in the export format
+    buffer: Vec<u8>,
+    entries: u64,
+    last_cursor: Option<String>,
+    max_entries: u64,
+    period: Duration,
+    last_send: Instant,
+    committed_cursor: Option<String>,
+}
+
+impl Relay {
+    /// Connects lazily, so an unreachable upstream fails the first send
+    pub fn new(config: &RelayConfig) -> Result<Self, Error> {
+        let tls = config
+            .tls
+            .then(|| {
+                tls::connector(
+                    &config.address,
+                    config.server_name.as_deref(),
+                    config.ca_file.as_deref(),
+                    config.cert_file.as_deref(),
+                    config.key_file.as_deref(),
+                )
+            })
+            .transpose()?;
+
+        Ok(Self {
+            address: config.address.clone(),
+            tls,
+            compression_level: match config.compression {
+                FileCompression::None => None,
+                FileCompression::Zstd => Some(config.compression_level),
+            },
+            timeout: config.timeout(),
+            connection: None,
+            buffer: Vec::new(),
+            entries: 0,
+            last_cursor: None,
+            max_entries: config.max_entries,
+            period: config.period(),
+            last_send: Instant::now(),
+            committed_cursor: None,
+        })
+    }
+
+    async fn connect(&self) -> io::Result<Box<dyn AsyncWrite + Send + Unpin>> {
+        let stream = TcpStream::connect(self.address.as_str()).await?;
+        stream.set_nodelay(true)?;
+
+        match &self.tls {
+            Some((connector, server_name)) => Ok(Box::new(
+                connector.connect(server_name.clone(), stream).await?,
+            )),
+            None => Ok(Box::new(stream)),
+        }
+    }
+
+    /// Rows stay buffered until the send succeeds, so a cancelled send is
+    /// retried by the next flush
+    async fn send(&mut self) -> Result<Quantities, InsertError> {
+        self.last_send = Instant::now();
+        if self.buffer.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let started = Instant::now();
+        let data = match self.compression_level {
+            Some(level) => {
+                zstd::bulk::compress(&self.buffer, level).map_err(InsertError::RelayError)?
+            }
+            None => self.buffer.clone(),
+        };
+        let written = async {
+            let mut connection = match self.connection.take() {
+                Some(connection) => connection,
+                None => self.connect().await?,
+            };
+            connection.write_all(&data).await?;
+            connection.flush().await?;
+            Ok::<_, io::Error>(connection)
+        };
+        let connection = tokio::time::timeout(self.timeout, written)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+            .map_err(InsertError::RelayError)?;
+        self.connection = Some(connection);
+        metrics::observe_stage_duration(PipelineStage::Insert, started.elapsed());
+
+        self.committed_cursor = self.last_cursor.take();
+        self.buffer.clear();
+
+        Ok(Quantities {
+            entries: std::mem::take(&mut self.entries),
+            transactions: 1,
+        })
+    }
+}
+
+impl Sink<LogRecordRow> for Relay {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        for row in rows {
+            self.last_cursor = Some(row.cursor.clone());
+            write_journal_entry(&mut self.buffer, &row.into_entry())
+                .expect("writing to a Vec can't fail");
+            self.entries += 1;
+        }
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if self.entries >= self.max_entries || self.last_send.elapsed() >= self.period {
+                Ok(self.send().await?)
+            } else {
+                Ok(Quantities::default())
+            }
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let res = self.send().await?;
+            if let Some(mut connection) = self.connection.take() {
+                let _ = connection.shutdown().await;
+            }
+
+            Ok(res)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
@@ -0,0 +1,247 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, info, warn};
+use systemd_journal_parser::{JournalEntry, JournalReadError};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+
+use crate::auth::Identities;
+use crate::config::{ParserConfig, RateLimitConfig};
+use crate::dead_letter::DeadLetterQueue;
+use crate::decompress::decompressing;
+use crate::journal::{read_journal_entries, EntryOrigin};
+use crate::listener::accept_failed;
+use crate::rate_limit::RateLimiter;
+use crate::watchdog::Watchdog;
+
+/// Content type systemd-journal-upload sends the export format as
+const JOURNAL_CONTENT_TYPE: &str = "application/vnd.fdo.journal";
+
+/// Request body buffered between the connection and the parser
+const BODY_BUFFER: usize = 64 * 1024;
+
+/// Where entries of an accepted connection go
+#[derive(Clone)]
+struct Uploads {
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    identities: Arc<Identities>,
+    /// Labels of the input, and the tenant and identity of the client
+    /// certificate, set per connection
+    origin: EntryOrigin,
+    /// Shared by the requests of a connection
+    rate_limit: Option<RateLimiter>,
+}
+
+/// Serves `POST /upload` like systemd-journal-remote, so systemd-journal-upload
+/// can send entries directly. Each request body is read as a stream in the
+/// export format, optionally compressed, and answered once all of its entries
+/// are queued. With TLS, entries are tagged with the tenant selected by the
+/// server name. Entries are tagged with the identity of the bearer token or,
+/// failing that, of the client certificate; unknown tokens are refused.
+/// `origin` holds the labels of the input, `rate_limit` applies to each
+/// connection.
+#[allow(clippy::too_many_arguments)]
+pub async fn accept_uploads(
+    listeners: Vec<std::net::TcpListener>,
+    tls: Option<TlsAcceptor>,
+    identities: Arc<Identities>,
+    config: ParserConfig,
+    sender: mpsc::Sender<JournalEntry>,
+    watchdog: Arc<Watchdog>,
+    dead_letters: Arc<DeadLetterQueue>,
+    origin: EntryOrigin,
+    rate_limit: RateLimitConfig,
+) -> std::io::Result<()> {
+    let mut accept_loops = Vec::new();
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let tls = tls.clone();
+        let uploads = Uploads {
+            config: config.clone(),
+            sender: sender.clone(),
+            watchdog: watchdog.clone(),
+            dead_letters: dead_letters.clone(),
+            identities: identities.clone(),
+            origin: origin.clone(),
+            rate_limit: None,
+        };
+        let rate_limit = rate_limit.clone();
+
+        accept_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(addr, err).await;
+                            continue;
+                        }
+                    },
+                    _ = uploads.sender.closed() => return Ok::<_, std::io::Error>(()),
+                };
+                info!("accepted upload connection from {}", peer);
+
+                let (tls, mut uploads) = (tls.clone(), uploads.clone());
+                uploads.rate_limit =
+                    RateLimiter::new(&rate_limit, &format!("upload connection from {}", peer));
+                tokio::task::spawn(async move {
+                    match tls {
+                        Some(tls) => match tls.accept(stream).await {
+                            Ok(stream) => {
+                                let connection = stream.get_ref().1;
+                                uploads.origin.tenant = connection.server_name().map(String::from);
+                                uploads.origin.identity = uploads
+                                    .identities
+                                    .by_certificate(connection.peer_certificates())
+                                    .map(String::from);
+                                debug!(
+                                    "connection from {} is for tenant {:?} as {:?}",
+                                    peer, uploads.origin.tenant, uploads.origin.identity
+                                );
+                                serve_connection(stream, peer, uploads).await
+                            }
+                            Err(err) => warn!("TLS handshake with {} failed: {}", peer, err),
+                        },
+                        None => serve_connection(stream, peer, uploads).await,
+                    }
+                });
+            }
+        }));
+    }
+
+    for accept_loop in accept_loops {
+        accept_loop.await??;
+    }
+
+    Ok(())
+}
+
+async fn serve_connection<S>(stream: S, peer: SocketAddr, uploads: Uploads)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let service = service_fn(move |request| {
+        let uploads = uploads.clone();
+        async move { Ok::<_, Infallible>(handle(request, uploads).await) }
+    });
+
+    match Http::new().serve_connection(stream, service).await {
+        Ok(()) => debug!("upload connection from {} closed", peer),
+        Err(err) => warn!("upload connection from {} failed: {}", peer, err),
+    }
+}
+
+async fn handle(request: Request<Body>, mut uploads: Uploads) -> Response<Body> {
+    if request.uri().path() != "/upload" {
+        return respond(StatusCode::NOT_FOUND, "Not found.\n");
+    }
+    if request.method() != Method::POST {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, "Unsupported method.\n");
+    }
+
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    if content_type != Some(JOURNAL_CONTENT_TYPE) {
+        return respond(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type: application/vnd.fdo.journal is required.\n",
+        );
+    }
+
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = token {
+        match uploads.identities.by_token(token.trim()) {
+            Some(identity) => uploads.origin.identity = Some(identity.to_string()),
+            None => return unauthorized("Unknown token.\n"),
+        }
+    }
+    if uploads.origin.identity.is_none() && uploads.identities.is_required() {
+        return unauthorized("Authentication required.\n");
+    }
+
+    let (reader, mut writer) = tokio::io::duplex(BODY_BUFFER);
+    let mut body = request.into_body();
+    let receive = async move {
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+            writer.write_all(&chunk).await?;
+        }
+        writer.shutdown().await
+    };
+    let sender = uploads.sender.clone();
+    let read = async move {
+        let reader = decompressing(reader)
+            .await
+            .map_err(JournalReadError::IOError)?;
+        read_journal_entries(
+            reader,
+            uploads.config,
+            uploads.sender,
+            uploads.watchdog,
+            uploads.dead_letters,
+            uploads.origin,
+            uploads.rate_limit,
+        )
+        .await
+    };
+
+    let (received, read) = tokio::join!(receive, read);
+    if let Err(err) = received {
+        // The parser stopping early closes the pipe, its error explains why
+        if read.is_ok() {
+            return respond(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to read body: {}\n", err),
+            );
+        }
+    }
+
+    match read {
+        Ok(()) if sender.is_closed() => {
+            respond(StatusCode::SERVICE_UNAVAILABLE, "Shutting down.\n")
+        }
+        Ok(()) => respond(StatusCode::ACCEPTED, "OK.\n"),
+        Err(JournalReadError::ParseError(info)) => respond(
+            StatusCode::BAD_REQUEST,
+            format!("Malformed entries: {}\n", info),
+        ),
+        Err(err) => respond(
+            StatusCode::BAD_REQUEST,
+            format!("Failed to read entries: {:?}\n", err),
+        ),
+    }
+}
+
+fn unauthorized(body: &'static str) -> Response<Body> {
+    let mut response = respond(StatusCode::UNAUTHORIZED, body);
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+fn respond<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
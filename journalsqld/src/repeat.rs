@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::config::RepeatCompressionConfig;
+use crate::row::LogRecordRow;
+
+/// Collapses runs of rows with the same machine, unit and `MESSAGE` into the
+/// first row of the run, counting the repeats in `repeat_count`, like syslog's
+/// "last message repeated N times". The collapsed row carries the cursor of
+/// the last row of the run, so resuming doesn't read the run again.
+pub struct RepeatCompressor {
+    enabled: bool,
+    window: Duration,
+    pending: Option<LogRecordRow>,
+}
+
+impl RepeatCompressor {
+    pub fn new(config: &RepeatCompressionConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            window: config.window(),
+            pending: None,
+        }
+    }
+
+    /// Window runs may span, `None` when disabled
+    pub fn window(&self) -> Option<Duration> {
+        self.enabled.then_some(self.window)
+    }
+
+    /// Returns the row to insert, if any: the row itself when disabled, or the
+    /// previous run once `row` doesn't continue it
+    pub fn push(&mut self, row: LogRecordRow) -> Option<LogRecordRow> {
+        if !self.enabled {
+            return Some(row);
+        }
+
+        if let Some(pending) = &mut self.pending {
+            if repeats(pending, &row) && row.timestamp - pending.timestamp <= self.window {
+                pending.repeat_count += 1;
+                pending.cursor = row.cursor;
+                return None;
+            }
+        }
+
+        self.pending.replace(row)
+    }
+
+    /// Takes the pending run once no repeat arrived within the window, so a
+    /// quiet source isn't held back
+    pub fn take_expired(&mut self) -> Option<LogRecordRow> {
+        let pending = self.pending.as_ref()?;
+        if OffsetDateTime::now_utc() - pending.ingested_at <= self.window {
+            return None;
+        }
+
+        self.pending.take()
+    }
+
+    /// Whether no run is held back
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_none()
+    }
+
+    pub fn take(&mut self) -> Option<LogRecordRow> {
+        self.pending.take()
+    }
+}
+
+fn repeats(previous: &LogRecordRow, row: &LogRecordRow) -> bool {
+    previous.machine_id == row.machine_id
+        && previous.boot_id == row.boot_id
+        && previous.field("_SYSTEMD_UNIT") == row.field("_SYSTEMD_UNIT")
+        && previous.field("MESSAGE").is_some()
+        && previous.field("MESSAGE") == row.field("MESSAGE")
+}
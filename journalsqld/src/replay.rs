@@ -0,0 +1,123 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::Error;
+
+const USAGE: &str =
+    "usage: journalsqld replay FILE [--speed N|--as-fast-as-possible] [--shift-timestamps]
+
+Re-ingests a captured journal export stream, - for stdin, through the full
+pipeline. Entries are paced by their __REALTIME_TIMESTAMP, at N times the
+original rate with --speed, 1 by default. --shift-timestamps moves the
+timestamps of the entries as if the capture started now.";
+
+/// Fields holding a timestamp in microseconds since the epoch, moved along
+/// when timestamps are shifted
+const TIMESTAMP_FIELDS: [&str; 2] = ["__REALTIME_TIMESTAMP", "_SOURCE_REALTIME_TIMESTAMP"];
+
+/// How a capture is replayed
+#[derive(Debug, Clone, Copy)]
+pub struct Replay {
+    /// Multiple of the original rate, `None` to replay as fast as possible
+    speed: Option<f64>,
+    /// Whether the timestamps are moved as if the capture started now
+    shift_timestamps: bool,
+}
+
+/// Capture file and options of `journalsqld replay`
+pub fn parse_args(mut args: impl Iterator<Item = OsString>) -> Result<(PathBuf, Replay), Error> {
+    let mut path = None;
+    let mut replay = Replay {
+        speed: Some(1.0),
+        shift_timestamps: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--speed") => {
+                let speed = args
+                    .next()
+                    .and_then(|speed| speed.to_str()?.parse::<f64>().ok())
+                    .filter(|speed| speed.is_finite() && *speed > 0.0)
+                    .ok_or("--speed requires a positive number")?;
+                replay.speed = Some(speed);
+            }
+            Some("--as-fast-as-possible") => replay.speed = None,
+            Some("--shift-timestamps") => replay.shift_timestamps = true,
+            Some(option) if option.starts_with("--") => return Err(USAGE.into()),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    Ok((path.ok_or(USAGE)?, replay))
+}
+
+/// Forwards the parsed entries of the capture to the consumer, waiting between
+/// them as long as between their timestamps, scaled by the speed. Entries
+/// without a valid `__REALTIME_TIMESTAMP` are forwarded right away.
+pub async fn pace(
+    replay: Replay,
+    mut receiver: mpsc::Receiver<JournalEntry>,
+    sender: mpsc::Sender<JournalEntry>,
+) {
+    // Timestamp of the first entry and when it was forwarded
+    let mut start: Option<(u64, Instant)> = None;
+    let shift = replay.shift_timestamps.then(now_micros);
+
+    while let Some(mut entry) = receiver.recv().await {
+        if let Some(timestamp) = realtime(&entry) {
+            let (first, started) = *start.get_or_insert((timestamp, Instant::now()));
+
+            if let Some(speed) = replay.speed {
+                let offset = Duration::from_micros(timestamp.saturating_sub(first));
+                tokio::time::sleep_until(started + offset.div_f64(speed)).await;
+            }
+            if let Some(now) = shift {
+                shift_timestamps(&mut entry, first, now);
+            }
+        } else {
+            warn!("entry lacks a valid __REALTIME_TIMESTAMP, replaying it without delay");
+        }
+
+        if let Err(err) = sender.send(entry).await {
+            debug!("producer channel closed: {:?}", err);
+            return;
+        }
+    }
+}
+
+fn realtime(entry: &JournalEntry) -> Option<u64> {
+    String::from(entry.get("__REALTIME_TIMESTAMP")?)
+        .parse()
+        .ok()
+}
+
+/// Moves the timestamps of `entry` by the distance between `first`, the
+/// timestamp of the first entry, and `now`
+fn shift_timestamps(entry: &mut JournalEntry, first: u64, now: u64) {
+    for name in TIMESTAMP_FIELDS {
+        let Some(timestamp) = entry
+            .get(name)
+            .and_then(|timestamp| String::from(timestamp).parse::<u64>().ok())
+        else {
+            continue;
+        };
+
+        let shifted = (now + timestamp).saturating_sub(first);
+        entry.put(name, JournalFieldValue::UTF8(shifted.to_string()));
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
@@ -0,0 +1,252 @@
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+
+use crate::inserter::{InsertError, Inserter};
+use crate::matcher::Matcher;
+use crate::row::LogRecordRow;
+
+/// What a route matches rows on
+enum RouteMatch {
+    /// Glob pattern of the machine ID
+    MachineId(String),
+    Tenant(String),
+    Matcher(Matcher),
+}
+
+impl RouteMatch {
+    fn matches(&self, row: &LogRecordRow) -> bool {
+        match self {
+            Self::MachineId(pattern) => glob_match(pattern.as_bytes(), row.machine_id.as_bytes()),
+            Self::Tenant(tenant) => row.tenant.as_deref() == Some(tenant.as_str()),
+            Self::Matcher(matcher) => matcher.matches(row),
+        }
+    }
+}
+
+/// Dispatches rows to per-route, per-tenant and per-machine inserters. Routes are tried in
+/// order and the first one matching the row wins, rows matching none go to the
+/// default inserter.
+pub struct InserterRouter {
+    routes: Vec<(RouteMatch, Inserter)>,
+    default: Inserter,
+    /// Route index of the last written row, `None` for the default inserter
+    last_route: Option<usize>,
+}
+
+impl InserterRouter {
+    pub fn new(default: Inserter) -> Self {
+        Self {
+            routes: Vec::new(),
+            default,
+            last_route: None,
+        }
+    }
+
+    pub fn with_route(mut self, machine_id_pattern: &str, inserter: Inserter) -> Self {
+        self.routes.push((
+            RouteMatch::MachineId(machine_id_pattern.to_string()),
+            inserter,
+        ));
+        self
+    }
+
+    pub fn with_tenant_route(mut self, tenant: &str, inserter: Inserter) -> Self {
+        self.routes
+            .push((RouteMatch::Tenant(tenant.to_string()), inserter));
+        self
+    }
+
+    pub fn with_matcher_route(mut self, matcher: Matcher, inserter: Inserter) -> Self {
+        self.routes.push((RouteMatch::Matcher(matcher), inserter));
+        self
+    }
+
+    /// Cursor of the most recent row, once every inserter has committed its rows.
+    /// Inserters flush independently, so an earlier cursor can't be trusted while
+    /// another inserter still buffers older rows.
+    pub fn committed_cursor(&self) -> Option<&str> {
+        if !self.is_empty() {
+            return None;
+        }
+
+        match self.last_route {
+            Some(index) => self.routes[index].1.committed_cursor(),
+            None => self.default.committed_cursor(),
+        }
+    }
+
+    /// Whether no inserter has buffered rows waiting to be inserted
+    pub fn is_empty(&self) -> bool {
+        self.inserters().all(Inserter::is_empty)
+    }
+
+    pub fn write(&mut self, row: LogRecordRow) {
+        self.last_route = self
+            .routes
+            .iter()
+            .position(|(route, _)| route.matches(&row));
+
+        match self.last_route {
+            Some(index) => self.routes[index].1.write(row),
+            None => self.default.write(row),
+        }
+    }
+
+    /// Commits every inserter whose entry or time limit has been reached, in
+    /// the order of their priority
+    pub async fn commit(&mut self) -> Result<Quantities, InsertError> {
+        let mut total = Quantities::default();
+        for inserter in self.inserters_by_priority() {
+            total += inserter.commit().await?;
+        }
+
+        Ok(total)
+    }
+
+    /// Inserts all remaining buffered rows, in the order of the priority of the
+    /// inserters
+    pub async fn end(&mut self) -> Result<Quantities, InsertError> {
+        let mut total = Quantities::default();
+        for inserter in self.inserters_by_priority() {
+            total += inserter.end().await?;
+        }
+
+        Ok(total)
+    }
+
+    /// Routes in the order they are tried, described as `match MATCHER`,
+    /// `tenant = NAME` or `machine_id ~ PATTERN`, followed by the default inserter as `None`
+    pub fn routes(&self) -> impl Iterator<Item = (Option<String>, &Inserter)> {
+        self.routes
+            .iter()
+            .map(|(route, inserter)| {
+                let route = match route {
+                    RouteMatch::MachineId(pattern) => format!("machine_id ~ {}", pattern),
+                    RouteMatch::Tenant(tenant) => format!("tenant = {}", tenant),
+                    RouteMatch::Matcher(matcher) => format!("match {}", matcher),
+                };
+                (Some(route), inserter)
+            })
+            .chain(std::iter::once((None, &self.default)))
+    }
+
+    fn inserters(&self) -> impl Iterator<Item = &Inserter> {
+        std::iter::once(&self.default).chain(self.routes.iter().map(|(_, inserter)| inserter))
+    }
+
+    /// Higher priorities first, the default inserter and then the routes in
+    /// order among equal ones
+    fn inserters_by_priority(&mut self) -> Vec<&mut Inserter> {
+        let mut inserters: Vec<_> = std::iter::once(&mut self.default)
+            .chain(self.routes.iter_mut().map(|(_, inserter)| inserter))
+            .collect();
+        inserters.sort_by_key(|inserter| std::cmp::Reverse(inserter.priority()));
+        inserters
+    }
+}
+
+/// Inserts into ClickHouse, per tenant and machine routes
+impl Sink<LogRecordRow> for InserterRouter {
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        for row in rows {
+            self.write(row);
+        }
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move { Ok(self.commit().await?) })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move { Ok(self.end().await?) })
+    }
+
+    fn is_empty(&self) -> bool {
+        InserterRouter::is_empty(self)
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        InserterRouter::committed_cursor(self)
+    }
+}
+
+/// Matches `value` against `pattern`, where `*` matches any sequence of bytes and
+/// `?` matches a single byte
+pub fn glob_match(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&b) if b == b'?' || b == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|b| *b == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        glob_match(pattern.as_bytes(), value.as_bytes())
+    }
+
+    #[test]
+    fn literal_patterns_match_exactly() {
+        assert!(matches("b7e3", "b7e3"));
+        assert!(!matches("b7e3", "b7e30"));
+        assert!(!matches("b7e3", "b7e"));
+        assert!(!matches("b7e3", "B7E3"));
+    }
+
+    #[test]
+    fn star_matches_any_sequence() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "b7e3"));
+        assert!(matches("b7e3*", "b7e3"));
+        assert!(matches("b7e3*", "b7e3a1f0"));
+        assert!(matches("*a1f0", "b7e3a1f0"));
+        assert!(matches("b7*f0", "b7e3a1f0"));
+        assert!(matches("**", "b7e3"));
+        // Backtracks past an earlier partial match
+        assert!(matches("*ab", "aab"));
+        assert!(matches("a*b*c", "abbbc"));
+        assert!(!matches("b7e3*", "a7e3a1f0"));
+        assert!(!matches("*a1f0", "b7e3a1f1"));
+        assert!(!matches("a*b*c", "abbb"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_byte() {
+        assert!(matches("b7e?", "b7e3"));
+        assert!(matches("?7e3", "b7e3"));
+        assert!(matches("????", "b7e3"));
+        assert!(matches("b?*", "b7"));
+        assert!(!matches("b7e?", "b7e"));
+        assert!(!matches("b7e?", "b7e30"));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn empty_pattern_matches_empty_value_only() {
+        assert!(matches("", ""));
+        assert!(!matches("", "b7e3"));
+        assert!(!matches("", "*"));
+    }
+}
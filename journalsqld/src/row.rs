@@ -1,12 +1,15 @@
 use std::collections::HashSet;
 
 use anyhow::Context;
-use clickhouse::Row;
+use journalsql_pipeline::Row;
 use lazy_static::lazy_static;
 use log::trace;
-use serde::Serialize;
+use systemd_journal_parser::{FieldName, JournalEntry, JournalFieldValue, TimestampError};
+use time::OffsetDateTime;
 
-use crate::journal::JournalEntry;
+use crate::config::BytesRenderingConfig;
+use crate::dead_letter::DropReason;
+use crate::kubernetes::KubernetesInfo;
 
 lazy_static! {
     static ref INSERT_IGNORED_FIELDS: HashSet<&'static str> = {
@@ -17,6 +20,7 @@ lazy_static! {
         ignored_fields.insert("_TRANSPORT");
         ignored_fields.insert("__CURSOR");
         ignored_fields.insert("__REALTIME_TIMESTAMP");
+        ignored_fields.insert(RECEIVED_AT_FIELD);
 
         // These fields are in __CURSOR
         ignored_fields.insert("__SEQNUM");
@@ -27,6 +31,16 @@ lazy_static! {
     };
 }
 
+/// Fields taken out of the entry into dedicated columns
+const REQUIRED_FIELDS: [&str; 6] = [
+    "_TRANSPORT",
+    "_MACHINE_ID",
+    "_BOOT_ID",
+    "_HOSTNAME",
+    "__REALTIME_TIMESTAMP",
+    "__CURSOR",
+];
+
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Debug, thiserror::Error)]
@@ -34,6 +48,9 @@ pub enum RowCreateError {
     #[error("Missing required field \"{field}\"")]
     MissingField { field: String },
 
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(TimestampError),
+
     #[error("{0}")]
     Unspecified(Error),
 }
@@ -44,26 +61,91 @@ impl RowCreateError {
             field: field.into(),
         }
     }
+
+    pub fn drop_reason(&self) -> DropReason {
+        match self {
+            Self::MissingField { .. } => DropReason::MissingField,
+            Self::InvalidTimestamp(_) => DropReason::InvalidTimestamp,
+            Self::Unspecified(_) => DropReason::MalformedInput,
+        }
+    }
 }
 
-#[derive(Serialize, Row)]
+/// Tenant of entries received over TLS, set from the server name the client
+/// presented
+pub const TENANT_FIELD: &str = "_JOURNALSQLD_TENANT";
+
+/// Label of the configured file input entries were read from
+pub const SOURCE_FIELD: &str = "_JOURNALSQLD_SOURCE";
+
+/// Authenticated identity of the forwarder which sent the entry
+pub const IDENTITY_FIELD: &str = "_JOURNALSQLD_IDENTITY";
+
+/// Labels of the input the entry was received on, as a JSON object
+pub const LABELS_FIELD: &str = "_JOURNALSQLD_LABELS";
+
+/// When the entry was read from its input, in microseconds since the epoch,
+/// becomes `ingested_at`
+pub const RECEIVED_AT_FIELD: &str = "_JOURNALSQLD_RECEIVED_AT";
+
+#[derive(Clone)]
 pub struct LogRecordRow {
     pub machine_id: String,
     pub boot_id: String,
     // systemd timestamps are in microseconds
-    #[serde(with = "clickhouse::serde::time::datetime64::micros")]
-    pub timestamp: time::OffsetDateTime,
+    pub timestamp: OffsetDateTime,
     pub hostname: String,
     pub transport: String,
     pub cursor: String,
     // Map(String, String)
-    pub record: Vec<(String, String)>,
+    pub record: Vec<(FieldName, String)>,
+    // When this entry was read from its input, see `RECEIVED_AT_FIELD`
+    pub ingested_at: OffsetDateTime,
+    pub kubernetes: KubernetesInfo,
+    /// Kept in `record` as well
+    pub tenant: Option<String>,
+    /// Number of identical entries the row stands for, see `RepeatCompressor`
+    pub repeat_count: u32,
 }
 
-impl TryFrom<JournalEntry> for LogRecordRow {
-    type Error = RowCreateError;
+impl LogRecordRow {
+    /// Value of a field kept in `record`
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.record
+            .iter()
+            .find(|(field, _)| field == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Checks that the entry can be turned into a row without consuming it, so
+    /// rejected entries can be dead-lettered intact
+    pub fn validate(entry: &JournalEntry) -> Result<(), RowCreateError> {
+        for field in REQUIRED_FIELDS {
+            if entry.get(field).is_none() {
+                return Err(RowCreateError::missing_field(field));
+            }
+        }
+
+        if let Some(Err(err)) = entry.realtime_timestamp() {
+            return Err(RowCreateError::InvalidTimestamp(err));
+        }
+
+        Ok(())
+    }
+
+    /// Takes the required fields into their columns and renders the rest into
+    /// the record, binary values as configured
+    pub fn from_entry(
+        mut value: JournalEntry,
+        bytes_rendering: &BytesRenderingConfig,
+    ) -> Result<Self, RowCreateError> {
+        // Entries not read through an input, e.g. imported ones, are received now
+        let ingested_at = value
+            .get(RECEIVED_AT_FIELD)
+            .and_then(|value| String::from(value).parse::<i128>().ok())
+            .and_then(|micros| OffsetDateTime::from_unix_timestamp_nanos(micros * 1000).ok())
+            .unwrap_or_else(OffsetDateTime::now_utc);
 
-    fn try_from(mut value: JournalEntry) -> Result<Self, Self::Error> {
         // Grab common fields
         let transport = value
             .take_transport()
@@ -89,7 +171,7 @@ impl TryFrom<JournalEntry> for LogRecordRow {
             .take_realtime_timestamp()
             .context("no timestamp supplied")
             .map_err(|_e| RowCreateError::missing_field("__REALTIME_TIMESTAMP"))?
-            .map_err(|e| RowCreateError::Unspecified(e.into()))?;
+            .map_err(RowCreateError::InvalidTimestamp)?;
 
         let cursor = value
             .take_cursor()
@@ -104,13 +186,17 @@ impl TryFrom<JournalEntry> for LogRecordRow {
             );
         }
 
-        let mut record: Vec<(String, String)> = Vec::with_capacity(value.fields.len());
-        for (key, field) in value.fields.into_iter() {
-            if INSERT_IGNORED_FIELDS.contains(key.as_str()) {
+        let tenant = value.get(TENANT_FIELD).map(String::from);
+
+        let mut record: Vec<(FieldName, String)> = Vec::with_capacity(value.len());
+        for (key, field) in value.into_iter() {
+            if INSERT_IGNORED_FIELDS.contains(&*key) {
                 continue;
             }
 
-            record.push((key, field.into()));
+            if let Some(field) = field.into_rendered(bytes_rendering.for_field(&key)) {
+                record.push((key, field));
+            }
         }
 
         Ok(LogRecordRow {
@@ -121,6 +207,38 @@ impl TryFrom<JournalEntry> for LogRecordRow {
             transport,
             cursor,
             record,
+            ingested_at,
+            kubernetes: KubernetesInfo::default(),
+            tenant,
+            repeat_count: 1,
         })
     }
+
+    /// Entry the row was made from, with the fields taken out into columns
+    /// put back. Binary values stay rendered.
+    pub fn into_entry(self) -> JournalEntry {
+        let mut entry = JournalEntry::default();
+        let timestamp = self.timestamp.unix_timestamp_nanos() / 1000;
+        for (field, value) in [
+            ("__CURSOR", self.cursor),
+            ("__REALTIME_TIMESTAMP", timestamp.to_string()),
+            ("_MACHINE_ID", self.machine_id),
+            ("_BOOT_ID", self.boot_id),
+            ("_HOSTNAME", self.hostname),
+            ("_TRANSPORT", self.transport),
+        ] {
+            entry.put(field, JournalFieldValue::UTF8(value));
+        }
+        for (field, value) in self.record {
+            entry.put_multi(field, JournalFieldValue::UTF8(value));
+        }
+
+        entry
+    }
+}
+
+impl Row for LogRecordRow {
+    fn cursor(&self) -> &str {
+        &self.cursor
+    }
 }
@@ -0,0 +1,54 @@
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+
+/// Resolution of the keep threshold, in parts per million
+const SCALE: u128 = 1_000_000;
+
+/// Keeps a deterministic share of entries, decided by a hash of their cursor, so
+/// replays of the same input make the same decisions. Entries without a cursor
+/// are always kept.
+pub struct Sampler {
+    // Entries whose scaled hash is below this are kept
+    threshold: u128,
+}
+
+impl Sampler {
+    pub fn new(percent: f64) -> Self {
+        let percent = percent.clamp(0.0, 100.0);
+
+        Self {
+            threshold: (percent / 100.0 * SCALE as f64).round() as u128,
+        }
+    }
+
+    /// Share of entries kept
+    pub fn percent(&self) -> f64 {
+        self.threshold as f64 * 100.0 / SCALE as f64
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold < SCALE
+    }
+
+    pub fn keep(&self, entry: &JournalEntry) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let Some(cursor) = entry.get("__CURSOR") else {
+            return true;
+        };
+
+        // FNV-1a is stable across releases and platforms, unlike the std hasher
+        let mut hasher = FnvHasher::default();
+        match cursor {
+            JournalFieldValue::UTF8(value) => hasher.write(value.as_bytes()),
+            JournalFieldValue::Bytes(value) => hasher.write(value),
+        }
+
+        // Maps the hash uniformly onto 0..SCALE
+        (hasher.finish() as u128 * SCALE) >> 64 < self.threshold
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::BTreeMap;
+
+use serde::ser::{Error as _, SerializeMap};
+use serde::Serialize;
+use systemd_journal_parser::FieldName;
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::{Config, RecordStorage};
+use crate::row::{LogRecordRow, IDENTITY_FIELD, LABELS_FIELD};
+
+pub const PIPELINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Trusted field journald adds to entries of namespaces other than the default
+const NAMESPACE_FIELD: &str = "_NAMESPACE";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    MachineId,
+    BootId,
+    Timestamp,
+    Hostname,
+    Transport,
+    Cursor,
+    Record,
+    RecordKeys(RecordStorage),
+    RecordValues(RecordStorage),
+    IngestedAt,
+    IngestHost,
+    PipelineVersion,
+    KubernetesPodUid,
+    KubernetesNamespace,
+    KubernetesContainer,
+    RepeatCount,
+    TimestampMicros,
+    SourceTimestampMicros,
+    Timezone,
+    Namespace,
+    SourceIdentity,
+    Labels,
+}
+
+const BASE_COLUMNS: [Column; 6] = [
+    Column::MachineId,
+    Column::BootId,
+    Column::Timestamp,
+    Column::Hostname,
+    Column::Transport,
+    Column::Cursor,
+];
+
+const INGEST_METADATA_COLUMNS: [Column; 3] = [
+    Column::IngestedAt,
+    Column::IngestHost,
+    Column::PipelineVersion,
+];
+
+const TIMESTAMP_COLUMNS: [Column; 2] = [Column::TimestampMicros, Column::SourceTimestampMicros];
+
+const KUBERNETES_COLUMNS: [Column; 3] = [
+    Column::KubernetesPodUid,
+    Column::KubernetesNamespace,
+    Column::KubernetesContainer,
+];
+
+impl Column {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MachineId => "machine_id",
+            Self::BootId => "boot_id",
+            Self::Timestamp => "timestamp",
+            Self::Hostname => "hostname",
+            Self::Transport => "transport",
+            Self::Cursor => "cursor",
+            Self::Record => "record",
+            Self::RecordKeys(RecordStorage::Nested) => "record.key",
+            Self::RecordKeys(_) => "record_keys",
+            Self::RecordValues(RecordStorage::Nested) => "record.value",
+            Self::RecordValues(_) => "record_values",
+            Self::IngestedAt => "ingested_at",
+            Self::IngestHost => "ingest_host",
+            Self::PipelineVersion => "pipeline_version",
+            Self::KubernetesPodUid => "k8s_pod_uid",
+            Self::KubernetesNamespace => "k8s_namespace",
+            Self::KubernetesContainer => "k8s_container",
+            Self::RepeatCount => "repeat_count",
+            Self::TimestampMicros => "timestamp_us",
+            Self::SourceTimestampMicros => "source_timestamp_us",
+            Self::Timezone => "timezone",
+            Self::Namespace => "namespace",
+            Self::SourceIdentity => "source_identity",
+            Self::Labels => "labels",
+        }
+    }
+}
+
+/// Set of columns written for every row, in insert order
+pub struct Schema {
+    columns: Vec<Column>,
+    ingest_host: String,
+    timezone_field: Option<String>,
+}
+
+impl Schema {
+    pub fn new(config: &Config) -> Self {
+        let mut columns = Vec::from(BASE_COLUMNS);
+        match config.clickhouse.record_storage {
+            RecordStorage::Map => columns.push(Column::Record),
+            storage => columns.extend([Column::RecordKeys(storage), Column::RecordValues(storage)]),
+        }
+
+        if config.ingest_metadata.enabled {
+            columns.extend(INGEST_METADATA_COLUMNS);
+        }
+        if config.kubernetes.enabled {
+            columns.extend(KUBERNETES_COLUMNS);
+        }
+        if config.repeat_compression.enabled {
+            columns.push(Column::RepeatCount);
+        }
+        let timezone_field = config
+            .timestamp_columns
+            .enabled
+            .then(|| config.timestamp_columns.timezone_field.clone())
+            .flatten();
+        if config.timestamp_columns.enabled {
+            columns.extend(TIMESTAMP_COLUMNS);
+            if timezone_field.is_some() {
+                columns.push(Column::Timezone);
+            }
+        }
+        if config.journal_upload.namespace.is_some() {
+            columns.push(Column::Namespace);
+        }
+        if !config.authentication.identities.is_empty() {
+            columns.push(Column::SourceIdentity);
+        }
+        if config.has_labels() {
+            columns.push(Column::Labels);
+        }
+
+        let ingest_host = config
+            .ingest_metadata
+            .host
+            .clone()
+            .unwrap_or_else(system_hostname);
+
+        Self {
+            columns,
+            ingest_host,
+            timezone_field,
+        }
+    }
+
+    /// Value of the configured timezone field of the entry
+    fn timezone<'a>(&self, row: &'a LogRecordRow) -> Option<&'a str> {
+        row.field(self.timezone_field.as_deref()?)
+    }
+
+    /// Comma separated column list for use in `INSERT INTO` statements
+    pub fn column_list(&self) -> String {
+        self.columns
+            .iter()
+            .map(|column| format!("`{}`", column.name()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// `SELECT` list computing the columns, in `column_list` order, from a table
+    /// in the legacy layout: the base columns and `record Map(String, String)`.
+    /// Values not kept in the legacy layout get the defaults of a fresh insert;
+    /// `ingested_at` is the entry timestamp and Kubernetes columns are NULL.
+    pub fn legacy_select_list(&self) -> String {
+        let expressions: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| match column {
+                Column::MachineId
+                | Column::BootId
+                | Column::Timestamp
+                | Column::Hostname
+                | Column::Transport
+                | Column::Cursor
+                | Column::Record => format!("`{}`", column.name()),
+                Column::RecordKeys(_) => String::from("mapKeys(`record`)"),
+                Column::RecordValues(_) => String::from("mapValues(`record`)"),
+                Column::IngestedAt => String::from("`timestamp`"),
+                Column::IngestHost => quote(&self.ingest_host),
+                Column::PipelineVersion => quote(PIPELINE_VERSION),
+                Column::KubernetesPodUid
+                | Column::KubernetesNamespace
+                | Column::KubernetesContainer => String::from("NULL"),
+                Column::RepeatCount => String::from("1"),
+                Column::TimestampMicros => String::from("toUnixTimestamp64Micro(`timestamp`)"),
+                Column::SourceTimestampMicros => {
+                    String::from("toInt64OrNull(`record`['_SOURCE_REALTIME_TIMESTAMP'])")
+                }
+                Column::Timezone => match &self.timezone_field {
+                    Some(field) => format!("nullIf(`record`[{}], '')", quote(field)),
+                    None => String::from("NULL"),
+                },
+                Column::Namespace => format!("nullIf(`record`[{}], '')", quote(NAMESPACE_FIELD)),
+                Column::SourceIdentity => {
+                    format!("nullIf(`record`[{}], '')", quote(IDENTITY_FIELD))
+                }
+                Column::Labels => format!(
+                    "JSONExtract(`record`[{}], 'Map(String, String)')",
+                    quote(LABELS_FIELD)
+                ),
+            })
+            .collect();
+
+        expressions.join(", ")
+    }
+
+    pub fn write_row_binary(&self, buf: &mut Vec<u8>, row: &LogRecordRow) {
+        for column in self.columns.iter() {
+            match column {
+                Column::MachineId => put_string(buf, &row.machine_id),
+                Column::BootId => put_string(buf, &row.boot_id),
+                Column::Timestamp => put_datetime64_micros(buf, &row.timestamp),
+                Column::Hostname => put_string(buf, &row.hostname),
+                Column::Transport => put_string(buf, &row.transport),
+                Column::Cursor => put_string(buf, &row.cursor),
+                Column::Record => {
+                    put_leb128(buf, row.record.len() as u64);
+                    for (key, value) in row.record.iter() {
+                        put_string(buf, key);
+                        put_string(buf, value);
+                    }
+                }
+                Column::RecordKeys(_) => {
+                    put_leb128(buf, row.record.len() as u64);
+                    for (key, _) in row.record.iter() {
+                        put_string(buf, key);
+                    }
+                }
+                Column::RecordValues(_) => {
+                    put_leb128(buf, row.record.len() as u64);
+                    for (_, value) in row.record.iter() {
+                        put_string(buf, value);
+                    }
+                }
+                Column::IngestedAt => put_datetime64_micros(buf, &row.ingested_at),
+                Column::IngestHost => put_string(buf, &self.ingest_host),
+                Column::PipelineVersion => put_string(buf, PIPELINE_VERSION),
+                Column::KubernetesPodUid => {
+                    put_nullable_string(buf, row.kubernetes.pod_uid.as_deref())
+                }
+                Column::KubernetesNamespace => {
+                    put_nullable_string(buf, row.kubernetes.namespace.as_deref())
+                }
+                Column::KubernetesContainer => {
+                    put_nullable_string(buf, row.kubernetes.container.as_deref())
+                }
+                Column::RepeatCount => buf.extend_from_slice(&row.repeat_count.to_le_bytes()),
+                Column::TimestampMicros => {
+                    buf.extend_from_slice(&epoch_micros(&row.timestamp).to_le_bytes())
+                }
+                Column::SourceTimestampMicros => match source_timestamp_micros(row) {
+                    Some(micros) => {
+                        buf.push(0);
+                        buf.extend_from_slice(&micros.to_le_bytes());
+                    }
+                    None => buf.push(1),
+                },
+                Column::Timezone => put_nullable_string(buf, self.timezone(row)),
+                Column::Namespace => put_nullable_string(buf, row.field(NAMESPACE_FIELD)),
+                Column::SourceIdentity => put_nullable_string(buf, row.field(IDENTITY_FIELD)),
+                Column::Labels => {
+                    let labels = labels(row);
+                    put_leb128(buf, labels.len() as u64);
+                    for (key, value) in labels.iter() {
+                        put_string(buf, key);
+                        put_string(buf, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes `row` as a single JSONEachRow line. Timestamps are formatted as
+    /// RFC 3339 and require `date_time_input_format=best_effort`.
+    pub fn write_json_each_row(
+        &self,
+        buf: &mut Vec<u8>,
+        row: &LogRecordRow,
+    ) -> Result<(), serde_json::Error> {
+        serde_json::to_writer(&mut *buf, &JsonRow { schema: self, row })?;
+        buf.push(b'\n');
+
+        Ok(())
+    }
+}
+
+/// Exact `__REALTIME_TIMESTAMP`, DateTime64 values don't compare reliably
+/// against cursors and other fields after a round trip
+fn epoch_micros(timestamp: &time::OffsetDateTime) -> i64 {
+    (timestamp.unix_timestamp_nanos() / 1000) as i64
+}
+
+fn source_timestamp_micros(row: &LogRecordRow) -> Option<i64> {
+    row.field("_SOURCE_REALTIME_TIMESTAMP")?.parse().ok()
+}
+
+/// Labels of the input the row was received on, empty without the field
+fn labels(row: &LogRecordRow) -> BTreeMap<String, String> {
+    row.field(LABELS_FIELD)
+        .and_then(|labels| serde_json::from_str(labels).ok())
+        .unwrap_or_default()
+}
+
+struct JsonRow<'a> {
+    schema: &'a Schema,
+    row: &'a LogRecordRow,
+}
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let row = self.row;
+        let mut map = serializer.serialize_map(Some(self.schema.columns.len()))?;
+        for column in self.schema.columns.iter() {
+            let name = column.name();
+            match column {
+                Column::MachineId => map.serialize_entry(name, &row.machine_id)?,
+                Column::BootId => map.serialize_entry(name, &row.boot_id)?,
+                Column::Timestamp => {
+                    let timestamp = row.timestamp.format(&Rfc3339).map_err(S::Error::custom)?;
+                    map.serialize_entry(name, &timestamp)?
+                }
+                Column::Hostname => map.serialize_entry(name, &row.hostname)?,
+                Column::Transport => map.serialize_entry(name, &row.transport)?,
+                Column::Cursor => map.serialize_entry(name, &row.cursor)?,
+                Column::Record => map.serialize_entry(name, &RecordMap(&row.record))?,
+                Column::RecordKeys(_) => {
+                    let keys: Vec<&str> = row.record.iter().map(|(key, _)| &**key).collect();
+                    map.serialize_entry(name, &keys)?
+                }
+                Column::RecordValues(_) => {
+                    let values: Vec<&str> =
+                        row.record.iter().map(|(_, value)| value.as_str()).collect();
+                    map.serialize_entry(name, &values)?
+                }
+                Column::IngestedAt => {
+                    let ingested_at = row.ingested_at.format(&Rfc3339).map_err(S::Error::custom)?;
+                    map.serialize_entry(name, &ingested_at)?
+                }
+                Column::IngestHost => map.serialize_entry(name, &self.schema.ingest_host)?,
+                Column::PipelineVersion => map.serialize_entry(name, PIPELINE_VERSION)?,
+                Column::KubernetesPodUid => map.serialize_entry(name, &row.kubernetes.pod_uid)?,
+                Column::KubernetesNamespace => {
+                    map.serialize_entry(name, &row.kubernetes.namespace)?
+                }
+                Column::KubernetesContainer => {
+                    map.serialize_entry(name, &row.kubernetes.container)?
+                }
+                Column::RepeatCount => map.serialize_entry(name, &row.repeat_count)?,
+                Column::TimestampMicros => {
+                    map.serialize_entry(name, &epoch_micros(&row.timestamp))?
+                }
+                Column::SourceTimestampMicros => {
+                    map.serialize_entry(name, &source_timestamp_micros(row))?
+                }
+                Column::Timezone => map.serialize_entry(name, &self.schema.timezone(row))?,
+                Column::Namespace => map.serialize_entry(name, &row.field(NAMESPACE_FIELD))?,
+                Column::SourceIdentity => map.serialize_entry(name, &row.field(IDENTITY_FIELD))?,
+                Column::Labels => map.serialize_entry(name, &labels(row))?,
+            }
+        }
+
+        map.end()
+    }
+}
+
+struct RecordMap<'a>(&'a [(FieldName, String)]);
+
+impl Serialize for RecordMap<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+fn system_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// ClickHouse string literal
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn put_leb128(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &str) {
+    put_leb128(buf, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn put_nullable_string(buf: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            buf.push(0);
+            put_string(buf, value);
+        }
+        None => buf.push(1),
+    }
+}
+
+fn put_datetime64_micros(buf: &mut Vec<u8>, timestamp: &time::OffsetDateTime) {
+    buf.extend_from_slice(&epoch_micros(timestamp).to_le_bytes());
+}
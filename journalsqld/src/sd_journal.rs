@@ -0,0 +1,138 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use log::{debug, error};
+use systemd::journal::{Journal, JournalSeek, OpenOptions};
+use systemd_journal_parser::{intern, write_journal_entry, JournalEntry, JournalFieldValue};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+/// Export format buffered between the reader thread and the parser
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// How long to wait for new entries before checking the consumer is still there
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens the local journal through libsystemd and follows it, resuming after
+/// `cursor` if given. `namespace` selects a journald namespace instead of the
+/// default one, `*` all of them. Entries are re-encoded in the export format, so they go
+/// through the same parsing as the `journalctl` pipe. Reading stops when the
+/// returned stream is dropped.
+pub async fn spawn(
+    after_cursor: Option<String>,
+    namespace: Option<String>,
+) -> Result<DuplexStream, std::io::Error> {
+    let (reader, mut writer) = tokio::io::duplex(PIPE_CAPACITY);
+    let (opened_sender, opened) = oneshot::channel();
+    let handle = Handle::current();
+
+    // sd_journal handles are bound to the thread which opened them
+    tokio::task::spawn_blocking(move || {
+        let mut journal = match open(after_cursor.as_deref(), namespace.as_deref()) {
+            Ok(journal) => journal,
+            Err(err) => {
+                let _ = opened_sender.send(Err(err));
+                return;
+            }
+        };
+        let _ = opened_sender.send(Ok(()));
+
+        if let Err(err) = follow(&mut journal, after_cursor.as_deref(), &handle, &mut writer) {
+            error!("Reading the journal failed: {}", err);
+        }
+    });
+
+    opened
+        .await
+        .expect("journal reader should report whether it opened")?;
+
+    Ok(reader)
+}
+
+fn open(after_cursor: Option<&str>, namespace: Option<&str>) -> Result<Journal, std::io::Error> {
+    let mut options = OpenOptions::default();
+    options.system(true).local_only(true);
+    let mut journal = match namespace {
+        None => options.open()?,
+        Some("*") => options.all_namespaces(true).open()?,
+        Some(namespace) => options.open_namespace(namespace)?,
+    };
+
+    match after_cursor {
+        Some(cursor) => journal.seek(JournalSeek::Cursor {
+            cursor: cursor.to_string(),
+        })?,
+        None => journal.seek(JournalSeek::Head)?,
+    };
+
+    Ok(journal)
+}
+
+fn follow(
+    journal: &mut Journal,
+    after_cursor: Option<&str>,
+    handle: &Handle,
+    writer: &mut DuplexStream,
+) -> Result<(), std::io::Error> {
+    let mut buffer = Vec::new();
+    let mut skip_cursor = after_cursor;
+
+    loop {
+        if journal.next()? == 0 {
+            journal.wait(Some(WAIT_TIMEOUT))?;
+            continue;
+        }
+
+        // Seeking positions the journal on the cursor's entry itself if it
+        // still exists, like `journalctl --after-cursor` it is skipped
+        if let Some(cursor) = skip_cursor.take() {
+            if journal.cursor()? == cursor {
+                continue;
+            }
+        }
+
+        let entry = read_entry(journal)?;
+
+        buffer.clear();
+        write_journal_entry(&mut buffer, &entry)?;
+        if handle.block_on(writer.write_all(&buffer)).is_err() {
+            debug!("Journal stream closed, stopping reader");
+            return Ok(());
+        }
+    }
+}
+
+/// Current entry with the address fields `journalctl --output=export` adds
+fn read_entry(journal: &mut Journal) -> Result<JournalEntry, std::io::Error> {
+    let mut entry = JournalEntry::default();
+
+    let realtime = journal
+        .timestamp()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    // _BOOT_ID is a regular field of the entry
+    let (monotonic, _) = journal.monotonic_timestamp()?;
+    let mut text = |key: &'static str, value: String| {
+        entry.put(key, JournalFieldValue::UTF8(value));
+    };
+    text("__CURSOR", journal.cursor()?);
+    text("__REALTIME_TIMESTAMP", realtime.to_string());
+    text("__MONOTONIC_TIMESTAMP", monotonic.to_string());
+
+    journal.restart_data();
+    while let Some(field) = journal.enumerate_data()? {
+        let Some(value) = field.value() else {
+            continue;
+        };
+
+        let key = intern(String::from_utf8_lossy(field.name()).into_owned());
+        let value = match std::str::from_utf8(value) {
+            Ok(value) => JournalFieldValue::UTF8(value.to_string()),
+            Err(_) => JournalFieldValue::Bytes(value.to_vec().into()),
+        };
+        entry.put_multi(key, value);
+    }
+
+    Ok(entry)
+}
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use time::OffsetDateTime;
+
+use crate::config::SloConfig;
+use crate::metrics;
+use crate::row::LogRecordRow;
+
+/// Granularity of the sliding windows
+const BUCKET: Duration = Duration::from_secs(10);
+
+struct Bucket {
+    start: Instant,
+    total: u64,
+    late: u64,
+}
+
+/// Tracks end-to-end delivery latency, from the journal timestamp of an entry to
+/// its successful insert, against an objective such as "99% of entries inserted
+/// within 30s". Error budget burn rates over sliding windows are exported as
+/// metrics: a burn rate of 1 uses up the budget exactly over the SLO period.
+pub struct SloTracker {
+    target: time::Duration,
+    // Allowed fraction of late entries
+    error_budget: f64,
+    windows: Vec<(String, Duration)>,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl SloTracker {
+    pub fn new(config: &SloConfig) -> Self {
+        Self {
+            target: time::Duration::seconds(config.latency_target as i64),
+            error_budget: 1.0 - config.objective / 100.0,
+            windows: config
+                .burn_rate_windows
+                .iter()
+                .map(|seconds| (format!("{}s", seconds), Duration::from_secs(*seconds)))
+                .collect(),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records rows which have just been inserted
+    pub fn record(&self, rows: &[LogRecordRow]) {
+        let inserted_at = OffsetDateTime::now_utc();
+        let total = rows.len() as u64;
+        let late = rows
+            .iter()
+            .filter(|row| inserted_at - row.timestamp > self.target)
+            .count() as u64;
+
+        metrics::inc_slo_entries(total - late, late).unwrap();
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("SLO buckets lock poisoned");
+        match buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.start) < BUCKET => {
+                bucket.total += total;
+                bucket.late += late;
+            }
+            _ => buckets.push_back(Bucket {
+                start: now,
+                total,
+                late,
+            }),
+        }
+
+        let longest = self.windows.iter().map(|(_, window)| *window).max();
+        while let (Some(bucket), Some(longest)) = (buckets.front(), longest) {
+            if now.duration_since(bucket.start) <= longest {
+                break;
+            }
+            buckets.pop_front();
+        }
+
+        for (label, window) in self.windows.iter() {
+            let (total, late) = buckets
+                .iter()
+                .filter(|bucket| now.duration_since(bucket.start) <= *window)
+                .fold((0, 0), |(total, late), bucket| {
+                    (total + bucket.total, late + bucket.late)
+                });
+
+            let burn_rate = match (total, late) {
+                (0, _) | (_, 0) => 0.0,
+                _ if self.error_budget <= 0.0 => f64::INFINITY,
+                _ => late as f64 / total as f64 / self.error_budget,
+            };
+            metrics::set_slo_burn_rate(label, burn_rate).unwrap();
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::SourceCursorsConfig;
+use crate::row::LogRecordRow;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SourceCursor {
+    pub machine_id: String,
+    pub boot_id: String,
+    pub cursor: String,
+    /// Timestamp of the entry, RFC 3339
+    pub timestamp: String,
+}
+
+/// Cursor of the last inserted entry per (machine_id, boot_id). Forwarders
+/// sharing the daemon look theirs up after a restart of either side instead of
+/// relying on a single state file, which only tracks the most recent entry of
+/// all sources.
+pub struct SourceCursors {
+    path: PathBuf,
+    cursors: Mutex<BTreeMap<(String, String), SourceCursor>>,
+    /// Whether there are changes since the last save
+    dirty: AtomicBool,
+}
+
+impl SourceCursors {
+    pub fn new(config: &SourceCursorsConfig) -> Self {
+        Self {
+            path: config.path.clone(),
+            cursors: Mutex::default(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Restores the cursors saved by an earlier run, if any
+    pub fn load(config: &SourceCursorsConfig) -> io::Result<Self> {
+        let saved: Vec<SourceCursor> = match fs::read(&config.path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let source_cursors = Self::new(config);
+        *source_cursors.cursors.lock().unwrap() = saved
+            .into_iter()
+            .map(|cursor| ((cursor.machine_id.clone(), cursor.boot_id.clone()), cursor))
+            .collect();
+
+        Ok(source_cursors)
+    }
+
+    /// Records rows which have just been inserted
+    pub fn record(&self, rows: &[LogRecordRow]) {
+        if rows.is_empty() {
+            return;
+        }
+
+        let mut cursors = self.cursors.lock().expect("source cursors lock poisoned");
+        for row in rows.iter() {
+            cursors.insert(
+                (row.machine_id.clone(), row.boot_id.clone()),
+                SourceCursor {
+                    machine_id: row.machine_id.clone(),
+                    boot_id: row.boot_id.clone(),
+                    cursor: row.cursor.clone(),
+                    timestamp: row.timestamp.format(&Rfc3339).unwrap_or_default(),
+                },
+            );
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Cursors of all sources, or of the boots of one machine
+    pub fn list(&self, machine_id: Option<&str>) -> Vec<SourceCursor> {
+        self.cursors
+            .lock()
+            .expect("source cursors lock poisoned")
+            .values()
+            .filter(|cursor| machine_id.map_or(true, |machine_id| cursor.machine_id == machine_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the cursors to the file if they changed since the last save
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let result = serde_json::to_vec_pretty(&self.list(None))
+            .map_err(io::Error::from)
+            .and_then(|data| {
+                let mut temporary = self.path.as_os_str().to_owned();
+                temporary.push(".tmp");
+                fs::write(&temporary, data)?;
+                fs::rename(&temporary, &self.path)
+            });
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use systemd_journal_parser::{write_journal_entry, EntryReader, JournalEntry};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+
+use crate::config::{ParserConfig, SpoolConfig};
+use crate::Error;
+
+const MANIFEST: &str = "manifest.json";
+
+/// Metadata of a segment, kept in the manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub created: String,
+    pub first_cursor: Option<String>,
+    pub last_cursor: Option<String>,
+    /// Range of `__REALTIME_TIMESTAMP` of the entries
+    #[serde(default)]
+    pub first_timestamp: Option<String>,
+    #[serde(default)]
+    pub last_timestamp: Option<String>,
+    pub entries: u64,
+    /// Size of the uncompressed export format data
+    pub bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl SegmentInfo {
+    /// Sequence number the segment name starts with
+    pub fn sequence(&self) -> Option<u64> {
+        self.name.split('.').next()?.parse().ok()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    next_segment: u64,
+    /// In the order they were written
+    segments: Vec<SegmentInfo>,
+}
+
+/// On-disk buffer of journal entries. Entries are stored in the journal export
+/// format as zstd compressed segments, listed in a manifest so the spool can be
+/// inspected and replayed in order after a restart.
+pub struct Spool {
+    path: PathBuf,
+    compression_level: i32,
+    manifest: Manifest,
+}
+
+impl Spool {
+    pub fn open(config: &SpoolConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.path)?;
+
+        let manifest = match fs::read(config.path.join(MANIFEST)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: config.path.clone(),
+            compression_level: config.compression_level,
+            manifest,
+        })
+    }
+
+    pub fn segments(&self) -> &[SegmentInfo] {
+        &self.manifest.segments
+    }
+
+    /// Writes entries to a new segment, returns `None` if there are none
+    pub fn write_segment(&mut self, entries: &[JournalEntry]) -> io::Result<Option<SegmentInfo>> {
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let mut data = Vec::new();
+        for entry in entries {
+            write_journal_entry(&mut data, entry)?;
+        }
+        let compressed = zstd::encode_all(data.as_slice(), self.compression_level)?;
+
+        let name = format!("{:016}.journal.zst", self.manifest.next_segment);
+        write_atomic(&self.path.join(&name), &compressed)?;
+
+        let timestamps = entries.iter().filter_map(realtime_timestamp);
+        let first_timestamp = timestamps.clone().min();
+        let last_timestamp = timestamps.max();
+
+        let segment = SegmentInfo {
+            name,
+            created: format_timestamp(OffsetDateTime::now_utc()),
+            first_cursor: entries.first().and_then(cursor),
+            last_cursor: entries.last().and_then(cursor),
+            first_timestamp: first_timestamp.map(format_timestamp),
+            last_timestamp: last_timestamp.map(format_timestamp),
+            entries: entries.len() as u64,
+            bytes: data.len() as u64,
+            compressed_bytes: compressed.len() as u64,
+        };
+
+        self.manifest.next_segment += 1;
+        self.manifest.segments.push(segment.clone());
+        self.save_manifest()?;
+
+        Ok(Some(segment))
+    }
+
+    /// Decompressed export format data of a segment
+    pub fn read_segment(&self, segment: &SegmentInfo) -> io::Result<Vec<u8>> {
+        zstd::decode_all(File::open(self.path.join(&segment.name))?)
+    }
+
+    pub fn remove_segment(&mut self, name: &str) -> io::Result<()> {
+        self.manifest
+            .segments
+            .retain(|segment| segment.name != name);
+        self.save_manifest()?;
+
+        match fs::remove_file(self.path.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn save_manifest(&self) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.manifest)?;
+        write_atomic(&self.path.join(MANIFEST), &data)
+    }
+}
+
+/// Sends spooled entries, oldest segment first. Segments are removed once all of
+/// their entries were handed to the consumer, a segment interrupted by shutdown
+/// is replayed again in full.
+pub async fn replay(
+    spool: &Mutex<Spool>,
+    config: &ParserConfig,
+    sender: &mpsc::Sender<JournalEntry>,
+) -> Result<(), Error> {
+    let segments = spool
+        .lock()
+        .expect("spool lock poisoned")
+        .segments()
+        .to_vec();
+
+    for segment in segments {
+        let data = spool
+            .lock()
+            .expect("spool lock poisoned")
+            .read_segment(&segment)?;
+        let mut reader = EntryReader::new(data.as_slice()).with_options(config.options());
+
+        while let Some(entry) = reader.next_entry().await? {
+            if sender.send(entry).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        spool
+            .lock()
+            .expect("spool lock poisoned")
+            .remove_segment(&segment.name)?;
+        info!(
+            "replayed spool segment {} ({} entries)",
+            segment.name, segment.entries
+        );
+    }
+
+    Ok(())
+}
+
+/// Closes the channel and writes the entries still queued in it to a segment
+pub fn spill(spool: &Mutex<Spool>, receiver: &mut mpsc::Receiver<JournalEntry>) {
+    receiver.close();
+
+    let mut entries = Vec::new();
+    while let Ok(entry) = receiver.try_recv() {
+        entries.push(entry);
+    }
+
+    match spool
+        .lock()
+        .expect("spool lock poisoned")
+        .write_segment(&entries)
+    {
+        Ok(Some(segment)) => info!("spooled {} entries to {}", segment.entries, segment.name),
+        Ok(None) => {}
+        Err(err) => error!("failed to spool {} entries: {}", entries.len(), err),
+    }
+}
+
+fn cursor(entry: &JournalEntry) -> Option<String> {
+    entry.get("__CURSOR").map(String::from)
+}
+
+fn realtime_timestamp(entry: &JournalEntry) -> Option<OffsetDateTime> {
+    let micros = String::from(entry.get("__REALTIME_TIMESTAMP")?)
+        .parse::<i128>()
+        .ok()?;
+
+    OffsetDateTime::from_unix_timestamp_nanos(micros * 1000).ok()
+}
+
+fn format_timestamp(timestamp: OffsetDateTime) -> String {
+    timestamp.format(&Rfc3339).unwrap_or_default()
+}
+
+/// Writes through a temporary file so readers never see partial contents
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,184 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use systemd_journal_parser::EntryReader;
+
+use crate::client::Client;
+use crate::config::{Config, DeadLetterConfig};
+use crate::import;
+use crate::spool::{SegmentInfo, Spool};
+use crate::Error;
+
+const USAGE: &str = "usage: journalsqld spool ls
+       journalsqld spool inspect SEGMENTS|dead-letter
+       journalsqld spool drop SEGMENTS|dead-letter
+       journalsqld spool replay SEGMENTS
+
+SEGMENTS is a sequence number, an inclusive range FIRST..LAST or \"all\".
+Run while journalsqld is stopped, it only reads the spool on startup and
+writes it on shutdown.";
+
+/// `journalsqld spool` subcommands for the spool and the dead-letter file. The
+/// ClickHouse client is only created for `replay`.
+pub async fn run<F>(config: &Config, args: Vec<OsString>, client: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<Client, Error>,
+{
+    let args = args
+        .iter()
+        .map(|arg| arg.to_str().ok_or(USAGE))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match args.as_slice() {
+        ["ls"] => list(config),
+        ["inspect", "dead-letter"] => inspect_dead_letters(&config.dead_letter),
+        ["inspect", selection] => inspect(config, selection).await,
+        ["drop", "dead-letter"] => drop_dead_letters(&config.dead_letter),
+        ["drop", selection] => drop_segments(config, selection),
+        ["replay", selection] => replay(config, client()?, selection).await,
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Prints the segments with their time range and size, and a summary of the
+/// dead-letter file
+fn list(config: &Config) -> Result<(), Error> {
+    let spool = Spool::open(&config.spool)?;
+
+    println!(
+        "{:<28} {:>10} {:>12} {:>12}  {:<25} {:<25}",
+        "SEGMENT", "ENTRIES", "BYTES", "COMPRESSED", "FIRST ENTRY", "LAST ENTRY"
+    );
+
+    let (mut entries, mut bytes, mut compressed_bytes) = (0, 0, 0);
+    for segment in spool.segments() {
+        println!(
+            "{:<28} {:>10} {:>12} {:>12}  {:<25} {:<25}",
+            segment.name,
+            segment.entries,
+            segment.bytes,
+            segment.compressed_bytes,
+            segment.first_timestamp.as_deref().unwrap_or("-"),
+            segment.last_timestamp.as_deref().unwrap_or("-")
+        );
+
+        entries += segment.entries;
+        bytes += segment.bytes;
+        compressed_bytes += segment.compressed_bytes;
+    }
+
+    println!(
+        "{:<28} {:>10} {:>12} {:>12}",
+        format!("{} segments", spool.segments().len()),
+        entries,
+        bytes,
+        compressed_bytes
+    );
+
+    match fs::read(&config.dead_letter.path) {
+        Ok(data) => println!(
+            "\ndead-letter {}: {} records, {} bytes",
+            config.dead_letter.path.display(),
+            data.iter().filter(|b| **b == b'\n').count(),
+            data.len()
+        ),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+/// Dumps the entries of the selected segments as JSON lines
+async fn inspect(config: &Config, selection: &str) -> Result<(), Error> {
+    let spool = Spool::open(&config.spool)?;
+    let mut stdout = io::stdout().lock();
+
+    for segment in select(&spool, selection)? {
+        let data = spool.read_segment(&segment)?;
+        let mut reader = EntryReader::new(data.as_slice()).with_options(config.parser.options());
+
+        while let Some(entry) = reader.next_entry().await? {
+            serde_json::to_writer(&mut stdout, &entry)?;
+            stdout.write_all(b"\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes the selected segments, e.g. corrupt ones which fail to replay
+fn drop_segments(config: &Config, selection: &str) -> Result<(), Error> {
+    let mut spool = Spool::open(&config.spool)?;
+
+    for segment in select(&spool, selection)? {
+        spool.remove_segment(&segment.name)?;
+        println!("dropped {} ({} entries)", segment.name, segment.entries);
+    }
+
+    Ok(())
+}
+
+/// Inserts the entries of the selected segments and deletes each segment once
+/// it is fully inserted
+async fn replay(config: &Config, client: Client, selection: &str) -> Result<(), Error> {
+    let mut spool = Spool::open(&config.spool)?;
+
+    for segment in select(&spool, selection)? {
+        let data = spool.read_segment(&segment)?;
+        let entries =
+            import::import_stream(config, client.clone(), &segment.name, data.as_slice()).await?;
+
+        spool.remove_segment(&segment.name)?;
+        println!("replayed {} ({} entries)", segment.name, entries);
+    }
+
+    Ok(())
+}
+
+fn inspect_dead_letters(config: &DeadLetterConfig) -> Result<(), Error> {
+    let data = fs::read(&config.path)?;
+    io::stdout().lock().write_all(&data)?;
+
+    Ok(())
+}
+
+fn drop_dead_letters(config: &DeadLetterConfig) -> Result<(), Error> {
+    OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&config.path)?;
+    println!("truncated {}", config.path.display());
+
+    Ok(())
+}
+
+fn select(spool: &Spool, selection: &str) -> Result<Vec<SegmentInfo>, Error> {
+    let range = parse_selection(selection)?;
+    let segments: Vec<_> = spool
+        .segments()
+        .iter()
+        .filter(|segment| segment.sequence().map_or(false, |seq| range.contains(&seq)))
+        .cloned()
+        .collect();
+
+    if segments.is_empty() {
+        return Err(format!("no segments match {:?}", selection).into());
+    }
+
+    Ok(segments)
+}
+
+fn parse_selection(selection: &str) -> Result<RangeInclusive<u64>, Error> {
+    if selection == "all" {
+        return Ok(0..=u64::MAX);
+    }
+
+    let (first, last) = selection.split_once("..").unwrap_or((selection, selection));
+    let first = first.parse().map_err(|_| USAGE)?;
+    let last = last.parse().map_err(|_| USAGE)?;
+
+    Ok(first..=last)
+}
@@ -0,0 +1,154 @@
+use std::io;
+
+use log::{debug, info, warn};
+use systemd_journal_parser::{parse_syslog_message, JournalEntry};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::listener::accept_failed;
+use crate::metrics;
+use crate::network::{read_delimited, NetworkEntries};
+
+/// Receives syslog messages (RFC 5424 or RFC 3164) as UDP datagrams and on TCP
+/// connections, framed by octet counting or newlines (RFC 6587), until the
+/// consumer goes away. Messages are translated into entries like journald
+/// stores them. Datagrams larger than `max_message_size` are truncated, TCP
+/// connections sending larger messages are closed.
+pub async fn accept_messages(
+    udp_sockets: Vec<std::net::UdpSocket>,
+    listeners: Vec<std::net::TcpListener>,
+    max_message_size: usize,
+    entries: NetworkEntries,
+) -> io::Result<()> {
+    let mut receive_loops = Vec::new();
+    for socket in udp_sockets {
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        let entries = entries.clone();
+
+        receive_loops.push(tokio::task::spawn(async move {
+            let mut buffer = vec![0; max_message_size];
+            loop {
+                let (length, peer) = tokio::select! {
+                    received = socket.recv_from(&mut buffer) => received?,
+                    _ = entries.closed() => return Ok::<_, io::Error>(()),
+                };
+                let Some(entry) = parse(&buffer[..length], &entries, peer) else {
+                    continue;
+                };
+                if !entries.submit(entry, "syslog", peer.ip()).await {
+                    return Ok(());
+                }
+            }
+        }));
+    }
+
+    for listener in listeners {
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let entries = entries.clone();
+
+        receive_loops.push(tokio::task::spawn(async move {
+            let addr = listener.local_addr()?;
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            accept_failed(addr, err).await;
+                            continue;
+                        }
+                    },
+                    _ = entries.closed() => return Ok::<_, io::Error>(()),
+                };
+                info!("accepted syslog connection from {}", peer);
+
+                let entries = entries.clone();
+                tokio::task::spawn(async move {
+                    let mut reader = BufReader::new(stream);
+                    let mut frame = Vec::new();
+                    loop {
+                        match read_frame(&mut reader, &mut frame, max_message_size).await {
+                            Ok(true) => {
+                                let Some(entry) = parse(&frame, &entries, peer) else {
+                                    continue;
+                                };
+                                if !entries.submit(entry, "syslog", peer.ip()).await {
+                                    return;
+                                }
+                            }
+                            Ok(false) => {
+                                debug!("syslog connection from {} closed", peer);
+                                return;
+                            }
+                            Err(err) => {
+                                warn!("syslog connection from {} failed: {}", peer, err);
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        }));
+    }
+
+    for receive_loop in receive_loops {
+        receive_loop.await??;
+    }
+
+    Ok(())
+}
+
+/// Malformed messages are skipped
+fn parse(
+    message: &[u8],
+    entries: &NetworkEntries,
+    peer: std::net::SocketAddr,
+) -> Option<JournalEntry> {
+    match parse_syslog_message(message, &entries.config().options()) {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            warn!(
+                "malformed syslog message from {}, skipping it: {}",
+                peer, err
+            );
+            metrics::inc_malformed_input_discarded(message.len() as u64);
+            None
+        }
+    }
+}
+
+/// Reads the next message of a TCP connection into `frame`, returns false at
+/// the end of the connection. Octet counting (`LENGTH MESSAGE`) is recognized
+/// by the leading digit, as messages otherwise start with `<`.
+async fn read_frame<R>(reader: &mut R, frame: &mut Vec<u8>, max_size: usize) -> io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    frame.clear();
+    let first = match reader.fill_buf().await?.first() {
+        Some(&first) => first,
+        None => return Ok(false),
+    };
+
+    if first.is_ascii_digit() {
+        let mut length = Vec::new();
+        (&mut *reader)
+            .take(11)
+            .read_until(b' ', &mut length)
+            .await?;
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|length| length.strip_suffix(' '))
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|&length| length <= max_size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid message length"))?;
+
+        frame.resize(length, 0);
+        reader.read_exact(frame).await?;
+    } else {
+        read_delimited(reader, frame, b'\n', max_size).await?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,262 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use journalsql_pipeline::{Quantities, Sink, SinkFuture};
+use time::format_description::well_known::Rfc3339;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::TlsConnector;
+
+use crate::config::{SyslogOutputConfig, SyslogProtocol};
+use crate::inserter::InsertError;
+use crate::matcher::Matcher;
+use crate::metrics::{self, PipelineStage};
+use crate::row::LogRecordRow;
+use crate::tls;
+use crate::Error;
+
+/// Longest values of the header fields, RFC 5424 section 6
+const MAX_HOSTNAME: usize = 255;
+const MAX_APP_NAME: usize = 48;
+const MAX_PROCID: usize = 128;
+const MAX_MSGID: usize = 32;
+
+enum Connection {
+    Datagram(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Send + Unpin>),
+}
+
+/// Forwards the rows matching a filter as RFC 5424 syslog messages, in UDP
+/// datagrams or over TCP or TLS connections framed by octet counting (RFC 6587,
+/// RFC 5425), batched by entry count and time like `Inserter`. A batch whose
+/// send failed is sent again in full, so the receiver may get messages twice.
+pub struct SyslogForwarder {
+    address: String,
+    protocol: SyslogProtocol,
+    tls: Option<(TlsConnector, rustls::ServerName)>,
+    filter: Option<Matcher>,
+    facility: u8,
+    max_message_size: usize,
+    timeout: Duration,
+    /// `None` until connected, and again after a failed or cancelled send left
+    /// the stream in an unknown state
+    connection: Option<Connection>,
+    /// Messages not sent yet
+    messages: Vec<Vec<u8>>,
+    last_cursor: Option<String>,
+    max_entries: u64,
+    period: Duration,
+    last_send: Instant,
+    committed_cursor: Option<String>,
+}
+
+impl SyslogForwarder {
+    /// Connects lazily, so an unreachable receiver fails the first send
+    pub fn new(config: &SyslogOutputConfig) -> Result<Self, Error> {
+        let tls = (config.protocol == SyslogProtocol::Tls)
+            .then(|| {
+                tls::connector(
+                    &config.address,
+                    config.server_name.as_deref(),
+                    config.ca_file.as_deref(),
+                    config.cert_file.as_deref(),
+                    config.key_file.as_deref(),
+                )
+            })
+            .transpose()?;
+
+        Ok(Self {
+            address: config.address.clone(),
+            protocol: config.protocol,
+            tls,
+            filter: config.filter.clone(),
+            facility: config.facility.min(23),
+            max_message_size: config.max_message_size,
+            timeout: config.timeout(),
+            connection: None,
+            messages: Vec::new(),
+            last_cursor: None,
+            max_entries: config.max_entries,
+            period: config.period(),
+            last_send: Instant::now(),
+            committed_cursor: None,
+        })
+    }
+
+    async fn connect(&self) -> io::Result<Connection> {
+        if self.protocol == SyslogProtocol::Udp {
+            let address = tokio::net::lookup_host(self.address.as_str())
+                .await?
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not found"))?;
+            let socket = UdpSocket::bind(if address.is_ipv6() {
+                "[::]:0"
+            } else {
+                "0.0.0.0:0"
+            })
+            .await?;
+            socket.connect(address).await?;
+            return Ok(Connection::Datagram(socket));
+        }
+
+        let stream = TcpStream::connect(self.address.as_str()).await?;
+        stream.set_nodelay(true)?;
+        match &self.tls {
+            Some((connector, server_name)) => Ok(Connection::Stream(Box::new(
+                connector.connect(server_name.clone(), stream).await?,
+            ))),
+            None => Ok(Connection::Stream(Box::new(stream))),
+        }
+    }
+
+    /// Messages stay buffered until the send succeeds, so a cancelled send is
+    /// retried by the next flush
+    async fn send(&mut self) -> Result<Quantities, InsertError> {
+        self.last_send = Instant::now();
+        if self.messages.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let started = Instant::now();
+        let written = async {
+            let mut connection = match self.connection.take() {
+                Some(connection) => connection,
+                None => self.connect().await?,
+            };
+            match &mut connection {
+                Connection::Datagram(socket) => {
+                    for message in &self.messages {
+                        socket.send(message).await?;
+                    }
+                }
+                Connection::Stream(stream) => {
+                    let mut data = Vec::new();
+                    for message in &self.messages {
+                        data.extend_from_slice(format!("{} ", message.len()).as_bytes());
+                        data.extend_from_slice(message);
+                    }
+                    stream.write_all(&data).await?;
+                    stream.flush().await?;
+                }
+            }
+            Ok::<_, io::Error>(connection)
+        };
+        let connection = tokio::time::timeout(self.timeout, written)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+            .map_err(InsertError::SyslogError)?;
+        self.connection = Some(connection);
+        metrics::observe_stage_duration(PipelineStage::Insert, started.elapsed());
+
+        self.committed_cursor = self.last_cursor.take();
+        let messages = std::mem::take(&mut self.messages);
+
+        Ok(Quantities {
+            entries: messages.len() as u64,
+            transactions: 1,
+        })
+    }
+
+    /// RFC 5424 message of `row`, truncated to `max_message_size`
+    fn message(&self, row: &LogRecordRow) -> Vec<u8> {
+        let severity = field_number(row, "PRIORITY").map_or(6, |severity| severity.min(7));
+        let facility =
+            field_number(row, "SYSLOG_FACILITY").map_or(self.facility, |facility| facility.min(23));
+        let app_name = row
+            .field("SYSLOG_IDENTIFIER")
+            .or_else(|| row.field("_COMM"));
+        let procid = row.field("_PID").or_else(|| row.field("SYSLOG_PID"));
+
+        let mut message = format!(
+            "<{}>1 {} {} {} {} {} - ",
+            facility * 8 + severity,
+            row.timestamp
+                .format(&Rfc3339)
+                .unwrap_or_else(|_| String::from("-")),
+            header_field(Some(&row.hostname), MAX_HOSTNAME),
+            header_field(app_name, MAX_APP_NAME),
+            header_field(procid, MAX_PROCID),
+            header_field(row.field("MESSAGE_ID"), MAX_MSGID),
+        );
+        message.push_str(row.field("MESSAGE").unwrap_or_default());
+        if message.len() > self.max_message_size {
+            let mut end = self.max_message_size;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+
+        message.into_bytes()
+    }
+}
+
+impl Sink<LogRecordRow> for SyslogForwarder {
+    /// Rows not matching the filter are skipped, but still move the committed
+    /// cursor along once the rows before them were sent
+    fn write_batch(&mut self, rows: Vec<LogRecordRow>) {
+        for row in rows {
+            if self
+                .filter
+                .as_ref()
+                .map_or(true, |filter| filter.matches(&row))
+            {
+                self.messages.push(self.message(&row));
+            }
+            self.last_cursor = Some(row.cursor);
+        }
+        if self.messages.is_empty() {
+            if let Some(cursor) = self.last_cursor.take() {
+                self.committed_cursor = Some(cursor);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if self.messages.len() as u64 >= self.max_entries
+                || self.last_send.elapsed() >= self.period
+            {
+                Ok(self.send().await?)
+            } else {
+                Ok(Quantities::default())
+            }
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let res = self.send().await?;
+            if let Some(Connection::Stream(mut stream)) = self.connection.take() {
+                let _ = stream.shutdown().await;
+            }
+
+            Ok(res)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+fn field_number(row: &LogRecordRow, field: &str) -> Option<u8> {
+    row.field(field)?.trim().parse().ok()
+}
+
+/// Header field of at most `max` printable ASCII characters, others replaced
+/// by `_`, or `-` when missing
+fn header_field(value: Option<&str>, max: usize) -> String {
+    match value.filter(|value| !value.is_empty()) {
+        Some(value) => value
+            .chars()
+            .take(max)
+            .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+            .collect(),
+        None => String::from("-"),
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, warn};
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, BufReader, SeekFrom};
+use tokio::task::JoinHandle;
+
+use crate::metrics;
+use crate::network::{read_delimited, NetworkEntries};
+
+/// How often a log file is checked for more data once at its end
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tasks following the log files of container runtimes, one per path
+#[derive(Default)]
+pub struct Tailers {
+    tailers: HashMap<PathBuf, JoinHandle<()>>,
+}
+
+impl Tailers {
+    /// Forgets files which are no longer followed, so they are picked up again
+    /// if they reappear
+    pub fn retain_running(&mut self) {
+        self.tailers.retain(|_, tailer| !tailer.is_finished());
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.tailers.contains_key(path)
+    }
+
+    /// Follows `path` from its start or its end, passing complete lines to
+    /// `parse` and submitting the entries it returns
+    pub fn spawn<P>(
+        &mut self,
+        path: PathBuf,
+        from_end: bool,
+        max_line_size: usize,
+        transport: &'static str,
+        entries: NetworkEntries,
+        parse: P,
+    ) where
+        P: FnMut(&[u8]) -> Option<JournalEntry> + Send + 'static,
+    {
+        debug!("tailing {}", path.display());
+        let tailed = path.clone();
+        let tailer = tokio::task::spawn(async move {
+            let result = tail(&tailed, from_end, max_line_size, transport, entries, parse);
+            if let Err(err) = result.await {
+                warn!("failed to read {}: {}", tailed.display(), err);
+            }
+        });
+        self.tailers.insert(path, tailer);
+    }
+}
+
+/// Reads lines as they are appended until the consumer goes away or the file
+/// is removed. A file replaced by another, or truncated, is read again from
+/// the start, as runtimes rotate logs by renaming them.
+async fn tail<P>(
+    path: &Path,
+    from_end: bool,
+    max_line_size: usize,
+    transport: &'static str,
+    entries: NetworkEntries,
+    mut parse: P,
+) -> io::Result<()>
+where
+    P: FnMut(&[u8]) -> Option<JournalEntry>,
+{
+    let mut file = File::open(path).await?;
+    let mut inode = file.metadata().await?.ino();
+    let mut position = if from_end {
+        file.seek(SeekFrom::End(0)).await?
+    } else {
+        0
+    };
+    let mut reader = BufReader::new(file);
+    let mut frame = Vec::new();
+    // Until the end of a line exceeding the limit
+    let mut skipping = false;
+
+    loop {
+        let before = frame.len();
+        let result = read_delimited(&mut reader, &mut frame, b'\n', max_line_size).await;
+        position += (frame.len() - before) as u64;
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(FOLLOW_INTERVAL) => {},
+                    _ = entries.closed() => return Ok(()),
+                }
+
+                let metadata = match tokio::fs::metadata(path).await {
+                    Ok(metadata) => metadata,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        debug!("{} was removed", path.display());
+                        return Ok(());
+                    }
+                    Err(err) => return Err(err),
+                };
+                if metadata.ino() != inode || metadata.len() < position {
+                    debug!("{} was rotated, reading it from the start", path.display());
+                    let file = File::open(path).await?;
+                    inode = file.metadata().await?.ino();
+                    reader = BufReader::new(file);
+                    position = 0;
+                    frame.clear();
+                }
+                continue;
+            }
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                warn!("line in {} is too long, skipping it", path.display());
+                metrics::inc_malformed_input_discarded(frame.len() as u64);
+                frame.clear();
+                skipping = true;
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
+
+        // The rest of the line is yet to be written
+        if frame.last() != Some(&b'\n') {
+            continue;
+        }
+        if skipping {
+            skipping = false;
+            frame.clear();
+            continue;
+        }
+
+        let entry = parse(&frame[..frame.len() - 1]);
+        frame.clear();
+        let Some(entry) = entry else {
+            continue;
+        };
+        if !entries
+            .submit(entry, transport, Ipv4Addr::LOCALHOST.into())
+            .await
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Fields of this machine, as container logs don't pass through its journal
+pub fn host_fields() -> io::Result<Vec<(&'static str, String)>> {
+    let machine_id = std::fs::read_to_string("/etc/machine-id")?;
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")?;
+
+    Ok(vec![
+        ("_MACHINE_ID", machine_id.trim().to_string()),
+        ("_BOOT_ID", boot_id.trim().replace('-', "")),
+        ("_HOSTNAME", hostname.trim().to_string()),
+    ])
+}
+
+/// `MESSAGE` of a container with `fields`, `CONTAINER_STREAM` and, like
+/// Docker's `journald` logging driver sets them, `PRIORITY` 6 for stdout and 3
+/// for stderr. `time`, when the runtime logged the line, becomes
+/// `_SOURCE_REALTIME_TIMESTAMP`.
+pub fn container_entry(
+    fields: &[(&'static str, String)],
+    stream: &str,
+    time: &str,
+    message: &str,
+) -> JournalEntry {
+    let mut entry = JournalEntry::default();
+    for (key, value) in fields {
+        entry.put(*key, text(value.clone()));
+    }
+    entry.put("MESSAGE", text(message.to_string()));
+    entry.put("CONTAINER_STREAM", text(stream.to_string()));
+    let priority = if stream == "stderr" { "3" } else { "6" };
+    entry.put("PRIORITY", text(priority.to_string()));
+    if let Ok(time) = OffsetDateTime::parse(time, &Rfc3339) {
+        let micros = time.unix_timestamp_nanos() / 1000;
+        entry.put("_SOURCE_REALTIME_TIMESTAMP", text(micros.to_string()));
+    }
+
+    entry
+}
+
+fn text(value: String) -> JournalFieldValue {
+    JournalFieldValue::UTF8(value)
+}
@@ -0,0 +1,438 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use log::warn;
+use rustls::server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DistinguishedName};
+use tokio_rustls::TlsConnector;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::InputTlsConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("I/O error")]
+    IOError(std::io::Error),
+
+    #[error("TLS error")]
+    RustlsError(rustls::Error),
+
+    #[error("No private key found in {0}")]
+    MissingKey(String),
+
+    #[error("Unsupported private key type in {0}")]
+    UnsupportedKey(String),
+
+    #[error("No CA certificate found in {0}")]
+    MissingCa(String),
+
+    #[error("allowed_clients requires client_ca_file")]
+    AllowedClientsWithoutCa,
+
+    #[error("cert_file and key_file must be set together")]
+    PartialClientAuth,
+
+    #[error("Invalid server name {0:?}")]
+    InvalidServerName(String),
+}
+
+pub fn load_certificates(path: &Path) -> Result<Vec<rustls::Certificate>, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(TlsError::IOError)?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(TlsError::IOError)?;
+
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+pub fn load_private_key(path: &Path) -> Result<rustls::PrivateKey, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(TlsError::IOError)?);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader).map_err(TlsError::IOError)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(rustls::PrivateKey(key)),
+            _ => continue,
+        }
+    }
+
+    Err(TlsError::MissingKey(path.display().to_string()))
+}
+
+/// Client TLS configuration trusting `ca_file` (or the system roots when unset) and
+/// optionally authenticating with a client certificate.
+pub fn client_config(
+    ca_file: Option<&Path>,
+    client_auth: Option<(&Path, &Path)>,
+) -> Result<rustls::ClientConfig, TlsError> {
+    let certs: Vec<Vec<u8>> = match ca_file {
+        Some(ca_file) => load_certificates(ca_file)?
+            .into_iter()
+            .map(|cert| cert.0)
+            .collect(),
+        None => rustls_native_certs::load_native_certs()
+            .map_err(TlsError::IOError)?
+            .into_iter()
+            .map(|cert| cert.0)
+            .collect(),
+    };
+
+    // Certificates rustls can't parse are skipped, same as hyper-rustls does for native roots
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_parsable_certificates(&certs);
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    match client_auth {
+        Some((cert_file, key_file)) => builder
+            .with_client_auth_cert(load_certificates(cert_file)?, load_private_key(key_file)?)
+            .map_err(TlsError::RustlsError),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Connector for TLS connections to the `HOST:PORT` address, presenting
+/// `server_name` or the host of the address when unset. The server is verified
+/// like `client_config` does.
+pub fn connector(
+    address: &str,
+    server_name: Option<&str>,
+    ca_file: Option<&Path>,
+    cert_file: Option<&Path>,
+    key_file: Option<&Path>,
+) -> Result<(TlsConnector, rustls::ServerName), TlsError> {
+    let client_auth = match (cert_file, key_file) {
+        (Some(cert_file), Some(key_file)) => Some((cert_file, key_file)),
+        (None, None) => None,
+        _ => return Err(TlsError::PartialClientAuth),
+    };
+    let config = client_config(ca_file, client_auth)?;
+
+    let server_name = server_name.unwrap_or_else(|| {
+        // IPv6 addresses are bracketed
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        host.trim_start_matches('[').trim_end_matches(']')
+    });
+    let server_name = rustls::ServerName::try_from(server_name)
+        .map_err(|_| TlsError::InvalidServerName(server_name.to_string()))?;
+
+    Ok((TlsConnector::from(Arc::new(config)), server_name))
+}
+
+/// Server TLS configuration presenting each tenant's certificate for its
+/// server name. Handshakes without a known server name fail, as do those of
+/// clients without an accepted certificate when `client_ca_file` is set.
+pub fn server_config(config: &InputTlsConfig) -> Result<rustls::ServerConfig, TlsError> {
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    for tenant in &config.tenants {
+        let key = load_private_key(&tenant.key_file)?;
+        let key = rustls::sign::any_supported_type(&key)
+            .map_err(|_| TlsError::UnsupportedKey(tenant.key_file.display().to_string()))?;
+        let certified = rustls::sign::CertifiedKey::new(load_certificates(&tenant.cert_file)?, key);
+
+        resolver
+            .add(&tenant.server_name, certified)
+            .map_err(TlsError::RustlsError)?;
+    }
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_file {
+        Some(ca_file) => {
+            let certs: Vec<Vec<u8>> = load_certificates(ca_file)?
+                .into_iter()
+                .map(|cert| cert.0)
+                .collect();
+            let mut roots = rustls::RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(&certs);
+            if added == 0 {
+                return Err(TlsError::MissingCa(ca_file.display().to_string()));
+            }
+
+            builder.with_client_cert_verifier(Arc::new(AllowedClients {
+                inner: AllowAnyAuthenticatedClient::new(roots).boxed(),
+                patterns: config.allowed_clients.clone(),
+            }))
+        }
+        None if !config.allowed_clients.is_empty() => {
+            return Err(TlsError::AllowedClientsWithoutCa)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(builder.with_cert_resolver(Arc::new(resolver)))
+}
+
+/// Verifies client certificates against the CAs, then their names against
+/// the allowed patterns
+struct AllowedClients {
+    inner: Arc<dyn ClientCertVerifier>,
+    patterns: Vec<String>,
+}
+
+impl ClientCertVerifier for AllowedClients {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        if self.patterns.is_empty() {
+            return Ok(verified);
+        }
+
+        let names = certificate_names(end_entity).ok_or(rustls::Error::InvalidCertificate(
+            CertificateError::BadEncoding,
+        ))?;
+        let allowed = names.iter().any(|name| {
+            self.patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name))
+        });
+        if !allowed {
+            warn!("refusing client certificate for {:?}", names);
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::Certificate,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::Certificate,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Common names and DNS and URI subject alternative names of a certificate
+pub fn certificate_names(cert: &rustls::Certificate) -> Option<Vec<String>> {
+    let (_, cert) = X509Certificate::from_der(&cert.0).ok()?;
+
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|name| name.as_str().ok())
+        .map(String::from)
+        .collect();
+    if let Ok(Some(alternative_names)) = cert.subject_alternative_name() {
+        for name in &alternative_names.value.general_names {
+            if let GeneralName::DNSName(name) | GeneralName::URI(name) = name {
+                names.push(name.to_string());
+            }
+        }
+    }
+
+    Some(names)
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any characters
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed, CN `forwarder-1.example.com`, SANs
+    /// `DNS:logs.eu.example.com` and `URI:spiffe://example.com/forwarder`
+    const CLIENT_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIB3TCCAYOgAwIBAgIUDFqNVaNBbDcFhKIwsPbiU65XVp0wCgYIKoZIzj0EAwIw
+IjEgMB4GA1UEAwwXZm9yd2FyZGVyLTEuZXhhbXBsZS5jb20wIBcNMjYxMDE1MTEw
+NjU2WhgPMjEyNjA5MjExMTA2NTZaMCIxIDAeBgNVBAMMF2ZvcndhcmRlci0xLmV4
+YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEGx7hVoLAoJuGEPvo
+bULDZRtsj0/16zYvNiaLGb9blpFSLcyK8p16u8uoiY6QsmCk9HMsm58+3lUDQusI
+1ZKRm6OBlDCBkTAdBgNVHQ4EFgQUxrt1soV1VPYdI6Tm+sfEzPbTHogwHwYDVR0j
+BBgwFoAUxrt1soV1VPYdI6Tm+sfEzPbTHogwDwYDVR0TAQH/BAUwAwEB/zA+BgNV
+HREENzA1ghNsb2dzLmV1LmV4YW1wbGUuY29thh5zcGlmZmU6Ly9leGFtcGxlLmNv
+bS9mb3J3YXJkZXIwCgYIKoZIzj0EAwIDSAAwRQIgRKiTs+ex8b4BgyJ/91IyidcA
+UKPg7xn0mWPv0maVW9MCIQCgpUVeI2ZJ4hPZjHb4EOP4ZRlEVz+d+MnjUpWFYjcI
+sQ==
+-----END CERTIFICATE-----
+";
+
+    /// Verifier standing in for the CA check
+    struct Ca {
+        trusted: bool,
+    }
+
+    impl ClientCertVerifier for Ca {
+        fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+            &[]
+        }
+
+        fn verify_client_cert(
+            &self,
+            _end_entity: &rustls::Certificate,
+            _intermediates: &[rustls::Certificate],
+            _now: SystemTime,
+        ) -> Result<ClientCertVerified, rustls::Error> {
+            if self.trusted {
+                Ok(ClientCertVerified::assertion())
+            } else {
+                Err(rustls::Error::InvalidCertificate(
+                    CertificateError::UnknownIssuer,
+                ))
+            }
+        }
+    }
+
+    fn client_cert() -> rustls::Certificate {
+        let mut certs = rustls_pemfile::certs(&mut CLIENT_CERT.as_bytes()).unwrap();
+        rustls::Certificate(certs.remove(0))
+    }
+
+    fn verify(
+        trusted: bool,
+        patterns: &[&str],
+        cert: &rustls::Certificate,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verifier = AllowedClients {
+            inner: Arc::new(Ca { trusted }),
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+        };
+        verifier.verify_client_cert(cert, &[], SystemTime::now())
+    }
+
+    #[test]
+    fn matches_exact_names() {
+        assert!(matches_pattern("a.example.com", "a.example.com"));
+        assert!(!matches_pattern("a.example.com", "b.example.com"));
+        assert!(!matches_pattern("a.example.com", "a.example.com.evil"));
+        assert!(!matches_pattern("a.example.com", "xa.example.com"));
+        assert!(matches_pattern("", ""));
+        assert!(!matches_pattern("", "a"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_pattern("*.example.com", "a.example.com"));
+        assert!(matches_pattern("*.example.com", "a.b.example.com"));
+        assert!(!matches_pattern("*.example.com", "example.com"));
+        assert!(!matches_pattern("*.example.com", "a.example.org"));
+
+        assert!(matches_pattern("forwarder-*", "forwarder-1"));
+        assert!(matches_pattern("forwarder-*", "forwarder-"));
+        assert!(!matches_pattern("forwarder-*", "forwarder"));
+
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*", "anything"));
+        assert!(matches_pattern("a*b*c", "abc"));
+        assert!(matches_pattern("a*b*c", "a-b-b-c"));
+        assert!(!matches_pattern("a*b*c", "acb"));
+        assert!(matches_pattern(
+            "spiffe://example.com/*",
+            "spiffe://example.com/forwarder"
+        ));
+    }
+
+    #[test]
+    fn literals_around_wildcards_do_not_overlap() {
+        assert!(!matches_pattern("ab*ba", "aba"));
+        assert!(matches_pattern("ab*ba", "abba"));
+        assert!(matches_pattern("ab*ba", "abxba"));
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(matches_pattern("a*a", "aa"));
+        assert!(!matches_pattern("a*bc*c", "abc"));
+        assert!(matches_pattern("a*bc*c", "abcc"));
+    }
+
+    #[test]
+    fn reads_certificate_names() {
+        assert_eq!(
+            certificate_names(&client_cert()).unwrap(),
+            [
+                "forwarder-1.example.com",
+                "logs.eu.example.com",
+                "spiffe://example.com/forwarder"
+            ]
+        );
+        assert_eq!(
+            certificate_names(&rustls::Certificate(b"garbage".to_vec())),
+            None
+        );
+    }
+
+    #[test]
+    fn accepts_certificates_with_an_allowed_name() {
+        let cert = client_cert();
+
+        assert!(verify(true, &[], &cert).is_ok());
+        assert!(verify(true, &["forwarder-1.example.com"], &cert).is_ok());
+        assert!(verify(true, &["*.eu.example.com"], &cert).is_ok());
+        assert!(verify(true, &["other", "spiffe://example.com/*"], &cert).is_ok());
+    }
+
+    #[test]
+    fn rejects_certificates() {
+        let cert = client_cert();
+
+        // Signed by an unknown CA, whatever the name
+        assert!(matches!(
+            verify(false, &["*"], &cert),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer
+            ))
+        ));
+        assert!(matches!(
+            verify(true, &["*.us.example.com", "forwarder-2.*"], &cert),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure
+            ))
+        ));
+        assert!(matches!(
+            verify(true, &["*"], &rustls::Certificate(b"garbage".to_vec())),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::BadEncoding
+            ))
+        ));
+    }
+}
@@ -0,0 +1,442 @@
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::config::{Config, DeadLetterOutput, JournalSource};
+use crate::repeat::RepeatCompressor;
+use crate::sampling::Sampler;
+use crate::slo::SloTracker;
+use crate::source_cursors::SourceCursors;
+use crate::transform::Transform;
+use crate::Error;
+
+const USAGE: &str = "usage: journalsqld topology [--format dot|mermaid]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Dot,
+    Mermaid,
+}
+
+/// Directed graph of pipeline stages, each node labeled with its settings
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<String>,
+    edges: Vec<(usize, usize, Option<String>)>,
+}
+
+impl Graph {
+    fn node<S: Into<String>>(&mut self, label: S) -> usize {
+        self.nodes.push(label.into());
+        self.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        self.edges.push((from, to, None));
+    }
+
+    fn labeled_edge<S: Into<String>>(&mut self, from: usize, to: usize, label: S) {
+        self.edges.push((from, to, Some(label.into())));
+    }
+
+    fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+
+        match format {
+            Format::Dot => {
+                out.push_str("digraph journalsqld {\n    rankdir=LR;\n    node [shape=box];\n");
+                for (id, label) in self.nodes.iter().enumerate() {
+                    let _ = writeln!(out, "    n{} [label=\"{}\"];", id, escape_dot(label));
+                }
+                for (from, to, label) in self.edges.iter() {
+                    match label {
+                        Some(label) => {
+                            let _ = writeln!(
+                                out,
+                                "    n{} -> n{} [label=\"{}\"];",
+                                from,
+                                to,
+                                escape_dot(label)
+                            );
+                        }
+                        None => {
+                            let _ = writeln!(out, "    n{} -> n{};", from, to);
+                        }
+                    }
+                }
+                out.push_str("}\n");
+            }
+            Format::Mermaid => {
+                out.push_str("flowchart LR\n");
+                for (id, label) in self.nodes.iter().enumerate() {
+                    let _ = writeln!(out, "    n{}[\"{}\"]", id, escape_mermaid(label));
+                }
+                for (from, to, label) in self.edges.iter() {
+                    match label {
+                        Some(label) => {
+                            let _ = writeln!(
+                                out,
+                                "    n{} -->|\"{}\"| n{}",
+                                from,
+                                escape_mermaid(label),
+                                to
+                            );
+                        }
+                        None => {
+                            let _ = writeln!(out, "    n{} --> n{}", from, to);
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// `journalsqld topology`, prints the configured pipeline as a Graphviz or
+/// Mermaid graph. Stages are described by the objects the daemon would run
+/// with; inputs are described from the configuration, as opening them has side
+/// effects.
+pub fn run<F>(config: &Config, args: Vec<OsString>, client: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<Client, Error>,
+{
+    let args = args
+        .iter()
+        .map(|arg| arg.to_str().ok_or(USAGE))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let format = match args.as_slice() {
+        [] | ["--format", "dot"] => Format::Dot,
+        ["--format", "mermaid"] => Format::Mermaid,
+        _ => return Err(USAGE.into()),
+    };
+
+    let graph = build(config, client()?);
+    print!("{}", graph.render(format));
+
+    Ok(())
+}
+
+fn build(config: &Config, db: Client) -> Graph {
+    let mut graph = Graph::default();
+
+    // Inputs
+    let tls_tenants = config.input_tls.enabled.then(|| {
+        let tenants: Vec<_> = config
+            .input_tls
+            .tenants
+            .iter()
+            .map(|tenant| tenant.server_name.as_str())
+            .collect();
+        format!("\nTLS tenants: {}", tenants.join(", "))
+    });
+    let mut inputs = Vec::new();
+    if config.journal_upload.enabled {
+        let source = match config.journal_upload.source {
+            JournalSource::Journalctl => String::from("journalctl -o export"),
+            JournalSource::SdJournal => String::from("sd_journal API"),
+            JournalSource::Directory => format!(
+                "journal files, inotify\n{}",
+                config
+                    .journal_upload
+                    .directory()
+                    .map_or_else(|err| err.to_string(), |dir| dir.display().to_string())
+            ),
+        };
+        inputs.push(graph.node(format!(
+            "{}\nstate {}",
+            source,
+            config.journal_upload.state_file.display()
+        )));
+    }
+    if !config.input.listen.is_empty() {
+        let mut listeners = format!("export listeners\n{}", addresses(&config.input.listen));
+        listeners.push_str(tls_tenants.as_deref().unwrap_or_default());
+        inputs.push(graph.node(listeners));
+    }
+    if !config.remote.listen.is_empty() {
+        let mut remote = format!(
+            "journal-remote uploads\nPOST /upload on {}",
+            addresses(&config.remote.listen)
+        );
+        if config.input_tls.enabled {
+            remote.push_str(", TLS");
+        }
+        inputs.push(graph.node(remote));
+    }
+    for file in &config.input.files {
+        let mut label = format!("file {}\n{}", file.label(), file.path.display());
+        if file.follow {
+            label.push_str(", followed");
+        }
+        inputs.push(graph.node(label));
+    }
+    if config.kafka.enabled {
+        inputs.push(graph.node(format!(
+            "Kafka {}\ntopics {}, group {}, {:?}",
+            config.kafka.brokers,
+            config.kafka.topics.join(", "),
+            config.kafka.group_id,
+            config.kafka.format
+        )));
+    }
+    if config.syslog.is_enabled() {
+        inputs.push(graph.node(format!(
+            "syslog receiver\n{}",
+            udp_tcp_addresses(&config.syslog.listen_udp, &config.syslog.listen_tcp)
+        )));
+    }
+    if config.gelf.is_enabled() {
+        inputs.push(graph.node(format!(
+            "GELF receiver\n{}",
+            udp_tcp_addresses(&config.gelf.listen_udp, &config.gelf.listen_tcp)
+        )));
+    }
+    if config.docker.enabled {
+        inputs.push(graph.node(format!(
+            "Docker json-file logs\n{}",
+            config.docker.containers_path.display()
+        )));
+    }
+    if config.pod_logs.enabled {
+        inputs.push(graph.node(format!(
+            "Kubernetes pod logs\n{}",
+            config.pod_logs.path.display()
+        )));
+    }
+    if !config.fluent.listen.is_empty() {
+        inputs.push(graph.node(format!(
+            "Fluent forward\n{}",
+            addresses(&config.fluent.listen)
+        )));
+    }
+    if !config.grpc.listen.is_empty() {
+        inputs.push(graph.node(format!(
+            "gRPC ingestion\n{}, {} batches in flight",
+            addresses(&config.grpc.listen),
+            config.grpc.max_in_flight
+        )));
+    }
+    if config.amqp.enabled {
+        inputs.push(graph.node(format!(
+            "AMQP queue {}\nprefetch {}, {:?}",
+            config.amqp.queue, config.amqp.prefetch, config.amqp.format
+        )));
+    }
+    // Only read when no other input is configured and no sockets are passed
+    if inputs.is_empty() {
+        inputs.push(graph.node("stdin\nexport format, gzip/zstd"));
+    }
+    let mut sockets = format!(
+        "activated sockets \"{}\"",
+        config.socket_activation.input_name
+    );
+    sockets.push_str(tls_tenants.as_deref().unwrap_or_default());
+    inputs.push(graph.node(sockets));
+    if config.spool.enabled {
+        inputs.push(graph.node(format!("spool replay\n{}", config.spool.path.display())));
+    }
+
+    let parser = &config.parser;
+    let parse = graph.node(format!(
+        "parse\nutf8 {:?}, keys {:?}, recover {}\nmax field {} B, {} fields, entry {} B",
+        parser.utf8,
+        parser.key_validation,
+        parser.recover,
+        parser.limits.max_field_size,
+        parser.limits.max_fields_per_entry,
+        parser.limits.max_entry_size
+    ));
+    for input in inputs {
+        graph.edge(input, parse);
+    }
+
+    let machines = 1 + config.clickhouse.machines.len();
+    let queue = graph.node(format!(
+        "queue\ncapacity {}",
+        4 * num_cpus::get() * machines
+    ));
+    graph.edge(parse, queue);
+
+    let dead_letters = config.dead_letter.enabled.then(|| {
+        graph.node(match config.dead_letter.output {
+            DeadLetterOutput::File => {
+                format!("dead letters\n{}", config.dead_letter.path.display())
+            }
+            DeadLetterOutput::Clickhouse => {
+                format!(
+                    "dead letters\nClickHouse table {}",
+                    config.dead_letter.table
+                )
+            }
+        })
+    });
+    if let Some(dead_letters) = dead_letters {
+        graph.labeled_edge(parse, dead_letters, "malformed");
+    }
+    if config.spool.enabled {
+        let spill = graph.node(format!("spool\n{}", config.spool.path.display()));
+        graph.labeled_edge(queue, spill, "on shutdown");
+    }
+
+    // Stages, in the order the consumer runs them
+    let mut last = queue;
+    let sampler = Sampler::new(config.sampling.percent);
+    if sampler.is_enabled() {
+        let node = graph.node(format!("sample\nkeep {}%", sampler.percent()));
+        graph.edge(last, node);
+        last = node;
+    }
+
+    let transform = Transform::new(&config.transform);
+    let steps = transform.steps();
+    if !steps.is_empty() {
+        let node = graph.node(format!("transform\n{}", steps.join(", ")));
+        graph.edge(last, node);
+        last = node;
+    }
+
+    let convert = graph.node(format!(
+        "convert\nbytes as {:?}, {} field overrides",
+        config.bytes_rendering.default,
+        config.bytes_rendering.fields.len()
+    ));
+    graph.edge(last, convert);
+    if let Some(dead_letters) = dead_letters {
+        graph.labeled_edge(convert, dead_letters, "invalid");
+    }
+    last = convert;
+
+    if let Some(window) = RepeatCompressor::new(&config.repeat_compression).window() {
+        let node = graph.node(format!("repeat compression\nwindow {:?}", window));
+        graph.edge(last, node);
+        last = node;
+    }
+
+    // Sinks
+    let slo = config
+        .slo
+        .enabled
+        .then(|| Arc::new(SloTracker::new(&config.slo)));
+    let source_cursors = config
+        .source_cursors
+        .enabled
+        .then(|| Arc::new(SourceCursors::new(&config.source_cursors)));
+    if config.clickhouse.enabled {
+        let router = crate::inserter_router(config, &db, slo.as_ref(), source_cursors.as_ref());
+        for (route, inserter) in router.routes() {
+            let sink = graph.node(inserter.describe().join("\n"));
+            match route {
+                Some(route) => graph.labeled_edge(last, sink, route),
+                None => graph.labeled_edge(last, sink, "default"),
+            }
+        }
+    }
+    if config.file_output.enabled {
+        let sink = graph.node(format!(
+            "{:?} files\n{}",
+            config.file_output.format,
+            config.file_output.directory.display()
+        ));
+        graph.edge(last, sink);
+    }
+    if config.relay.enabled {
+        let sink = graph.node(format!("upstream journalsqld\n{}", config.relay.address));
+        graph.edge(last, sink);
+    }
+    if config.syslog_output.enabled {
+        let sink = graph.node(format!(
+            "syslog forwarder\n{:?} {}",
+            config.syslog_output.protocol, config.syslog_output.address
+        ));
+        graph.edge(last, sink);
+    }
+    if config.otlp.enabled {
+        let sink = graph.node(format!("OTLP exporter\n{}", config.otlp.endpoint));
+        graph.edge(last, sink);
+    }
+    if config.archive.enabled {
+        let sink = graph.node(format!("S3 archive\n{}", config.archive.bucket));
+        graph.edge(last, sink);
+    }
+    if config.sqlite.enabled {
+        let sink = graph.node(format!("SQLite\n{}", config.sqlite.path.display()));
+        graph.edge(last, sink);
+    }
+    if config.duckdb.enabled {
+        let sink = graph.node(format!("DuckDB\n{}", config.duckdb.path.display()));
+        graph.edge(last, sink);
+    }
+
+    graph
+}
+
+/// Comma separated list of listen addresses
+fn addresses<T: std::fmt::Display>(addresses: &[T]) -> String {
+    addresses
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Datagram and stream listen addresses, those of a protocol left out when
+/// there are none
+fn udp_tcp_addresses(udp: &[SocketAddr], tcp: &[SocketAddr]) -> String {
+    let mut protocols = Vec::new();
+    if !udp.is_empty() {
+        protocols.push(format!("UDP {}", addresses(udp)));
+    }
+    if !tcp.is_empty() {
+        protocols.push(format!("TCP {}", addresses(tcp)));
+    }
+    protocols.join("\n")
+}
+
+fn escape_dot(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;").replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::default();
+        let from = graph.node("queue\ncapacity 1000");
+        let to = graph.node("file \"a\\b\"");
+        graph.labeled_edge(from, to, "MESSAGE=\"x\"");
+        graph
+    }
+
+    #[test]
+    fn renders_dot() {
+        assert_eq!(
+            graph().render(Format::Dot),
+            "digraph journalsqld {\n    rankdir=LR;\n    node [shape=box];\n\
+             \x20   n0 [label=\"queue\\ncapacity 1000\"];\n\
+             \x20   n1 [label=\"file \\\"a\\\\b\\\"\"];\n\
+             \x20   n0 -> n1 [label=\"MESSAGE=\\\"x\\\"\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn renders_mermaid() {
+        assert_eq!(
+            graph().render(Format::Mermaid),
+            "flowchart LR\n    n0[\"queue<br>capacity 1000\"]\n    n1[\"file #quot;a\\b#quot;\"]\n\
+             \x20   n0 -->|\"MESSAGE=#quot;x#quot;\"| n1\n"
+        );
+    }
+}
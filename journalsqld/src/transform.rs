@@ -0,0 +1,123 @@
+use chardetng::EncodingDetector;
+use log::debug;
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+
+use crate::config::TransformConfig;
+
+const ESC: u8 = 0x1b;
+
+/// Rewrites field values before entries are turned into rows
+pub struct Transform {
+    strip_ansi_escapes: bool,
+    detect_charset: bool,
+    expand_structured_data: bool,
+}
+
+impl Transform {
+    pub fn new(config: &TransformConfig) -> Self {
+        Self {
+            strip_ansi_escapes: config.strip_ansi_escapes,
+            detect_charset: config.detect_charset,
+            expand_structured_data: config.expand_structured_data,
+        }
+    }
+
+    /// Names of the enabled steps, in the order they are applied
+    pub fn steps(&self) -> Vec<&'static str> {
+        let mut steps = Vec::new();
+        if self.detect_charset {
+            steps.push("detect_charset");
+        }
+        if self.strip_ansi_escapes {
+            steps.push("strip_ansi_escapes");
+        }
+        if self.expand_structured_data {
+            steps.push("expand_structured_data");
+        }
+
+        steps
+    }
+
+    pub fn apply(&self, entry: &mut JournalEntry) {
+        if self.detect_charset {
+            transcode_message(entry);
+        }
+
+        if self.strip_ansi_escapes {
+            for (_, value) in entry.iter_mut() {
+                strip_ansi_escapes(value);
+            }
+        }
+
+        if self.expand_structured_data {
+            expand_structured_data(entry);
+        }
+    }
+}
+
+/// Decodes a MESSAGE which isn't valid UTF-8 with the most likely legacy
+/// charset and records the charset in MESSAGE_CHARSET
+fn transcode_message(entry: &mut JournalEntry) {
+    let Some(JournalFieldValue::Bytes(data)) = entry.get("MESSAGE") else {
+        return;
+    };
+    if std::str::from_utf8(data).is_ok() {
+        return;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(data, true);
+    let encoding = detector.guess(None, false);
+    let (text, _, _) = encoding.decode(data);
+    let text = text.into_owned();
+
+    entry.put("MESSAGE", JournalFieldValue::UTF8(text));
+    entry.put(
+        "MESSAGE_CHARSET",
+        JournalFieldValue::UTF8(String::from(encoding.name())),
+    );
+}
+
+fn strip_ansi_escapes(value: &mut JournalFieldValue) {
+    match value {
+        JournalFieldValue::UTF8(text) if text.as_bytes().contains(&ESC) => {
+            let stripped = strip_ansi_escapes::strip(text.as_bytes());
+            *text = String::from_utf8_lossy(&stripped).into_owned();
+        }
+        JournalFieldValue::Bytes(data) if data.contains(&ESC) => {
+            *data = strip_ansi_escapes::strip(&data[..]).into();
+        }
+        _ => {}
+    }
+}
+
+/// Adds each parameter of SYSLOG_STRUCTURED_DATA as a
+/// `SYSLOG_SD_<SD-ID>_<PARAM-NAME>` field, names uppercased and characters not
+/// allowed in field keys replaced with underscores. Malformed structured data
+/// is left as it is.
+fn expand_structured_data(entry: &mut JournalEntry) {
+    let elements = match entry.structured_data() {
+        Some(Ok(elements)) => elements,
+        Some(Err(err)) => {
+            debug!("not expanding SYSLOG_STRUCTURED_DATA: {}", err);
+            return;
+        }
+        None => return,
+    };
+
+    for element in elements {
+        for (name, value) in element.params {
+            let key = format!("SYSLOG_SD_{}_{}", key_part(&element.id), key_part(&name));
+            entry.put_multi(key, JournalFieldValue::UTF8(value));
+        }
+    }
+}
+
+fn key_part(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9') => c,
+            _ => '_',
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading and parsing input, busy while a parsed entry waits for channel space
+    Producer,
+    /// Buffering and inserting rows, busy while there are uncommitted rows
+    Consumer,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Producer => write!(f, "producer"),
+            Self::Consumer => write!(f, "consumer"),
+        }
+    }
+}
+
+/// Liveness tracking of the pipeline stages. A stage which stays busy for longer
+/// than the stall timeout marks the process unhealthy; a stalled consumer is asked
+/// to abandon its in-flight insert and retry it.
+pub struct Watchdog {
+    started: Instant,
+    timeout: Duration,
+    // Milliseconds since `started` plus one, zero while idle
+    producer_busy_since: AtomicU64,
+    consumer_busy_since: AtomicU64,
+    produced: AtomicU64,
+    consumed: AtomicU64,
+    healthy: AtomicBool,
+    restart: Arc<Notify>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            timeout,
+            producer_busy_since: AtomicU64::new(0),
+            consumer_busy_since: AtomicU64::new(0),
+            produced: AtomicU64::new(0),
+            consumed: AtomicU64::new(0),
+            healthy: AtomicBool::new(true),
+            restart: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Marks the stage as having pending work, keeping the earlier time if it already had
+    pub fn busy(&self, stage: Stage) {
+        let now = self.started.elapsed().as_millis() as u64 + 1;
+        let busy_since = self.busy_since(stage);
+        let _ = busy_since.compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn idle(&self, stage: Stage) {
+        self.busy_since(stage).store(0, Ordering::Relaxed);
+    }
+
+    pub fn produced(&self) {
+        self.produced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn consumed(&self) {
+        self.consumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Resolves when the watchdog asks the consumer to restart its in-flight work
+    pub async fn restart_requested(&self) {
+        self.restart.notified().await
+    }
+
+    /// Notified when the watchdog asks the consumer to restart, for the sinks
+    /// running in tasks of their own
+    pub fn restart_signal(&self) -> Arc<Notify> {
+        self.restart.clone()
+    }
+
+    /// Checks stage liveness until the future is dropped
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval((self.timeout / 4).max(Duration::from_secs(1)));
+        let mut stalled = Vec::new();
+
+        loop {
+            interval.tick().await;
+
+            let now = self.started.elapsed();
+            let mut still_stalled = Vec::new();
+            for stage in [Stage::Producer, Stage::Consumer] {
+                let since = self.busy_since(stage).load(Ordering::Relaxed);
+                if since == 0 {
+                    continue;
+                }
+
+                let busy_for = now.saturating_sub(Duration::from_millis(since - 1));
+                if busy_for < self.timeout {
+                    continue;
+                }
+
+                still_stalled.push(stage);
+                error!(
+                    "{} stage stalled for {:?}, produced={} consumed={}",
+                    stage,
+                    busy_for,
+                    self.produced.load(Ordering::Relaxed),
+                    self.consumed.load(Ordering::Relaxed)
+                );
+
+                if stage == Stage::Consumer {
+                    info!("restarting consumer stage");
+                    self.restart.notify_waiters();
+                }
+            }
+
+            if still_stalled.is_empty() && !stalled.is_empty() {
+                info!("pipeline stages recovered");
+            }
+
+            self.healthy
+                .store(still_stalled.is_empty(), Ordering::Relaxed);
+            stalled = still_stalled;
+        }
+    }
+
+    fn busy_since(&self, stage: Stage) -> &AtomicU64 {
+        match stage {
+            Stage::Producer => &self.producer_busy_since,
+            Stage::Consumer => &self.consumer_busy_since,
+        }
+    }
+}
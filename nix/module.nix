@@ -0,0 +1,163 @@
+{ config, lib, pkgs, ... }:
+
+let
+  cfg = config.services.journalsqld;
+
+  settingsFormat = pkgs.formats.toml { };
+  configFile = settingsFormat.generate "journalsqld.toml" cfg.settings;
+
+  journalctlArgs = [
+    "--output=export"
+  ]
+  ++ lib.optional cfg.journalctl.follow "--follow"
+  ++ lib.optional (cfg.journalctl.since != null) "--since=${cfg.journalctl.since}"
+  ++ cfg.journalctl.extraArgs;
+
+  socketActivated = cfg.listenStreams != [ ];
+in
+{
+  options.services.journalsqld = {
+    enable = lib.mkEnableOption "journalsqld, shipping the systemd journal to ClickHouse";
+
+    package = lib.mkOption {
+      type = lib.types.package;
+      description = "journalsqld package to use.";
+    };
+
+    settings = lib.mkOption {
+      type = settingsFormat.type;
+      default = { };
+      example = lib.literalExpression ''
+        {
+          clickhouse.table = "logs2";
+          ingest_metadata.enabled = true;
+        }
+      '';
+      description = ''
+        journalsqld configuration, see `doc/journalsqld.toml` for available options.
+        Do not put credentials here, as the file ends up in the Nix store.
+      '';
+    };
+
+    clickhouseUriFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      example = "/run/secrets/journalsqld-clickhouse-uri";
+      description = ''
+        File containing the ClickHouse URI including credentials. It is passed
+        to the service as a systemd credential and exported as `CLICKHOUSE_URI`.
+      '';
+    };
+
+    logLevel = lib.mkOption {
+      type = lib.types.str;
+      default = "info";
+      description = "Value of `RUST_LOG` for the service.";
+    };
+
+    listenStreams = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      example = [ "19532" "/run/journalsqld/export.sock" ];
+      description = ''
+        Addresses of a `journalsqld.socket` unit, in `ListenStream=` syntax.
+        When set, journalsqld is started on demand and reads export format
+        connections on these sockets instead of following the local journal.
+      '';
+    };
+
+    journalctl = {
+      package = lib.mkOption {
+        type = lib.types.package;
+        default = config.systemd.package;
+        defaultText = lib.literalExpression "config.systemd.package";
+        description = "Package providing `journalctl`.";
+      };
+
+      follow = lib.mkOption {
+        type = lib.types.bool;
+        default = true;
+        description = "Whether to keep following the journal for new entries.";
+      };
+
+      since = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        example = "-1h";
+        description = "Only ship entries newer than this, in `journalctl --since` syntax.";
+      };
+
+      extraArgs = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [ ];
+        example = [ "--namespace=*" ];
+        description = "Additional arguments passed to `journalctl`.";
+      };
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    systemd.sockets.journalsqld = lib.mkIf socketActivated {
+      description = "journalsqld export format input";
+      wantedBy = [ "sockets.target" ];
+      listenStreams = cfg.listenStreams;
+      socketConfig.FileDescriptorName =
+        cfg.settings.socket_activation.input_name or "export";
+    };
+
+    systemd.services.journalsqld = {
+      description = "Ship systemd journal to ClickHouse";
+      wantedBy = lib.optional (!socketActivated) "multi-user.target";
+      requires = lib.optional socketActivated "journalsqld.socket";
+      wants = [ "network-online.target" ];
+      after = [ "network-online.target" ];
+
+      environment = {
+        JOURNALSQLD_CONFIG = configFile;
+        RUST_LOG = cfg.logLevel;
+      };
+
+      script = ''
+        set -o pipefail
+        ${lib.optionalString (cfg.clickhouseUriFile != null) ''
+          CLICKHOUSE_URI="$(< "$CREDENTIALS_DIRECTORY/clickhouse-uri")"
+          export CLICKHOUSE_URI
+        ''}
+        ${if socketActivated then ''
+          exec ${cfg.package}/bin/journalsqld
+        '' else ''
+          ${cfg.journalctl.package}/bin/journalctl ${lib.escapeShellArgs journalctlArgs} \
+            | ${cfg.package}/bin/journalsqld
+        ''}
+      '';
+
+      serviceConfig = {
+        DynamicUser = true;
+        SupplementaryGroups = [ "systemd-journal" ];
+        LoadCredential = lib.optional (cfg.clickhouseUriFile != null)
+          "clickhouse-uri:${cfg.clickhouseUriFile}";
+        Restart = "on-failure";
+        RestartSec = 5;
+
+        CapabilityBoundingSet = "";
+        LockPersonality = true;
+        MemoryDenyWriteExecute = true;
+        NoNewPrivileges = true;
+        PrivateDevices = true;
+        PrivateTmp = true;
+        ProtectClock = true;
+        ProtectControlGroups = true;
+        ProtectHome = true;
+        ProtectHostname = true;
+        ProtectKernelLogs = true;
+        ProtectKernelModules = true;
+        ProtectKernelTunables = true;
+        ProtectSystem = "strict";
+        RestrictAddressFamilies = [ "AF_INET" "AF_INET6" "AF_UNIX" ];
+        RestrictNamespaces = true;
+        RestrictRealtime = true;
+        SystemCallArchitectures = "native";
+      };
+    };
+  };
+}
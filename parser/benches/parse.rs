@@ -0,0 +1,57 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use systemd_journal_parser::testgen::Generator;
+use systemd_journal_parser::{parse_entries, parse_journal_field};
+
+/// Export format entry with typical metadata fields and a MESSAGE of `message_size` bytes
+fn entry(message_size: usize) -> Vec<u8> {
+    Generator::new(0)
+        .with_entries(1)
+        .with_message_size(message_size..=message_size)
+        .generate()
+}
+
+fn parse_entry(mut input: &[u8]) -> usize {
+    let mut fields = 0;
+    while input.first() != Some(&b'\n') {
+        let (remaining, _) = parse_journal_field(input).expect("valid field");
+        input = remaining;
+        fields += 1;
+    }
+
+    fields
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_entry");
+    for message_size in [64, 4 * 1024, 256 * 1024] {
+        let input = entry(message_size);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(message_size),
+            &input,
+            |b, input| b.iter(|| parse_entry(input)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_parse_entries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_entries");
+    for (name, binary_ratio, extra_fields) in
+        [("text", 0.0, 0), ("mixed", 0.2, 8), ("binary", 1.0, 0)]
+    {
+        let input = Generator::new(0)
+            .with_entries(10_000)
+            .with_binary_ratio(binary_ratio)
+            .with_extra_fields(extra_fields)
+            .generate();
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| parse_entries(input).1.fields)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_parse_entries);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+
+use nom::Offset;
+
+use crate::{parse_raw_field, FieldErrorKind, JournalEntry, ParseErrorInfo, ParseOptions};
+
+/// Counters of a `parse_entries` call
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    /// Input taken up by the returned entries, including blank lines
+    pub bytes_consumed: usize,
+    pub entries: usize,
+    /// Fields of the returned entries
+    pub fields: usize,
+    /// Error which stopped parsing, the remaining input starts at the entry
+    /// containing it
+    pub error: Option<ParseErrorInfo>,
+}
+
+pub fn parse_entries(input: &[u8]) -> (Vec<JournalEntry>, ParseStats, &[u8]) {
+    parse_entries_with(input, &ParseOptions::default())
+}
+
+/// Parses every complete export format entry in `input`. Returns the entries,
+/// statistics and the rest of the input, which starts with the first entry
+/// without its terminating blank line yet, so it can be prepended to the next
+/// chunk of a stream.
+pub fn parse_entries_with<'a>(
+    input: &'a [u8],
+    options: &ParseOptions,
+) -> (Vec<JournalEntry>, ParseStats, &'a [u8]) {
+    let mut entries = Vec::new();
+    let mut stats = ParseStats::default();
+    let mut entry = JournalEntry::default();
+    let mut entry_fields = 0;
+    // Start of the entry being parsed
+    let mut start = input;
+    let mut rest = input;
+
+    while let Some((&first, after)) = rest.split_first() {
+        if first == b'\n' {
+            rest = after;
+            if !entry.is_empty() {
+                entries.push(core::mem::take(&mut entry));
+                stats.entries += 1;
+                stats.fields += entry_fields;
+                entry_fields = 0;
+            }
+            start = rest;
+            continue;
+        }
+
+        let error = match parse_raw_field(rest, options) {
+            Ok((remaining, (key, value))) => {
+                entry_fields += 1;
+                if entry_fields > options.limits.max_fields_per_entry {
+                    Some((FieldErrorKind::TooManyFields, 0))
+                } else if start.offset(remaining) > options.limits.max_entry_size {
+                    Some((FieldErrorKind::EntryTooLarge, 0))
+                } else {
+                    entry.put_multi(key, value.into_value());
+                    rest = remaining;
+                    None
+                }
+            }
+            // Partially buffered entry would not fit anyway
+            Err(nom::Err::Incomplete(_))
+                if input.len() - input.offset(start) > options.limits.max_entry_size =>
+            {
+                Some((FieldErrorKind::EntryTooLarge, 0))
+            }
+            Err(nom::Err::Incomplete(_)) => break,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                Some((e.kind, rest.offset(e.input)))
+            }
+        };
+
+        if let Some((kind, at)) = error {
+            let offset = input.offset(rest) as u64;
+            stats.error = Some(ParseErrorInfo::new(kind, rest, offset, at));
+            break;
+        }
+    }
+
+    stats.bytes_consumed = input.offset(start);
+    (entries, stats, start)
+}
@@ -0,0 +1,355 @@
+//! The streaming nom parsers fields of the export format are built from, for
+//! custom framing around fields. Like the rest of the crate they return
+//! `Incomplete` until enough input is available, and `Failure` for input which
+//! violates the options.
+//!
+//! A field is a key, parsed by [`parse_field_key`], followed by either value
+//! encoding and a terminating newline:
+//!
+//! ```
+//! use nom::{branch::alt, bytes::complete::tag, sequence::tuple};
+//! use systemd_journal_parser::combinators::{
+//!     parse_bytes_value, parse_field_key, parse_utf8_value,
+//! };
+//! use systemd_journal_parser::ParseOptions;
+//!
+//! let options = ParseOptions::default();
+//! let (rest, (key, value, _)) = tuple((
+//!     |i| parse_field_key(i, &options),
+//!     alt((
+//!         |i| parse_utf8_value(i, &options),
+//!         |i| parse_bytes_value(i, &options),
+//!     )),
+//!     tag(b"\n"),
+//! ))(b"MESSAGE=hello\n".as_slice())
+//! .unwrap();
+//!
+//! assert_eq!(key, "MESSAGE");
+//! assert_eq!(String::from(&value.into_value()), "hello");
+//! assert!(rest.is_empty());
+//! ```
+
+use alloc::string::{String, ToString};
+
+use nom::{
+    bytes::streaming::{tag, take},
+    error::context,
+    number::streaming::le_u64,
+    Needed,
+};
+
+use crate::{
+    binary_value, failure, intern, is_valid_field_key, FieldErrorKind, FieldName, FieldResult,
+    JournalFieldValue, ParseOptions, Utf8Mode,
+};
+
+/// Field value which may still borrow binary data from the input
+pub enum RawValue<'a> {
+    Value(JournalFieldValue),
+    Binary(&'a [u8]),
+}
+
+impl RawValue<'_> {
+    /// Copies borrowed binary data out of the input
+    pub fn into_value(self) -> JournalFieldValue {
+        match self {
+            Self::Value(value) => value,
+            Self::Binary(data) => JournalFieldValue::Bytes(binary_value(data)),
+        }
+    }
+}
+
+/// Streaming equivalent of `take_till(|b| b == b'=' || b == b'\n')`
+fn field_key(input: &[u8]) -> FieldResult<&[u8]> {
+    match memchr::memchr2(b'=', b'\n', input) {
+        Some(end) => Ok((&input[end..], &input[..end])),
+        None => Err(nom::Err::Incomplete(Needed::new(1))),
+    }
+}
+
+/// Key of a field, up to but not including the `=` or newline after it.
+/// Decoded according to `options.utf8`, with `Fallback` decoding lossily, and
+/// checked against the naming rules if `options.validate_keys` is set.
+pub fn parse_field_key<'a>(input: &'a [u8], options: &ParseOptions) -> FieldResult<'a, FieldName> {
+    let (input, raw_key) = context("field key", field_key)(input)?;
+    let key = match core::str::from_utf8(raw_key) {
+        Ok(key) => intern(key),
+        Err(_) if options.utf8 == Utf8Mode::Strict => {
+            return Err(failure(raw_key, FieldErrorKind::InvalidUtf8))
+        }
+        Err(_) => FieldName::Owned(String::from_utf8_lossy(raw_key).into_owned()),
+    };
+
+    if options.validate_keys && !is_valid_field_key(&key) {
+        return Err(failure(raw_key, FieldErrorKind::InvalidKey));
+    }
+
+    Ok((input, key))
+}
+
+/// Streaming equivalent of `take_until("\n")`. The line length isn't known
+/// up front, so at least the terminating newline is needed.
+fn line_contents(input: &[u8]) -> FieldResult<&[u8]> {
+    match memchr::memchr(b'\n', input) {
+        Some(end) => Ok((&input[end..], &input[..end])),
+        None => Err(nom::Err::Incomplete(Needed::new(1))),
+    }
+}
+
+/// Text value: `=` and the value up to, not including, the terminating newline
+pub fn parse_utf8_value<'a>(
+    input: &'a [u8],
+    options: &ParseOptions,
+) -> FieldResult<'a, RawValue<'a>> {
+    let (input, _) = context("equals sign separator", tag(b"="))(input)?;
+    let (input, line) = match context("contents until terminating newline", line_contents)(input) {
+        Err(nom::Err::Incomplete(_)) if input.len() as u64 > options.limits.max_field_size => {
+            return Err(failure(input, FieldErrorKind::FieldTooLarge))
+        }
+        result => result?,
+    };
+
+    if line.len() as u64 > options.limits.max_field_size {
+        return Err(failure(line, FieldErrorKind::FieldTooLarge));
+    }
+
+    let value = match core::str::from_utf8(line) {
+        Ok(line) => JournalFieldValue::UTF8(line.to_string()),
+        Err(_) => match options.utf8 {
+            Utf8Mode::Strict => return Err(failure(line, FieldErrorKind::InvalidUtf8)),
+            Utf8Mode::Lossy => JournalFieldValue::UTF8(String::from_utf8_lossy(line).into_owned()),
+            Utf8Mode::Fallback => return Ok((input, RawValue::Binary(line))),
+        },
+    };
+
+    Ok((input, RawValue::Value(value)))
+}
+
+/// Binary value: a newline, the little-endian 64-bit size and the data, not
+/// including the terminating newline
+pub fn parse_bytes_value<'a>(
+    input: &'a [u8],
+    options: &ParseOptions,
+) -> FieldResult<'a, RawValue<'a>> {
+    let (input, _) = context("newline separator", tag(b"\n"))(input)?;
+    let (data, size) = context("binary data size prefix", le_u64)(input)?;
+
+    if size > options.limits.max_field_size {
+        return Err(failure(input, FieldErrorKind::FieldTooLarge));
+    }
+
+    // Size prefix is known, so ask for the rest of the value and the newline
+    // terminating the field at once
+    let size = usize::try_from(size).map_err(|_| failure(input, FieldErrorKind::FieldTooLarge))?;
+    if data.len() < size {
+        return Err(nom::Err::Incomplete(Needed::new(size - data.len() + 1)));
+    }
+
+    let (input, data) = context("binary data", take(size))(data)?;
+
+    Ok((input, RawValue::Binary(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_journal_field_with, JournalField, MAX_FIELD_KEY_LENGTH};
+
+    fn options(utf8: Utf8Mode) -> ParseOptions {
+        ParseOptions {
+            utf8,
+            ..Default::default()
+        }
+    }
+
+    fn error_kind(result: FieldResult<'_, JournalField>) -> Option<FieldErrorKind> {
+        match result {
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Some(e.kind),
+            _ => None,
+        }
+    }
+
+    fn needed<T>(result: FieldResult<'_, T>) -> Option<Needed> {
+        match result {
+            Err(nom::Err::Incomplete(needed)) => Some(needed),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn strict_rejects_invalid_utf8() {
+        let options = options(Utf8Mode::Strict);
+
+        assert_eq!(
+            error_kind(parse_journal_field_with(b"MESSAGE=a\xffb\n", &options)),
+            Some(FieldErrorKind::InvalidUtf8)
+        );
+        assert_eq!(
+            error_kind(parse_journal_field_with(b"MESS\xffAGE=ab\n", &options)),
+            Some(FieldErrorKind::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn lossy_replaces_invalid_sequences() {
+        let (rest, field) =
+            parse_journal_field_with(b"MESS\xffAGE=a\xffb\n", &options(Utf8Mode::Lossy)).unwrap();
+
+        assert!(rest.is_empty());
+        assert_eq!(field.key, "MESS\u{fffd}AGE");
+        assert!(matches!(field.value, JournalFieldValue::UTF8(ref value) if value == "a\u{fffd}b"));
+    }
+
+    #[test]
+    fn fallback_keeps_invalid_values_as_bytes() {
+        let (rest, field) =
+            parse_journal_field_with(b"MESS\xffAGE=a\xffb\n", &options(Utf8Mode::Fallback))
+                .unwrap();
+
+        assert!(rest.is_empty());
+        assert_eq!(field.key, "MESS\u{fffd}AGE");
+        assert!(
+            matches!(field.value, JournalFieldValue::Bytes(ref data) if data[..] == b"a\xffb"[..])
+        );
+    }
+
+    #[test]
+    fn valid_utf8_is_text_in_every_mode() {
+        for mode in [Utf8Mode::Strict, Utf8Mode::Lossy, Utf8Mode::Fallback] {
+            let (_, field) =
+                parse_journal_field_with("MESSAGE=grüße\n".as_bytes(), &options(mode)).unwrap();
+
+            assert!(matches!(field.value, JournalFieldValue::UTF8(ref value) if value == "grüße"));
+        }
+    }
+
+    #[test]
+    fn field_key_rules() {
+        assert!(is_valid_field_key("MESSAGE"));
+        assert!(is_valid_field_key("_SYSTEMD_UNIT"));
+        assert!(is_valid_field_key("CODE_LINE2"));
+        assert!(is_valid_field_key(&"A".repeat(MAX_FIELD_KEY_LENGTH)));
+
+        assert!(!is_valid_field_key(""));
+        assert!(!is_valid_field_key("message"));
+        assert!(!is_valid_field_key("2FA"));
+        assert!(!is_valid_field_key("SYSTEMD-UNIT"));
+        assert!(!is_valid_field_key(&"A".repeat(MAX_FIELD_KEY_LENGTH + 1)));
+    }
+
+    #[test]
+    fn invalid_keys_fail_only_when_validated() {
+        let mut options = ParseOptions::default();
+        let (_, field) = parse_journal_field_with(b"message=hello\n", &options).unwrap();
+        assert_eq!(field.key, "message");
+
+        options.validate_keys = true;
+        assert_eq!(
+            error_kind(parse_journal_field_with(b"message=hello\n", &options)),
+            Some(FieldErrorKind::InvalidKey)
+        );
+        assert_eq!(
+            error_kind(parse_journal_field_with(
+                b"message\n\x05\0\0\0\0\0\0\0hello\n",
+                &options
+            )),
+            Some(FieldErrorKind::InvalidKey)
+        );
+        assert!(parse_journal_field_with(b"MESSAGE=hello\n", &options).is_ok());
+    }
+
+    #[test]
+    fn values_over_the_field_size_limit_fail() {
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+
+        assert!(parse_journal_field_with(b"A=abcd\n", &options).is_ok());
+        assert!(parse_journal_field_with(b"A\n\x04\0\0\0\0\0\0\0abcd\n", &options).is_ok());
+        assert_eq!(
+            error_kind(parse_journal_field_with(b"A=abcde\n", &options)),
+            Some(FieldErrorKind::FieldTooLarge)
+        );
+        assert_eq!(
+            error_kind(parse_journal_field_with(
+                b"A\n\x05\0\0\0\0\0\0\0abcde\n",
+                &options
+            )),
+            Some(FieldErrorKind::FieldTooLarge)
+        );
+    }
+
+    #[test]
+    fn oversized_values_fail_before_they_are_complete() {
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+
+        // Text value without its newline yet, but already over the limit
+        assert_eq!(
+            error_kind(parse_journal_field_with(b"A=abcde", &options)),
+            Some(FieldErrorKind::FieldTooLarge)
+        );
+        assert!(matches!(
+            parse_journal_field_with(b"A=abcd", &options),
+            Err(nom::Err::Incomplete(_))
+        ));
+
+        // Binary value is rejected by its size prefix alone
+        assert_eq!(
+            error_kind(parse_journal_field_with(
+                b"A\n\xff\xff\0\0\0\0\0\0",
+                &options
+            )),
+            Some(FieldErrorKind::FieldTooLarge)
+        );
+    }
+
+    #[test]
+    fn needed_hints_are_exact() {
+        let options = ParseOptions::default();
+
+        assert_eq!(
+            needed(parse_field_key(b"MESSA", &options)),
+            Some(Needed::new(1))
+        );
+        assert_eq!(
+            needed(parse_utf8_value(b"=hello", &options)),
+            Some(Needed::new(1))
+        );
+
+        // Rest of the size prefix
+        assert_eq!(
+            needed(parse_bytes_value(b"\n\x05\0\0", &options)),
+            Some(Needed::new(5))
+        );
+        // Rest of the value and the terminating newline
+        assert_eq!(
+            needed(parse_bytes_value(b"\n\x05\0\0\0\0\0\0\0", &options)),
+            Some(Needed::new(6))
+        );
+        assert_eq!(
+            needed(parse_bytes_value(b"\n\x05\0\0\0\0\0\0\0he", &options)),
+            Some(Needed::new(4))
+        );
+        assert_eq!(
+            needed(parse_journal_field_with(
+                b"A\n\x05\0\0\0\0\0\0\0hello",
+                &options
+            )),
+            Some(Needed::new(1))
+        );
+    }
+
+    #[test]
+    fn binary_values_may_contain_newlines() {
+        let (rest, field) = parse_journal_field_with(
+            b"A\n\x05\0\0\0\0\0\0\0a\nb=c\nB=1\n",
+            &ParseOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(rest, b"B=1\n");
+        assert!(
+            matches!(field.value, JournalFieldValue::Bytes(ref data) if data[..] == b"a\nb=c"[..])
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// Position of an entry in the journal, parsed from `__CURSOR`
+/// (`s=…;i=…;b=…;m=…;t=…;x=…`). IDs are 128-bit values, written as 32 hex digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    /// Identifies the sequence `seqnum` counts in, shared by the journal files
+    /// of a machine until it is reset
+    pub seqnum_id: u128,
+    pub seqnum: u64,
+    pub boot_id: u128,
+    /// Microseconds since boot
+    pub monotonic: u64,
+    /// Microseconds since the epoch
+    pub realtime: u64,
+    /// Hash of the entry contents, not present in all cursors
+    pub xor_hash: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorError {
+    /// A required part is absent
+    MissingPart(char),
+    /// A part is not a `key=value` pair with a hex value
+    InvalidPart,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPart(key) => write!(f, "cursor lacks \"{}=\"", key),
+            Self::InvalidPart => write!(f, "invalid cursor part"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CursorError {}
+
+impl Cursor {
+    /// Number of entries missing between `previous` and this cursor, `None` if
+    /// they aren't in the same sequence or this one doesn't come after it
+    pub fn gap_since(&self, previous: &Cursor) -> Option<u64> {
+        if self.seqnum_id != previous.seqnum_id || self.seqnum <= previous.seqnum {
+            return None;
+        }
+
+        Some(self.seqnum - previous.seqnum - 1)
+    }
+
+    pub fn realtime_timestamp(&self) -> Option<time::OffsetDateTime> {
+        time::OffsetDateTime::from_unix_timestamp_nanos(i128::from(self.realtime) * 1000).ok()
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = CursorError;
+
+    /// Unknown parts are ignored, like journald does
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let (mut seqnum_id, mut seqnum, mut boot_id) = (None, None, None);
+        let (mut monotonic, mut realtime, mut xor_hash) = (None, None, None);
+
+        for part in cursor.split(';') {
+            let (key, value) = part.split_once('=').ok_or(CursorError::InvalidPart)?;
+            match key {
+                "s" => seqnum_id = Some(parse_id(value)?),
+                "i" => seqnum = Some(parse_hex(value)?),
+                "b" => boot_id = Some(parse_id(value)?),
+                "m" => monotonic = Some(parse_hex(value)?),
+                "t" => realtime = Some(parse_hex(value)?),
+                "x" => xor_hash = Some(parse_hex(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            seqnum_id: seqnum_id.ok_or(CursorError::MissingPart('s'))?,
+            seqnum: seqnum.ok_or(CursorError::MissingPart('i'))?,
+            boot_id: boot_id.ok_or(CursorError::MissingPart('b'))?,
+            monotonic: monotonic.ok_or(CursorError::MissingPart('m'))?,
+            realtime: realtime.ok_or(CursorError::MissingPart('t'))?,
+            xor_hash,
+        })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "s={:032x};i={:x};b={:032x};m={:x};t={:x}",
+            self.seqnum_id, self.seqnum, self.boot_id, self.monotonic, self.realtime
+        )?;
+        if let Some(xor_hash) = self.xor_hash {
+            write!(f, ";x={:x}", xor_hash)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_hex(value: &str) -> Result<u64, CursorError> {
+    u64::from_str_radix(value, 16).map_err(|_| CursorError::InvalidPart)
+}
+
+fn parse_id(value: &str) -> Result<u128, CursorError> {
+    if value.len() != 32 {
+        return Err(CursorError::InvalidPart);
+    }
+
+    u128::from_str_radix(value, 16).map_err(|_| CursorError::InvalidPart)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::ToString;
+
+    use super::*;
+
+    const SEQNUM_ID: &str = "0123456789abcdef0123456789abcdef";
+    const BOOT_ID: &str = "fedcba9876543210fedcba9876543210";
+    const CURSOR: &str = concat!(
+        "s=0123456789abcdef0123456789abcdef;i=1a;",
+        "b=fedcba9876543210fedcba9876543210;m=3e8;t=5f5e100;x=abc"
+    );
+
+    #[test]
+    fn parses_all_parts() {
+        let cursor: Cursor = CURSOR.parse().unwrap();
+
+        assert_eq!(cursor.seqnum_id, 0x0123456789abcdef0123456789abcdef);
+        assert_eq!(cursor.seqnum, 0x1a);
+        assert_eq!(cursor.boot_id, 0xfedcba9876543210fedcba9876543210);
+        assert_eq!(cursor.monotonic, 1000);
+        assert_eq!(cursor.realtime, 100_000_000);
+        assert_eq!(cursor.xor_hash, Some(0xabc));
+        assert_eq!(cursor.to_string(), CURSOR);
+        assert_eq!(
+            cursor.realtime_timestamp().map(|t| t.unix_timestamp()),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_parts_and_missing_xor_hash() {
+        let cursor: Cursor = format!("s={};i=1;b={};m=0;t=0;z=1", SEQNUM_ID, BOOT_ID)
+            .parse()
+            .unwrap();
+
+        assert_eq!(cursor.xor_hash, None);
+        assert!(!cursor.to_string().contains(";x="));
+        assert!(!cursor.to_string().contains(";z="));
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        let without_seqnum = CURSOR.replace(";i=1a", "");
+        assert_eq!(
+            without_seqnum.parse::<Cursor>(),
+            Err(CursorError::MissingPart('i'))
+        );
+
+        let short_boot_id = CURSOR.replace("b=fedcba98", "b=");
+        assert_eq!(
+            short_boot_id.parse::<Cursor>(),
+            Err(CursorError::InvalidPart)
+        );
+
+        let not_hex = CURSOR.replace("i=1a", "i=xy");
+        assert_eq!(not_hex.parse::<Cursor>(), Err(CursorError::InvalidPart));
+
+        assert_eq!("".parse::<Cursor>(), Err(CursorError::InvalidPart));
+        assert_eq!("s;i=1".parse::<Cursor>(), Err(CursorError::InvalidPart));
+    }
+
+    #[test]
+    fn gap_since_counts_missing_entries() {
+        let previous: Cursor = CURSOR.parse().unwrap();
+        let next = Cursor {
+            seqnum: previous.seqnum + 3,
+            ..previous
+        };
+
+        assert_eq!(next.gap_since(&previous), Some(2));
+        assert_eq!(previous.gap_since(&next), None);
+        assert_eq!(previous.gap_since(&previous), None);
+        assert_eq!(
+            Cursor {
+                seqnum_id: 1,
+                ..next
+            }
+            .gap_since(&previous),
+            None
+        );
+    }
+}
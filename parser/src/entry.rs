@@ -0,0 +1,657 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+#[cfg(feature = "serde")]
+use alloc::vec;
+use alloc::vec::Vec;
+use core::num::ParseIntError;
+use core::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::ser::SerializeMap;
+
+use crate::{
+    parse_structured_data, Cursor, CursorError, FieldName, JournalFieldValue, MessageId,
+    MessageIdError, Priority, PriorityError, SdElement, StructuredDataError, TimestampError,
+};
+
+type FieldHasher = core::hash::BuildHasherDefault<fnv::FnvHasher>;
+
+#[cfg(feature = "preserve-order")]
+type FieldMap = indexmap::IndexMap<FieldName, JournalFieldValue, FieldHasher>;
+#[cfg(all(feature = "std", not(feature = "preserve-order")))]
+type FieldMap = std::collections::HashMap<FieldName, JournalFieldValue, FieldHasher>;
+#[cfg(all(feature = "std", not(feature = "preserve-order")))]
+type FieldMapIntoIter = std::collections::hash_map::IntoIter<FieldName, JournalFieldValue>;
+// `alloc` has no hash map
+#[cfg(not(any(feature = "std", feature = "preserve-order")))]
+type FieldMap = alloc::collections::BTreeMap<FieldName, JournalFieldValue>;
+#[cfg(not(any(feature = "std", feature = "preserve-order")))]
+type FieldMapIntoIter = alloc::collections::btree_map::IntoIter<FieldName, JournalFieldValue>;
+
+/// Fields of a journal entry. journald allows a field to occur more than once;
+/// the first value of each key is kept in the map and any further values, in
+/// order, in `repeated`. With the `preserve-order` feature fields iterate in
+/// the order of their first values, further values of a multi-valued field
+/// following its first one rather than where they were interleaved with other
+/// fields.
+#[derive(Clone, Debug)]
+pub struct JournalEntry {
+    fields: FieldMap,
+    repeated: Vec<(FieldName, JournalFieldValue)>,
+}
+
+// Multi-valued fields are serialized as arrays of values, like journalctl does
+#[cfg(feature = "serde")]
+impl serde::Serialize for JournalEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for (key, value) in self.fields.iter() {
+            map.serialize_key(key)?;
+            if self.repeated.iter().any(|(k, _)| k == key) {
+                map.serialize_value(&self.get_all(key).collect::<Vec<_>>())?;
+            } else {
+                map.serialize_value(value)?;
+            }
+        }
+
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for JournalEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(JournalEntryVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct JournalEntryVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for JournalEntryVisitor {
+    type Value = JournalEntry;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a map of journal fields")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut entry = JournalEntry::default();
+        while let Some((key, values)) = map.next_entry::<String, FieldValues>()? {
+            let key = crate::intern(key);
+            for value in values.0 {
+                entry.put_multi(key.clone(), value);
+            }
+        }
+
+        Ok(entry)
+    }
+}
+
+/// Value of a serialized field: a single value, including binary values encoded
+/// as arrays of bytes, or an array of values of a multi-valued field
+#[cfg(feature = "serde")]
+struct FieldValues(Vec<JournalFieldValue>);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FieldValues {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(FieldValuesVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct FieldValuesVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for FieldValuesVisitor {
+    type Value = FieldValues;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a field value or an array of field values")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        serde::de::Visitor::visit_str(crate::JournalFieldValueVisitor, value)
+            .map(|value| FieldValues(vec![value]))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        serde::de::Visitor::visit_string(crate::JournalFieldValueVisitor, value)
+            .map(|value| FieldValues(vec![value]))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(FieldValues(vec![JournalFieldValue::Bytes(
+            crate::binary_value(value),
+        )]))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::new();
+        let mut values = Vec::new();
+        while let Some(element) = seq.next_element::<SeqElement>()? {
+            match element {
+                SeqElement::Byte(byte) if values.is_empty() => bytes.push(byte),
+                SeqElement::Value(value) if bytes.is_empty() => values.push(value),
+                _ => {
+                    return Err(serde::de::Error::custom(
+                        "array mixes bytes and field values",
+                    ))
+                }
+            }
+        }
+
+        if values.is_empty() {
+            values.push(JournalFieldValue::Bytes(bytes.into()));
+        }
+
+        Ok(FieldValues(values))
+    }
+}
+
+#[cfg(feature = "serde")]
+enum SeqElement {
+    Byte(u8),
+    Value(JournalFieldValue),
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SeqElement {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(SeqElementVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct SeqElementVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for SeqElementVisitor {
+    type Value = SeqElement;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a byte or a field value")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u8::try_from(value)
+            .map(SeqElement::Byte)
+            .map_err(|_| E::custom("byte value out of range"))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u8::try_from(value)
+            .map(SeqElement::Byte)
+            .map_err(|_| E::custom("byte value out of range"))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        serde::de::Visitor::visit_str(crate::JournalFieldValueVisitor, value).map(SeqElement::Value)
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        serde::de::Visitor::visit_seq(crate::JournalFieldValueVisitor, seq).map(SeqElement::Value)
+    }
+}
+
+impl JournalEntry {
+    /// Sets the value of a field, replacing all of its previous values. Returns
+    /// whether the field was present.
+    pub fn put<K: Into<FieldName>>(&mut self, key: K, value: JournalFieldValue) -> bool {
+        let key = key.into();
+        if !self.repeated.is_empty() {
+            self.repeated.retain(|(k, _)| *k != key);
+        }
+
+        self.fields.insert(key, value).is_some()
+    }
+
+    /// Adds a value to a field, keeping any values it already has
+    pub fn put_multi<K: Into<FieldName>>(&mut self, key: K, value: JournalFieldValue) {
+        let key = key.into();
+        if self.fields.contains_key(&*key) {
+            self.repeated.push((key, value));
+        } else {
+            self.fields.insert(key, value);
+        }
+    }
+
+    /// First value of a field
+    pub fn get(&self, key: &str) -> Option<&JournalFieldValue> {
+        self.fields.get(key)
+    }
+
+    /// All values of a field, in the order they were added
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a JournalFieldValue> {
+        self.fields.get(key).into_iter().chain(
+            self.repeated
+                .iter()
+                .filter(move |(k, _)| k == key)
+                .map(|(_, value)| value),
+        )
+    }
+
+    /// Removes all values of a field, returning the first one
+    pub fn remove(&mut self, key: &str) -> Option<JournalFieldValue> {
+        if !self.repeated.is_empty() {
+            self.repeated.retain(|(k, _)| k != key);
+        }
+
+        #[cfg(feature = "preserve-order")]
+        return self.fields.shift_remove(key);
+        #[cfg(not(feature = "preserve-order"))]
+        self.fields.remove(key)
+    }
+
+    /// Number of field values, counting each value of multi-valued fields
+    pub fn len(&self) -> usize {
+        self.fields.len() + self.repeated.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Number of distinct fields, unlike `len` multi-valued fields count once
+    pub fn field_count(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Estimated memory held by the entry: keys and values plus their
+    /// per-field bookkeeping, without the map's spare capacity. Interned keys
+    /// are shared and not counted. Computed from lengths, so it's cheap enough
+    /// to call for every entry.
+    pub fn approx_size_bytes(&self) -> usize {
+        let overhead = core::mem::size_of::<(FieldName, JournalFieldValue)>();
+
+        core::mem::size_of::<Self>()
+            + self
+                .iter()
+                .map(|(key, value)| match key {
+                    Cow::Borrowed(_) => overhead + value.len(),
+                    Cow::Owned(key) => overhead + key.len() + value.len(),
+                })
+                .sum::<usize>()
+    }
+
+    /// Distinct field keys, in the order they were added with the
+    /// `preserve-order` feature
+    pub fn keys(&self) -> impl Iterator<Item = &FieldName> {
+        self.fields.keys()
+    }
+
+    /// All field values, multi-valued fields yield one pair per value
+    #[cfg(not(feature = "preserve-order"))]
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldName, &JournalFieldValue)> {
+        self.fields
+            .iter()
+            .chain(self.repeated.iter().map(|(key, value)| (key, value)))
+    }
+
+    /// All field values, multi-valued fields yield one pair per value
+    #[cfg(feature = "preserve-order")]
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldName, &JournalFieldValue)> {
+        self.fields.iter().flat_map(move |(key, value)| {
+            core::iter::once((key, value)).chain(
+                self.repeated
+                    .iter()
+                    .filter(move |(k, _)| k == key)
+                    .map(|(key, value)| (key, value)),
+            )
+        })
+    }
+
+    #[cfg(not(feature = "preserve-order"))]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&FieldName, &mut JournalFieldValue)> {
+        self.fields
+            .iter_mut()
+            .chain(self.repeated.iter_mut().map(|(key, value)| (&*key, value)))
+    }
+
+    #[cfg(feature = "preserve-order")]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&FieldName, &mut JournalFieldValue)> {
+        let mut repeated: Vec<_> = self.repeated.iter_mut().map(Some).collect();
+        let mut values = Vec::with_capacity(self.fields.len() + repeated.len());
+        for (key, value) in self.fields.iter_mut() {
+            values.push((key, value));
+            for slot in repeated.iter_mut() {
+                if slot.as_ref().map_or(false, |(k, _)| k == key) {
+                    if let Some((key, value)) = slot.take() {
+                        values.push((&*key, value));
+                    }
+                }
+            }
+        }
+
+        values.into_iter()
+    }
+
+    pub fn take_transport(&mut self) -> Option<String> {
+        self.remove("_TRANSPORT").map(|field| field.into())
+    }
+
+    pub fn take_hostname(&mut self) -> Option<String> {
+        self.remove("_HOSTNAME").map(|field| field.into())
+    }
+
+    pub fn take_machine_id(&mut self) -> Option<String> {
+        self.remove("_MACHINE_ID").map(|field| field.into())
+    }
+
+    pub fn take_boot_id(&mut self) -> Option<String> {
+        self.remove("_BOOT_ID").map(|field| field.into())
+    }
+
+    fn parse_realtime_timerstamp(
+        entry: &JournalFieldValue,
+    ) -> Result<time::OffsetDateTime, TimestampError> {
+        let micros = String::from(entry).parse::<i128>()?;
+        let nanos = micros.checked_mul(1000).ok_or(TimestampError::OutOfRange)?;
+
+        time::OffsetDateTime::from_unix_timestamp_nanos(nanos)
+            .map_err(|_| TimestampError::OutOfRange)
+    }
+
+    pub fn realtime_timestamp(&self) -> Option<Result<time::OffsetDateTime, TimestampError>> {
+        self.get("__REALTIME_TIMESTAMP")
+            .map(Self::parse_realtime_timerstamp)
+    }
+
+    pub fn take_realtime_timestamp(
+        &mut self,
+    ) -> Option<Result<time::OffsetDateTime, TimestampError>> {
+        self.remove("__REALTIME_TIMESTAMP")
+            .map(|v| Self::parse_realtime_timerstamp(&v))
+    }
+
+    fn parse_monotonic_timestamp(entry: &JournalFieldValue) -> Result<Duration, ParseIntError> {
+        let micros = String::from(entry).parse::<u64>()?;
+
+        Ok(Duration::from_micros(micros))
+    }
+
+    /// Time since boot of `_BOOT_ID` when the entry was written
+    pub fn monotonic_timestamp(&self) -> Option<Result<Duration, ParseIntError>> {
+        self.get("__MONOTONIC_TIMESTAMP")
+            .map(Self::parse_monotonic_timestamp)
+    }
+
+    pub fn take_monotonic_timestamp(&mut self) -> Option<Result<Duration, ParseIntError>> {
+        self.remove("__MONOTONIC_TIMESTAMP")
+            .map(|v| Self::parse_monotonic_timestamp(&v))
+    }
+
+    pub fn take_cursor(&mut self) -> Option<String> {
+        self.remove("__CURSOR").map(|field| field.into())
+    }
+
+    /// `__CURSOR` parsed into its parts
+    pub fn cursor(&self) -> Option<Result<Cursor, CursorError>> {
+        self.get("__CURSOR")
+            .map(|value| String::from(value).parse())
+    }
+
+    /// `MESSAGE_ID` parsed into its 128-bit value, see `MessageId::name` for
+    /// well-known IDs
+    pub fn message_id(&self) -> Option<Result<MessageId, MessageIdError>> {
+        self.get("MESSAGE_ID")
+            .map(|value| String::from(value).parse())
+    }
+
+    /// `PRIORITY` as a syslog severity
+    pub fn priority(&self) -> Option<Result<Priority, PriorityError>> {
+        self.get("PRIORITY")
+            .map(|value| String::from(value).parse())
+    }
+
+    /// Elements of `SYSLOG_STRUCTURED_DATA`, which journald keeps from RFC 5424
+    /// syslog messages
+    pub fn structured_data(&self) -> Option<Result<Vec<SdElement>, StructuredDataError>> {
+        self.get("SYSLOG_STRUCTURED_DATA")
+            .map(|value| parse_structured_data(&String::from(value)))
+    }
+}
+
+impl Default for JournalEntry {
+    fn default() -> Self {
+        Self {
+            #[cfg(any(feature = "std", feature = "preserve-order"))]
+            fields: FieldMap::with_capacity_and_hasher(16, FieldHasher::default()),
+            #[cfg(not(any(feature = "std", feature = "preserve-order")))]
+            fields: FieldMap::new(),
+            repeated: Vec::new(),
+        }
+    }
+}
+
+#[cfg(not(feature = "preserve-order"))]
+impl IntoIterator for JournalEntry {
+    type Item = (FieldName, JournalFieldValue);
+    type IntoIter =
+        core::iter::Chain<FieldMapIntoIter, alloc::vec::IntoIter<(FieldName, JournalFieldValue)>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.into_iter().chain(self.repeated)
+    }
+}
+
+// Same order as `iter`
+#[cfg(feature = "preserve-order")]
+impl IntoIterator for JournalEntry {
+    type Item = (FieldName, JournalFieldValue);
+    type IntoIter = alloc::vec::IntoIter<(FieldName, JournalFieldValue)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut repeated: Vec<_> = self.repeated.into_iter().map(Some).collect();
+        let mut values = Vec::with_capacity(self.fields.len() + repeated.len());
+        for (key, value) in self.fields {
+            let further: Vec<_> = repeated
+                .iter_mut()
+                .filter(|slot| slot.as_ref().map_or(false, |(k, _)| *k == key))
+                .filter_map(Option::take)
+                .collect();
+            values.push((key, value));
+            values.extend(further);
+        }
+
+        values.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    fn utf8(value: &str) -> JournalFieldValue {
+        JournalFieldValue::UTF8(String::from(value))
+    }
+
+    /// `A` repeated around `B`, as journald allows
+    fn entry_with_repeated_field() -> JournalEntry {
+        let mut entry = JournalEntry::default();
+        entry.put_multi("A", utf8("1"));
+        entry.put_multi("B", utf8("2"));
+        entry.put_multi("A", utf8("3"));
+        entry
+    }
+
+    fn rendered<'a>(
+        fields: impl Iterator<Item = (&'a FieldName, &'a JournalFieldValue)>,
+    ) -> Vec<(String, String)> {
+        fields
+            .map(|(key, value)| (key.to_string(), String::from(value)))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_field_keeps_all_values() {
+        let entry = entry_with_repeated_field();
+
+        assert_eq!(entry.len(), 3);
+        assert_eq!(entry.field_count(), 2);
+        assert_eq!(entry.get("A").map(String::from).as_deref(), Some("1"));
+        assert_eq!(
+            entry.get_all("A").map(String::from).collect::<Vec<_>>(),
+            ["1", "3"]
+        );
+    }
+
+    #[test]
+    fn put_replaces_repeated_values() {
+        let mut entry = entry_with_repeated_field();
+
+        assert!(entry.put("A", utf8("4")));
+        assert_eq!(
+            entry.get_all("A").map(String::from).collect::<Vec<_>>(),
+            ["4"]
+        );
+        assert_eq!(entry.len(), 2);
+    }
+
+    #[test]
+    fn remove_drops_repeated_values() {
+        let mut entry = entry_with_repeated_field();
+
+        assert_eq!(entry.remove("A").map(String::from).as_deref(), Some("1"));
+        assert_eq!(entry.get_all("A").count(), 0);
+        assert_eq!(entry.len(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    fn json_round_trip(entry: &JournalEntry) -> JournalEntry {
+        let json = serde_json::to_string(entry).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_repeated_and_binary_values() {
+        let mut entry = entry_with_repeated_field();
+        entry.put(
+            "BINARY",
+            JournalFieldValue::Bytes(crate::binary_value(b"\0\xff\n")),
+        );
+        entry.put_multi("B", JournalFieldValue::Bytes(crate::binary_value(b"")));
+
+        let parsed = json_round_trip(&entry);
+
+        assert_eq!(parsed.len(), entry.len());
+        assert_eq!(
+            parsed.get_all("A").map(String::from).collect::<Vec<_>>(),
+            ["1", "3"]
+        );
+        let Some(JournalFieldValue::Bytes(data)) = parsed.get("BINARY") else {
+            panic!("binary value is missing");
+        };
+        assert_eq!(data[..], b"\0\xff\n"[..]);
+        let [JournalFieldValue::UTF8(text), JournalFieldValue::Bytes(data)] =
+            &parsed.get_all("B").collect::<Vec<_>>()[..]
+        else {
+            panic!("B lost a value or changed type");
+        };
+        assert_eq!(text, "2");
+        assert!(data.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializes_journalctl_json() {
+        let entry: JournalEntry = serde_json::from_str(
+            r#"{"MESSAGE":[104,105],"A":["x",[1,2]],"B":"base64:AAE=","C":"plain"}"#,
+        )
+        .unwrap();
+
+        let Some(JournalFieldValue::Bytes(data)) = entry.get("MESSAGE") else {
+            panic!("array of bytes is not a binary value");
+        };
+        assert_eq!(data[..], b"hi"[..]);
+        assert!(matches!(
+            entry.get_all("A").collect::<Vec<_>>()[..],
+            [JournalFieldValue::UTF8(_), JournalFieldValue::Bytes(_)]
+        ));
+        assert!(
+            matches!(entry.get("B"), Some(JournalFieldValue::Bytes(data)) if data[..] == [0, 1])
+        );
+        assert_eq!(entry.get("C").map(String::from).as_deref(), Some("plain"));
+
+        assert!(serde_json::from_str::<JournalEntry>(r#"{"A":[1,"x"]}"#).is_err());
+        assert!(serde_json::from_str::<JournalEntry>(r#"{"A":[256]}"#).is_err());
+    }
+
+    #[cfg(feature = "preserve-order")]
+    #[test]
+    fn preserve_order_groups_repeated_values_after_the_first() {
+        let expected = [("A", "1"), ("A", "3"), ("B", "2")]
+            .map(|(key, value)| (String::from(key), String::from(value)));
+
+        let mut entry = entry_with_repeated_field();
+        assert_eq!(rendered(entry.iter()), expected);
+        assert_eq!(
+            entry.keys().map(|key| key.to_string()).collect::<Vec<_>>(),
+            ["A", "B"]
+        );
+        assert_eq!(
+            rendered(entry.iter_mut().map(|(key, value)| (key, &*value))),
+            expected
+        );
+        assert_eq!(
+            entry
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), String::from(value)))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[cfg(all(feature = "preserve-order", feature = "std"))]
+    #[test]
+    fn preserve_order_writes_repeated_values_after_the_first() {
+        let mut out = Vec::new();
+        crate::write_journal_entry(&mut out, &entry_with_repeated_field()).unwrap();
+
+        assert_eq!(out, b"A=1\nA=3\nB=2\n\n");
+    }
+}
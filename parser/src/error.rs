@@ -0,0 +1,142 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::num::ParseIntError;
+
+use nom::error::{ContextError, ErrorKind, ParseError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldErrorKind {
+    /// Error produced by a nom combinator
+    Nom(ErrorKind),
+    /// Key or value is not valid UTF-8 in strict mode
+    InvalidUtf8,
+    /// Key does not follow journald field naming rules
+    InvalidKey,
+    /// Field value is larger than `ParserLimits::max_field_size`
+    FieldTooLarge,
+    /// Entry has more fields than `ParserLimits::max_fields_per_entry`
+    TooManyFields,
+    /// Entry is larger than `ParserLimits::max_entry_size`
+    EntryTooLarge,
+}
+
+impl fmt::Display for FieldErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nom(kind) => write!(f, "{}", kind.description()),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            Self::InvalidKey => write!(f, "invalid field key"),
+            Self::FieldTooLarge => write!(f, "field size limit exceeded"),
+            Self::TooManyFields => write!(f, "field count limit exceeded"),
+            Self::EntryTooLarge => write!(f, "entry size limit exceeded"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FieldError<I> {
+    pub input: I,
+    pub kind: FieldErrorKind,
+}
+
+impl<I> FieldError<I> {
+    pub fn new(input: I, kind: FieldErrorKind) -> Self {
+        Self { input, kind }
+    }
+}
+
+impl<I> ParseError<I> for FieldError<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        Self::new(input, FieldErrorKind::Nom(kind))
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I> ContextError<I> for FieldError<I> {}
+
+const EXCERPT_LIMIT: usize = 64;
+
+/// Parse failure within a byte stream, with enough context to locate the
+/// offending input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseErrorInfo {
+    pub kind: FieldErrorKind,
+    /// Absolute offset of the offending bytes from the start of the stream
+    pub offset: u64,
+    /// Key of the field being parsed, if it could be determined
+    pub key: Option<String>,
+    /// Up to 64 bytes of input starting at `offset`
+    pub excerpt: Vec<u8>,
+}
+
+impl ParseErrorInfo {
+    /// Builds error info for an error at `at` within `input`, where `input`
+    /// starts at the beginning of the field being parsed and at stream offset
+    /// `input_offset`.
+    pub fn new(kind: FieldErrorKind, input: &[u8], input_offset: u64, at: usize) -> Self {
+        let key = input
+            .iter()
+            .take(EXCERPT_LIMIT)
+            .position(|b| *b == b'=' || *b == b'\n')
+            .filter(|end| *end > 0)
+            .map(|end| String::from_utf8_lossy(&input[..end]).into_owned());
+
+        let at = at.min(input.len());
+        let end = input.len().min(at + EXCERPT_LIMIT);
+
+        Self {
+            kind,
+            offset: input_offset + at as u64,
+            key,
+            excerpt: input[at..end].to_vec(),
+        }
+    }
+
+    pub fn hexdump(&self) -> String {
+        self.excerpt
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for ParseErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)?;
+        if let Some(key) = &self.key {
+            write!(f, " in field {:?}", key)?;
+        }
+        write!(f, ": [{}]", self.hexdump())
+    }
+}
+
+/// `__REALTIME_TIMESTAMP` that isn't a number or outside the supported range
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimestampError {
+    Invalid(ParseIntError),
+    OutOfRange,
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(err) => write!(f, "{}", err),
+            Self::OutOfRange => write!(f, "timestamp out of range"),
+        }
+    }
+}
+
+impl From<ParseIntError> for TimestampError {
+    fn from(err: ParseIntError) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TimestampError {}
@@ -0,0 +1,163 @@
+use std::io::{self, Write};
+
+use crate::{JournalEntry, JournalField, JournalFieldValue};
+
+/// Writes a single field in the journal export format. Text values containing a
+/// newline can't be represented as `KEY=value` lines, so they are written in the
+/// size-prefixed binary encoding, same as `Bytes` values.
+pub fn write_journal_field<W: Write>(writer: &mut W, field: &JournalField) -> io::Result<()> {
+    write_field(writer, &field.key, &field.value)
+}
+
+/// Writes an entry in the journal export format, including the terminating blank
+/// line. Address fields (`__CURSOR`, `__REALTIME_TIMESTAMP`, ...) come first, the
+/// rest are sorted by key so output is deterministic. With the `preserve-order`
+/// feature fields are written in the order they were added instead, further
+/// values of a multi-valued field following its first one.
+pub fn write_journal_entry<W: Write>(writer: &mut W, entry: &JournalEntry) -> io::Result<()> {
+    write_fields(writer, entry)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(feature = "preserve-order")]
+fn write_fields<W: Write>(writer: &mut W, entry: &JournalEntry) -> io::Result<()> {
+    for key in entry.keys() {
+        for value in entry.get_all(key) {
+            write_field(writer, key, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "preserve-order"))]
+fn write_fields<W: Write>(writer: &mut W, entry: &JournalEntry) -> io::Result<()> {
+    let mut fields: Vec<_> = entry.iter().collect();
+    fields.sort_by(|(a, _), (b, _)| (!a.starts_with("__"), *a).cmp(&(!b.starts_with("__"), *b)));
+
+    for (key, value) in fields {
+        write_field(writer, key, value)?;
+    }
+
+    Ok(())
+}
+
+fn write_field<W: Write>(writer: &mut W, key: &str, value: &JournalFieldValue) -> io::Result<()> {
+    let data = match value {
+        JournalFieldValue::UTF8(value) if !value.contains('\n') => {
+            writer.write_all(key.as_bytes())?;
+            writer.write_all(b"=")?;
+            writer.write_all(value.as_bytes())?;
+            return writer.write_all(b"\n");
+        }
+        JournalFieldValue::UTF8(value) => value.as_bytes(),
+        JournalFieldValue::Bytes(value) => &value[..],
+    };
+
+    writer.write_all(key.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{intern, parse_entries};
+
+    fn text(value: &str) -> JournalFieldValue {
+        JournalFieldValue::UTF8(String::from(value))
+    }
+
+    fn round_trip(entry: &JournalEntry) -> JournalEntry {
+        let mut out = Vec::new();
+        write_journal_entry(&mut out, entry).unwrap();
+
+        let (mut entries, stats, rest) = parse_entries(&out);
+        assert_eq!(stats.error, None);
+        assert!(rest.is_empty());
+        assert_eq!(entries.len(), 1);
+        entries.pop().unwrap()
+    }
+
+    #[test]
+    fn text_fields_round_trip() {
+        let mut entry = JournalEntry::default();
+        entry.put("MESSAGE", text("hello = world"));
+        entry.put("__CURSOR", text("s=1"));
+        entry.put("EMPTY", text(""));
+
+        #[cfg(not(feature = "preserve-order"))]
+        {
+            let mut out = Vec::new();
+            write_journal_entry(&mut out, &entry).unwrap();
+            assert_eq!(out, b"__CURSOR=s=1\nEMPTY=\nMESSAGE=hello = world\n\n");
+        }
+
+        let parsed = round_trip(&entry);
+        assert_eq!(parsed.len(), 3);
+        for key in ["MESSAGE", "__CURSOR", "EMPTY"] {
+            assert!(
+                matches!(parsed.get(key), Some(JournalFieldValue::UTF8(_))),
+                "{}",
+                key
+            );
+            assert_eq!(
+                parsed.get(key).map(String::from),
+                entry.get(key).map(String::from)
+            );
+        }
+    }
+
+    #[test]
+    fn values_with_newlines_are_size_prefixed() {
+        let field = JournalField {
+            key: intern("MESSAGE"),
+            value: text("line 1\nline 2"),
+        };
+        let mut out = Vec::new();
+        write_journal_field(&mut out, &field).unwrap();
+
+        assert_eq!(out, b"MESSAGE\n\x0d\0\0\0\0\0\0\0line 1\nline 2\n");
+    }
+
+    #[test]
+    fn binary_fields_round_trip() {
+        let mut entry = JournalEntry::default();
+        entry.put(
+            "BINARY",
+            JournalFieldValue::Bytes(crate::binary_value(b"a\n\0\xff")),
+        );
+        entry.put("MULTILINE", text("line 1\nline 2\n"));
+
+        let parsed = round_trip(&entry);
+
+        let Some(JournalFieldValue::Bytes(data)) = parsed.get("BINARY") else {
+            panic!("binary value is missing");
+        };
+        assert_eq!(data[..], b"a\n\0\xff"[..]);
+        // Size-prefixed values are parsed as binary values, whatever they contain
+        let Some(JournalFieldValue::Bytes(data)) = parsed.get("MULTILINE") else {
+            panic!("multi-line value is missing");
+        };
+        assert_eq!(data[..], b"line 1\nline 2\n"[..]);
+    }
+
+    #[test]
+    fn repeated_fields_round_trip() {
+        let mut entry = JournalEntry::default();
+        entry.put_multi("A", text("1"));
+        entry.put_multi("B", text("2"));
+        entry.put_multi("A", JournalFieldValue::Bytes(crate::binary_value(b"3\n")));
+
+        let parsed = round_trip(&entry);
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(
+            parsed.get_all("A").map(String::from).collect::<Vec<_>>(),
+            ["1", "3\n"]
+        );
+        assert_eq!(parsed.get("B").map(String::from).as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,61 @@
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+/// Key of an entry field. Well-known keys are borrowed from a static table
+/// instead of being allocated for every entry, see [`intern`].
+pub type FieldName = Cow<'static, str>;
+
+/// Keys journald attaches to most entries, sorted for binary search
+const WELL_KNOWN: &[&str] = &[
+    "CODE_FILE",
+    "CODE_FUNC",
+    "CODE_LINE",
+    "ERRNO",
+    "INVOCATION_ID",
+    "MESSAGE",
+    "MESSAGE_ID",
+    "PRIORITY",
+    "SYSLOG_FACILITY",
+    "SYSLOG_IDENTIFIER",
+    "SYSLOG_PID",
+    "SYSLOG_TIMESTAMP",
+    "_AUDIT_LOGINUID",
+    "_AUDIT_SESSION",
+    "_BOOT_ID",
+    "_CAP_EFFECTIVE",
+    "_CMDLINE",
+    "_COMM",
+    "_EXE",
+    "_GID",
+    "_HOSTNAME",
+    "_MACHINE_ID",
+    "_PID",
+    "_RUNTIME_SCOPE",
+    "_SELINUX_CONTEXT",
+    "_SOURCE_MONOTONIC_TIMESTAMP",
+    "_SOURCE_REALTIME_TIMESTAMP",
+    "_STREAM_ID",
+    "_SYSTEMD_CGROUP",
+    "_SYSTEMD_INVOCATION_ID",
+    "_SYSTEMD_OWNER_UID",
+    "_SYSTEMD_SLICE",
+    "_SYSTEMD_UNIT",
+    "_SYSTEMD_USER_SLICE",
+    "_SYSTEMD_USER_UNIT",
+    "_TRANSPORT",
+    "_UID",
+    "__CURSOR",
+    "__MONOTONIC_TIMESTAMP",
+    "__REALTIME_TIMESTAMP",
+    "__SEQNUM",
+    "__SEQNUM_ID",
+];
+
+/// Returns a borrowed name for well-known keys and an owned one otherwise,
+/// reusing `key` if it's already a `String`
+pub fn intern<K: AsRef<str> + Into<String>>(key: K) -> FieldName {
+    match WELL_KNOWN.binary_search(&key.as_ref()) {
+        Ok(index) => Cow::Borrowed(WELL_KNOWN[index]),
+        Err(_) => Cow::Owned(key.into()),
+    }
+}
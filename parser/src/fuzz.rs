@@ -0,0 +1,73 @@
+use alloc::string::ToString;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+use crate::{
+    parse_entries_with, parse_native_datagram, BytesRendering, JournalEntry, ParseOptions, Utf8Mode,
+};
+
+/// Entry point for fuzzers such as cargo-fuzz. The first byte selects the
+/// parse options, the rest is parsed as a complete export stream, as a native
+/// protocol datagram and, with the `json` feature, as a JSON line. The typed
+/// accessors are run on the resulting entries. Returns the number of entries
+/// parsed.
+///
+/// Malformed input only ever produces errors, so any panic is a bug.
+pub fn fuzz_parse(data: &[u8]) -> usize {
+    let Some((&mode, data)) = data.split_first() else {
+        return 0;
+    };
+    let options = ParseOptions {
+        utf8: match mode % 3 {
+            0 => Utf8Mode::Strict,
+            1 => Utf8Mode::Lossy,
+            _ => Utf8Mode::Fallback,
+        },
+        validate_keys: mode & 4 != 0,
+        ..ParseOptions::default()
+    };
+
+    let (mut entries, _, _) = parse_entries_with(data, &options);
+    if let Ok(entry) = parse_native_datagram(data, &options) {
+        entries.push(entry);
+    }
+    #[cfg(feature = "json")]
+    if let Ok(entry) = crate::parse_json_entry(data, &options) {
+        entries.push(entry);
+    }
+
+    for entry in entries.iter() {
+        exercise(entry);
+    }
+
+    entries.len()
+}
+
+fn exercise(entry: &JournalEntry) {
+    if let Some(Ok(cursor)) = entry.cursor() {
+        let _ = cursor.to_string().parse::<crate::Cursor>();
+    }
+    if let Some(Ok(message_id)) = entry.message_id() {
+        let _ = (message_id.name(), message_id.hyphenated().to_string());
+    }
+    let _ = entry.priority();
+    let _ = entry.structured_data();
+    let _ = entry.realtime_timestamp();
+    let _ = entry.monotonic_timestamp();
+
+    for (_, value) in entry.iter() {
+        for rendering in [
+            BytesRendering::Base64,
+            BytesRendering::LossyUtf8,
+            BytesRendering::Hex,
+            BytesRendering::Drop,
+        ] {
+            let _ = value.render(rendering);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    {
+        let _ = crate::write_journal_entry(&mut Vec::new(), entry);
+    }
+}
@@ -0,0 +1,480 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::{
+    Cursor, FieldErrorKind, FieldName, JournalEntry, JournalFieldValue, ParseOptions, Utf8Mode,
+};
+
+const SIGNATURE: &[u8; 8] = b"LPKSHHRH";
+// Size of the header fields present since the first format version
+const HEADER_MIN_SIZE: usize = 208;
+const OBJECT_HEADER_SIZE: usize = 16;
+
+const INCOMPATIBLE_COMPRESSED_XZ: u32 = 1 << 0;
+const INCOMPATIBLE_COMPRESSED_LZ4: u32 = 1 << 1;
+const INCOMPATIBLE_KEYED_HASH: u32 = 1 << 2;
+const INCOMPATIBLE_COMPRESSED_ZSTD: u32 = 1 << 3;
+const INCOMPATIBLE_COMPACT: u32 = 1 << 4;
+const INCOMPATIBLE_SUPPORTED: u32 = INCOMPATIBLE_COMPRESSED_XZ
+    | INCOMPATIBLE_COMPRESSED_LZ4
+    | INCOMPATIBLE_KEYED_HASH
+    | INCOMPATIBLE_COMPRESSED_ZSTD
+    | INCOMPATIBLE_COMPACT;
+
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_ENTRY_ARRAY: u8 = 6;
+
+const OBJECT_COMPRESSED_XZ: u8 = 1 << 0;
+const OBJECT_COMPRESSED_LZ4: u8 = 1 << 1;
+const OBJECT_COMPRESSED_ZSTD: u8 = 1 << 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalFileError {
+    #[error("I/O error")]
+    IOError(io::Error),
+
+    #[error("Not a journal file")]
+    InvalidSignature,
+
+    #[error("Unsupported incompatible flags {0:#x}")]
+    UnsupportedFlags(u32),
+
+    #[error("Invalid object at offset {offset}: {reason}")]
+    InvalidObject { offset: u64, reason: &'static str },
+
+    #[error("Unsupported compression of object at offset {0}")]
+    UnsupportedCompression(u64),
+
+    #[error("Object at offset {offset} rejected: {kind}")]
+    Rejected { offset: u64, kind: FieldErrorKind },
+}
+
+impl JournalFileError {
+    fn invalid(offset: u64, reason: &'static str) -> Self {
+        Self::InvalidObject { offset, reason }
+    }
+}
+
+/// Fields of the journal file header needed for reading entries
+#[derive(Clone, Debug)]
+pub struct JournalFileHeader {
+    pub compatible_flags: u32,
+    pub incompatible_flags: u32,
+    pub state: u8,
+    pub file_id: [u8; 16],
+    pub machine_id: [u8; 16],
+    pub seqnum_id: [u8; 16],
+    pub header_size: u64,
+    pub arena_size: u64,
+    pub n_objects: u64,
+    pub n_entries: u64,
+    pub entry_array_offset: u64,
+    pub head_entry_realtime: u64,
+    pub tail_entry_realtime: u64,
+}
+
+impl JournalFileHeader {
+    fn is_compact(&self) -> bool {
+        self.incompatible_flags & INCOMPATIBLE_COMPACT != 0
+    }
+}
+
+/// Reader of systemd's on-disk `.journal` files, iterating entries in the order
+/// they were appended
+pub struct JournalFile<R> {
+    reader: R,
+    header: JournalFileHeader,
+    /// End of the arena, bounded by the length of the file so corrupt sizes
+    /// can't cause huge allocations
+    file_size: u64,
+    options: ParseOptions,
+}
+
+impl JournalFile<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, JournalFileError> {
+        let file = File::open(path).map_err(JournalFileError::IOError)?;
+        Self::new(file)
+    }
+}
+
+impl<R: Read + Seek> JournalFile<R> {
+    pub fn new(mut reader: R) -> Result<Self, JournalFileError> {
+        let mut buf = [0; HEADER_MIN_SIZE];
+        reader
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| reader.read_exact(&mut buf))
+            .map_err(JournalFileError::IOError)?;
+
+        if &buf[0..8] != SIGNATURE {
+            return Err(JournalFileError::InvalidSignature);
+        }
+
+        let header = JournalFileHeader {
+            compatible_flags: read_u32(&buf, 8),
+            incompatible_flags: read_u32(&buf, 12),
+            state: buf[16],
+            file_id: read_id(&buf, 24),
+            machine_id: read_id(&buf, 40),
+            seqnum_id: read_id(&buf, 72),
+            header_size: read_u64(&buf, 88),
+            arena_size: read_u64(&buf, 96),
+            n_objects: read_u64(&buf, 144),
+            n_entries: read_u64(&buf, 152),
+            entry_array_offset: read_u64(&buf, 176),
+            head_entry_realtime: read_u64(&buf, 184),
+            tail_entry_realtime: read_u64(&buf, 192),
+        };
+
+        if header.incompatible_flags & !INCOMPATIBLE_SUPPORTED != 0 {
+            return Err(JournalFileError::UnsupportedFlags(
+                header.incompatible_flags & !INCOMPATIBLE_SUPPORTED,
+            ));
+        }
+
+        let length = reader
+            .seek(SeekFrom::End(0))
+            .map_err(JournalFileError::IOError)?;
+        let file_size = header
+            .header_size
+            .saturating_add(header.arena_size)
+            .min(length);
+
+        Ok(Self {
+            reader,
+            header,
+            file_size,
+            options: ParseOptions::default(),
+        })
+    }
+
+    /// Options applied to entries: UTF-8 handling, key validation and limits
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn header(&self) -> &JournalFileHeader {
+        &self.header
+    }
+
+    pub fn entries(&mut self) -> JournalFileEntries<'_, R> {
+        self.entries_from(0)
+    }
+
+    /// Entries after the first `skip`, which are passed over without being
+    /// read. A file which is still being written to can be followed by
+    /// opening it again as it grows and skipping the entries seen before.
+    pub fn entries_from(&mut self, skip: u64) -> JournalFileEntries<'_, R> {
+        JournalFileEntries {
+            array_offset: self.header.entry_array_offset,
+            remaining: self.header.n_entries.saturating_sub(skip),
+            skip,
+            items: Vec::new(),
+            index: 0,
+            file: self,
+        }
+    }
+
+    /// Reads the object at `offset`, checking its type and that its size is
+    /// within `max_size`
+    fn read_object(
+        &mut self,
+        offset: u64,
+        object_type: u8,
+        max_size: u64,
+    ) -> Result<(u8, Vec<u8>), JournalFileError> {
+        let file_size = self.file_size;
+        if offset % 8 != 0 || offset < self.header.header_size || offset >= file_size {
+            return Err(JournalFileError::invalid(offset, "offset out of bounds"));
+        }
+
+        let mut header = [0; OBJECT_HEADER_SIZE];
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.reader.read_exact(&mut header))
+            .map_err(JournalFileError::IOError)?;
+
+        let size = read_u64(&header, 8);
+        if header[0] != object_type {
+            return Err(JournalFileError::invalid(offset, "unexpected object type"));
+        }
+        if size < OBJECT_HEADER_SIZE as u64 || size > file_size - offset {
+            return Err(JournalFileError::invalid(offset, "invalid object size"));
+        }
+        if size > max_size {
+            return Err(rejected(offset, FieldErrorKind::EntryTooLarge));
+        }
+
+        let mut object = vec![0; size as usize];
+        object[..OBJECT_HEADER_SIZE].copy_from_slice(&header);
+        self.reader
+            .read_exact(&mut object[OBJECT_HEADER_SIZE..])
+            .map_err(JournalFileError::IOError)?;
+
+        Ok((header[1], object))
+    }
+
+    /// Returns the offset of the next entry array and the entry offsets of this one
+    fn read_entry_array(&mut self, offset: u64) -> Result<(u64, Vec<u64>), JournalFileError> {
+        let (_, object) = self.read_object(offset, OBJECT_ENTRY_ARRAY, u64::MAX)?;
+        if object.len() < 24 {
+            return Err(JournalFileError::invalid(offset, "truncated entry array"));
+        }
+
+        let next = read_u64(&object, 16);
+        // Arrays are appended, a chain pointing backwards would loop
+        if next != 0 && next <= offset {
+            return Err(JournalFileError::invalid(offset, "entry array chain loops"));
+        }
+        let items = if self.header.is_compact() {
+            object[24..]
+                .chunks_exact(4)
+                .map(|item| read_u32(item, 0) as u64)
+                .collect()
+        } else {
+            object[24..]
+                .chunks_exact(8)
+                .map(|item| read_u64(item, 0))
+                .collect()
+        };
+
+        Ok((next, items))
+    }
+
+    fn read_entry(&mut self, offset: u64) -> Result<JournalEntry, JournalFileError> {
+        let limits = self.options.limits;
+        let (_, object) = self.read_object(offset, OBJECT_ENTRY, limits.max_entry_size as u64)?;
+        if object.len() < 64 {
+            return Err(JournalFileError::invalid(offset, "truncated entry"));
+        }
+
+        let seqnum = read_u64(&object, 16);
+        let realtime = read_u64(&object, 24);
+        let monotonic = read_u64(&object, 32);
+        let boot_id = read_id(&object, 40);
+        let xor_hash = read_u64(&object, 56);
+
+        let data_offsets: Vec<u64> = if self.header.is_compact() {
+            object[64..]
+                .chunks_exact(4)
+                .map(|item| read_u32(item, 0) as u64)
+                .collect()
+        } else {
+            object[64..]
+                .chunks_exact(16)
+                .map(|item| read_u64(item, 0))
+                .collect()
+        };
+
+        if data_offsets.len() > limits.max_fields_per_entry {
+            return Err(rejected(offset, FieldErrorKind::TooManyFields));
+        }
+
+        let mut entry = JournalEntry::default();
+        for data_offset in data_offsets {
+            let (key, value) = self.read_data(data_offset)?;
+            entry.put_multi(key, value);
+        }
+
+        // Address fields, as added by journalctl
+        let cursor = Cursor {
+            seqnum_id: u128::from_be_bytes(self.header.seqnum_id),
+            seqnum,
+            boot_id: u128::from_be_bytes(boot_id),
+            monotonic,
+            realtime,
+            xor_hash: Some(xor_hash),
+        };
+        entry.put("__CURSOR", JournalFieldValue::UTF8(cursor.to_string()));
+        entry.put(
+            "__REALTIME_TIMESTAMP",
+            JournalFieldValue::UTF8(realtime.to_string()),
+        );
+        entry.put(
+            "__MONOTONIC_TIMESTAMP",
+            JournalFieldValue::UTF8(monotonic.to_string()),
+        );
+        entry.put("__SEQNUM", JournalFieldValue::UTF8(seqnum.to_string()));
+        entry.put(
+            "__SEQNUM_ID",
+            JournalFieldValue::UTF8(format_id(&self.header.seqnum_id)),
+        );
+        entry.put("_BOOT_ID", JournalFieldValue::UTF8(format_id(&boot_id)));
+
+        Ok(entry)
+    }
+
+    fn read_data(
+        &mut self,
+        offset: u64,
+    ) -> Result<(FieldName, JournalFieldValue), JournalFileError> {
+        let max_field_size = self.options.limits.max_field_size;
+        // Field sizes don't include the key, allow for the longest valid one
+        let max_size = max_field_size.saturating_add(256);
+        let (flags, object) = self.read_object(offset, OBJECT_DATA, max_size)?;
+
+        let payload_offset = if self.header.is_compact() { 72 } else { 64 };
+        if object.len() < payload_offset {
+            return Err(JournalFileError::invalid(offset, "truncated data"));
+        }
+
+        let payload = decompress(offset, flags, &object[payload_offset..], max_size)?;
+        let separator = payload
+            .iter()
+            .position(|b| *b == b'=')
+            .ok_or_else(|| JournalFileError::invalid(offset, "data without field separator"))?;
+        let (raw_key, raw_value) = (&payload[..separator], &payload[separator + 1..]);
+
+        let utf8 = self.options.utf8;
+        let key = match std::str::from_utf8(raw_key) {
+            Ok(key) => crate::intern(key),
+            Err(_) if utf8 == Utf8Mode::Strict => {
+                return Err(rejected(offset, FieldErrorKind::InvalidUtf8))
+            }
+            Err(_) => FieldName::Owned(String::from_utf8_lossy(raw_key).into_owned()),
+        };
+
+        if self.options.validate_keys && !crate::is_valid_field_key(&key) {
+            return Err(rejected(offset, FieldErrorKind::InvalidKey));
+        }
+
+        if raw_value.len() as u64 > max_field_size {
+            return Err(rejected(offset, FieldErrorKind::FieldTooLarge));
+        }
+
+        let value = match std::str::from_utf8(raw_value) {
+            Ok(value) => JournalFieldValue::UTF8(value.to_string()),
+            Err(_) => match utf8 {
+                Utf8Mode::Strict => return Err(rejected(offset, FieldErrorKind::InvalidUtf8)),
+                Utf8Mode::Lossy => {
+                    JournalFieldValue::UTF8(String::from_utf8_lossy(raw_value).into_owned())
+                }
+                Utf8Mode::Fallback => JournalFieldValue::Bytes(crate::binary_value(raw_value)),
+            },
+        };
+
+        Ok((key, value))
+    }
+}
+
+/// Iterator over the entries of a journal file
+pub struct JournalFileEntries<'a, R> {
+    file: &'a mut JournalFile<R>,
+    array_offset: u64,
+    remaining: u64,
+    /// Entries still to be passed over
+    skip: u64,
+    items: Vec<u64>,
+    index: usize,
+}
+
+impl<'a, R: Read + Seek> Iterator for JournalFileEntries<'a, R> {
+    type Item = Result<JournalEntry, JournalFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining == 0 {
+                return None;
+            }
+
+            if let Some(offset) = self.items.get(self.index).copied() {
+                self.index += 1;
+                // Unused slots of the last entry array
+                if offset == 0 {
+                    self.remaining = 0;
+                    return None;
+                }
+
+                self.remaining -= 1;
+                return Some(self.file.read_entry(offset));
+            }
+
+            if self.array_offset == 0 {
+                return None;
+            }
+
+            match self.file.read_entry_array(self.array_offset) {
+                Ok((next, items)) => {
+                    let skipped = self.skip.min(items.len() as u64);
+                    self.skip -= skipped;
+                    self.array_offset = next;
+                    self.items = items;
+                    self.index = skipped as usize;
+                }
+                Err(err) => {
+                    self.remaining = 0;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+fn rejected(offset: u64, kind: FieldErrorKind) -> JournalFileError {
+    JournalFileError::Rejected { offset, kind }
+}
+
+fn decompress(
+    offset: u64,
+    flags: u8,
+    payload: &[u8],
+    max_size: u64,
+) -> Result<Vec<u8>, JournalFileError> {
+    if flags & OBJECT_COMPRESSED_ZSTD != 0 {
+        let decoder =
+            zstd::stream::read::Decoder::new(payload).map_err(JournalFileError::IOError)?;
+        let mut data = Vec::new();
+        decoder
+            .take(max_size.saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(|_| JournalFileError::invalid(offset, "corrupted zstd data"))?;
+        if data.len() as u64 > max_size {
+            return Err(rejected(offset, FieldErrorKind::FieldTooLarge));
+        }
+
+        Ok(data)
+    } else if flags & OBJECT_COMPRESSED_LZ4 != 0 {
+        // Uncompressed size followed by a raw LZ4 block
+        if payload.len() < 8 {
+            return Err(JournalFileError::invalid(offset, "truncated lz4 data"));
+        }
+        let size = read_u64(payload, 0);
+        if size > max_size {
+            return Err(rejected(offset, FieldErrorKind::FieldTooLarge));
+        }
+
+        lz4_flex::block::decompress(&payload[8..], size as usize)
+            .map_err(|_| JournalFileError::invalid(offset, "corrupted lz4 data"))
+    } else if flags & OBJECT_COMPRESSED_XZ != 0 {
+        Err(JournalFileError::UnsupportedCompression(offset))
+    } else {
+        Ok(Vec::from(payload))
+    }
+}
+
+// Callers check object sizes first, bytes beyond the buffer read as zero
+// rather than panicking on corrupt input
+
+fn read_array<const N: usize>(buf: &[u8], at: usize) -> [u8; N] {
+    buf.get(at..at.saturating_add(N))
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or([0; N])
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(read_array(buf, at))
+}
+
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(read_array(buf, at))
+}
+
+fn read_id(buf: &[u8], at: usize) -> [u8; 16] {
+    read_array(buf, at)
+}
+
+fn format_id(id: &[u8; 16]) -> String {
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
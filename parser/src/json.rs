@@ -0,0 +1,353 @@
+use serde_json::{Map, Value};
+
+use crate::{is_valid_field_key, JournalEntry, JournalFieldValue, ParseOptions};
+
+#[derive(Debug, thiserror::Error)]
+pub enum JsonEntryError {
+    #[error("JSON error: {0}")]
+    JsonError(serde_json::Error),
+
+    #[error("Entry is not a JSON object")]
+    NotAnObject,
+
+    #[error("Invalid field key \"{0}\"")]
+    InvalidKey(String),
+
+    #[error("Invalid value for field \"{0}\"")]
+    InvalidValue(String),
+
+    #[error("Entry has too many fields")]
+    TooManyFields,
+
+    #[error("Entry exceeds the size limit")]
+    EntryTooLarge,
+}
+
+/// Parses a single line of `journalctl --output=json` into an entry.
+///
+/// Binary values are encoded by journald as arrays of byte values, fields which
+/// occur more than once as arrays of values. Fields journalctl omitted as `null`
+/// are skipped.
+pub fn parse_json_entry(
+    line: &[u8],
+    options: &ParseOptions,
+) -> Result<JournalEntry, JsonEntryError> {
+    if line.len() > options.limits.max_entry_size {
+        return Err(JsonEntryError::EntryTooLarge);
+    }
+
+    let fields: Map<String, Value> = match serde_json::from_slice(line) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(_) => return Err(JsonEntryError::NotAnObject),
+        Err(err) => return Err(JsonEntryError::JsonError(err)),
+    };
+
+    if fields.len() > options.limits.max_fields_per_entry {
+        return Err(JsonEntryError::TooManyFields);
+    }
+
+    let mut entry = JournalEntry::default();
+    for (key, value) in fields {
+        if options.validate_keys && !is_valid_field_key(&key) {
+            return Err(JsonEntryError::InvalidKey(key));
+        }
+        let key = crate::intern(key);
+
+        let values = match value {
+            Value::Array(values) if !is_byte_array(&values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            if value.is_null() {
+                continue;
+            }
+
+            match convert_value(value, options) {
+                Some(value) => entry.put_multi(key.clone(), value),
+                None => return Err(JsonEntryError::InvalidValue(key.into_owned())),
+            }
+        }
+    }
+
+    if entry.len() > options.limits.max_fields_per_entry {
+        return Err(JsonEntryError::TooManyFields);
+    }
+
+    Ok(entry)
+}
+
+fn is_byte_array(values: &[Value]) -> bool {
+    values.first().map(Value::is_number).unwrap_or(true)
+}
+
+fn convert_value(value: Value, options: &ParseOptions) -> Option<JournalFieldValue> {
+    let value = match value {
+        Value::String(value) => JournalFieldValue::UTF8(value),
+        Value::Array(values) => {
+            let bytes = values
+                .iter()
+                .map(|value| value.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()?;
+
+            JournalFieldValue::Bytes(bytes.into())
+        }
+        _ => return None,
+    };
+
+    let size = match &value {
+        JournalFieldValue::UTF8(value) => value.len(),
+        JournalFieldValue::Bytes(value) => value.len(),
+    };
+
+    if size as u64 > options.limits.max_field_size {
+        return None;
+    }
+
+    Some(value)
+}
+
+#[cfg(feature = "tokio")]
+pub use self::reader::JsonEntryReader;
+
+#[cfg(feature = "tokio")]
+mod reader {
+    use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+    use super::{parse_json_entry, JsonEntryError};
+    use crate::{JournalEntry, JournalReadError, ParseOptions};
+
+    /// Reads `journalctl --output=json` entries, one per line, from an async byte stream.
+    pub struct JsonEntryReader<R> {
+        reader: BufReader<R>,
+        options: ParseOptions,
+        line: Vec<u8>,
+        offset: u64,
+    }
+
+    impl<R: AsyncRead + Unpin> JsonEntryReader<R> {
+        pub fn new(reader: R) -> Self {
+            Self {
+                reader: BufReader::new(reader),
+                options: ParseOptions::default(),
+                line: Vec::new(),
+                offset: 0,
+            }
+        }
+
+        pub fn with_options(mut self, options: ParseOptions) -> Self {
+            self.options = options;
+            self
+        }
+
+        /// Total number of bytes consumed from the stream
+        pub fn offset(&self) -> u64 {
+            self.offset
+        }
+
+        /// Returns the next entry, or `None` once the stream is exhausted. A line
+        /// which fails to parse is consumed, so reading can continue after an error.
+        pub async fn next_entry(&mut self) -> Result<Option<JournalEntry>, JournalReadError> {
+            loop {
+                if !self.read_line().await? {
+                    return Ok(None);
+                }
+
+                if self.line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                return parse_json_entry(&self.line, &self.options)
+                    .map(Some)
+                    .map_err(JournalReadError::JsonError);
+            }
+        }
+
+        /// Reads the next line into the buffer, skipping the rest of lines longer
+        /// than the entry size limit
+        async fn read_line(&mut self) -> Result<bool, JournalReadError> {
+            self.line.clear();
+            let mut too_large = false;
+
+            loop {
+                let available = self
+                    .reader
+                    .fill_buf()
+                    .await
+                    .map_err(JournalReadError::IOError)?;
+                if available.is_empty() {
+                    return self.line_result(too_large, !self.line.is_empty());
+                }
+
+                let (length, complete) = match available.iter().position(|b| *b == b'\n') {
+                    Some(index) => (index + 1, true),
+                    None => (available.len(), false),
+                };
+
+                if !too_large {
+                    self.line.extend_from_slice(&available[..length]);
+                    if self.line.len() > self.options.limits.max_entry_size {
+                        self.line.clear();
+                        too_large = true;
+                    }
+                }
+
+                self.reader.consume(length);
+                self.offset += length as u64;
+
+                if complete {
+                    return self.line_result(too_large, true);
+                }
+            }
+        }
+
+        fn line_result(&self, too_large: bool, read: bool) -> Result<bool, JournalReadError> {
+            if too_large {
+                Err(JournalReadError::JsonError(JsonEntryError::EntryTooLarge))
+            } else {
+                Ok(read)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<JournalEntry, JsonEntryError> {
+        parse_json_entry(line.as_bytes(), &ParseOptions::default())
+    }
+
+    fn values(entry: &JournalEntry, key: &str) -> Vec<String> {
+        entry.get_all(key).map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_text_fields() {
+        let entry =
+            parse(r#"{"__CURSOR":"s=1","MESSAGE":"hello \"world\"\n","PRIORITY":"6","EMPTY":""}"#)
+                .unwrap();
+
+        assert_eq!(entry.len(), 4);
+        assert_eq!(values(&entry, "MESSAGE"), ["hello \"world\"\n"]);
+        assert_eq!(values(&entry, "PRIORITY"), ["6"]);
+        assert_eq!(values(&entry, "EMPTY"), [""]);
+    }
+
+    #[test]
+    fn parses_binary_values() {
+        let entry = parse(r#"{"MESSAGE":[104,105,0,255],"EMPTY":[]}"#).unwrap();
+
+        let Some(JournalFieldValue::Bytes(message)) = entry.get("MESSAGE") else {
+            panic!("expected a binary value, got {:?}", entry.get("MESSAGE"));
+        };
+        assert_eq!(&message[..], b"hi\0\xff");
+        let Some(JournalFieldValue::Bytes(empty)) = entry.get("EMPTY") else {
+            panic!("expected a binary value, got {:?}", entry.get("EMPTY"));
+        };
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn parses_repeated_fields() {
+        let entry = parse(r#"{"TAG":["a","b",null],"MIXED":["text",[98,105,110]]}"#).unwrap();
+
+        assert_eq!(values(&entry, "TAG"), ["a", "b"]);
+        let mixed: Vec<_> = entry.get_all("MIXED").collect();
+        assert!(matches!(mixed[0], JournalFieldValue::UTF8(value) if value == "text"));
+        assert!(matches!(mixed[1], JournalFieldValue::Bytes(value) if &value[..] == b"bin"));
+        assert_eq!(mixed.len(), 2);
+
+        let entry = parse(r#"{"BLOBS":[[1,2],[3]]}"#).unwrap();
+        let blobs: Vec<_> = entry.get_all("BLOBS").collect();
+        assert!(matches!(
+            blobs[..],
+            [JournalFieldValue::Bytes(_), JournalFieldValue::Bytes(_)]
+        ));
+    }
+
+    #[test]
+    fn skips_null_values() {
+        let entry = parse(r#"{"MESSAGE":"hi","_SOURCE_REALTIME_TIMESTAMP":null}"#).unwrap();
+
+        assert_eq!(entry.len(), 1);
+        assert!(entry.get("_SOURCE_REALTIME_TIMESTAMP").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(matches!(parse("[1]"), Err(JsonEntryError::NotAnObject)));
+        assert!(matches!(
+            parse(r#"{"A":"#),
+            Err(JsonEntryError::JsonError(_))
+        ));
+
+        for line in [
+            r#"{"A":1}"#,
+            r#"{"A":true}"#,
+            r#"{"A":{"B":"c"}}"#,
+            r#"{"A":[1,"x"]}"#,
+            r#"{"A":[256]}"#,
+            r#"{"A":[-1]}"#,
+            r#"{"A":["x",1]}"#,
+        ] {
+            let Err(JsonEntryError::InvalidValue(key)) = parse(line) else {
+                panic!("{} was accepted", line);
+            };
+            assert_eq!(key, "A");
+        }
+    }
+
+    #[test]
+    fn applies_options() {
+        let options = ParseOptions {
+            validate_keys: true,
+            ..Default::default()
+        };
+        let result = parse_json_entry(br#"{"lower":"x"}"#, &options);
+        assert!(matches!(result, Err(JsonEntryError::InvalidKey(key)) if key == "lower"));
+
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 2;
+        let result = parse_json_entry(br#"{"A":[1,2,3]}"#, &options);
+        assert!(matches!(result, Err(JsonEntryError::InvalidValue(_))));
+
+        let mut options = ParseOptions::default();
+        options.limits.max_fields_per_entry = 2;
+        assert!(parse_json_entry(br#"{"A":["1","2"]}"#, &options).is_ok());
+        let result = parse_json_entry(br#"{"A":["1","2"],"B":"3"}"#, &options);
+        assert!(matches!(result, Err(JsonEntryError::TooManyFields)));
+
+        let mut options = ParseOptions::default();
+        options.limits.max_entry_size = 8;
+        let result = parse_json_entry(br#"{"A":"123"}"#, &options);
+        assert!(matches!(result, Err(JsonEntryError::EntryTooLarge)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn reader_continues_after_errors() {
+        use crate::JournalReadError;
+
+        let input = b"{\"A\":\"1\"}\n\n{\"A\":\"12345678901234\"}\nnot json\n{\"A\":\"2\"}";
+        let mut options = ParseOptions::default();
+        options.limits.max_entry_size = 16;
+        let mut reader = JsonEntryReader::new(&input[..]).with_options(options);
+
+        let entry = reader.next_entry().await.unwrap().unwrap();
+        assert_eq!(values(&entry, "A"), ["1"]);
+        assert!(matches!(
+            reader.next_entry().await,
+            Err(JournalReadError::JsonError(JsonEntryError::EntryTooLarge))
+        ));
+        assert!(matches!(
+            reader.next_entry().await,
+            Err(JournalReadError::JsonError(JsonEntryError::JsonError(_)))
+        ));
+        let entry = reader.next_entry().await.unwrap().unwrap();
+        assert_eq!(values(&entry, "A"), ["2"]);
+        assert!(reader.next_entry().await.unwrap().is_none());
+        assert_eq!(reader.offset(), input.len() as u64);
+    }
+}
@@ -1,68 +1,216 @@
-use nom::{
-    branch::alt,
-    bytes::streaming::{tag, take_till, take_until},
-    error::context,
-    multi::length_data,
-    number::complete::le_u64,
-    sequence::pair,
-    IResult,
-};
-
-#[cfg(feature = "bytes-as-base64")]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![forbid(unsafe_code)]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use nom::{branch::alt, bytes::streaming::tag, sequence::pair, IResult};
+
 use base64::{engine::general_purpose::STANDARD as b64, Engine};
 
+use crate::combinators::{parse_bytes_value, parse_field_key, parse_utf8_value, RawValue};
+
+mod batch;
+pub mod combinators;
+mod cursor;
+mod entry;
+mod error;
+#[cfg(feature = "std")]
+mod export;
+mod field_name;
+mod fuzz;
+#[cfg(feature = "journal-file")]
+mod journal_file;
+#[cfg(feature = "json")]
+mod json;
+mod message_id;
+mod native;
+mod priority;
+#[cfg(feature = "tokio")]
+mod reader;
+mod structured_data;
+mod syslog;
+#[cfg(feature = "std")]
+pub mod testgen;
+mod verbose;
+
+pub use batch::{parse_entries, parse_entries_with, ParseStats};
+pub use cursor::{Cursor, CursorError};
+pub use entry::JournalEntry;
+pub use error::{FieldError, FieldErrorKind, ParseErrorInfo, TimestampError};
+#[cfg(feature = "std")]
+pub use export::{write_journal_entry, write_journal_field};
+pub use field_name::{intern, FieldName};
+pub use fuzz::fuzz_parse;
+#[cfg(feature = "journal-file")]
+pub use journal_file::{JournalFile, JournalFileEntries, JournalFileError, JournalFileHeader};
+#[cfg(all(feature = "json", feature = "tokio"))]
+pub use json::JsonEntryReader;
+#[cfg(feature = "json")]
+pub use json::{parse_json_entry, JsonEntryError};
+pub use message_id::{MessageId, MessageIdError};
+pub use native::parse_native_datagram;
+pub use priority::{Priority, PriorityError};
+#[cfg(feature = "tokio")]
+pub use reader::{EntryReader, JournalReadError};
+pub use structured_data::{parse_structured_data, SdElement, StructuredDataError};
+pub use syslog::{parse_syslog_message, SyslogError};
+pub use verbose::{parse_verbose_entries, VerboseEntryError};
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JournalField {
-    pub key: String,
+    pub key: FieldName,
     pub value: JournalFieldValue,
 }
 
+/// Storage of binary field values. With the `bytes` feature these are
+/// reference-counted slices of the read buffer, cheap to clone and pass along.
+#[cfg(feature = "bytes")]
+pub type BinaryValue = bytes::Bytes;
+#[cfg(not(feature = "bytes"))]
+pub type BinaryValue = Vec<u8>;
+
 #[derive(Clone, Debug)]
 pub enum JournalFieldValue {
     UTF8(String),
-    Bytes(Vec<u8>),
+    Bytes(BinaryValue),
 }
 
-impl From<&JournalFieldValue> for String {
-    fn from(value: &JournalFieldValue) -> Self {
-        match value {
-            JournalFieldValue::UTF8(value) => value.clone(),
-
-            #[cfg(feature = "bytes-as-base64")]
-            JournalFieldValue::Bytes(value) => {
-                let mut v = String::from("base64:");
-                v += b64.encode(value).as_str();
-                v
-            }
+/// Copies `data` into a binary value
+pub(crate) fn binary_value(data: &[u8]) -> BinaryValue {
+    #[cfg(feature = "bytes")]
+    return bytes::Bytes::copy_from_slice(data);
+
+    #[cfg(not(feature = "bytes"))]
+    return data.to_vec();
+}
+
+/// How binary values are turned into text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BytesRendering {
+    /// `base64:` followed by the base64 encoded value
+    Base64,
+    /// Invalid sequences replaced with U+FFFD
+    #[default]
+    LossyUtf8,
+    /// `hex:` followed by the value in lowercase hex
+    Hex,
+    /// Binary values are left out
+    Drop,
+}
 
-            #[cfg(not(feature = "bytes-as-base64"))]
-            JournalFieldValue::Bytes(value) => {
-                String::from_utf8_lossy(&strip_ansi_escapes::strip(value)).to_string()
+impl BytesRendering {
+    /// Text of a binary value, `None` for `Drop`
+    pub fn render(self, value: &[u8]) -> Option<String> {
+        match self {
+            Self::Base64 => Some(String::from("base64:") + &b64.encode(value)),
+            Self::LossyUtf8 => Some(String::from_utf8_lossy(value).into_owned()),
+            Self::Hex => {
+                let mut text = String::with_capacity(4 + 2 * value.len());
+                text.push_str("hex:");
+                for byte in value {
+                    text.push(HEX_DIGITS[usize::from(byte >> 4)] as char);
+                    text.push(HEX_DIGITS[usize::from(byte & 0xf)] as char);
+                }
+                Some(text)
             }
+            Self::Drop => None,
         }
     }
 }
 
-impl From<JournalFieldValue> for String {
-    fn from(value: JournalFieldValue) -> Self {
-        match value {
-            JournalFieldValue::UTF8(value) => value,
-
-            #[cfg(feature = "bytes-as-base64")]
-            JournalFieldValue::Bytes(value) => {
-                let mut v = String::from("base64:");
-                v += b64.encode(value).as_str();
-                v
-            }
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
 
-            #[cfg(not(feature = "bytes-as-base64"))]
-            JournalFieldValue::Bytes(value) => {
-                String::from_utf8_lossy(&strip_ansi_escapes::strip(value)).to_string()
-            }
+impl JournalFieldValue {
+    /// Length of the value in bytes
+    pub fn len(&self) -> usize {
+        match self {
+            Self::UTF8(value) => value.len(),
+            Self::Bytes(value) => value.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Text of the value, binary values rendered as requested. `None` for
+    /// binary values rendered with `BytesRendering::Drop`.
+    pub fn render(&self, rendering: BytesRendering) -> Option<String> {
+        match self {
+            Self::UTF8(value) => Some(value.clone()),
+            Self::Bytes(value) => rendering.render(value),
+        }
+    }
+
+    /// Like `render`, without copying text values
+    pub fn into_rendered(self, rendering: BytesRendering) -> Option<String> {
+        match self {
+            Self::UTF8(value) => Some(value),
+            Self::Bytes(value) => rendering.render(&value),
+        }
+    }
+
+    /// Serializes the value as text, binary values rendered as requested and
+    /// `BytesRendering::Drop` serialized as none
+    #[cfg(feature = "serde")]
+    pub fn rendered(&self, rendering: BytesRendering) -> RenderedValue<'_> {
+        RenderedValue {
+            value: self,
+            rendering,
+        }
+    }
+}
+
+/// Value serialized with a chosen `BytesRendering`, see
+/// `JournalFieldValue::rendered`
+#[cfg(feature = "serde")]
+pub struct RenderedValue<'a> {
+    value: &'a JournalFieldValue,
+    rendering: BytesRendering,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RenderedValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.value {
+            JournalFieldValue::UTF8(value) => serializer.serialize_str(value),
+            JournalFieldValue::Bytes(value) => match self.rendering.render(value) {
+                Some(text) => serializer.serialize_str(&text),
+                None => serializer.serialize_none(),
+            },
         }
     }
 }
 
+// Conversions render binary values with the default `BytesRendering`
+impl From<&JournalFieldValue> for String {
+    fn from(value: &JournalFieldValue) -> Self {
+        value.render(BytesRendering::default()).unwrap_or_default()
+    }
+}
+
+impl From<JournalFieldValue> for String {
+    fn from(value: JournalFieldValue) -> Self {
+        value
+            .into_rendered(BytesRendering::default())
+            .unwrap_or_default()
+    }
+}
+
+// Binary values are serialized losslessly, as arrays of bytes like journalctl
+// does; use `JournalFieldValue::rendered` for text
 #[cfg(feature = "serde")]
 impl serde::Serialize for JournalFieldValue {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -71,46 +219,200 @@ impl serde::Serialize for JournalFieldValue {
     {
         match self {
             Self::UTF8(value) => serializer.serialize_str(value),
+            Self::Bytes(value) => serializer.collect_seq(value.iter().map(|b| i32::from(*b))),
+        }
+    }
+}
 
-            #[cfg(feature = "bytes-as-base64")]
-            Self::Bytes(value) => {
-                let encoded = b64.encode(value);
-                serializer.serialize_str(&encoded)
-            }
+/// Accepts what `Serialize` produces: plain strings become `UTF8`, strings
+/// prefixed with `base64:` and sequences of bytes become `Bytes`.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for JournalFieldValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(JournalFieldValueVisitor)
+    }
+}
 
-            #[cfg(not(feature = "bytes-as-base64"))]
-            Self::Bytes(value) => serializer.collect_seq(value.iter().map(|b| i32::from(*b))),
+#[cfg(feature = "serde")]
+pub(crate) struct JournalFieldValueVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for JournalFieldValueVisitor {
+    type Value = JournalFieldValue;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("a string or a sequence of bytes")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        match value.strip_prefix("base64:") {
+            Some(encoded) => b64
+                .decode(encoded)
+                .map(|value| JournalFieldValue::Bytes(value.into()))
+                .map_err(E::custom),
+            None => Ok(JournalFieldValue::UTF8(value.to_string())),
+        }
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        if value.starts_with("base64:") {
+            self.visit_str(&value)
+        } else {
+            Ok(JournalFieldValue::UTF8(value))
         }
     }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(JournalFieldValue::Bytes(binary_value(value)))
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(JournalFieldValue::Bytes(value.into()))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+
+        Ok(JournalFieldValue::Bytes(bytes.into()))
+    }
+}
+
+/// How to handle field keys and text values which aren't valid UTF-8
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Utf8Mode {
+    /// Fail parsing
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+    /// Keep invalid values as `JournalFieldValue::Bytes`, keys are decoded lossily
+    #[default]
+    Fallback,
+}
+
+/// Upper bounds protecting against corrupted or hostile input. Defaults match
+/// the limits used by systemd-journal-remote.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ParserLimits {
+    pub max_field_size: u64,
+    pub max_fields_per_entry: usize,
+    pub max_entry_size: usize,
 }
 
-fn parse_utf8_value(input: &[u8]) -> IResult<&[u8], JournalFieldValue> {
-    let (input, (_, line)) = pair(
-        context("equals sign separator", tag(b"=")),
-        context("contents until terminating newline", take_until("\n")),
-    )(input)?;
-    let utf8_line = unsafe { std::str::from_utf8_unchecked(line) }.to_string();
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_field_size: 768 * 1024 * 1024,
+            max_fields_per_entry: 1024,
+            max_entry_size: 770 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    pub utf8: Utf8Mode,
+    /// Reject keys which don't follow journald field naming rules
+    pub validate_keys: bool,
+    pub limits: ParserLimits,
+    /// Truncate values exceeding `limits.max_field_size` and mark the entry
+    /// with `TRUNCATED_FIELD` instead of failing. Only `EntryReader` supports
+    /// this, as it can skip the rest of the value without buffering it.
+    pub truncate_oversized: bool,
+    /// Return the last entry of a stream which ends without its terminating
+    /// blank line, e.g. output cut off by `head`, instead of dropping it. A
+    /// trailing incomplete field is discarded. Only `EntryReader` supports
+    /// this, other parsers can't tell the end of the input from a chunk.
+    pub flush_at_eof: bool,
+}
+
+/// Marker field added to entries with truncated values
+pub const TRUNCATED_FIELD: &str = "_TRUNCATED";
+
+pub type FieldResult<'a, T> = IResult<&'a [u8], T, FieldError<&'a [u8]>>;
+
+pub const MAX_FIELD_KEY_LENGTH: usize = 64;
+
+/// Checks `key` against journald field naming rules: only uppercase ASCII letters,
+/// digits and underscores, not starting with a digit, at most 64 characters long.
+pub fn is_valid_field_key(key: &str) -> bool {
+    let key = key.as_bytes();
+
+    !key.is_empty()
+        && key.len() <= MAX_FIELD_KEY_LENGTH
+        && !key[0].is_ascii_digit()
+        && key
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || *b == b'_')
+}
+
+pub(crate) fn failure(input: &[u8], kind: FieldErrorKind) -> nom::Err<FieldError<&[u8]>> {
+    nom::Err::Failure(FieldError::new(input, kind))
+}
 
-    Ok((input, JournalFieldValue::UTF8(utf8_line)))
+pub fn parse_journal_field(input: &[u8]) -> FieldResult<JournalField> {
+    parse_journal_field_with(input, &ParseOptions::default())
 }
 
-fn parse_bytes_value(input: &[u8]) -> IResult<&[u8], JournalFieldValue> {
-    let (input, (_, data)) = pair(
-        context("newline separator", tag(b"\n")),
-        context("binary data with size prefix", length_data(le_u64)),
-    )(input)?;
+pub fn parse_journal_field_with<'a>(
+    input: &'a [u8],
+    options: &ParseOptions,
+) -> FieldResult<'a, JournalField> {
+    let (input, (key, value)) = parse_raw_field(input, options)?;
 
-    Ok((input, JournalFieldValue::Bytes(Vec::from(data))))
+    Ok((
+        input,
+        JournalField {
+            key,
+            value: value.into_value(),
+        },
+    ))
 }
 
-pub fn parse_journal_field(input: &[u8]) -> IResult<&[u8], JournalField> {
-    let (input, raw_key) = context("field key", take_till(|b| b == b'=' || b == b'\n'))(input)?;
-    let key = unsafe { std::str::from_utf8_unchecked(raw_key) }.to_string();
+/// Parses a field without copying binary values out of the input yet
+pub(crate) fn parse_raw_field<'a>(
+    input: &'a [u8],
+    options: &ParseOptions,
+) -> FieldResult<'a, (FieldName, RawValue<'a>)> {
+    let (input, key) = parse_field_key(input, options)?;
 
-    let parse_either = alt((parse_utf8_value, parse_bytes_value));
+    let parse_either = alt((
+        |i| parse_utf8_value(i, options),
+        |i| parse_bytes_value(i, options),
+    ));
     let mut parse_all = pair(parse_either, tag(b"\n"));
 
     let (input, (value, _)) = parse_all(input)?;
 
-    Ok((input, JournalField { key, value }))
+    Ok((input, (key, value)))
 }
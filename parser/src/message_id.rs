@@ -0,0 +1,179 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// 128-bit ID in `MESSAGE_ID`, identifying a message type as listed in the
+/// journal catalog
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageId(pub u128);
+
+/// Well-known IDs, named as in systemd's `sd-messages.h`
+const WELL_KNOWN: &[(MessageId, &str)] = &[
+    (MessageId::JOURNAL_START, "JOURNAL_START"),
+    (MessageId::JOURNAL_STOP, "JOURNAL_STOP"),
+    (MessageId::JOURNAL_DROPPED, "JOURNAL_DROPPED"),
+    (MessageId::JOURNAL_MISSED, "JOURNAL_MISSED"),
+    (MessageId::COREDUMP, "COREDUMP"),
+    (MessageId::SESSION_START, "SESSION_START"),
+    (MessageId::SESSION_STOP, "SESSION_STOP"),
+    (MessageId::SEAT_START, "SEAT_START"),
+    (MessageId::SEAT_STOP, "SEAT_STOP"),
+    (MessageId::TIME_CHANGE, "TIME_CHANGE"),
+    (MessageId::STARTUP_FINISHED, "STARTUP_FINISHED"),
+    (MessageId::SLEEP_START, "SLEEP_START"),
+    (MessageId::SLEEP_STOP, "SLEEP_STOP"),
+    (MessageId::SHUTDOWN, "SHUTDOWN"),
+    (MessageId::UNIT_STARTING, "UNIT_STARTING"),
+    (MessageId::UNIT_STARTED, "UNIT_STARTED"),
+    (MessageId::UNIT_FAILED, "UNIT_FAILED"),
+    (MessageId::UNIT_STOPPING, "UNIT_STOPPING"),
+    (MessageId::UNIT_STOPPED, "UNIT_STOPPED"),
+    (MessageId::UNIT_RELOADING, "UNIT_RELOADING"),
+    (MessageId::UNIT_RELOADED, "UNIT_RELOADED"),
+    (MessageId::UNIT_PROCESS_EXIT, "UNIT_PROCESS_EXIT"),
+    (MessageId::SPAWN_FAILED, "SPAWN_FAILED"),
+];
+
+impl MessageId {
+    pub const JOURNAL_START: Self = Self(0xf77379a8490b408bbe5f6940505a777b);
+    pub const JOURNAL_STOP: Self = Self(0xd93fb3c9c24d451a97cea615ce59c00b);
+    pub const JOURNAL_DROPPED: Self = Self(0xa596d6fe7bfa4994828e72309e95d61e);
+    pub const JOURNAL_MISSED: Self = Self(0xe9bf28e6e834481bb6f48f548ad13606);
+    pub const COREDUMP: Self = Self(0xfc2e22bc6ee647b6b90729ab34a250b1);
+    pub const SESSION_START: Self = Self(0x8d45620c1a4348dbb17410da57c60c66);
+    pub const SESSION_STOP: Self = Self(0x3354939424b4456d9802ca8333ed424a);
+    pub const SEAT_START: Self = Self(0xfcbefc5da23d428093f97c82a9290f7b);
+    pub const SEAT_STOP: Self = Self(0xe7852bfe46784ed0accde04bc864c2d5);
+    pub const TIME_CHANGE: Self = Self(0xc7a787079b354eaaa9e77b371893cd27);
+    pub const STARTUP_FINISHED: Self = Self(0xb07a249cd024414a82dd00cd181378ff);
+    pub const SLEEP_START: Self = Self(0x6bbd95ee977941e497c48be27c254128);
+    pub const SLEEP_STOP: Self = Self(0x8811e6df2a8e40f58a94cea26f8ebf14);
+    pub const SHUTDOWN: Self = Self(0x98268866d1d54a499c4e98921d93bc40);
+    pub const UNIT_STARTING: Self = Self(0x7d4958e842da4a758f6c1cdc7b36dcc5);
+    pub const UNIT_STARTED: Self = Self(0x39f53479d3a045ac8e11786248231fbf);
+    pub const UNIT_FAILED: Self = Self(0xbe02cf6855d2428ba40df7e9d022f03d);
+    pub const UNIT_STOPPING: Self = Self(0xde5b426a63be47a7b6ac3eaac82e2f6f);
+    pub const UNIT_STOPPED: Self = Self(0x9d1aaa27d60140bd96365438aad20286);
+    pub const UNIT_RELOADING: Self = Self(0xd34d037fff1847e6ae669a370e694725);
+    pub const UNIT_RELOADED: Self = Self(0x7b05ebc668384222baa8881179cfda54);
+    pub const UNIT_PROCESS_EXIT: Self = Self(0x98e322203f7a4ed290d09fe03c09fe15);
+    pub const SPAWN_FAILED: Self = Self(0x641257651c1b4ec9a8624d7a40a9e1e7);
+
+    /// Name of a well-known ID, e.g. `UNIT_FAILED`
+    pub fn name(&self) -> Option<&'static str> {
+        WELL_KNOWN
+            .iter()
+            .find(|(id, _)| id == self)
+            .map(|(_, name)| *name)
+    }
+
+    /// Hyphenated UUID form, as used by `journalctl --list-catalog`
+    pub fn hyphenated(&self) -> impl fmt::Display {
+        Hyphenated(self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageIdError;
+
+impl fmt::Display for MessageIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message ID is not 32 hex digits")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MessageIdError {}
+
+impl FromStr for MessageId {
+    type Err = MessageIdError;
+
+    /// Accepts both the plain form used in `MESSAGE_ID` fields and the
+    /// hyphenated UUID form
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let mut value = 0u128;
+        let mut digits = 0;
+
+        for (i, c) in id.chars().enumerate() {
+            if c == '-' && matches!(i, 8 | 13 | 18 | 23) && id.len() == 36 {
+                continue;
+            }
+            let digit = c.to_digit(16).ok_or(MessageIdError)?;
+            value = (value << 4) | u128::from(digit);
+            digits += 1;
+        }
+
+        if digits != 32 {
+            return Err(MessageIdError);
+        }
+
+        Ok(Self(value))
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+struct Hyphenated(u128);
+
+impl fmt::Display for Hyphenated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            id >> 96,
+            (id >> 80) & 0xffff,
+            (id >> 64) & 0xffff,
+            (id >> 48) & 0xffff,
+            id & 0xffff_ffff_ffff
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn parses_plain_and_hyphenated_forms() {
+        let plain: MessageId = "be02cf6855d2428ba40df7e9d022f03d".parse().unwrap();
+        let hyphenated: MessageId = "be02cf68-55d2-428b-a40d-f7e9d022f03d".parse().unwrap();
+        let uppercase: MessageId = "BE02CF6855D2428BA40DF7E9D022F03D".parse().unwrap();
+
+        assert_eq!(plain, MessageId::UNIT_FAILED);
+        assert_eq!(hyphenated, MessageId::UNIT_FAILED);
+        assert_eq!(uppercase, MessageId::UNIT_FAILED);
+        assert_eq!(plain.to_string(), "be02cf6855d2428ba40df7e9d022f03d");
+        assert_eq!(
+            plain.hyphenated().to_string(),
+            "be02cf68-55d2-428b-a40d-f7e9d022f03d"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        for id in [
+            "",
+            "be02cf6855d2428ba40df7e9d022f03",
+            "be02cf6855d2428ba40df7e9d022f03d0",
+            "be02cf6855d2428ba40df7e9d022f03g",
+            // Hyphens only at the UUID positions
+            "be02cf6-855d2-428b-a40d-f7e9d022f03d",
+            "be02-cf6855d2428ba40df7e9d022f03d",
+        ] {
+            assert_eq!(id.parse::<MessageId>(), Err(MessageIdError), "{}", id);
+        }
+    }
+
+    #[test]
+    fn names_well_known_ids() {
+        assert_eq!(MessageId::UNIT_FAILED.name(), Some("UNIT_FAILED"));
+        assert_eq!(MessageId::JOURNAL_START.name(), Some("JOURNAL_START"));
+        assert_eq!(MessageId(1).name(), None);
+    }
+}
@@ -0,0 +1,161 @@
+use nom::Offset;
+
+use crate::{parse_journal_field_with, FieldErrorKind, JournalEntry, ParseErrorInfo, ParseOptions};
+
+/// Parses a datagram of the journald native protocol, as sent by `sd_journal_send()`
+/// to `/run/systemd/journal/socket`, into an entry.
+///
+/// Fields are framed like in the export format, including the size-prefixed
+/// encoding for values with embedded newlines, but a datagram carries exactly one
+/// entry without a terminating blank line. Like journald, a trailing incomplete
+/// field is ignored. Datagrams passed as memfds have to be read by the caller.
+pub fn parse_native_datagram(
+    datagram: &[u8],
+    options: &ParseOptions,
+) -> Result<JournalEntry, ParseErrorInfo> {
+    if datagram.len() > options.limits.max_entry_size {
+        return Err(ParseErrorInfo::new(
+            FieldErrorKind::EntryTooLarge,
+            datagram,
+            0,
+            0,
+        ));
+    }
+
+    let mut entry = JournalEntry::default();
+    let mut input = datagram;
+
+    while !input.is_empty() {
+        if input[0] == b'\n' {
+            input = &input[1..];
+            continue;
+        }
+
+        let offset = datagram.offset(input) as u64;
+        match parse_journal_field_with(input, options) {
+            Ok((remaining, field)) => {
+                if entry.len() >= options.limits.max_fields_per_entry {
+                    return Err(ParseErrorInfo::new(
+                        FieldErrorKind::TooManyFields,
+                        input,
+                        offset,
+                        0,
+                    ));
+                }
+
+                entry.put_multi(field.key, field.value);
+                input = remaining;
+            }
+            Err(nom::Err::Incomplete(_)) => break,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                return Err(ParseErrorInfo::new(
+                    e.kind,
+                    input,
+                    offset,
+                    input.offset(e.input),
+                ));
+            }
+        }
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::JournalFieldValue;
+
+    fn parse(datagram: &[u8]) -> Result<JournalEntry, ParseErrorInfo> {
+        parse_native_datagram(datagram, &ParseOptions::default())
+    }
+
+    fn values(entry: &JournalEntry, key: &str) -> Vec<String> {
+        entry.get_all(key).map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_fields_without_terminating_blank_line() {
+        let entry = parse(b"MESSAGE=hello = world\nPRIORITY=6\nEMPTY=\n").unwrap();
+
+        assert_eq!(entry.len(), 3);
+        assert_eq!(values(&entry, "MESSAGE"), ["hello = world"]);
+        assert_eq!(values(&entry, "PRIORITY"), ["6"]);
+        assert_eq!(values(&entry, "EMPTY"), [""]);
+
+        // Blank lines between fields are skipped
+        let entry = parse(b"\nA=1\n\n\nB=2\n\n").unwrap();
+        assert_eq!(entry.len(), 2);
+    }
+
+    #[test]
+    fn parses_size_prefixed_values() {
+        let entry = parse(b"MESSAGE\n\x07\0\0\0\0\0\0\0a\nb\0c=d\nPRIORITY=3\n").unwrap();
+
+        let Some(JournalFieldValue::Bytes(message)) = entry.get("MESSAGE") else {
+            panic!("expected a binary value, got {:?}", entry.get("MESSAGE"));
+        };
+        assert_eq!(&message[..], b"a\nb\0c=d");
+        assert_eq!(values(&entry, "PRIORITY"), ["3"]);
+    }
+
+    #[test]
+    fn keeps_repeated_fields() {
+        let entry = parse(b"TAG=a\nTAG\n\x01\0\0\0\0\0\0\0b\nTAG=c\n").unwrap();
+
+        assert_eq!(values(&entry, "TAG"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn ignores_trailing_incomplete_field() {
+        let entry = parse(b"A=1\nB=2").unwrap();
+        assert_eq!(values(&entry, "A"), ["1"]);
+        assert!(entry.get("B").is_none());
+
+        let entry = parse(b"A=1\nB\n\x10\0\0\0\0\0\0\0short\n").unwrap();
+        assert_eq!(entry.len(), 1);
+
+        let entry = parse(b"A=1\nB\n\x10\0\0").unwrap();
+        assert_eq!(entry.len(), 1);
+    }
+
+    #[test]
+    fn reports_error_offsets() {
+        let options = ParseOptions {
+            validate_keys: true,
+            ..Default::default()
+        };
+        let error = parse_native_datagram(b"A=1\nbad=2\n", &options).unwrap_err();
+        assert_eq!(error.kind, FieldErrorKind::InvalidKey);
+        assert_eq!(error.offset, 4);
+        assert_eq!(error.key.as_deref(), Some("bad"));
+
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+        let error = parse_native_datagram(b"A=1\nB=12345\n", &options).unwrap_err();
+        assert_eq!(error.kind, FieldErrorKind::FieldTooLarge);
+        assert_eq!(error.key.as_deref(), Some("B"));
+        let error =
+            parse_native_datagram(b"A=1\nB\n\x05\0\0\0\0\0\0\012345\n", &options).unwrap_err();
+        assert_eq!(error.kind, FieldErrorKind::FieldTooLarge);
+    }
+
+    #[test]
+    fn applies_entry_limits() {
+        let mut options = ParseOptions::default();
+        options.limits.max_fields_per_entry = 2;
+        assert!(parse_native_datagram(b"A=1\nA=2\n", &options).is_ok());
+        let error = parse_native_datagram(b"A=1\nA=2\nB=3\n", &options).unwrap_err();
+        assert_eq!(error.kind, FieldErrorKind::TooManyFields);
+        assert_eq!(error.offset, 8);
+
+        let mut options = ParseOptions::default();
+        options.limits.max_entry_size = 8;
+        let error = parse_native_datagram(b"MESSAGE=hi\n", &options).unwrap_err();
+        assert_eq!(error.kind, FieldErrorKind::EntryTooLarge);
+        assert_eq!(error.offset, 0);
+    }
+}
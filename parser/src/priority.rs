@@ -0,0 +1,131 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// Syslog severity in `PRIORITY`. Ordered by value like in `journalctl -p`, so
+/// more severe levels compare as smaller: `priority <= Priority::Warning`
+/// matches warnings and worse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[repr(u8)]
+pub enum Priority {
+    Emerg = 0,
+    Alert = 1,
+    Crit = 2,
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+    Debug = 7,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 8] = [
+        Priority::Emerg,
+        Priority::Alert,
+        Priority::Crit,
+        Priority::Err,
+        Priority::Warning,
+        Priority::Notice,
+        Priority::Info,
+        Priority::Debug,
+    ];
+
+    /// Name as accepted by `journalctl -p`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Emerg => "emerg",
+            Priority::Alert => "alert",
+            Priority::Crit => "crit",
+            Priority::Err => "err",
+            Priority::Warning => "warning",
+            Priority::Notice => "notice",
+            Priority::Info => "info",
+            Priority::Debug => "debug",
+        }
+    }
+}
+
+/// Values above 7 only carry severity in their low bits when a facility is
+/// included, as in syslog `<PRI>` headers, so only those bits are used
+impl From<u8> for Priority {
+    fn from(value: u8) -> Self {
+        Self::ALL[usize::from(value & 7)]
+    }
+}
+
+impl From<Priority> for u8 {
+    fn from(priority: Priority) -> Self {
+        priority as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriorityError;
+
+impl fmt::Display for PriorityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority is neither 0-7 nor a level name")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PriorityError {}
+
+impl FromStr for Priority {
+    type Err = PriorityError;
+
+    /// Accepts `0`-`7` and level names, plus the common aliases `error` and
+    /// `warn`, case-insensitively
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(value) = value.parse::<u8>() {
+            return Self::ALL
+                .get(usize::from(value))
+                .copied()
+                .ok_or(PriorityError);
+        }
+
+        let value = match value {
+            _ if value.eq_ignore_ascii_case("error") => "err",
+            _ if value.eq_ignore_ascii_case("warn") => "warning",
+            _ => value,
+        };
+
+        Self::ALL
+            .into_iter()
+            .find(|priority| value.eq_ignore_ascii_case(priority.as_str()))
+            .ok_or(PriorityError)
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_names_and_aliases() {
+        assert_eq!("0".parse(), Ok(Priority::Emerg));
+        assert_eq!("7".parse(), Ok(Priority::Debug));
+        assert_eq!("err".parse(), Ok(Priority::Err));
+        assert_eq!("Error".parse(), Ok(Priority::Err));
+        assert_eq!("WARN".parse(), Ok(Priority::Warning));
+
+        for value in ["8", "-1", "", "fatal"] {
+            assert_eq!(value.parse::<Priority>(), Err(PriorityError), "{}", value);
+        }
+    }
+
+    #[test]
+    fn keeps_the_severity_bits_of_syslog_values() {
+        // <30> is daemon.info
+        assert_eq!(Priority::from(30), Priority::Info);
+        assert_eq!(u8::from(Priority::Warning), 4);
+        assert!(Priority::Crit < Priority::Warning);
+    }
+}
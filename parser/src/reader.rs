@@ -0,0 +1,635 @@
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BytesMut};
+use nom::{Needed, Offset};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    parse_raw_field, FieldErrorKind, FieldName, JournalEntry, JournalFieldValue, ParseErrorInfo,
+    ParseOptions, RawValue, Utf8Mode, TRUNCATED_FIELD,
+};
+
+const READ_CHUNK: usize = 8192;
+const MAX_READ_CHUNK: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalReadError {
+    #[error("I/O error")]
+    IOError(std::io::Error),
+
+    #[error("Parse error: {0}")]
+    ParseError(ParseErrorInfo),
+
+    #[cfg(feature = "json")]
+    #[error("JSON parse error: {0}")]
+    JsonError(crate::JsonEntryError),
+}
+
+/// Reads journal export format entries from an async byte stream.
+pub struct EntryReader<R> {
+    reader: R,
+    options: ParseOptions,
+    buffer: BytesMut,
+    position: usize,
+    // Stream offset of the start of the buffer
+    buffer_offset: u64,
+    parse_time: Duration,
+    // Size of the fields already consumed for an entry which failed to parse
+    partial_entry_size: u64,
+}
+
+impl<R: AsyncRead + Unpin> EntryReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            options: ParseOptions::default(),
+            buffer: BytesMut::with_capacity(READ_CHUNK),
+            position: 0,
+            buffer_offset: 0,
+            parse_time: Duration::ZERO,
+            partial_entry_size: 0,
+        }
+    }
+
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Time spent parsing the fields of the most recently returned entry.
+    pub fn last_entry_parse_time(&self) -> Duration {
+        self.parse_time
+    }
+
+    /// Total number of bytes consumed from the stream
+    pub fn offset(&self) -> u64 {
+        self.buffer_offset + self.position as u64
+    }
+
+    /// Returns the next complete entry, or `None` once the stream is exhausted.
+    pub async fn next_entry(&mut self) -> Result<Option<JournalEntry>, JournalReadError> {
+        let mut entry = JournalEntry::default();
+        let mut entry_size = 0;
+        let mut field_count = 0;
+        let mut parse_time = Duration::ZERO;
+        let limits = self.options.limits;
+
+        loop {
+            let input = &self.buffer[self.position..];
+
+            // Blank line terminates the entry
+            if input.first() == Some(&b'\n') {
+                self.position += 1;
+                if entry.is_empty() {
+                    continue;
+                }
+
+                self.parse_time = parse_time;
+                return Ok(Some(entry));
+            }
+
+            let started = Instant::now();
+            let result = parse_raw_field(input, &self.options);
+            parse_time += started.elapsed();
+
+            let needed = match result {
+                Ok((remaining, (key, value))) => {
+                    field_count += 1;
+                    if field_count > limits.max_fields_per_entry {
+                        self.partial_entry_size = entry_size as u64;
+                        return Err(self.parse_error(FieldErrorKind::TooManyFields, 0));
+                    }
+
+                    let position = self.buffer.len() - remaining.len();
+                    let field_size = position - self.position;
+                    if entry_size + field_size > limits.max_entry_size {
+                        self.partial_entry_size = entry_size as u64;
+                        return Err(self.parse_error(FieldErrorKind::EntryTooLarge, 0));
+                    }
+                    entry_size += field_size;
+
+                    let value = match value {
+                        RawValue::Value(value) => {
+                            self.position = position;
+                            value
+                        }
+                        RawValue::Binary(data) => {
+                            let start = input.offset(data);
+                            JournalFieldValue::Bytes(
+                                self.take_binary(position, start..start + data.len()),
+                            )
+                        }
+                    };
+
+                    entry.put_multi(key, value);
+                    continue;
+                }
+                Err(nom::Err::Incomplete(Needed::Size(needed))) => Some(needed.get()),
+                Err(nom::Err::Incomplete(Needed::Unknown)) => Some(1),
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e))
+                    if e.kind == FieldErrorKind::FieldTooLarge
+                        && self.options.truncate_oversized =>
+                {
+                    None
+                }
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                    let at = input.offset(e.input);
+                    self.partial_entry_size = entry_size as u64;
+                    return Err(self.parse_error(e.kind, at));
+                }
+            };
+
+            let Some(needed) = needed else {
+                let Some((key, value)) = self.take_truncated_field().await? else {
+                    return Ok(self.flush(entry, parse_time));
+                };
+
+                field_count += 1;
+                entry_size += key.len() + value.len();
+                if field_count > limits.max_fields_per_entry {
+                    self.partial_entry_size = entry_size as u64;
+                    return Err(self.parse_error(FieldErrorKind::TooManyFields, 0));
+                }
+                if entry_size > limits.max_entry_size {
+                    self.partial_entry_size = entry_size as u64;
+                    return Err(self.parse_error(FieldErrorKind::EntryTooLarge, 0));
+                }
+
+                entry.put_multi(key, value);
+                entry.put(TRUNCATED_FIELD, JournalFieldValue::UTF8(String::from("1")));
+                continue;
+            };
+
+            // Partially buffered field would not fit into the entry anyway
+            let buffered = self.buffer.len() - self.position;
+            if entry_size.saturating_add(buffered).saturating_add(needed) > limits.max_entry_size {
+                self.partial_entry_size = entry_size as u64;
+                return Err(self.parse_error(FieldErrorKind::EntryTooLarge, 0));
+            }
+
+            if !self.fill_buffer(needed).await? {
+                return Ok(self.flush(entry, parse_time));
+            }
+        }
+    }
+
+    /// Ends reading at the end of the stream, returning the unterminated last
+    /// entry with `flush_at_eof`
+    fn flush(&mut self, entry: JournalEntry, parse_time: Duration) -> Option<JournalEntry> {
+        if !self.options.flush_at_eof || entry.is_empty() {
+            return None;
+        }
+
+        self.position = self.buffer.len();
+        self.parse_time = parse_time;
+        Some(entry)
+    }
+
+    /// Recovers from a parse error by skipping input up to and including the next
+    /// blank line, which plausibly starts the next entry. Returns the number of
+    /// discarded bytes, including the already consumed part of the broken entry.
+    pub async fn resync(&mut self) -> Result<u64, JournalReadError> {
+        let mut discarded = std::mem::take(&mut self.partial_entry_size);
+
+        loop {
+            let input = &self.buffer[self.position..];
+            if let Some(index) = memchr::memmem::find(input, b"\n\n") {
+                self.position += index + 2;
+                return Ok(discarded + index as u64 + 2);
+            }
+
+            // Trailing newline might be the first half of the boundary
+            let skip = input.len() - usize::from(input.last() == Some(&b'\n'));
+            self.position += skip;
+            discarded += skip as u64;
+
+            if !self.fill_buffer(1).await? {
+                discarded += (self.buffer.len() - self.position) as u64;
+                self.position = self.buffer.len();
+                return Ok(discarded);
+            }
+        }
+    }
+
+    /// Error for the field starting at the current position, `at` bytes into it
+    fn parse_error(&self, kind: FieldErrorKind, at: usize) -> JournalReadError {
+        JournalReadError::ParseError(ParseErrorInfo::new(
+            kind,
+            &self.buffer[self.position..],
+            self.offset(),
+            at,
+        ))
+    }
+
+    /// Takes the binary value at `range` of the field starting at the current
+    /// position and moves past the field, which ends at `end`. With the `bytes`
+    /// feature the consumed part of the buffer is split off and frozen, so the
+    /// value shares its allocation instead of being copied.
+    #[cfg(feature = "bytes")]
+    fn take_binary(&mut self, end: usize, range: std::ops::Range<usize>) -> bytes::Bytes {
+        let start = self.position;
+        let consumed = self.buffer.split_to(end).freeze();
+        self.buffer_offset += end as u64;
+        self.position = 0;
+
+        consumed.slice(start + range.start..start + range.end)
+    }
+
+    #[cfg(not(feature = "bytes"))]
+    fn take_binary(&mut self, end: usize, range: std::ops::Range<usize>) -> Vec<u8> {
+        let value = self.buffer[self.position..][range].to_vec();
+        self.position = end;
+
+        value
+    }
+
+    /// Takes the field at the current position, whose value exceeds the field
+    /// size limit, keeping the first `max_field_size` bytes of the value and
+    /// skipping the rest. Returns `None` if the stream ends within the field.
+    async fn take_truncated_field(
+        &mut self,
+    ) -> Result<Option<(FieldName, JournalFieldValue)>, JournalReadError> {
+        let max = usize::try_from(self.options.limits.max_field_size).unwrap_or(usize::MAX);
+        let input = &self.buffer[self.position..];
+
+        // The key and the start of the value were parsed before the value
+        // turned out too large, so they are buffered
+        let corrupted = || self.parse_error(FieldErrorKind::Nom(nom::error::ErrorKind::Verify), 0);
+        let key_end = memchr::memchr2(b'=', b'\n', input).ok_or_else(corrupted)?;
+        let key = crate::intern(String::from_utf8_lossy(&input[..key_end]).into_owned());
+
+        if input[key_end] == b'=' {
+            // Text values are too large once more than `max` bytes are buffered
+            let start = key_end + 1;
+            let data = input.get(start..start + max).ok_or_else(corrupted)?;
+            let value = truncated_text(data, self.options.utf8);
+            self.position += start + max;
+
+            if !self.skip_line().await? {
+                return Ok(None);
+            }
+
+            Ok(Some((key, value)))
+        } else {
+            let prefix = input
+                .get(key_end + 1..key_end + 9)
+                .and_then(|prefix| <[u8; 8]>::try_from(prefix).ok())
+                .ok_or_else(corrupted)?;
+            let size = u64::from_le_bytes(prefix);
+            self.position += key_end + 9;
+
+            while self.buffer.len() - self.position < max {
+                let needed = max - (self.buffer.len() - self.position);
+                if !self.fill_buffer(needed).await? {
+                    return Ok(None);
+                }
+            }
+            let value = JournalFieldValue::Bytes(crate::binary_value(
+                &self.buffer[self.position..self.position + max],
+            ));
+            self.position += max;
+
+            // Rest of the value and the newline terminating the field
+            if !self.skip(size - max as u64 + 1).await? {
+                return Ok(None);
+            }
+
+            Ok(Some((key, value)))
+        }
+    }
+
+    /// Skips input up to and including the next newline, returns whether one
+    /// was found
+    async fn skip_line(&mut self) -> Result<bool, JournalReadError> {
+        loop {
+            if let Some(index) = memchr::memchr(b'\n', &self.buffer[self.position..]) {
+                self.position += index + 1;
+                return Ok(true);
+            }
+
+            self.position = self.buffer.len();
+            if !self.fill_buffer(1).await? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Skips `count` bytes of input, returns whether the stream had as many
+    async fn skip(&mut self, mut count: u64) -> Result<bool, JournalReadError> {
+        loop {
+            let available = (self.buffer.len() - self.position) as u64;
+            if available >= count {
+                self.position += count as usize;
+                return Ok(true);
+            }
+
+            count -= available;
+            self.position = self.buffer.len();
+            if !self.fill_buffer(1).await? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Reads at least `needed` more bytes, as hinted by the parser, unless the
+    /// stream ends first. Returns whether anything was read.
+    async fn fill_buffer(&mut self, needed: usize) -> Result<bool, JournalReadError> {
+        self.buffer_offset += self.position as u64;
+        self.buffer.advance(self.position);
+        self.position = 0;
+
+        let mut total = 0;
+        while total < needed {
+            // Grows with the data actually received rather than trusting a size
+            // prefix with a large up front allocation
+            self.buffer
+                .reserve((needed - total).clamp(READ_CHUNK, MAX_READ_CHUNK));
+
+            let read = self
+                .reader
+                .read_buf(&mut self.buffer)
+                .await
+                .map_err(JournalReadError::IOError)?;
+            if read == 0 {
+                break;
+            }
+
+            total += read;
+        }
+
+        Ok(total > 0)
+    }
+}
+
+/// Text value cut at an arbitrary byte, which may split a UTF-8 sequence
+fn truncated_text(data: &[u8], utf8: Utf8Mode) -> JournalFieldValue {
+    let valid = match std::str::from_utf8(data) {
+        Ok(text) => Some(text),
+        // Only the sequence at the cut is incomplete
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&data[..err.valid_up_to()]).ok()
+        }
+        Err(_) => None,
+    };
+
+    match (valid, utf8) {
+        (Some(text), _) => JournalFieldValue::UTF8(String::from(text)),
+        (None, Utf8Mode::Lossy) => {
+            JournalFieldValue::UTF8(String::from_utf8_lossy(data).into_owned())
+        }
+        (None, _) => JournalFieldValue::Bytes(crate::binary_value(data)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+
+    /// Yields `data` in reads of at most `size` bytes, splitting fields across
+    /// buffer refills
+    struct Chunked<'a> {
+        data: &'a [u8],
+        size: usize,
+    }
+
+    impl AsyncRead for Chunked<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let size = self.size.min(self.data.len()).min(buf.remaining());
+            let (chunk, rest) = self.data.split_at(size);
+            buf.put_slice(chunk);
+            self.data = rest;
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn chunked(data: &[u8], size: usize) -> Chunked<'_> {
+        Chunked { data, size }
+    }
+
+    fn limits(max_fields_per_entry: usize, max_entry_size: usize) -> ParseOptions {
+        let mut options = ParseOptions::default();
+        options.limits.max_fields_per_entry = max_fields_per_entry;
+        options.limits.max_entry_size = max_entry_size;
+        options
+    }
+
+    async fn read_all<R: AsyncRead + Unpin>(
+        reader: R,
+        options: ParseOptions,
+    ) -> Result<Vec<JournalEntry>, JournalReadError> {
+        let mut reader = EntryReader::new(reader).with_options(options);
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().await? {
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    fn parse_error(result: Result<Vec<JournalEntry>, JournalReadError>) -> ParseErrorInfo {
+        match result {
+            Err(JournalReadError::ParseError(info)) => info,
+            other => panic!(
+                "expected a parse error, got {:?}",
+                other.map(|entries| entries.len())
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn field_count_limit() {
+        let entries = read_all(&b"A=1\nB=2\n\n"[..], limits(2, usize::MAX)).await;
+        assert_eq!(entries.unwrap().len(), 1);
+
+        let error = parse_error(read_all(&b"A=1\nB=2\nC=3\n\n"[..], limits(2, usize::MAX)).await);
+        assert_eq!(error.kind, FieldErrorKind::TooManyFields);
+        assert_eq!(error.offset, 8);
+        assert_eq!(error.key.as_deref(), Some("C"));
+    }
+
+    #[tokio::test]
+    async fn entry_size_limit() {
+        let entries = read_all(&b"A=1\nB=2\n\n"[..], limits(usize::MAX, 8)).await;
+        assert_eq!(entries.unwrap().len(), 1);
+
+        // Fully buffered entry
+        let error = parse_error(read_all(&b"A=1\nB=2\nC=3\n\n"[..], limits(usize::MAX, 8)).await);
+        assert_eq!(error.kind, FieldErrorKind::EntryTooLarge);
+        assert_eq!(error.offset, 8);
+
+        // Binary value whose size prefix alone exceeds the limit
+        let error =
+            parse_error(read_all(&b"A\n\x64\0\0\0\0\0\0\0abc"[..], limits(usize::MAX, 64)).await);
+        assert_eq!(error.kind, FieldErrorKind::EntryTooLarge);
+        assert_eq!(error.offset, 0);
+    }
+
+    #[tokio::test]
+    async fn field_size_limit() {
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+
+        let error = parse_error(read_all(&b"A=1\nB=abcde\n\n"[..], options).await);
+        assert_eq!(error.kind, FieldErrorKind::FieldTooLarge);
+        assert_eq!(error.key.as_deref(), Some("B"));
+    }
+
+    #[tokio::test]
+    async fn resync_skips_to_the_next_entry() {
+        let input = b"A=1\n\nB=2\nbad=3\nC=4\n\nD=5\n\n";
+        let options = ParseOptions {
+            validate_keys: true,
+            ..Default::default()
+        };
+
+        for size in 1..=input.len() {
+            let mut reader = EntryReader::new(chunked(input, size)).with_options(options.clone());
+
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.get("A").map(String::from).as_deref(), Some("1"));
+
+            let error = match reader.next_entry().await {
+                Err(JournalReadError::ParseError(info)) => info,
+                _ => panic!("expected a parse error with {}-byte reads", size),
+            };
+            assert_eq!(error.kind, FieldErrorKind::InvalidKey);
+            assert_eq!(error.offset, 9);
+
+            // `B=2\n` was consumed before the error
+            assert_eq!(reader.resync().await.unwrap(), 15);
+            assert_eq!(reader.offset(), 20);
+
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.get("D").map(String::from).as_deref(), Some("5"));
+            assert!(entry.get("B").is_none());
+            assert!(reader.next_entry().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn resync_discards_the_rest_of_the_stream_without_a_boundary() {
+        let input = b"A=1\nbad=2\n";
+        let options = ParseOptions {
+            validate_keys: true,
+            ..Default::default()
+        };
+
+        for size in 1..=input.len() {
+            let mut reader = EntryReader::new(chunked(input, size)).with_options(options.clone());
+
+            assert!(reader.next_entry().await.is_err());
+            assert_eq!(reader.resync().await.unwrap(), input.len() as u64);
+            assert!(reader.next_entry().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn fields_split_across_refills() {
+        let binary: Vec<u8> = (0..100).collect();
+        let mut input = b"A=1\nBIN\n".to_vec();
+        input.extend_from_slice(&(binary.len() as u64).to_le_bytes());
+        input.extend_from_slice(&binary);
+        input.extend_from_slice(b"\nC=\xff\n\nD=4\n\n");
+
+        for size in 1..=input.len() {
+            let mut reader = EntryReader::new(chunked(&input, size));
+
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.get("A").map(String::from).as_deref(), Some("1"));
+            let Some(JournalFieldValue::Bytes(data)) = entry.get("BIN") else {
+                panic!("binary value is missing with {}-byte reads", size);
+            };
+            assert_eq!(data[..], binary[..]);
+            let Some(JournalFieldValue::Bytes(data)) = entry.get("C") else {
+                panic!("invalid UTF-8 value is missing with {}-byte reads", size);
+            };
+            assert_eq!(data[..], b"\xff"[..]);
+
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.get("D").map(String::from).as_deref(), Some("4"));
+            assert!(reader.next_entry().await.unwrap().is_none());
+            assert_eq!(reader.offset(), input.len() as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn truncates_values_at_the_field_size_limit() {
+        let mut input = b"A=hello world\nBIN\n".to_vec();
+        input.extend_from_slice(&10u64.to_le_bytes());
+        input.extend_from_slice(b"0123456789\n");
+        // Cut within the two bytes of `é`
+        input.extend_from_slice("C=abcé\nD=ok\n\nE=abcd\n\n".as_bytes());
+
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+        options.truncate_oversized = true;
+
+        for size in 1..=input.len() {
+            let mut reader = EntryReader::new(chunked(&input, size)).with_options(options.clone());
+
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            let text = |key| entry.get(key).map(String::from);
+            assert_eq!(text("A").as_deref(), Some("hell"), "{}-byte reads", size);
+            assert_eq!(text("C").as_deref(), Some("abc"));
+            assert_eq!(text("D").as_deref(), Some("ok"));
+            assert_eq!(text(TRUNCATED_FIELD).as_deref(), Some("1"));
+            let Some(JournalFieldValue::Bytes(data)) = entry.get("BIN") else {
+                panic!("binary value is missing with {}-byte reads", size);
+            };
+            assert_eq!(data[..], b"0123"[..]);
+
+            // Values of exactly the limit are kept whole
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.get("E").map(String::from).as_deref(), Some("abcd"));
+            assert!(entry.get(TRUNCATED_FIELD).is_none());
+            assert!(reader.next_entry().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn unterminated_last_entry_is_dropped_by_default() {
+        let entries = read_all(&b"A=1\n\nB=2\n"[..], ParseOptions::default()).await;
+
+        assert_eq!(entries.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn flush_at_eof_returns_the_unterminated_last_entry() {
+        let options = ParseOptions {
+            flush_at_eof: true,
+            ..Default::default()
+        };
+
+        for input in [
+            &b"A=1\n\nB=2\nC=3\n"[..],
+            // Trailing incomplete fields are discarded
+            &b"A=1\n\nB=2\nC=3\nD=4"[..],
+            &b"A=1\n\nB=2\nC=3\nD\n\x05\0\0\0\0\0\0\0ab"[..],
+        ] {
+            for size in 1..=input.len() {
+                let entries = read_all(chunked(input, size), options.clone())
+                    .await
+                    .unwrap();
+
+                assert_eq!(entries.len(), 2, "{}-byte reads of {:?}", size, input);
+                assert_eq!(entries[1].get("B").map(String::from).as_deref(), Some("2"));
+                assert_eq!(entries[1].get("C").map(String::from).as_deref(), Some("3"));
+                assert!(entries[1].get("D").is_none());
+            }
+        }
+
+        // Nothing but blank lines after the last entry
+        let entries = read_all(&b"A=1\n\n\n"[..], options).await;
+        assert_eq!(entries.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Element of RFC 5424 structured data, `[exampleSDID@32473 iut="3" eventID="1011"]`,
+/// as journald keeps it in `SYSLOG_STRUCTURED_DATA`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SdElement {
+    pub id: String,
+    /// Parameters in order, names may repeat
+    pub params: Vec<(String, String)>,
+}
+
+impl SdElement {
+    /// First value of a parameter
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Structured data which doesn't follow the RFC 5424 grammar, with the byte
+/// offset where parsing failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StructuredDataError {
+    pub offset: usize,
+}
+
+impl fmt::Display for StructuredDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed structured data at byte {}", self.offset)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StructuredDataError {}
+
+/// Parses RFC 5424 `STRUCTURED-DATA` into its elements. The nil value `-`
+/// yields no elements. Parameter values are unescaped; a backslash before any
+/// character other than `"`, `\` and `]` is kept, as the RFC requires.
+pub fn parse_structured_data(input: &str) -> Result<Vec<SdElement>, StructuredDataError> {
+    let mut elements = Vec::new();
+    if input == "-" {
+        return Ok(elements);
+    }
+
+    let mut scanner = Scanner { input, offset: 0 };
+    loop {
+        scanner.expect('[')?;
+        let id = String::from(scanner.name()?);
+
+        let mut params = Vec::new();
+        while scanner.peek() == Some(' ') {
+            scanner.offset += 1;
+            let name = String::from(scanner.name()?);
+            scanner.expect('=')?;
+            params.push((name, scanner.quoted()?));
+        }
+        scanner.expect(']')?;
+        elements.push(SdElement { id, params });
+
+        if scanner.peek().is_none() {
+            return Ok(elements);
+        }
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn error(&self) -> StructuredDataError {
+        StructuredDataError {
+            offset: self.offset,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), StructuredDataError> {
+        if self.peek() != Some(expected) {
+            return Err(self.error());
+        }
+
+        self.offset += expected.len_utf8();
+        Ok(())
+    }
+
+    /// `SD-NAME`: printable ASCII except `=`, space, `]` and `"`
+    fn name(&mut self) -> Result<&'a str, StructuredDataError> {
+        let rest = &self.input[self.offset..];
+        let length = rest
+            .bytes()
+            .take_while(|b| b.is_ascii_graphic() && !matches!(b, b'=' | b']' | b'"'))
+            .count();
+        if length == 0 {
+            return Err(self.error());
+        }
+
+        self.offset += length;
+        Ok(&rest[..length])
+    }
+
+    /// `PARAM-VALUE` in double quotes
+    fn quoted(&mut self) -> Result<String, StructuredDataError> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+        let mut chars = self.input[self.offset..].char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.offset += index + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.clone().next() {
+                    Some((_, escaped @ ('"' | '\\' | ']'))) => {
+                        chars.next();
+                        value.push(escaped);
+                    }
+                    _ => value.push('\\'),
+                },
+                c => value.push(c),
+            }
+        }
+
+        // Unterminated value
+        self.offset = self.input.len();
+        Err(self.error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn element(id: &str, params: &[(&str, &str)]) -> SdElement {
+        SdElement {
+            id: id.into(),
+            params: params
+                .iter()
+                .map(|&(name, value)| (name.into(), value.into()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn nil_value_has_no_elements() {
+        assert_eq!(parse_structured_data("-"), Ok(vec![]));
+    }
+
+    #[test]
+    fn parses_multiple_elements() {
+        let elements = parse_structured_data(
+            r#"[exampleSDID@32473 iut="3" eventID="1011"][origin][meta a="" a="2"]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            elements,
+            [
+                element("exampleSDID@32473", &[("iut", "3"), ("eventID", "1011")]),
+                element("origin", &[]),
+                element("meta", &[("a", ""), ("a", "2")]),
+            ]
+        );
+        assert_eq!(elements[0].param("eventID"), Some("1011"));
+        assert_eq!(elements[2].param("a"), Some(""));
+        assert_eq!(elements[2].param("b"), None);
+    }
+
+    #[test]
+    fn unescapes_param_values() {
+        let elements = parse_structured_data(
+            r#"[id quote="a\"b" bracket="[x\]" backslash="c:\\d" other="\n"]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            elements,
+            [element(
+                "id",
+                &[
+                    ("quote", "a\"b"),
+                    ("bracket", "[x]"),
+                    ("backslash", "c:\\d"),
+                    // Not an escape sequence, the backslash stays
+                    ("other", "\\n"),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn keeps_non_ascii_values() {
+        let elements = parse_structured_data(r#"[id v="žluťoučký ]"]"#).unwrap();
+        assert_eq!(elements[0].param("v"), Some("žluťoučký ]"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for (input, offset) in [
+            ("", 0),
+            ("id", 0),
+            ("[]", 1),
+            ("[id", 3),
+            ("[id a=\"1\"", 9),
+            // Unterminated value, the escaped quote doesn't end it
+            (r#"[id a="1\"]"#, 11),
+            ("[id a=1]", 6),
+            ("[id a]", 5),
+            ("[id a=\"1\"] ", 10),
+            ("[id a=\"1\"]-", 10),
+            ("[id  a=\"1\"]", 4),
+        ] {
+            assert_eq!(
+                parse_structured_data(input),
+                Err(StructuredDataError { offset }),
+                "{:?}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use crate::{
+    binary_value, FieldErrorKind, JournalEntry, JournalFieldValue, ParseOptions, Utf8Mode,
+};
+
+/// Priority of messages without a `<PRI>` part, `user.notice` as RFC 3164
+/// prescribes
+const DEFAULT_PRIORITY: u8 = 13;
+
+/// Byte order mark RFC 5424 allows in front of UTF-8 messages
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Syslog message which can't be translated into an entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyslogError {
+    /// `<PRI>` is malformed or out of range
+    InvalidPriority,
+    /// The RFC 5424 header ends early or its structured data is malformed,
+    /// with the byte offset where parsing failed
+    InvalidHeader(usize),
+    /// A value exceeds the parser limits or isn't valid UTF-8 in strict mode
+    Field(FieldErrorKind),
+}
+
+impl fmt::Display for SyslogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPriority => write!(f, "invalid priority"),
+            Self::InvalidHeader(offset) => write!(f, "malformed header at byte {}", offset),
+            Self::Field(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SyslogError {}
+
+/// Translates an RFC 5424 or RFC 3164 (BSD) syslog message into the fields
+/// journald would store for it:
+///
+/// - `PRIORITY` and `SYSLOG_FACILITY` from the `<PRI>` part
+/// - `SYSLOG_TIMESTAMP` as sent, journald doesn't interpret it either
+/// - `_HOSTNAME`
+/// - `SYSLOG_IDENTIFIER` and `SYSLOG_PID` from APP-NAME and PROCID, or the
+///   BSD `tag[pid]:`
+/// - `SYSLOG_MSGID` and `SYSLOG_STRUCTURED_DATA` of RFC 5424 messages
+/// - `MESSAGE`
+///
+/// Nil (`-`) values are left out. Address fields, `_TRANSPORT` and the IDs of
+/// the sending machine are up to the caller.
+pub fn parse_syslog_message(
+    input: &[u8],
+    options: &ParseOptions,
+) -> Result<JournalEntry, SyslogError> {
+    if input.len() > options.limits.max_entry_size {
+        return Err(SyslogError::Field(FieldErrorKind::EntryTooLarge));
+    }
+
+    // Framing leftovers of datagrams and streams
+    let mut end = input.len();
+    while end > 0 && matches!(input[end - 1], b'\n' | b'\r' | b'\0') {
+        end -= 1;
+    }
+    let input = &input[..end];
+
+    let (priority, mut offset) = parse_priority(input)?;
+    let mut entry = JournalEntry::default();
+    entry.put("PRIORITY", text((priority & 7).to_string()));
+    entry.put("SYSLOG_FACILITY", text((priority >> 3).to_string()));
+
+    let message = if input[offset..].starts_with(b"1 ") {
+        offset += 2;
+        parse_rfc5424_header(input, &mut offset, &mut entry)?;
+        let message = &input[offset..];
+        message.strip_prefix(BOM).unwrap_or(message)
+    } else {
+        parse_rfc3164_header(input, &mut offset, &mut entry);
+        &input[offset..]
+    };
+
+    if message.len() as u64 > options.limits.max_field_size {
+        return Err(SyslogError::Field(FieldErrorKind::FieldTooLarge));
+    }
+    let message = match core::str::from_utf8(message) {
+        Ok(message) => text(message.to_string()),
+        Err(_) => match options.utf8 {
+            Utf8Mode::Strict => return Err(SyslogError::Field(FieldErrorKind::InvalidUtf8)),
+            Utf8Mode::Lossy => text(String::from_utf8_lossy(message).into_owned()),
+            Utf8Mode::Fallback => JournalFieldValue::Bytes(binary_value(message)),
+        },
+    };
+    entry.put("MESSAGE", message);
+
+    Ok(entry)
+}
+
+fn text(value: String) -> JournalFieldValue {
+    JournalFieldValue::UTF8(value)
+}
+
+/// `<PRI>` and the offset after it, the default priority if it is missing
+fn parse_priority(input: &[u8]) -> Result<(u8, usize), SyslogError> {
+    let Some(rest) = input.strip_prefix(b"<") else {
+        return Ok((DEFAULT_PRIORITY, 0));
+    };
+
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    if !(1..=3).contains(&digits) || rest.get(digits) != Some(&b'>') {
+        return Err(SyslogError::InvalidPriority);
+    }
+
+    let priority: u16 = rest[..digits]
+        .iter()
+        .fold(0, |value, digit| value * 10 + u16::from(digit - b'0'));
+    let priority = u8::try_from(priority)
+        .ok()
+        .filter(|&priority| priority <= 191)
+        .ok_or(SyslogError::InvalidPriority)?;
+
+    Ok((priority, digits + 2))
+}
+
+/// Space-terminated header field of RFC 5424, `None` for the nil value
+fn header_field<'a>(input: &'a [u8], offset: &mut usize) -> Result<Option<&'a str>, SyslogError> {
+    let rest = &input[*offset..];
+    let length = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+    let value = core::str::from_utf8(&rest[..length])
+        .ok()
+        .filter(|value| !value.is_empty())
+        .ok_or(SyslogError::InvalidHeader(*offset))?;
+
+    *offset += length;
+    if *offset < input.len() {
+        *offset += 1;
+    }
+
+    Ok((value != "-").then_some(value))
+}
+
+fn parse_rfc5424_header(
+    input: &[u8],
+    offset: &mut usize,
+    entry: &mut JournalEntry,
+) -> Result<(), SyslogError> {
+    let fields = [
+        "SYSLOG_TIMESTAMP",
+        "_HOSTNAME",
+        "SYSLOG_IDENTIFIER",
+        "SYSLOG_PID",
+        "SYSLOG_MSGID",
+    ];
+    for key in fields {
+        if let Some(value) = header_field(input, offset)? {
+            entry.put(key, text(value.to_string()));
+        }
+    }
+
+    let start = *offset;
+    let length =
+        structured_data_length(&input[start..]).ok_or(SyslogError::InvalidHeader(start))?;
+    let structured_data = core::str::from_utf8(&input[start..start + length])
+        .map_err(|_| SyslogError::InvalidHeader(start))?;
+    if structured_data != "-" {
+        entry.put("SYSLOG_STRUCTURED_DATA", text(structured_data.to_string()));
+    }
+
+    *offset = start + length;
+    if *offset < input.len() {
+        if input[*offset] != b' ' {
+            return Err(SyslogError::InvalidHeader(*offset));
+        }
+        *offset += 1;
+    }
+
+    Ok(())
+}
+
+/// Length of the `STRUCTURED-DATA` at the start of `input`: the nil value or
+/// elements in brackets, whose quoted values may contain escaped `]` and `"`
+fn structured_data_length(input: &[u8]) -> Option<usize> {
+    if input.starts_with(b"-") {
+        return Some(1);
+    }
+
+    let mut length = 0;
+    while input.get(length) == Some(&b'[') {
+        let mut quoted = false;
+        let mut escaped = false;
+        loop {
+            length += 1;
+            match *input.get(length)? {
+                _ if escaped => escaped = false,
+                b'\\' if quoted => escaped = true,
+                b'"' => quoted = !quoted,
+                b']' if !quoted => break,
+                _ => {}
+            }
+        }
+        length += 1;
+    }
+
+    (length > 0).then_some(length)
+}
+
+fn parse_rfc3164_header(input: &[u8], offset: &mut usize, entry: &mut JournalEntry) {
+    let rest = &input[*offset..];
+    let timestamp = rfc3164_timestamp_length(rest);
+    if let Some(length) = timestamp {
+        if let Ok(timestamp) = core::str::from_utf8(&rest[..length]) {
+            entry.put("SYSLOG_TIMESTAMP", text(timestamp.to_string()));
+        }
+        *offset += length;
+        if input.get(*offset) == Some(&b' ') {
+            *offset += 1;
+        }
+    }
+
+    // A hostname follows the timestamp, unless the first word after it is the
+    // tag itself. Without a timestamp the first word is part of the message.
+    let rest = &input[*offset..];
+    let word = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+    let is_tag = rest[..word].contains(&b'[') || rest[..word].ends_with(b":");
+    if timestamp.is_some() && word > 0 && word < rest.len() && !is_tag {
+        if let Ok(hostname) = core::str::from_utf8(&rest[..word]) {
+            entry.put("_HOSTNAME", text(hostname.to_string()));
+        }
+        *offset += word + 1;
+    }
+
+    // tag[pid]: message, as journald parses it
+    let rest = &input[*offset..];
+    let tag = rest
+        .iter()
+        .position(|&b| matches!(b, b'[' | b':' | b' '))
+        .unwrap_or(rest.len());
+    let mut after = tag;
+    let mut pid = None;
+    if rest.get(after) == Some(&b'[') {
+        if let Some(length) = rest[after + 1..].iter().position(|&b| b == b']') {
+            pid = Some(&rest[after + 1..after + 1 + length]);
+            after += length + 2;
+        }
+    }
+    if tag == 0 || rest.get(after) != Some(&b':') {
+        // No tag, the whole rest is the message
+        return;
+    }
+    after += 1;
+
+    let (Ok(identifier), pid) = (core::str::from_utf8(&rest[..tag]), pid) else {
+        return;
+    };
+    entry.put("SYSLOG_IDENTIFIER", text(identifier.to_string()));
+    if let Some(Ok(pid)) = pid.map(core::str::from_utf8) {
+        entry.put("SYSLOG_PID", text(pid.to_string()));
+    }
+
+    if rest.get(after) == Some(&b' ') {
+        after += 1;
+    }
+    *offset += after;
+}
+
+/// Length of a BSD `Mmm dd hh:mm:ss` timestamp or an RFC 3339 one, as rsyslog
+/// sends with high precision timestamps enabled
+fn rfc3164_timestamp_length(input: &[u8]) -> Option<usize> {
+    if input.len() >= 15
+        && input[..3].iter().all(u8::is_ascii_alphabetic)
+        && input[3] == b' '
+        && input[6] == b' '
+        && input[9] == b':'
+        && input[12] == b':'
+    {
+        return Some(15);
+    }
+
+    let length = input.iter().position(|&b| b == b' ')?;
+    let looks_rfc3339 = length >= 19
+        && input[..4].iter().all(u8::is_ascii_digit)
+        && input[4] == b'-'
+        && input[10] == b'T';
+    looks_rfc3339.then_some(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn parse(input: &[u8]) -> Result<JournalEntry, SyslogError> {
+        parse_syslog_message(input, &ParseOptions::default())
+    }
+
+    fn field(entry: &JournalEntry, key: &str) -> Option<String> {
+        entry.get(key).map(String::from)
+    }
+
+    #[test]
+    fn decodes_priority_and_facility() {
+        let entry = parse(b"<34>hello").unwrap();
+        assert_eq!(field(&entry, "PRIORITY").as_deref(), Some("2"));
+        assert_eq!(field(&entry, "SYSLOG_FACILITY").as_deref(), Some("4"));
+
+        let entry = parse(b"<191>hello").unwrap();
+        assert_eq!(field(&entry, "PRIORITY").as_deref(), Some("7"));
+        assert_eq!(field(&entry, "SYSLOG_FACILITY").as_deref(), Some("23"));
+
+        // user.notice without a `<PRI>` part
+        let entry = parse(b"hello").unwrap();
+        assert_eq!(field(&entry, "PRIORITY").as_deref(), Some("5"));
+        assert_eq!(field(&entry, "SYSLOG_FACILITY").as_deref(), Some("1"));
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some("hello"));
+
+        for input in [
+            &b"<192>hello"[..],
+            b"<>hello",
+            b"<1234>hello",
+            b"<34hello",
+            b"<",
+        ] {
+            assert_eq!(
+                parse(input).err(),
+                Some(SyslogError::InvalidPriority),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parses_rfc5424_messages() {
+        let entry = parse(
+            b"<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - \
+              \xef\xbb\xbf'su root' failed for lonvick on /dev/pts/8\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            field(&entry, "SYSLOG_TIMESTAMP").as_deref(),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(
+            field(&entry, "_HOSTNAME").as_deref(),
+            Some("mymachine.example.com")
+        );
+        assert_eq!(field(&entry, "SYSLOG_IDENTIFIER").as_deref(), Some("su"));
+        assert_eq!(field(&entry, "SYSLOG_MSGID").as_deref(), Some("ID47"));
+        assert_eq!(field(&entry, "SYSLOG_PID"), None);
+        assert_eq!(field(&entry, "SYSLOG_STRUCTURED_DATA"), None);
+        assert_eq!(
+            field(&entry, "MESSAGE").as_deref(),
+            Some("'su root' failed for lonvick on /dev/pts/8")
+        );
+    }
+
+    #[test]
+    fn keeps_rfc5424_structured_data() {
+        let entry = parse(
+            br#"<165>1 - host app 1234 - [ex@32473 iut="3"][x@1 v="a \"]\" b"] An application event"#,
+        )
+        .unwrap();
+
+        assert_eq!(field(&entry, "SYSLOG_PID").as_deref(), Some("1234"));
+        assert_eq!(field(&entry, "SYSLOG_MSGID"), None);
+        assert_eq!(
+            field(&entry, "SYSLOG_STRUCTURED_DATA").as_deref(),
+            Some(r#"[ex@32473 iut="3"][x@1 v="a \"]\" b"]"#)
+        );
+        assert_eq!(
+            field(&entry, "MESSAGE").as_deref(),
+            Some("An application event")
+        );
+    }
+
+    #[test]
+    fn leaves_out_rfc5424_nil_values() {
+        let entry = parse(b"<13>1 - - - - - -").unwrap();
+
+        let mut keys: Vec<_> = entry.keys().map(|key| key.to_string()).collect();
+        keys.sort();
+        assert_eq!(keys, ["MESSAGE", "PRIORITY", "SYSLOG_FACILITY"]);
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some(""));
+
+        let entry = parse(b"<13>1 - host - - - - message").unwrap();
+        assert_eq!(field(&entry, "_HOSTNAME").as_deref(), Some("host"));
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some("message"));
+    }
+
+    #[test]
+    fn rejects_malformed_rfc5424_headers() {
+        for input in [
+            // Ends within the header
+            &b"<34>1 2003-10-11T22:14:15.003Z host"[..],
+            b"<34>1 - - - -",
+            // Empty header field
+            b"<34>1 -  - - - - message",
+            // Unterminated structured data
+            b"<34>1 - - - - - [id a=\"1\"",
+            br#"<34>1 - - - - - [id a="1]"#,
+            // No space between structured data and message
+            b"<34>1 - - - - - [id]message",
+            b"<34>1 - - - - - message",
+        ] {
+            assert!(
+                matches!(parse(input), Err(SyslogError::InvalidHeader(_))),
+                "{:?}",
+                core::str::from_utf8(input)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_rfc3164_messages() {
+        let entry = parse(b"<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed\0").unwrap();
+        assert_eq!(
+            field(&entry, "SYSLOG_TIMESTAMP").as_deref(),
+            Some("Oct 11 22:14:15")
+        );
+        assert_eq!(field(&entry, "_HOSTNAME").as_deref(), Some("mymachine"));
+        assert_eq!(field(&entry, "SYSLOG_IDENTIFIER").as_deref(), Some("su"));
+        assert_eq!(field(&entry, "SYSLOG_PID").as_deref(), Some("123"));
+        assert_eq!(
+            field(&entry, "MESSAGE").as_deref(),
+            Some("'su root' failed")
+        );
+
+        // As sent to /dev/log, without a hostname
+        let entry = parse(b"<13>Oct  1 02:03:04 cron: job done").unwrap();
+        assert_eq!(field(&entry, "_HOSTNAME"), None);
+        assert_eq!(field(&entry, "SYSLOG_IDENTIFIER").as_deref(), Some("cron"));
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some("job done"));
+
+        // High precision timestamp
+        let entry = parse(b"<13>2023-05-01T10:00:00.123+02:00 host app: hi").unwrap();
+        assert_eq!(
+            field(&entry, "SYSLOG_TIMESTAMP").as_deref(),
+            Some("2023-05-01T10:00:00.123+02:00")
+        );
+        assert_eq!(field(&entry, "_HOSTNAME").as_deref(), Some("host"));
+    }
+
+    #[test]
+    fn rfc3164_without_header_is_all_message() {
+        let entry = parse(b"<13>just a message").unwrap();
+
+        assert_eq!(field(&entry, "_HOSTNAME"), None);
+        assert_eq!(field(&entry, "SYSLOG_IDENTIFIER"), None);
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some("just a message"));
+
+        // Truncated timestamp
+        let entry = parse(b"<13>Oct 11 22:1").unwrap();
+        assert_eq!(field(&entry, "SYSLOG_TIMESTAMP"), None);
+        assert_eq!(field(&entry, "MESSAGE").as_deref(), Some("Oct 11 22:1"));
+    }
+
+    #[test]
+    fn applies_limits_and_utf8_mode() {
+        let mut options = ParseOptions::default();
+        options.limits.max_field_size = 4;
+        assert_eq!(
+            parse_syslog_message(b"<13>hello", &options).err(),
+            Some(SyslogError::Field(FieldErrorKind::FieldTooLarge))
+        );
+
+        options.limits.max_entry_size = 8;
+        assert_eq!(
+            parse_syslog_message(b"<13>1 - - - - - - hi", &options).err(),
+            Some(SyslogError::Field(FieldErrorKind::EntryTooLarge))
+        );
+
+        let options = ParseOptions {
+            utf8: Utf8Mode::Strict,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_syslog_message(b"<13>a\xffb", &options).err(),
+            Some(SyslogError::Field(FieldErrorKind::InvalidUtf8))
+        );
+        assert!(matches!(
+            parse(b"<13>a\xffb").unwrap().get("MESSAGE"),
+            Some(JournalFieldValue::Bytes(_))
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::{intern, write_journal_entry, Cursor, JournalEntry, JournalFieldValue};
+
+const UNITS: &[(&str, &str)] = &[
+    ("sshd.service", "sshd"),
+    ("nginx.service", "nginx"),
+    ("systemd-logind.service", "systemd-logind"),
+    ("cron.service", "CRON"),
+    ("postgresql.service", "postgres"),
+    ("kernel", "kernel"),
+];
+
+const TRANSPORTS: &[&str] = &["journal", "stdout", "syslog", "kernel"];
+
+const WORDS: &[&str] = &[
+    "accepted",
+    "connection",
+    "from",
+    "port",
+    "session",
+    "opened",
+    "closed",
+    "for",
+    "user",
+    "request",
+    "completed",
+    "in",
+    "ms",
+    "failed",
+    "to",
+    "open",
+    "file",
+    "retrying",
+    "timeout",
+    "started",
+    "stopped",
+    "reloading",
+    "configuration",
+    "GET",
+    "POST",
+    "/api/v1/items",
+    "200",
+    "404",
+    "503",
+    "warning:",
+    "error:",
+    "disk",
+    "usage",
+    "above",
+    "threshold",
+];
+
+/// Deterministic generator of export format streams resembling a busy host,
+/// for benchmarks and property tests. The same settings and seed always
+/// produce the same bytes.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    seed: u64,
+    entries: usize,
+    message_size: RangeInclusive<usize>,
+    extra_fields: usize,
+    binary_ratio: f64,
+    boots: usize,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            entries: 1000,
+            message_size: 16..=256,
+            extra_fields: 0,
+            binary_ratio: 0.0,
+            boots: 1,
+        }
+    }
+
+    pub fn with_entries(mut self, entries: usize) -> Self {
+        self.entries = entries;
+        self
+    }
+
+    /// Bounds of the MESSAGE length in bytes
+    pub fn with_message_size(mut self, message_size: RangeInclusive<usize>) -> Self {
+        self.message_size = message_size;
+        self
+    }
+
+    /// Number of additional `CUSTOM_FIELD_<n>` fields per entry
+    pub fn with_extra_fields(mut self, extra_fields: usize) -> Self {
+        self.extra_fields = extra_fields;
+        self
+    }
+
+    /// Share of entries, from 0 to 1, whose MESSAGE is a binary payload
+    /// written in the size-prefixed encoding
+    pub fn with_binary_ratio(mut self, binary_ratio: f64) -> Self {
+        self.binary_ratio = binary_ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Number of boots the entries are spread over, in order
+    pub fn with_boots(mut self, boots: usize) -> Self {
+        self.boots = boots.max(1);
+        self
+    }
+
+    pub fn generate(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.write_to(&mut output)
+            .expect("writing to a Vec does not fail");
+        output
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for entry in self.entries() {
+            write_journal_entry(writer, &entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// The generated entries, lazily
+    pub fn entries(&self) -> impl Iterator<Item = JournalEntry> + '_ {
+        let mut rng = SplitMix64(self.seed);
+        let machine_id = rng.next_u128();
+        let seqnum_id = rng.next_u128();
+        let entries_per_boot = (self.entries + self.boots - 1) / self.boots;
+        let mut boot_id = 0;
+        let mut monotonic = 0;
+        // 2023-04-11T17:36:07Z
+        let mut realtime = 1_681_234_567_000_000;
+
+        (0..self.entries).map(move |index| {
+            if index % entries_per_boot == 0 {
+                boot_id = rng.next_u128();
+                monotonic = 1_000_000 + rng.below(10_000_000);
+            }
+            let step = rng.below(50_000);
+            monotonic += step;
+            realtime += step;
+
+            let cursor = Cursor {
+                seqnum_id,
+                seqnum: index as u64 + 1,
+                boot_id,
+                monotonic,
+                realtime,
+                xor_hash: Some(rng.next_u64()),
+            };
+            self.entry(&mut rng, &cursor, machine_id)
+        })
+    }
+
+    fn entry(&self, rng: &mut SplitMix64, cursor: &Cursor, machine_id: u128) -> JournalEntry {
+        let (unit, identifier) = UNITS[rng.below(UNITS.len() as u64) as usize];
+        let transport = if unit == "kernel" {
+            "kernel"
+        } else {
+            TRANSPORTS[rng.below(TRANSPORTS.len() as u64 - 1) as usize]
+        };
+
+        let mut entry = JournalEntry::default();
+        let mut text = |key: &str, value: String| {
+            entry.put(intern(key), JournalFieldValue::UTF8(value));
+        };
+        text("__CURSOR", cursor.to_string());
+        text("__REALTIME_TIMESTAMP", cursor.realtime.to_string());
+        text("__MONOTONIC_TIMESTAMP", cursor.monotonic.to_string());
+        text("_BOOT_ID", format!("{:032x}", cursor.boot_id));
+        text("_MACHINE_ID", format!("{:032x}", machine_id));
+        text("_HOSTNAME", String::from("testgen"));
+        text("_TRANSPORT", String::from(transport));
+        // Mostly informational, like real logs
+        text(
+            "PRIORITY",
+            [6, 6, 6, 6, 5, 4, 3][rng.below(7) as usize].to_string(),
+        );
+        text("SYSLOG_IDENTIFIER", String::from(identifier));
+        if unit != "kernel" {
+            text("_SYSTEMD_UNIT", String::from(unit));
+            text("_PID", (100 + rng.below(60_000)).to_string());
+        }
+        for field in 0..self.extra_fields {
+            text(
+                &format!("CUSTOM_FIELD_{}", field),
+                format!("{:x}", rng.next_u64()),
+            );
+        }
+
+        let size = rng.in_range(&self.message_size);
+        let message = if rng.chance(self.binary_ratio) {
+            let payload: Vec<u8> = (0..size).map(|_| rng.next_u64() as u8).collect();
+            JournalFieldValue::Bytes(payload.into())
+        } else {
+            JournalFieldValue::UTF8(message(rng, size))
+        };
+        entry.put("MESSAGE", message);
+
+        entry
+    }
+}
+
+/// Words from the vocabulary, cut to `size` bytes
+fn message(rng: &mut SplitMix64, size: usize) -> String {
+    let mut message = String::with_capacity(size + 16);
+    while message.len() < size {
+        if !message.is_empty() {
+            message.push(' ');
+        }
+        message.push_str(WORDS[rng.below(WORDS.len() as u64) as usize]);
+    }
+    message.truncate(size);
+
+    message
+}
+
+/// Small, fast and good enough for test data; avoids a dependency
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_u128(&mut self) -> u128 {
+        (u128::from(self.next_u64()) << 64) | u128::from(self.next_u64())
+    }
+
+    /// Uniform in `0..bound`, `bound` must not be 0
+    fn below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+
+    fn in_range(&mut self, range: &RangeInclusive<usize>) -> usize {
+        let (start, end) = (*range.start(), *range.end());
+        if end <= start {
+            return start;
+        }
+
+        start + self.below((end - start) as u64 + 1) as usize
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
@@ -0,0 +1,350 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{
+    intern, is_valid_field_key, Cursor, CursorError, FieldErrorKind, FieldName, JournalEntry,
+    JournalFieldValue, ParseOptions,
+};
+
+/// Error in `journalctl --output=verbose` text, with the 1-based line number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerboseEntryError {
+    /// A field precedes the first entry header
+    MissingHeader(usize),
+    /// The `[s=…;i=…;…]` part of an entry header is not a valid cursor
+    InvalidHeader(usize, CursorError),
+    /// An unindented line which is neither a header nor part of a value
+    UnexpectedLine(usize),
+    /// A field exceeds the parser limits
+    Field(usize, FieldErrorKind),
+}
+
+impl fmt::Display for VerboseEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader(line) => write!(f, "line {}: field outside of an entry", line),
+            Self::InvalidHeader(line, err) => write!(f, "line {}: invalid header: {}", line, err),
+            Self::UnexpectedLine(line) => write!(f, "line {}: expected an entry header", line),
+            Self::Field(line, kind) => write!(f, "line {}: {}", line, kind),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerboseEntryError {}
+
+/// Parses the output of `journalctl --output=verbose`, e.g. pasted from a
+/// ticket, into entries.
+///
+/// The address fields (`__CURSOR`, `__REALTIME_TIMESTAMP`, …) are taken from
+/// the cursor at the end of each header line. Values spanning several lines are
+/// joined back together. journalctl replaces binary values with a
+/// `[… blob data]` placeholder, these fields are left out. Only lines with a
+/// valid field key start a field, other lines continue the previous value.
+pub fn parse_verbose_entries(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<Vec<JournalEntry>, VerboseEntryError> {
+    let mut entries = Vec::new();
+    let mut current: Option<VerboseEntry> = None;
+
+    for (index, line) in input.lines().enumerate() {
+        let number = index + 1;
+        if line.trim().is_empty() {
+            if let Some(current) = &mut current {
+                current.blank_lines += 1;
+            }
+            continue;
+        }
+
+        let indented = line.starts_with(char::is_whitespace);
+        if !indented {
+            if let Some(cursor) = parse_header(line) {
+                let cursor = cursor.map_err(|err| VerboseEntryError::InvalidHeader(number, err))?;
+                if let Some(entry) = current.take() {
+                    entries.push(entry.finish(options)?);
+                }
+                current = Some(VerboseEntry::new(cursor));
+                continue;
+            }
+
+            // Boot separators and the like
+            if line.starts_with("-- ") && line.ends_with(" --") {
+                continue;
+            }
+        }
+
+        let current = current
+            .as_mut()
+            .ok_or(VerboseEntryError::MissingHeader(number))?;
+        match parse_field(line).filter(|_| indented) {
+            Some((key, value)) => current.start_field(number, key, value, options)?,
+            None => current.continue_field(number, line)?,
+        }
+    }
+
+    if let Some(entry) = current {
+        entries.push(entry.finish(options)?);
+    }
+
+    Ok(entries)
+}
+
+/// Cursor of a header line, `Tue 2023-04-11 19:36:07.123456 CEST [s=…;i=…;…]`
+fn parse_header(line: &str) -> Option<Result<Cursor, CursorError>> {
+    let (_, cursor) = line.rsplit_once(" [")?;
+    let cursor = cursor.strip_suffix(']')?;
+
+    cursor.starts_with("s=").then(|| cursor.parse())
+}
+
+fn parse_field(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.trim_start().split_once('=')?;
+
+    is_valid_field_key(key).then_some((key, value))
+}
+
+fn is_blob_placeholder(value: &str) -> bool {
+    value.starts_with('[') && value.ends_with(" blob data]")
+}
+
+/// Entry being read; the last field is kept apart until it is known whether
+/// further lines belong to it
+struct VerboseEntry {
+    cursor: Cursor,
+    entry: JournalEntry,
+    field: Option<(usize, FieldName, String)>,
+    blank_lines: usize,
+}
+
+impl VerboseEntry {
+    fn new(cursor: Cursor) -> Self {
+        Self {
+            cursor,
+            entry: JournalEntry::default(),
+            field: None,
+            blank_lines: 0,
+        }
+    }
+
+    fn start_field(
+        &mut self,
+        line: usize,
+        key: &str,
+        value: &str,
+        options: &ParseOptions,
+    ) -> Result<(), VerboseEntryError> {
+        self.end_field(options)?;
+        self.blank_lines = 0;
+
+        if !is_blob_placeholder(value) {
+            self.field = Some((line, intern(key), String::from(value)));
+        }
+
+        Ok(())
+    }
+
+    fn continue_field(&mut self, line: usize, text: &str) -> Result<(), VerboseEntryError> {
+        let (_, _, value) = self
+            .field
+            .as_mut()
+            .ok_or(VerboseEntryError::UnexpectedLine(line))?;
+
+        // Blank lines only belong to the value if more of it follows
+        for _ in 0..=self.blank_lines {
+            value.push('\n');
+        }
+        self.blank_lines = 0;
+        value.push_str(text);
+
+        Ok(())
+    }
+
+    fn end_field(&mut self, options: &ParseOptions) -> Result<(), VerboseEntryError> {
+        let Some((line, key, value)) = self.field.take() else {
+            return Ok(());
+        };
+
+        if value.len() as u64 > options.limits.max_field_size {
+            return Err(VerboseEntryError::Field(
+                line,
+                FieldErrorKind::FieldTooLarge,
+            ));
+        }
+        if self.entry.len() >= options.limits.max_fields_per_entry {
+            return Err(VerboseEntryError::Field(
+                line,
+                FieldErrorKind::TooManyFields,
+            ));
+        }
+        self.entry.put_multi(key, JournalFieldValue::UTF8(value));
+
+        Ok(())
+    }
+
+    fn finish(mut self, options: &ParseOptions) -> Result<JournalEntry, VerboseEntryError> {
+        self.end_field(options)?;
+
+        // Address fields, as added by journalctl
+        let cursor = &self.cursor;
+        let mut text = |key: &'static str, value: String| {
+            self.entry.put(key, JournalFieldValue::UTF8(value));
+        };
+        text("__CURSOR", cursor.to_string());
+        text("__REALTIME_TIMESTAMP", cursor.realtime.to_string());
+        text("__MONOTONIC_TIMESTAMP", cursor.monotonic.to_string());
+        text("__SEQNUM", cursor.seqnum.to_string());
+        text("__SEQNUM_ID", format!("{:032x}", cursor.seqnum_id));
+        text("_BOOT_ID", format!("{:032x}", cursor.boot_id));
+
+        Ok(self.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURSOR: &str = "s=739ad463348b4ceca5a9e69c95a3c93f;i=4ece7;\
+                          b=6d0b4b7e4f2c4c2bbd3c5a4b8c7d6e5f;m=3d6f6f5;t=5f91f1f5f0c2a;x=1a2b3c";
+
+    fn parse(input: &str) -> Result<Vec<JournalEntry>, VerboseEntryError> {
+        parse_verbose_entries(input, &ParseOptions::default())
+    }
+
+    fn header(seqnum: u64) -> String {
+        let cursor = CURSOR.replace("i=4ece7", &format!("i={:x}", seqnum));
+        format!("Tue 2023-04-11 19:36:07.123456 CEST [{}]", cursor)
+    }
+
+    fn field(entry: &JournalEntry, key: &str) -> Option<String> {
+        entry.get(key).map(String::from)
+    }
+
+    #[test]
+    fn takes_address_fields_from_the_header() {
+        let input = format!(
+            "{}\n    _TRANSPORT=journal\n    MESSAGE=hello = world\n",
+            header(1)
+        );
+        let entries = parse(&input).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(field(entry, "MESSAGE").as_deref(), Some("hello = world"));
+        assert_eq!(field(entry, "_TRANSPORT").as_deref(), Some("journal"));
+        assert_eq!(
+            field(entry, "__CURSOR"),
+            Some(CURSOR.replace("i=4ece7", "i=1"))
+        );
+        assert_eq!(
+            field(entry, "__REALTIME_TIMESTAMP").as_deref(),
+            Some("1681286949178410")
+        );
+        assert_eq!(
+            field(entry, "__MONOTONIC_TIMESTAMP").as_deref(),
+            Some("64419573")
+        );
+        assert_eq!(field(entry, "__SEQNUM").as_deref(), Some("1"));
+        assert_eq!(
+            field(entry, "__SEQNUM_ID").as_deref(),
+            Some("739ad463348b4ceca5a9e69c95a3c93f")
+        );
+        assert_eq!(
+            field(entry, "_BOOT_ID").as_deref(),
+            Some("6d0b4b7e4f2c4c2bbd3c5a4b8c7d6e5f")
+        );
+    }
+
+    #[test]
+    fn splits_entries_at_headers() {
+        let input = format!(
+            "-- Boot 6d0b4b7e4f2c4c2bbd3c5a4b8c7d6e5f --\n\
+             {}\n    MESSAGE=first\n\n{}\n    MESSAGE=second\n",
+            header(1),
+            header(2)
+        );
+        let entries = parse(&input).unwrap();
+
+        let messages: Vec<_> = entries
+            .iter()
+            .map(|entry| field(entry, "MESSAGE").unwrap())
+            .collect();
+        assert_eq!(messages, ["first", "second"]);
+        assert_eq!(field(&entries[1], "__SEQNUM").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn joins_multiline_values() {
+        let input = format!(
+            "{}\n    MESSAGE=line 1\nline 2\n\n  not=a field\n    PRIORITY=6\n\n",
+            header(1)
+        );
+        let entries = parse(&input).unwrap();
+
+        assert_eq!(
+            field(&entries[0], "MESSAGE").as_deref(),
+            Some("line 1\nline 2\n\n  not=a field")
+        );
+        // Trailing blank lines aren't part of the last value
+        assert_eq!(field(&entries[0], "PRIORITY").as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn keeps_repeated_fields() {
+        let input = format!("{}\n    TAG=a\n    TAG=b\n    TAG=c\n", header(1));
+        let entries = parse(&input).unwrap();
+
+        let tags: Vec<_> = entries[0].get_all("TAG").map(String::from).collect();
+        assert_eq!(tags, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn leaves_out_binary_placeholders() {
+        let input = format!(
+            "{}\n    COREDUMP=[4.2K blob data]\n    MESSAGE=[not a blob]\n",
+            header(1)
+        );
+        let entries = parse(&input).unwrap();
+
+        assert!(entries[0].get("COREDUMP").is_none());
+        assert_eq!(
+            field(&entries[0], "MESSAGE").as_deref(),
+            Some("[not a blob]")
+        );
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        assert_eq!(
+            parse("\n    MESSAGE=orphan\n").err(),
+            Some(VerboseEntryError::MissingHeader(2))
+        );
+        assert_eq!(
+            parse(&format!("{}\nunexpected\n", header(1))).err(),
+            Some(VerboseEntryError::UnexpectedLine(2))
+        );
+        assert!(matches!(
+            parse("Tue 2023-04-11 19:36:07.123456 CEST [s=123;i=1]"),
+            Err(VerboseEntryError::InvalidHeader(1, _))
+        ));
+
+        let options = ParseOptions {
+            limits: crate::ParserLimits {
+                max_field_size: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_verbose_entries(
+                &format!("{}\n    A=1\n    MESSAGE=hello\n", header(1)),
+                &options
+            )
+            .err(),
+            Some(VerboseEntryError::Field(3, FieldErrorKind::FieldTooLarge))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::process::ExitCode;
+
+use journalsql_pipeline::Error;
+
+const USAGE: &str = "usage:
+    journalsqlctl local-query <database> <sql>
+    journalsqlctl analyze <dump> --duckdb <database>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command, database, sql] if command == "local-query" => local_query(database, sql),
+        [command, dump, flag, database] if command == "analyze" && flag == "--duckdb" => {
+            analyze(dump, database)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("journalsqlctl: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Queries a database written by the SQLite sink, printing tab-separated rows
+/// under a header line
+#[cfg(feature = "sqlite")]
+fn local_query(database: &str, sql: &str) -> Result<(), Error> {
+    let (columns, rows) = journalsql_pipeline::local_query(database, sql)?;
+    println!("{}", columns.join("\t"));
+    for row in rows {
+        println!("{}", row.join("\t"));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn local_query(_database: &str, _sql: &str) -> Result<(), Error> {
+    Err("built without the sqlite feature".into())
+}
+
+/// Loads an export format dump into a DuckDB database, ready for `duckdb
+/// <database>`. Malformed entries are skipped.
+#[cfg(feature = "duckdb")]
+fn analyze(dump: &str, database: &str) -> Result<(), Error> {
+    use journalsql_pipeline::{DuckdbSink, Pipeline};
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let stats = runtime.block_on(async {
+        let file = tokio::fs::File::open(dump).await?;
+
+        Pipeline::builder()
+            .with_source(dump, file)
+            .with_recover(true)
+            .with_sink(DuckdbSink::open(database)?)
+            .build()?
+            .run()
+            .await
+            .map_err(Error::from)
+    })?;
+
+    eprintln!(
+        "loaded {} entries into table logs of {}, skipped {} malformed bytes",
+        stats.entries_written, database, stats.bytes_discarded
+    );
+
+    Ok(())
+}
+
+#[cfg(not(feature = "duckdb"))]
+fn analyze(_dump: &str, _database: &str) -> Result<(), Error> {
+    Err("built without the duckdb feature".into())
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use duckdb::types::{TimeUnit, Value};
+use duckdb::{params, Connection};
+use systemd_journal_parser::JournalEntry;
+
+use crate::{Error, Quantities, Row, Sink, SinkFuture};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS logs (
+    machine_id VARCHAR,
+    boot_id VARCHAR,
+    timestamp TIMESTAMP,
+    hostname VARCHAR,
+    transport VARCHAR,
+    cursor VARCHAR,
+    message VARCHAR,
+    -- All fields as a JSON object
+    record VARCHAR NOT NULL
+);
+";
+
+/// Appends entries to the `logs` table of a DuckDB database through the
+/// appender API, so export dumps can be analyzed locally with SQL
+pub struct DuckdbSink {
+    // Shared with the blocking task during appends, which may outlive a
+    // cancelled flush
+    connection: Arc<Mutex<Connection>>,
+    rows: Vec<JournalEntry>,
+    committed_cursor: Option<String>,
+}
+
+impl DuckdbSink {
+    /// Opens or creates the database at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> duckdb::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            rows: Vec::new(),
+            committed_cursor: None,
+        })
+    }
+
+    /// Appends the buffered rows, which stay buffered until flushed to the
+    /// table
+    async fn append_buffered(&mut self) -> Result<Quantities, Error> {
+        if self.rows.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let connection = self.connection.clone();
+        let batch = self.rows.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap_or_else(|err| err.into_inner());
+            append(&connection, &batch)
+        })
+        .await??;
+
+        let rows = std::mem::take(&mut self.rows);
+        self.committed_cursor = rows.last().map(|row| row.cursor().to_string());
+
+        Ok(Quantities {
+            entries: rows.len() as u64,
+            transactions: 1,
+        })
+    }
+}
+
+impl Sink for DuckdbSink {
+    fn write_batch(&mut self, rows: Vec<JournalEntry>) {
+        self.rows.extend(rows);
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.append_buffered())
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.append_buffered())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+fn append(connection: &Connection, batch: &[JournalEntry]) -> duckdb::Result<()> {
+    let mut appender = connection.appender("logs")?;
+    for entry in batch {
+        let timestamp = match entry.realtime_timestamp() {
+            Some(Ok(timestamp)) => Value::Timestamp(
+                TimeUnit::Microsecond,
+                (timestamp.unix_timestamp_nanos() / 1000) as i64,
+            ),
+            _ => Value::Null,
+        };
+        let record = serde_json::to_string(entry)
+            .map_err(|err| duckdb::Error::ToSqlConversionFailure(Box::new(err)))?;
+
+        appender.append_row(params![
+            text(entry, "_MACHINE_ID"),
+            text(entry, "_BOOT_ID"),
+            timestamp,
+            text(entry, "_HOSTNAME"),
+            text(entry, "_TRANSPORT"),
+            text(entry, "__CURSOR"),
+            text(entry, "MESSAGE"),
+            record,
+        ])?;
+    }
+
+    // Rows are only visible once flushed
+    appender.flush();
+    Ok(())
+}
+
+fn text(entry: &JournalEntry, key: &str) -> Option<String> {
+    entry.get(key).map(String::from)
+}
@@ -0,0 +1,755 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, error, warn};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::{Error, Quantities, Row, Sink, SinkFuture};
+
+/// First wait before a failed flush of a sink is retried, doubled up to
+/// `MAX_RETRY_BACKOFF` while it keeps failing
+const MIN_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What a sink does with rows once its queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum QueueFull {
+    /// Waits for room, slowing down the inputs and the other sinks
+    #[default]
+    Block,
+    /// Drops the rows for this sink only
+    Drop,
+}
+
+/// Queue in front of a sink. Sinks batch and send rows independently of each
+/// other, so a slow or failing sink only affects the others once its queue is
+/// full, and not at all when it drops rows then.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct SinkQueueConfig {
+    /// Entries queued at most
+    pub entries: usize,
+    pub when_full: QueueFull,
+    /// Whether the checkpoints of the inputs wait for the sink
+    pub checkpoint: bool,
+}
+
+impl Default for SinkQueueConfig {
+    fn default() -> Self {
+        Self {
+            entries: 100_000,
+            when_full: QueueFull::default(),
+            checkpoint: true,
+        }
+    }
+}
+
+/// The task of a fanout sink ended before its queue was closed
+#[derive(Debug, thiserror::Error)]
+#[error("Sink {0} stopped")]
+pub struct SinkStopped(pub String);
+
+/// Rows written to the fanout at once, numbered in order
+struct Batch<R> {
+    seq: u64,
+    rows: Vec<R>,
+}
+
+/// Sink of a fanout, running in its own task behind a queue
+struct Branch<R> {
+    name: String,
+    when_full: QueueFull,
+    checkpoint: bool,
+    /// `None` once shut down
+    queue: Option<mpsc::Sender<Batch<R>>>,
+    /// Batches waiting for room in the queue
+    pending: VecDeque<Batch<R>>,
+    /// Seq of the last batch queued
+    queued: u64,
+    /// Seq of the last batch queued or dropped
+    handled: u64,
+    /// Seq of the last batch committed by the sink
+    committed: Arc<AtomicU64>,
+    /// Sent by the sink since the last flush of the fanout
+    sent: Arc<Mutex<Quantities>>,
+    task: Option<JoinHandle<Result<Quantities, Error>>>,
+}
+
+impl<R: Row> Branch<R> {
+    /// Moves pending batches into the queue while it has room, dropping them
+    /// when full if the sink does
+    fn try_queue(&mut self, dropped: fn(&str, u64)) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+
+        while let Some(batch) = self.pending.pop_front() {
+            let seq = batch.seq;
+            if batch.rows.is_empty() {
+                self.handled = seq;
+                continue;
+            }
+            match queue.try_send(batch) {
+                Ok(()) => self.queued = seq,
+                Err(TrySendError::Full(batch)) if self.when_full == QueueFull::Block => {
+                    self.pending.push_front(batch);
+                    return;
+                }
+                Err(TrySendError::Full(batch)) => dropped(&self.name, batch.rows.len() as u64),
+                // The task only ends after the queue is closed
+                Err(TrySendError::Closed(_)) => {}
+            }
+            self.handled = seq;
+        }
+    }
+
+    /// Waits for room for the pending batches
+    async fn queue_pending(&mut self) -> Result<(), Error> {
+        let Some(queue) = &self.queue else {
+            return Ok(());
+        };
+
+        while let Some(batch) = self.pending.front() {
+            if batch.rows.is_empty() {
+                self.handled = batch.seq;
+                self.pending.pop_front();
+                continue;
+            }
+            let permit = queue
+                .reserve()
+                .await
+                .map_err(|_| SinkStopped(self.name.clone()))?;
+            let batch = self.pending.pop_front().expect("pending batch");
+            self.queued = batch.seq;
+            self.handled = batch.seq;
+            permit.send(batch);
+        }
+
+        Ok(())
+    }
+
+    /// Seq of the last batch the sink is done with, whether it committed it,
+    /// dropped it or had nothing to commit after it
+    fn done(&self) -> u64 {
+        let committed = self.committed.load(Ordering::Relaxed);
+        if committed >= self.queued && self.pending.is_empty() {
+            self.handled
+        } else {
+            committed
+        }
+    }
+}
+
+/// Decides whether a row goes to the sinks of a route
+type Route<R> = Box<dyn Fn(&R) -> bool + Send>;
+
+/// Sends rows to several sinks, each behind its own queue and running in its
+/// own task, so each batches, fails and retries independently. Failed flushes
+/// are retried with a backoff while the rows stay buffered in the sink.
+/// Checkpoints only advance through the rows every checkpointed sink is done
+/// with. Rows matching a route only go to its sinks, the first matching route
+/// applies.
+pub struct Fanout<R: Row> {
+    branches: Vec<Branch<R>>,
+    /// Conditions and the indices of the branches their rows go to
+    routes: Vec<(Route<R>, Vec<usize>)>,
+    /// Abandons in-flight flushes of the sinks, which are retried
+    restart: Option<Arc<Notify>>,
+    /// Called with the sink name and the number of rows its full queue dropped
+    dropped: fn(&str, u64),
+    /// Seq of the last batch written
+    seq: u64,
+    /// Seq and last cursor of the batches not checkpointed yet
+    written: VecDeque<(u64, String)>,
+    committed_cursor: Option<String>,
+}
+
+impl<R: Row> Default for Fanout<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Row> Fanout<R> {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+            routes: Vec::new(),
+            restart: None,
+            dropped: |name, rows| debug!("queue of sink {} full, dropped {} rows", name, rows),
+            seq: 0,
+            written: VecDeque::new(),
+            committed_cursor: None,
+        }
+    }
+
+    /// Abandons the in-flight flushes of the sinks added afterwards whenever
+    /// `restart` is notified, e.g. by a watchdog. The rows stay buffered and
+    /// are retried.
+    pub fn with_restart_signal(mut self, restart: Arc<Notify>) -> Self {
+        self.restart = Some(restart);
+        self
+    }
+
+    /// Reports rows dropped because the queue of a sink was full, e.g. to
+    /// metrics
+    pub fn with_dropped_callback(mut self, dropped: fn(&str, u64)) -> Self {
+        self.dropped = dropped;
+        self
+    }
+
+    /// Adds `sink`, starting its task
+    pub fn with_sink(
+        mut self,
+        name: &str,
+        sink: Box<dyn Sink<R>>,
+        config: &SinkQueueConfig,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(config.entries.max(1));
+        let committed = Arc::new(AtomicU64::new(0));
+        let sent = Arc::new(Mutex::new(Quantities::default()));
+        let task = tokio::task::spawn(run_sink(
+            name.to_string(),
+            sink,
+            receiver,
+            committed.clone(),
+            sent.clone(),
+            self.restart.clone(),
+        ));
+
+        self.branches.push(Branch {
+            name: name.to_string(),
+            when_full: config.when_full,
+            checkpoint: config.checkpoint,
+            queue: Some(sender),
+            pending: VecDeque::new(),
+            queued: 0,
+            handled: 0,
+            committed,
+            sent,
+            task: Some(task),
+        });
+        self
+    }
+
+    /// Sends the rows `route` returns true for only to the sinks named in
+    /// `sinks`, unless an earlier route takes them
+    pub fn with_route<F>(mut self, route: F, sinks: &[String]) -> Self
+    where
+        F: Fn(&R) -> bool + Send + 'static,
+    {
+        let branches = self
+            .branches
+            .iter()
+            .enumerate()
+            .filter(|(_, branch)| sinks.contains(&branch.name))
+            .map(|(index, _)| index)
+            .collect();
+        self.routes.push((Box::new(route), branches));
+        self
+    }
+
+    pub fn has_sinks(&self) -> bool {
+        !self.branches.is_empty()
+    }
+
+    pub fn has_sink(&self, name: &str) -> bool {
+        self.branches.iter().any(|branch| branch.name == name)
+    }
+
+    /// Quantities sent by the sinks since the last call, and the checkpoint
+    /// moved past the batches every checkpointed sink is done with
+    fn collect(&mut self) -> Quantities {
+        let mut total = Quantities::default();
+        for branch in &self.branches {
+            total += std::mem::take(&mut *branch.sent.lock().unwrap());
+        }
+
+        let done = self
+            .branches
+            .iter()
+            .filter(|branch| branch.checkpoint)
+            .map(Branch::done)
+            .min()
+            .unwrap_or(self.seq);
+        while self.written.front().map_or(false, |(seq, _)| *seq <= done) {
+            let (_, cursor) = self.written.pop_front().expect("written batch");
+            self.committed_cursor = Some(cursor);
+        }
+
+        total
+    }
+}
+
+impl<R: Row> Sink<R> for Fanout<R> {
+    fn write_batch(&mut self, rows: Vec<R>) {
+        let Some(last) = rows.last() else {
+            return;
+        };
+
+        self.seq += 1;
+        self.written
+            .push_back((self.seq, last.cursor().to_string()));
+
+        // Branches of the matching route of each row, `None` for all of them
+        let targets: Vec<Option<&[usize]>> = rows
+            .iter()
+            .map(|row| {
+                self.routes
+                    .iter()
+                    .find(|(route, _)| route(row))
+                    .map(|(_, branches)| branches.as_slice())
+            })
+            .collect();
+        for (index, branch) in self.branches.iter_mut().enumerate() {
+            // Batches without rows for the sink still go through its pending
+            // batches, so it's done with them once done with those before
+            let rows = rows
+                .iter()
+                .zip(targets.iter())
+                .filter(|(_, target)| target.map_or(true, |branches| branches.contains(&index)))
+                .map(|(row, _)| row.clone())
+                .collect();
+            branch.pending.push_back(Batch {
+                seq: self.seq,
+                rows,
+            });
+            branch.try_queue(self.dropped);
+        }
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            for branch in self.branches.iter_mut() {
+                branch.queue_pending().await?;
+            }
+
+            Ok(self.collect())
+        })
+    }
+
+    /// Lets every sink write its remaining rows, failing with the error of
+    /// a checkpointed sink, those of the others are only logged
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            let mut result = Ok(());
+            for branch in self.branches.iter_mut() {
+                if let Err(err) = branch.queue_pending().await {
+                    result = result.and(Err(err));
+                }
+                branch.queue = None;
+            }
+
+            for branch in self.branches.iter_mut() {
+                let Some(task) = branch.task.take() else {
+                    continue;
+                };
+                let res = task
+                    .await
+                    .unwrap_or_else(|_| Err(SinkStopped(branch.name.clone()).into()));
+                match res {
+                    Ok(sent) => *branch.sent.lock().unwrap() += sent,
+                    Err(err) if branch.checkpoint => result = result.and(Err(err)),
+                    Err(err) => error!("failed to shut down sink {}: {}", branch.name, err),
+                }
+            }
+
+            result.map(|()| self.collect())
+        })
+    }
+
+    /// Whether every checkpointed sink is done with all rows
+    fn is_empty(&self) -> bool {
+        self.branches
+            .iter()
+            .filter(|branch| branch.checkpoint)
+            .all(|branch| branch.done() == self.seq)
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+/// Writes the queued batches to `sink`, flushing it after each and every
+/// second, until the queue is closed
+async fn run_sink<R: Row>(
+    name: String,
+    mut sink: Box<dyn Sink<R>>,
+    mut queue: mpsc::Receiver<Batch<R>>,
+    committed: Arc<AtomicU64>,
+    sent: Arc<Mutex<Quantities>>,
+    restart: Option<Arc<Notify>>,
+) -> Result<Quantities, Error> {
+    // Seq and last cursor of the batches not committed yet
+    let mut batches: VecDeque<(u64, String)> = VecDeque::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut backoff = Duration::ZERO;
+    let mut retry_at: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            batch = queue.recv() => match batch {
+                Some(batch) => {
+                    if let Some(row) = batch.rows.last() {
+                        batches.push_back((batch.seq, row.cursor().to_string()));
+                    }
+                    sink.write_batch(batch.rows);
+                }
+                None => break,
+            },
+            _ = interval.tick() => {},
+        }
+        if retry_at.map_or(false, |at| Instant::now() < at) {
+            continue;
+        }
+
+        let restart_requested = async {
+            match &restart {
+                Some(restart) => restart.notified().await,
+                None => std::future::pending().await,
+            }
+        };
+        let res = tokio::select! {
+            res = sink.flush() => res,
+            _ = restart_requested => {
+                warn!("flush of sink {} abandoned, retrying", name);
+                continue;
+            },
+        };
+        match res {
+            Ok(res) => {
+                backoff = Duration::ZERO;
+                retry_at = None;
+                if res.entries > 0 {
+                    debug!("{}: sent={} txns={}", name, res.entries, res.transactions);
+                    *sent.lock().unwrap() += res;
+                }
+            }
+            Err(err) => {
+                backoff = (backoff * 2).clamp(MIN_RETRY_BACKOFF, MAX_RETRY_BACKOFF);
+                warn!("sink {} failed, retrying in {:?}: {}", name, backoff, err);
+                retry_at = Some(Instant::now() + backoff);
+            }
+        }
+        advance(sink.as_ref(), &mut batches, &committed);
+    }
+
+    let res = sink.shutdown().await?;
+    advance(sink.as_ref(), &mut batches, &committed);
+
+    Ok(res)
+}
+
+/// Marks the batches through the one ending in the committed cursor of `sink`
+/// as committed
+fn advance<R>(sink: &dyn Sink<R>, batches: &mut VecDeque<(u64, String)>, committed: &AtomicU64) {
+    let Some(cursor) = sink.committed_cursor() else {
+        return;
+    };
+    let Some(position) = batches.iter().rposition(|(_, last)| last == cursor) else {
+        return;
+    };
+
+    let seq = batches[position].0;
+    batches.drain(..=position);
+    committed.store(seq, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use tokio::sync::Semaphore;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct TestRow(String);
+
+    impl Row for TestRow {
+        fn cursor(&self) -> &str {
+            &self.0
+        }
+    }
+
+    fn batch(cursor: &str) -> Vec<TestRow> {
+        vec![TestRow(cursor.to_string())]
+    }
+
+    /// What the test sink did, shared with the test
+    struct Probe {
+        /// Flushes with rows wait for a permit, so the sink lags until given
+        gate: Semaphore,
+        fail: AtomicBool,
+        received: AtomicUsize,
+        written: Mutex<Vec<String>>,
+    }
+
+    impl Probe {
+        fn written(&self) -> Vec<String> {
+            self.written.lock().unwrap().clone()
+        }
+    }
+
+    struct TestSink {
+        probe: Arc<Probe>,
+        rows: Vec<TestRow>,
+        committed_cursor: Option<String>,
+    }
+
+    impl Sink<TestRow> for TestSink {
+        fn write_batch(&mut self, rows: Vec<TestRow>) {
+            self.probe.received.fetch_add(1, Ordering::Relaxed);
+            self.rows.extend(rows);
+        }
+
+        fn flush(&mut self) -> SinkFuture<'_> {
+            Box::pin(async move {
+                if self.rows.is_empty() {
+                    return Ok(Quantities::default());
+                }
+
+                self.probe.gate.acquire().await?.forget();
+                if self.probe.fail.load(Ordering::Relaxed) {
+                    return Err("unavailable".into());
+                }
+                let rows = std::mem::take(&mut self.rows);
+                self.committed_cursor = rows.last().map(|row| row.0.clone());
+                let mut written = self.probe.written.lock().unwrap();
+                written.extend(rows.iter().map(|row| row.0.clone()));
+
+                Ok(Quantities {
+                    entries: rows.len() as u64,
+                    transactions: 1,
+                })
+            })
+        }
+
+        fn shutdown(&mut self) -> SinkFuture<'_> {
+            self.flush()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.rows.is_empty()
+        }
+
+        fn committed_cursor(&self) -> Option<&str> {
+            self.committed_cursor.as_deref()
+        }
+    }
+
+    /// Sink flushing `permits` times before it lags
+    fn sink(permits: usize) -> (Box<dyn Sink<TestRow>>, Arc<Probe>) {
+        let probe = Arc::new(Probe {
+            gate: Semaphore::new(permits),
+            fail: AtomicBool::new(false),
+            received: AtomicUsize::new(0),
+            written: Mutex::new(Vec::new()),
+        });
+        let sink = TestSink {
+            probe: probe.clone(),
+            rows: Vec::new(),
+            committed_cursor: None,
+        };
+
+        (Box::new(sink), probe)
+    }
+
+    fn queue(entries: usize, when_full: QueueFull, checkpoint: bool) -> SinkQueueConfig {
+        SinkQueueConfig {
+            entries,
+            when_full,
+            checkpoint,
+        }
+    }
+
+    /// Waits up to five seconds for `condition`
+    async fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Flushes until the checkpoint reaches `cursor`
+    async fn wait_for_checkpoint(fanout: &mut Fanout<TestRow>, cursor: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            fanout.flush().await.unwrap();
+            if fanout.committed_cursor() == Some(cursor) {
+                return;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "checkpoint at {:?}",
+                fanout.committed_cursor()
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+    fn count_dropped(_: &str, rows: u64) {
+        DROPPED.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    #[tokio::test]
+    async fn lagging_sink_doesnt_hold_back_others() {
+        let (fast, fast_probe) = sink(100);
+        let (slow, slow_probe) = sink(0);
+        let mut fanout = Fanout::new()
+            .with_sink("fast", fast, &queue(10, QueueFull::Block, true))
+            .with_sink("slow", slow, &queue(10, QueueFull::Block, true));
+
+        for cursor in ["a", "b", "c"] {
+            fanout.write_batch(batch(cursor));
+        }
+        fanout.flush().await.unwrap();
+        wait_for(|| fast_probe.written() == ["a", "b", "c"]).await;
+        assert!(slow_probe.written().is_empty());
+        fanout.flush().await.unwrap();
+        assert_eq!(fanout.committed_cursor(), None);
+        assert!(!fanout.is_empty());
+
+        slow_probe.gate.add_permits(100);
+        wait_for_checkpoint(&mut fanout, "c").await;
+        assert_eq!(slow_probe.written(), ["a", "b", "c"]);
+        assert!(fanout.is_empty());
+
+        fanout.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_rows_for_its_sink_only() {
+        let (fast, fast_probe) = sink(100);
+        let (slow, slow_probe) = sink(0);
+        let mut fanout = Fanout::new()
+            .with_dropped_callback(count_dropped)
+            .with_sink("fast", fast, &queue(10, QueueFull::Block, true))
+            .with_sink("slow", slow, &queue(1, QueueFull::Drop, true));
+
+        // The first batch is taken by the lagging sink, the second waits in
+        // its queue and the third doesn't fit
+        fanout.write_batch(batch("a"));
+        wait_for(|| slow_probe.received.load(Ordering::Relaxed) == 1).await;
+        fanout.write_batch(batch("b"));
+        fanout.write_batch(batch("c"));
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        // Doesn't wait for the lagging sink
+        fanout.flush().await.unwrap();
+
+        slow_probe.gate.add_permits(100);
+        wait_for_checkpoint(&mut fanout, "c").await;
+        assert_eq!(fast_probe.written(), ["a", "b", "c"]);
+        assert_eq!(slow_probe.written(), ["a", "b"]);
+
+        fanout.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_queue_blocks_flush() {
+        let (slow, slow_probe) = sink(0);
+        let mut fanout = Fanout::new().with_sink("slow", slow, &queue(1, QueueFull::Block, true));
+
+        fanout.write_batch(batch("a"));
+        wait_for(|| slow_probe.received.load(Ordering::Relaxed) == 1).await;
+        fanout.write_batch(batch("b"));
+        fanout.write_batch(batch("c"));
+        let flushed = tokio::time::timeout(Duration::from_millis(100), fanout.flush()).await;
+        assert!(flushed.is_err(), "flush didn't wait for room");
+
+        slow_probe.gate.add_permits(100);
+        wait_for_checkpoint(&mut fanout, "c").await;
+        assert_eq!(slow_probe.written(), ["a", "b", "c"]);
+
+        fanout.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkpoint_is_minimum_of_checkpointed_sinks() {
+        let (fast, _) = sink(100);
+        // Commits the first batch, then lags
+        let (slow, slow_probe) = sink(1);
+        let (failing, failing_probe) = sink(100);
+        failing_probe.fail.store(true, Ordering::Relaxed);
+        let mut fanout = Fanout::new()
+            .with_sink("fast", fast, &queue(10, QueueFull::Block, true))
+            .with_sink("slow", slow, &queue(10, QueueFull::Block, true))
+            .with_sink("failing", failing, &queue(10, QueueFull::Block, false));
+
+        for cursor in ["a", "b", "c"] {
+            fanout.write_batch(batch(cursor));
+        }
+        wait_for_checkpoint(&mut fanout, "a").await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        fanout.flush().await.unwrap();
+        assert_eq!(fanout.committed_cursor(), Some("a"));
+
+        slow_probe.gate.add_permits(100);
+        wait_for_checkpoint(&mut fanout, "c").await;
+        assert!(failing_probe.written().is_empty());
+
+        // Only checkpointed sinks fail the shutdown
+        fanout.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_flush_is_retried() {
+        let (failing, failing_probe) = sink(100);
+        failing_probe.fail.store(true, Ordering::Relaxed);
+        let mut fanout =
+            Fanout::new().with_sink("failing", failing, &queue(10, QueueFull::Block, true));
+
+        fanout.write_batch(batch("a"));
+        wait_for(|| failing_probe.received.load(Ordering::Relaxed) == 1).await;
+        fanout.flush().await.unwrap();
+        assert_eq!(fanout.committed_cursor(), None);
+
+        failing_probe.fail.store(false, Ordering::Relaxed);
+        wait_for_checkpoint(&mut fanout, "a").await;
+        assert_eq!(failing_probe.written(), ["a"]);
+
+        fanout.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn routed_rows_only_go_to_their_sinks() {
+        let (all, all_probe) = sink(100);
+        let (audit, audit_probe) = sink(100);
+        let mut fanout = Fanout::new()
+            .with_sink("all", all, &queue(10, QueueFull::Block, true))
+            .with_sink("audit", audit, &queue(10, QueueFull::Block, true))
+            .with_route(
+                |row: &TestRow| row.0.starts_with("audit"),
+                &["audit".to_string()],
+            );
+
+        fanout.write_batch(vec![
+            TestRow("a".to_string()),
+            TestRow("audit-b".to_string()),
+        ]);
+        fanout.write_batch(batch("c"));
+        wait_for_checkpoint(&mut fanout, "c").await;
+        assert_eq!(all_probe.written(), ["a", "c"]);
+        assert_eq!(audit_probe.written(), ["audit-b"]);
+
+        fanout.shutdown().await.unwrap();
+    }
+}
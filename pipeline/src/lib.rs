@@ -0,0 +1,44 @@
+//! Embeddable journal ingestion: export format streams are parsed, passed
+//! through a chain of stages and written to a sink in batches. `journalsqld`
+//! sends its rows through the same [`Sink`] trait and [`Fanout`].
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), journalsql_pipeline::PipelineError> {
+//! use journalsql_pipeline::{Error, FnSink, JournalEntry, Pipeline};
+//!
+//! let stats = Pipeline::builder()
+//!     .with_source("stdin", tokio::io::stdin())
+//!     .with_stage(|entry: JournalEntry| entry.get("MESSAGE").is_some().then_some(entry))
+//!     .with_sink(FnSink::new(|batch: Vec<JournalEntry>| async move {
+//!         println!("{} entries", batch.len());
+//!         Ok::<_, Error>(())
+//!     }))
+//!     .build()?
+//!     .run()
+//!     .await?;
+//! println!("{} entries read", stats.entries_read);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "duckdb")]
+mod duckdb;
+mod fanout;
+mod pipeline;
+mod sink;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stage;
+
+#[cfg(feature = "duckdb")]
+pub use self::duckdb::DuckdbSink;
+pub use fanout::{Fanout, QueueFull, SinkQueueConfig, SinkStopped};
+pub use pipeline::{Pipeline, PipelineBuilder, PipelineError, PipelineStats};
+pub use sink::{FnSink, Quantities, Row, Sink, SinkFuture};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{local_query, SqliteSink};
+pub use stage::Stage;
+
+pub use systemd_journal_parser::{JournalEntry, ParseOptions};
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -0,0 +1,266 @@
+use std::time::Duration;
+
+use log::{debug, warn};
+use systemd_journal_parser::{EntryReader, JournalEntry, JournalReadError, ParseOptions};
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::MissedTickBehavior;
+
+use crate::{Error, Sink, Stage};
+
+const DEFAULT_BATCH_SIZE: usize = 100_000;
+const DEFAULT_PERIOD: Duration = Duration::from_secs(5);
+const DEFAULT_CAPACITY: usize = 1024;
+
+type BoxedSource = Box<dyn AsyncRead + Send + Unpin>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error("No sink configured")]
+    NoSink,
+
+    #[error("Source {0:?} failed: {1}")]
+    Source(String, JournalReadError),
+
+    #[error("Source task failed: {0}")]
+    Task(JoinError),
+
+    #[error("Sink failed: {0}")]
+    Sink(Error),
+}
+
+/// Counters of a finished run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PipelineStats {
+    pub entries_read: u64,
+    /// Entries dropped by a stage
+    pub entries_dropped: u64,
+    pub entries_written: u64,
+    /// Transactions of the sink the entries were written in
+    pub batches_written: u64,
+    /// Malformed input skipped while recovering
+    pub bytes_discarded: u64,
+}
+
+pub struct PipelineBuilder {
+    sources: Vec<(String, BoxedSource)>,
+    stages: Vec<Box<dyn Stage>>,
+    sink: Option<Box<dyn Sink>>,
+    options: ParseOptions,
+    recover: bool,
+    batch_size: usize,
+    period: Duration,
+    capacity: usize,
+}
+
+impl PipelineBuilder {
+    /// Adds an export format stream, read concurrently with the other sources.
+    /// `name` identifies it in errors and logs.
+    pub fn with_source<S, R>(mut self, name: S, reader: R) -> Self
+    where
+        S: Into<String>,
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        self.sources.push((name.into(), Box::new(reader)));
+        self
+    }
+
+    /// Appends a stage, stages run in the order they were added
+    pub fn with_stage<S: Stage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn with_sink<S: Sink + 'static>(mut self, sink: S) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn with_parse_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Skip malformed input up to the next entry instead of failing the source
+    pub fn with_recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    /// Largest batch passed to the sink
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Interval at which incomplete batches are written
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Entries buffered between the sources and the stages
+    pub fn with_channel_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn build(self) -> Result<Pipeline, PipelineError> {
+        Ok(Pipeline {
+            sources: self.sources,
+            stages: self.stages,
+            sink: self.sink.ok_or(PipelineError::NoSink)?,
+            options: self.options,
+            recover: self.recover,
+            batch_size: self.batch_size,
+            period: self.period,
+            capacity: self.capacity,
+        })
+    }
+}
+
+/// Sources feeding a chain of stages feeding a sink. Runs until every source
+/// is exhausted, or until the first source or sink error.
+pub struct Pipeline {
+    sources: Vec<(String, BoxedSource)>,
+    stages: Vec<Box<dyn Stage>>,
+    sink: Box<dyn Sink>,
+    options: ParseOptions,
+    recover: bool,
+    batch_size: usize,
+    period: Duration,
+    capacity: usize,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder {
+            sources: Vec::new(),
+            stages: Vec::new(),
+            sink: None,
+            options: ParseOptions::default(),
+            recover: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            period: DEFAULT_PERIOD,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Must be called within a tokio runtime, sources are read on spawned tasks
+    pub async fn run(mut self) -> Result<PipelineStats, PipelineError> {
+        let (sender, mut receiver) = mpsc::channel(self.capacity);
+        let mut sources = JoinSet::new();
+        for (name, reader) in std::mem::take(&mut self.sources) {
+            sources.spawn(read_source(
+                name,
+                reader,
+                self.options.clone(),
+                self.recover,
+                sender.clone(),
+            ));
+        }
+        // Channel closes once every source is done
+        drop(sender);
+
+        let mut stats = PipelineStats::default();
+        let mut batch = Vec::new();
+        let mut interval = tokio::time::interval(self.period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                entry = receiver.recv() => {
+                    let Some(entry) = entry else {
+                        break;
+                    };
+
+                    stats.entries_read += 1;
+                    match self.apply_stages(entry) {
+                        Some(entry) => batch.push(entry),
+                        None => stats.entries_dropped += 1,
+                    }
+
+                    if batch.len() >= self.batch_size {
+                        self.flush(&mut batch, &mut stats).await?;
+                    }
+                }
+                Some(result) = sources.join_next() => {
+                    stats.bytes_discarded += result.map_err(PipelineError::Task)??;
+                }
+                _ = interval.tick() => self.flush(&mut batch, &mut stats).await?,
+            }
+        }
+
+        while let Some(result) = sources.join_next().await {
+            stats.bytes_discarded += result.map_err(PipelineError::Task)??;
+        }
+
+        self.flush(&mut batch, &mut stats).await?;
+        let sent = self.sink.shutdown().await.map_err(PipelineError::Sink)?;
+        stats.entries_written += sent.entries;
+        stats.batches_written += sent.transactions;
+        debug!("pipeline finished: {:?}", stats);
+
+        Ok(stats)
+    }
+
+    fn apply_stages(&mut self, entry: JournalEntry) -> Option<JournalEntry> {
+        self.stages
+            .iter_mut()
+            .try_fold(entry, |entry, stage| stage.apply(entry))
+    }
+
+    async fn flush(
+        &mut self,
+        batch: &mut Vec<JournalEntry>,
+        stats: &mut PipelineStats,
+    ) -> Result<(), PipelineError> {
+        if !batch.is_empty() {
+            self.sink.write_batch(std::mem::take(batch));
+        }
+
+        let sent = self.sink.flush().await.map_err(PipelineError::Sink)?;
+        stats.entries_written += sent.entries;
+        stats.batches_written += sent.transactions;
+
+        Ok(())
+    }
+}
+
+/// Returns the number of malformed bytes skipped
+async fn read_source(
+    name: String,
+    reader: BoxedSource,
+    options: ParseOptions,
+    recover: bool,
+    sender: mpsc::Sender<JournalEntry>,
+) -> Result<u64, PipelineError> {
+    let mut reader = EntryReader::new(reader).with_options(options);
+    let mut discarded = 0;
+
+    loop {
+        match reader.next_entry().await {
+            Ok(Some(entry)) => {
+                if sender.send(entry).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(JournalReadError::ParseError(info)) if recover => {
+                warn!(
+                    "{}: malformed input, skipping to next entry: {}",
+                    name, info
+                );
+                discarded += reader
+                    .resync()
+                    .await
+                    .map_err(|err| PipelineError::Source(name.clone(), err))?;
+            }
+            Err(err) => return Err(PipelineError::Source(name, err)),
+        }
+    }
+
+    debug!("{}: end of input", name);
+    Ok(discarded)
+}
@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use systemd_journal_parser::{JournalEntry, JournalFieldValue};
+
+use crate::Error;
+
+/// Entry as written to sinks, the parsed entry itself or a row made from it
+pub trait Row: Clone + Send + 'static {
+    /// `__CURSOR` of the entry, empty if it has none
+    fn cursor(&self) -> &str;
+}
+
+impl Row for JournalEntry {
+    fn cursor(&self) -> &str {
+        match self.get("__CURSOR") {
+            Some(JournalFieldValue::UTF8(cursor)) => cursor,
+            _ => "",
+        }
+    }
+}
+
+/// Entries and transactions sent by a sink
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Quantities {
+    pub entries: u64,
+    pub transactions: u64,
+}
+
+impl std::ops::AddAssign for Quantities {
+    fn add_assign(&mut self, other: Self) {
+        self.entries += other.entries;
+        self.transactions += other.transactions;
+    }
+}
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<Quantities, Error>> + Send + 'a>>;
+
+/// Destination of the entries, such as a database table. Sinks buffer the
+/// rows written to them and send them in batches of their own choosing, rows
+/// must stay buffered until they were sent, so a failed or cancelled flush is
+/// retried by the next one.
+pub trait Sink<R = JournalEntry>: Send {
+    /// Buffers `rows` to be sent by a later flush
+    fn write_batch(&mut self, rows: Vec<R>);
+
+    /// Sends the buffered rows whose batch is due, called after every write
+    /// and periodically
+    fn flush(&mut self) -> SinkFuture<'_>;
+
+    /// Sends all remaining buffered rows, called once after the last write
+    fn shutdown(&mut self) -> SinkFuture<'_>;
+
+    /// Whether no rows are buffered waiting to be sent
+    fn is_empty(&self) -> bool;
+
+    /// Cursor of the most recent row, once every row before it was sent too.
+    /// Recorded as the checkpoint of the inputs.
+    fn committed_cursor(&self) -> Option<&str>;
+}
+
+/// Sink passing the buffered rows to a closure on every flush, e.g.
+/// `FnSink::new(|batch: Vec<JournalEntry>| async move { .. })`. The closure
+/// gets a copy, so the rows stay buffered until it succeeds.
+pub struct FnSink<R, F> {
+    write: F,
+    rows: Vec<R>,
+    committed_cursor: Option<String>,
+}
+
+impl<R, F> FnSink<R, F> {
+    pub fn new(write: F) -> Self {
+        Self {
+            write,
+            rows: Vec::new(),
+            committed_cursor: None,
+        }
+    }
+}
+
+impl<R, F, Fut> Sink<R> for FnSink<R, F>
+where
+    R: Row,
+    F: FnMut(Vec<R>) -> Fut + Send,
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    fn write_batch(&mut self, rows: Vec<R>) {
+        self.rows.extend(rows);
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(async move {
+            if self.rows.is_empty() {
+                return Ok(Quantities::default());
+            }
+
+            (self.write)(self.rows.clone()).await?;
+            let rows = std::mem::take(&mut self.rows);
+            self.committed_cursor = rows.last().map(|row| row.cursor().to_string());
+
+            Ok(Quantities {
+                entries: rows.len() as u64,
+                transactions: 1,
+            })
+        })
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        self.flush()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
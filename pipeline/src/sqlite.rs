@@ -0,0 +1,192 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use systemd_journal_parser::JournalEntry;
+
+use crate::{Error, Quantities, Row, Sink, SinkFuture};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS logs (
+    id INTEGER PRIMARY KEY,
+    machine_id TEXT,
+    boot_id TEXT,
+    -- Microseconds since the epoch
+    timestamp INTEGER,
+    hostname TEXT,
+    transport TEXT,
+    cursor TEXT,
+    message TEXT,
+    -- All fields as a JSON object
+    record TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS logs_timestamp ON logs (timestamp);
+CREATE INDEX IF NOT EXISTS logs_machine_boot ON logs (machine_id, boot_id, timestamp);
+";
+
+const FULL_TEXT_SCHEMA: &str = "
+CREATE VIRTUAL TABLE IF NOT EXISTS logs_fts USING fts5 (
+    message,
+    content = 'logs',
+    content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS logs_fts_insert AFTER INSERT ON logs BEGIN
+    INSERT INTO logs_fts (rowid, message) VALUES (new.id, new.message);
+END;
+CREATE TRIGGER IF NOT EXISTS logs_fts_delete AFTER DELETE ON logs BEGIN
+    INSERT INTO logs_fts (logs_fts, rowid, message) VALUES ('delete', old.id, old.message);
+END;
+";
+
+const INSERT: &str = "
+INSERT INTO logs (machine_id, boot_id, timestamp, hostname, transport, cursor, message, record)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+";
+
+/// Writes entries to a local SQLite database, one transaction per flush, for
+/// single-node deployments without ClickHouse. The database uses WAL mode, so
+/// it can be queried while entries are written.
+pub struct SqliteSink {
+    // Shared with the blocking task during inserts, which may outlive a
+    // cancelled flush
+    connection: Arc<Mutex<Connection>>,
+    rows: Vec<JournalEntry>,
+    committed_cursor: Option<String>,
+}
+
+impl SqliteSink {
+    /// Opens or creates the database at `path`. With `full_text`, MESSAGE is
+    /// indexed in the FTS5 table `logs_fts`.
+    pub fn open<P: AsRef<Path>>(path: P, full_text: bool) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+        if full_text {
+            connection.execute_batch(FULL_TEXT_SCHEMA)?;
+        }
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            rows: Vec::new(),
+            committed_cursor: None,
+        })
+    }
+
+    /// Inserts the buffered rows, which stay buffered until committed
+    async fn insert_buffered(&mut self) -> Result<Quantities, Error> {
+        if self.rows.is_empty() {
+            return Ok(Quantities::default());
+        }
+
+        let connection = self.connection.clone();
+        let batch = self.rows.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|err| err.into_inner());
+            insert(&mut connection, &batch)
+        })
+        .await??;
+
+        let rows = std::mem::take(&mut self.rows);
+        self.committed_cursor = rows.last().map(|row| row.cursor().to_string());
+
+        Ok(Quantities {
+            entries: rows.len() as u64,
+            transactions: 1,
+        })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_batch(&mut self, rows: Vec<JournalEntry>) {
+        self.rows.extend(rows);
+    }
+
+    fn flush(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.insert_buffered())
+    }
+
+    fn shutdown(&mut self) -> SinkFuture<'_> {
+        Box::pin(self.insert_buffered())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn committed_cursor(&self) -> Option<&str> {
+        self.committed_cursor.as_deref()
+    }
+}
+
+fn insert(connection: &mut Connection, batch: &[JournalEntry]) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    {
+        let mut statement = transaction.prepare_cached(INSERT)?;
+        for entry in batch {
+            let timestamp = entry
+                .realtime_timestamp()
+                .and_then(Result::ok)
+                .map(|timestamp| (timestamp.unix_timestamp_nanos() / 1000) as i64);
+            let record = serde_json::to_string(entry)
+                .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+
+            statement.execute(params![
+                text(entry, "_MACHINE_ID"),
+                text(entry, "_BOOT_ID"),
+                timestamp,
+                text(entry, "_HOSTNAME"),
+                text(entry, "_TRANSPORT"),
+                text(entry, "__CURSOR"),
+                text(entry, "MESSAGE"),
+                record,
+            ])?;
+        }
+    }
+
+    transaction.commit()
+}
+
+fn text(entry: &JournalEntry, key: &str) -> Option<String> {
+    entry.get(key).map(String::from)
+}
+
+/// Runs `sql` against the database at `path` opened read-only. Returns the
+/// column names and the rows, values rendered as text.
+pub fn local_query<P: AsRef<Path>>(
+    path: P,
+    sql: &str,
+) -> rusqlite::Result<(Vec<String>, Vec<Vec<String>>)> {
+    let connection = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let mut statement = connection.prepare(sql)?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut results = statement.query([])?;
+    while let Some(result) = results.next()? {
+        let values = (0..columns.len())
+            .map(|index| result.get_ref(index).map(render))
+            .collect::<rusqlite::Result<_>>()?;
+        rows.push(values);
+    }
+
+    Ok((columns, rows))
+}
+
+fn render(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::from("NULL"),
+        ValueRef::Integer(value) => value.to_string(),
+        ValueRef::Real(value) => value.to_string(),
+        ValueRef::Text(value) => String::from_utf8_lossy(value).into_owned(),
+        ValueRef::Blob(value) => value.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
@@ -0,0 +1,16 @@
+use systemd_journal_parser::JournalEntry;
+
+/// Step between parsing and the sink, which rewrites an entry or drops it by
+/// returning `None`
+pub trait Stage: Send {
+    fn apply(&mut self, entry: JournalEntry) -> Option<JournalEntry>;
+}
+
+impl<F> Stage for F
+where
+    F: FnMut(JournalEntry) -> Option<JournalEntry> + Send,
+{
+    fn apply(&mut self, entry: JournalEntry) -> Option<JournalEntry> {
+        self(entry)
+    }
+}